@@ -0,0 +1,330 @@
+//! Assembles the shaders naga cannot compile from SPIR-V assembly in `src/shaders`, so
+//! the modules loaded at runtime always match their source.
+//!
+//! Only the subset of `spirv-as` syntax these shaders use is understood. Ids are numbered
+//! in order of first appearance, like `spirv-as` does, and modules target SPIR-V 1.4.
+
+use std::{collections::HashMap, env, fs, path::Path};
+
+/// Assembled into `$OUT_DIR/<name>.spv`
+const SHADERS: &[&str] = &["meshlets_task", "meshlets_mesh"];
+
+const SPIRV_VERSION: u32 = 0x0001_0400;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Results {
+    Nothing,
+    Id,
+    TypeAndId,
+}
+
+fn opcode(name: &str) -> Option<(u32, Results)> {
+    use Results::*;
+    Some(match name {
+        "OpExtension" => (10, Nothing),
+        "OpExtInstImport" => (11, Id),
+        "OpExtInst" => (12, TypeAndId),
+        "OpMemoryModel" => (14, Nothing),
+        "OpEntryPoint" => (15, Nothing),
+        "OpExecutionMode" => (16, Nothing),
+        "OpCapability" => (17, Nothing),
+        "OpTypeVoid" => (19, Id),
+        "OpTypeBool" => (20, Id),
+        "OpTypeInt" => (21, Id),
+        "OpTypeFloat" => (22, Id),
+        "OpTypeVector" => (23, Id),
+        "OpTypeMatrix" => (24, Id),
+        "OpTypeArray" => (28, Id),
+        "OpTypeRuntimeArray" => (29, Id),
+        "OpTypeStruct" => (30, Id),
+        "OpTypePointer" => (32, Id),
+        "OpTypeFunction" => (33, Id),
+        "OpConstant" => (43, TypeAndId),
+        "OpConstantComposite" => (44, TypeAndId),
+        "OpFunction" => (54, TypeAndId),
+        "OpFunctionEnd" => (56, Nothing),
+        "OpVariable" => (59, TypeAndId),
+        "OpLoad" => (61, TypeAndId),
+        "OpStore" => (62, Nothing),
+        "OpAccessChain" => (65, TypeAndId),
+        "OpDecorate" => (71, Nothing),
+        "OpMemberDecorate" => (72, Nothing),
+        "OpVectorShuffle" => (79, TypeAndId),
+        "OpCompositeConstruct" => (80, TypeAndId),
+        "OpCompositeExtract" => (81, TypeAndId),
+        "OpCompositeInsert" => (82, TypeAndId),
+        "OpFNegate" => (127, TypeAndId),
+        "OpIAdd" => (128, TypeAndId),
+        "OpFAdd" => (129, TypeAndId),
+        "OpFSub" => (131, TypeAndId),
+        "OpIMul" => (132, TypeAndId),
+        "OpFMul" => (133, TypeAndId),
+        "OpMatrixTimesVector" => (145, TypeAndId),
+        "OpDot" => (148, TypeAndId),
+        "OpLogicalOr" => (166, TypeAndId),
+        "OpLogicalAnd" => (167, TypeAndId),
+        "OpLogicalNot" => (168, TypeAndId),
+        "OpIEqual" => (170, TypeAndId),
+        "OpULessThan" => (176, TypeAndId),
+        "OpFOrdLessThan" => (184, TypeAndId),
+        "OpFOrdGreaterThanEqual" => (190, TypeAndId),
+        "OpShiftRightLogical" => (194, TypeAndId),
+        "OpBitwiseAnd" => (199, TypeAndId),
+        "OpControlBarrier" => (224, Nothing),
+        "OpAtomicLoad" => (227, TypeAndId),
+        "OpAtomicStore" => (228, Nothing),
+        "OpAtomicIAdd" => (234, TypeAndId),
+        "OpSelectionMerge" => (247, Nothing),
+        "OpLabel" => (248, Id),
+        "OpBranch" => (249, Nothing),
+        "OpBranchConditional" => (250, Nothing),
+        "OpReturn" => (253, Nothing),
+        "OpEmitMeshTasksEXT" => (5294, Nothing),
+        "OpSetMeshOutputsEXT" => (5295, Nothing),
+        _ => return None,
+    })
+}
+
+fn is_terminator(op: &str) -> bool {
+    matches!(
+        op,
+        "OpBranch" | "OpBranchConditional" | "OpReturn" | "OpEmitMeshTasksEXT"
+    )
+}
+
+fn enumerant(name: &str) -> Option<u32> {
+    Some(match name {
+        // capabilities
+        "Shader" => 1,
+        "MeshShadingEXT" => 5283,
+        // addressing and memory models
+        "Logical" => 0,
+        "GLSL450" => 1,
+        // execution models
+        "TaskEXT" => 5364,
+        "MeshEXT" => 5365,
+        // execution modes
+        "LocalSize" => 17,
+        "OutputVertices" => 26,
+        "OutputPrimitivesEXT" => 5270,
+        "OutputTrianglesEXT" => 5298,
+        // storage classes
+        "Input" => 1,
+        "Uniform" => 2,
+        "Output" => 3,
+        "Workgroup" => 4,
+        "PushConstant" => 9,
+        "StorageBuffer" => 12,
+        "TaskPayloadWorkgroupEXT" => 5402,
+        // decorations
+        "Block" => 2,
+        "ColMajor" => 5,
+        "ArrayStride" => 6,
+        "MatrixStride" => 7,
+        "BuiltIn" => 11,
+        "Flat" => 14,
+        "Invariant" => 18,
+        "NonWritable" => 24,
+        "Location" => 30,
+        "Binding" => 33,
+        "DescriptorSet" => 34,
+        "Offset" => 35,
+        // builtins
+        "Position" => 0,
+        "WorkgroupId" => 26,
+        "LocalInvocationIndex" => 29,
+        "PrimitiveTriangleIndicesEXT" => 5296,
+        // function and selection control
+        "None" => 0,
+        _ => return None,
+    })
+}
+
+fn glsl_std_450(name: &str) -> Option<u32> {
+    Some(match name {
+        "Sqrt" => 31,
+        "FMax" => 40,
+        "Length" => 66,
+        "Normalize" => 69,
+        _ => return None,
+    })
+}
+
+enum Token<'a> {
+    Word(&'a str),
+    String(&'a str),
+}
+
+fn tokenize(line: &str) -> Vec<Token<'_>> {
+    let mut tokens = vec![];
+    let mut rest = line.trim_start();
+    while !rest.is_empty() {
+        if let Some(quoted) = rest.strip_prefix('"') {
+            let end = quoted.find('"').unwrap_or(quoted.len());
+            tokens.push(Token::String(&quoted[..end]));
+            rest = quoted.get(end + 1..).unwrap_or("");
+        } else {
+            let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+            tokens.push(Token::Word(&rest[..end]));
+            rest = &rest[end..];
+        }
+        rest = rest.trim_start();
+    }
+    tokens
+}
+
+fn string_words(string: &str) -> impl Iterator<Item = u32> {
+    let mut bytes = string.as_bytes().to_vec();
+    bytes.resize((bytes.len() / 4 + 1) * 4, 0);
+    (0..bytes.len() / 4)
+        .map(move |i| u32::from_le_bytes(bytes[4 * i..4 * i + 4].try_into().unwrap()))
+}
+
+fn assemble(source: &str) -> Result<Vec<u32>, String> {
+    let mut ids = HashMap::<String, u32>::new();
+    let mut id_of = |name: &str| {
+        let next = ids.len() as u32 + 1;
+        *ids.entry(name.to_owned()).or_insert(next)
+    };
+    // defined ids and the instruction defining types, for float constants
+    let mut defined = HashMap::<&str, &str>::new();
+    let mut used = vec![];
+    let mut in_function = false;
+    let mut in_block = false;
+    let mut body = vec![];
+
+    for (index, line) in source.lines().enumerate() {
+        let line_number = index + 1;
+        let code = line.split(';').next().unwrap_or("");
+        let mut tokens = tokenize(code);
+        if tokens.is_empty() {
+            continue;
+        }
+
+        let result = match tokens.as_slice() {
+            [Token::Word(result), Token::Word("="), ..] => {
+                let result = *result;
+                tokens.drain(..2);
+                Some(result)
+            }
+            _ => None,
+        };
+        let op = match tokens.first() {
+            Some(Token::Word(op)) => *op,
+            _ => return Err(format!("{line_number}: expected an instruction")),
+        };
+        let (code, kind) =
+            opcode(op).ok_or_else(|| format!("{line_number}: unknown instruction {op}"))?;
+        let mut operands = &tokens[1..];
+
+        let mut words = vec![];
+        let mut result_type = None;
+        match (kind, result) {
+            (Results::TypeAndId, Some(result)) => {
+                let Some(Token::Word(ty)) = operands.first() else {
+                    return Err(format!("{line_number}: {op} needs a result type"));
+                };
+                used.push((line_number, *ty));
+                result_type = Some(*ty);
+                words.push(id_of(ty));
+                words.push(id_of(result));
+                operands = &operands[1..];
+            }
+            (Results::Id, Some(result)) => words.push(id_of(result)),
+            (Results::Nothing, None) => {}
+            (Results::Nothing, Some(_)) => {
+                return Err(format!("{line_number}: {op} has no result"))
+            }
+            (_, None) => return Err(format!("{line_number}: {op} needs a result")),
+        }
+        if let Some(result) = result {
+            if defined.insert(result, op).is_some() {
+                return Err(format!("{line_number}: {result} is defined twice"));
+            }
+        }
+        let float_constant = op == "OpConstant"
+            && result_type.and_then(|ty| defined.get(ty)) == Some(&"OpTypeFloat");
+
+        for (i, operand) in operands.iter().enumerate() {
+            match *operand {
+                Token::String(string) => words.extend(string_words(string)),
+                Token::Word(id) if id.starts_with('%') => {
+                    used.push((line_number, id));
+                    words.push(id_of(id));
+                }
+                Token::Word(number) if float_constant => {
+                    let value: f32 = number
+                        .parse()
+                        .map_err(|_| format!("{line_number}: invalid float {number}"))?;
+                    words.push(value.to_bits());
+                }
+                Token::Word(number) if number.starts_with(|c: char| c.is_ascii_digit()) => {
+                    let value: u32 = number
+                        .parse()
+                        .map_err(|_| format!("{line_number}: invalid integer {number}"))?;
+                    words.push(value);
+                }
+                Token::Word(name) => {
+                    let value = if op == "OpExtInst" && i == 1 {
+                        glsl_std_450(name)
+                    } else {
+                        enumerant(name)
+                    };
+                    words.push(
+                        value.ok_or_else(|| format!("{line_number}: unknown operand {name}"))?,
+                    );
+                }
+            }
+        }
+
+        // every block of a function ends with a single terminator
+        match op {
+            "OpFunction" => in_function = true,
+            "OpFunctionEnd" if in_block => {
+                return Err(format!("{line_number}: function ends inside a block"))
+            }
+            "OpFunctionEnd" => in_function = false,
+            "OpLabel" if in_block => {
+                return Err(format!(
+                    "{line_number}: the previous block is not terminated"
+                ))
+            }
+            "OpLabel" => in_block = true,
+            _ if in_function && !in_block => {
+                return Err(format!("{line_number}: {op} is outside of a block"))
+            }
+            _ if is_terminator(op) => in_block = false,
+            _ => {}
+        }
+
+        body.push((words.len() as u32 + 1) << 16 | code);
+        body.extend(words);
+    }
+
+    if let Some((line_number, id)) = used.iter().find(|(_, id)| !defined.contains_key(id)) {
+        return Err(format!("{line_number}: {id} is never defined"));
+    }
+
+    let bound = ids.len() as u32 + 1;
+    let mut module = vec![0x0723_0203, SPIRV_VERSION, 0, bound, 0];
+    module.extend(body);
+    Ok(module)
+}
+
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    let out_dir = env::var("OUT_DIR").unwrap();
+
+    for name in SHADERS {
+        let path = format!("src/shaders/{name}.spvasm");
+        println!("cargo:rerun-if-changed={path}");
+
+        let source = fs::read_to_string(&path).unwrap_or_else(|e| panic!("{path}: {e}"));
+        let module = assemble(&source).unwrap_or_else(|e| panic!("{path}:{e}"));
+        let bytes = module
+            .iter()
+            .flat_map(|word| word.to_le_bytes())
+            .collect::<Vec<_>>();
+        fs::write(Path::new(&out_dir).join(format!("{name}.spv")), bytes).unwrap();
+    }
+}
@@ -1,18 +1,294 @@
-pub fn load_gltf() -> ! {
-    let (gltf, buffers, _images) =
-        gltf::import("./asset/Sponza/NewSponza_Main_glTF_002.gltf").unwrap();
-    for mesh in gltf.meshes() {
-        println!("Mesh #{}", mesh.index());
-        for primitive in mesh.primitives() {
-            println!("- Primitive #{}", primitive.index());
-            let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
-            if let Some(iter) = reader.read_positions() {
-                for vertex_position in iter {
-                    println!("{:?}", vertex_position);
-                }
+use crate::{
+    backend_vulkan::mesh::{MeshData, MeshStats, MorphTarget, SkinVertex, Vertex},
+    scene::{
+        AnimationChannel, AnimationClip, Interpolation, Keyframes, Material, NodeHandle, Transform,
+    },
+};
+use anyhow::Result;
+use glam::{Mat4, Quat, Vec3, Vec4};
+use gltf::animation::util::ReadOutputs;
+use std::path::Path;
+
+/// A glTF primitive, meshes with several materials are split into one primitive each
+#[derive(Clone, Debug)]
+pub struct GltfPrimitive {
+    pub data: MeshData,
+    /// Index into [`GltfAsset::materials`]
+    pub material: Option<usize>,
+    /// Measured before the import optimized the primitive
+    pub imported_stats: MeshStats,
+    /// Measured after the import optimized the primitive
+    pub optimized_stats: MeshStats,
+}
+
+#[derive(Clone, Debug)]
+pub struct GltfMesh {
+    pub name: String,
+    pub primitives: Vec<GltfPrimitive>,
+}
+
+#[derive(Clone, Debug)]
+pub struct GltfNode {
+    pub name: String,
+    pub transform: Transform,
+    /// Index into [`GltfAsset::meshes`]
+    pub mesh: Option<usize>,
+    /// Index into [`GltfAsset::skins`], deforms the node's mesh
+    pub skin: Option<usize>,
+    /// Weights of the mesh's morph targets, the mesh's defaults unless the node overrides them
+    pub morph_weights: Vec<f32>,
+    /// Indices into [`GltfAsset::nodes`]
+    pub children: Vec<usize>,
+}
+
+#[derive(Clone, Debug)]
+pub struct GltfSkin {
+    pub name: String,
+    /// Indices into [`GltfAsset::nodes`]
+    pub joints: Vec<usize>,
+    /// One per joint, from mesh space to the joint's space in the bind pose
+    pub inverse_bind_matrices: Vec<Mat4>,
+}
+
+/// CPU-side contents of a glTF file, ready to be added to a renderer
+#[derive(Clone, Debug, Default)]
+pub struct GltfAsset {
+    pub meshes: Vec<GltfMesh>,
+    pub materials: Vec<Material>,
+    pub nodes: Vec<GltfNode>,
+    pub skins: Vec<GltfSkin>,
+    /// Root nodes of the default scene
+    pub roots: Vec<usize>,
+    /// Channels target indices into [`GltfAsset::nodes`]
+    pub animations: Vec<AnimationClip<usize>>,
+}
+
+/// What [`crate::PoogieRenderer::add_gltf`] added to the scene
+#[derive(Clone, Debug)]
+pub struct GltfInstance {
+    /// Parent of all of the asset's root nodes
+    pub root: NodeHandle,
+    /// The asset's animations, targeting the new nodes
+    pub animations: Vec<AnimationClip>,
+}
+
+pub fn load_gltf(path: impl AsRef<Path>) -> Result<GltfAsset> {
+    let path = path.as_ref();
+    let (gltf, buffers, _images) = gltf::import(path)?;
+
+    let materials = gltf
+        .materials()
+        .map(|material| {
+            let pbr = material.pbr_metallic_roughness();
+            Material {
+                name: material.name().unwrap_or("unnamed").to_owned(),
+                base_color: Vec4::from(pbr.base_color_factor()),
+                metallic: pbr.metallic_factor(),
+                roughness: pbr.roughness_factor(),
+                // the factor is clamped to 1, HDR emission is scaled by the strength
+                emissive: Vec3::from(material.emissive_factor())
+                    * material.emissive_strength().unwrap_or(1.0),
             }
-        }
-    }
+        })
+        .collect();
+
+    let meshes = gltf
+        .meshes()
+        .map(|mesh| {
+            let primitives = mesh
+                .primitives()
+                .filter(|primitive| primitive.mode() == gltf::mesh::Mode::Triangles)
+                .filter_map(|primitive| {
+                    let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
+
+                    let positions = reader.read_positions()?.map(Vec3::from).collect::<Vec<_>>();
+
+                    let normals = match reader.read_normals() {
+                        Some(normals) => normals.map(Vec3::from).collect(),
+                        None => vec![Vec3::ZERO; positions.len()],
+                    };
+
+                    let colors = match reader.read_colors(0) {
+                        Some(colors) => colors.into_rgb_f32().map(Vec3::from).collect(),
+                        None => vec![Vec3::ONE; positions.len()],
+                    };
+
+                    let indices = match reader.read_indices() {
+                        Some(indices) => indices.into_u32().collect(),
+                        None => (0..positions.len() as u32).collect(),
+                    };
+
+                    // only the first set of joints and weights is used
+                    let skin = match (reader.read_joints(0), reader.read_weights(0)) {
+                        (Some(joints), Some(weights)) => joints
+                            .into_u16()
+                            .zip(weights.into_f32())
+                            .map(|(joints, weights)| SkinVertex {
+                                joints: joints.map(u32::from),
+                                weights,
+                            })
+                            .collect(),
+                        _ => vec![],
+                    };
+
+                    // tangent offsets are skipped, vertices have no tangents
+                    let morph_targets = reader
+                        .read_morph_targets()
+                        .map(|(positions, normals, _)| MorphTarget {
+                            positions: positions.map_or(vec![], |p| p.map(Vec3::from).collect()),
+                            normals: normals.map_or(vec![], |n| n.map(Vec3::from).collect()),
+                        })
+                        .collect();
+
+                    let vertices = positions
+                        .into_iter()
+                        .zip(normals)
+                        .zip(colors)
+                        .map(|((position, normal), color)| Vertex {
+                            position,
+                            normal,
+                            color,
+                        })
+                        .collect::<Vec<_>>();
+
+                    let mut data = MeshData {
+                        vertices,
+                        indices,
+                        skin,
+                        morph_targets,
+                        ..Default::default()
+                    };
+                    if reader.read_normals().is_none() {
+                        data.compute_normals();
+                    }
+
+                    let imported_stats = data.analyze();
+                    data.optimize();
+                    let optimized_stats = data.analyze();
+                    log::info!(
+                        "Optimized primitive {} of mesh {}:\n  before: {imported_stats}\n  after:  {optimized_stats}",
+                        primitive.index(),
+                        mesh.name().unwrap_or("unnamed"),
+                    );
+                    data.build_meshlets();
+                    data.generate_lods();
+
+                    Some(GltfPrimitive {
+                        data,
+                        material: primitive.material().index(),
+                        imported_stats,
+                        optimized_stats,
+                    })
+                })
+                .collect();
+
+            GltfMesh {
+                name: mesh.name().unwrap_or("unnamed").to_owned(),
+                primitives,
+            }
+        })
+        .collect();
+
+    let nodes = gltf
+        .nodes()
+        .map(|node| {
+            let (translation, rotation, scale) = node.transform().decomposed();
+            GltfNode {
+                name: node.name().unwrap_or("unnamed").to_owned(),
+                transform: Transform {
+                    translation: Vec3::from(translation),
+                    rotation: Quat::from_array(rotation),
+                    scale: Vec3::from(scale),
+                },
+                mesh: node.mesh().map(|mesh| mesh.index()),
+                skin: node.skin().map(|skin| skin.index()),
+                morph_weights: node
+                    .weights()
+                    .or_else(|| node.mesh().and_then(|mesh| mesh.weights()))
+                    .unwrap_or_default()
+                    .to_vec(),
+                children: node.children().map(|child| child.index()).collect(),
+            }
+        })
+        .collect();
+
+    let skins = gltf
+        .skins()
+        .map(|skin| {
+            let joints = skin.joints().map(|joint| joint.index()).collect::<Vec<_>>();
+            // missing inverse bind matrices are identities
+            let inverse_bind_matrices = match skin
+                .reader(|buffer| Some(&buffers[buffer.index()]))
+                .read_inverse_bind_matrices()
+            {
+                Some(matrices) => matrices.map(|m| Mat4::from_cols_array_2d(&m)).collect(),
+                None => vec![Mat4::IDENTITY; joints.len()],
+            };
+
+            GltfSkin {
+                name: skin.name().unwrap_or("unnamed").to_owned(),
+                joints,
+                inverse_bind_matrices,
+            }
+        })
+        .collect();
+
+    let roots = gltf
+        .default_scene()
+        .or_else(|| gltf.scenes().next())
+        .map(|scene| scene.nodes().map(|node| node.index()).collect())
+        .unwrap_or_default();
+
+    let animations = gltf
+        .animations()
+        .map(|animation| {
+            let channels = animation
+                .channels()
+                .filter_map(|channel| {
+                    let reader = channel.reader(|buffer| Some(&buffers[buffer.index()]));
+                    let times = reader.read_inputs()?.collect();
+                    let keyframes = match reader.read_outputs()? {
+                        ReadOutputs::Translations(values) => {
+                            Keyframes::Translation(values.map(Vec3::from).collect())
+                        }
+                        ReadOutputs::Rotations(values) => {
+                            Keyframes::Rotation(values.into_f32().map(Quat::from_array).collect())
+                        }
+                        ReadOutputs::Scales(values) => {
+                            Keyframes::Scale(values.map(Vec3::from).collect())
+                        }
+                        ReadOutputs::MorphTargetWeights(values) => {
+                            Keyframes::Weights(values.into_f32().collect())
+                        }
+                    };
+
+                    Some(AnimationChannel {
+                        target: channel.target().node().index(),
+                        interpolation: match channel.sampler().interpolation() {
+                            gltf::animation::Interpolation::Step => Interpolation::Step,
+                            gltf::animation::Interpolation::Linear => Interpolation::Linear,
+                            gltf::animation::Interpolation::CubicSpline => {
+                                Interpolation::CubicSpline
+                            }
+                        },
+                        times,
+                        keyframes,
+                    })
+                })
+                .collect();
+
+            AnimationClip::new(animation.name().unwrap_or("unnamed"), channels)
+        })
+        .collect();
+
+    log::info!("Loaded glTF {path:?}");
 
-    todo!()
+    Ok(GltfAsset {
+        meshes,
+        materials,
+        nodes,
+        skins,
+        roots,
+        animations,
+    })
 }
@@ -0,0 +1,306 @@
+use anyhow::Result;
+use std::path::Path;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum HdrError {
+    #[error("Missing Radiance header")]
+    MissingHeader,
+    #[error("Unsupported pixel format {0}")]
+    UnsupportedFormat(String),
+    #[error("Unsupported or invalid resolution {0:?}")]
+    InvalidResolution(String),
+    #[error("Pixel data ends early or is malformed")]
+    InvalidPixels,
+}
+
+/// Decoded Radiance `.hdr` image, rows go from top to bottom
+#[derive(Clone, Debug)]
+pub struct HdrImage {
+    pub width: u32,
+    pub height: u32,
+    /// Linear RGB radiance, alpha is always 1
+    pub pixels: Vec<[f32; 4]>,
+}
+
+/// Largest width or height accepted
+const MAX_SIZE: u32 = 1 << 16;
+/// Most pixels accepted, those of a 16384 by 8192 environment
+const MAX_PIXELS: usize = 1 << 27;
+/// Most pixels decoded per byte of pixel data. Adaptive RLE of a single color stays below
+/// 16, old-style runs could otherwise grow a small file into gigabytes.
+const MAX_PIXELS_PER_BYTE: usize = 64;
+
+pub fn load_hdr(path: impl AsRef<Path>) -> Result<HdrImage> {
+    let bytes = std::fs::read(path)?;
+    Ok(parse_hdr(&bytes)?)
+}
+
+/// Parses a Radiance RGBE file with flat, old-style or adaptive run length encoded scanlines
+pub fn parse_hdr(bytes: &[u8]) -> Result<HdrImage, HdrError> {
+    let mut reader = Reader { bytes, position: 0 };
+
+    let magic = reader.line().ok_or(HdrError::MissingHeader)?;
+    if magic != "#?RADIANCE" && magic != "#?RGBE" {
+        return Err(HdrError::MissingHeader);
+    }
+
+    // variables until the first empty line
+    loop {
+        let line = reader.line().ok_or(HdrError::MissingHeader)?;
+        if line.is_empty() {
+            break;
+        }
+        if let Some(format) = line.strip_prefix("FORMAT=") {
+            if format != "32-bit_rle_rgbe" {
+                return Err(HdrError::UnsupportedFormat(format.to_owned()));
+            }
+        }
+    }
+
+    // only the standard orientation is supported, e.g. "-Y 512 +X 1024"
+    let resolution = reader.line().ok_or(HdrError::MissingHeader)?;
+    let (width, height) = match resolution.split_whitespace().collect::<Vec<_>>()[..] {
+        ["-Y", height, "+X", width] => (width.parse().ok(), height.parse().ok()),
+        _ => (None, None),
+    };
+    let (Some(width), Some(height)) = (width, height) else {
+        return Err(HdrError::InvalidResolution(resolution));
+    };
+    if width == 0 || height == 0 || width > MAX_SIZE || height > MAX_SIZE {
+        return Err(HdrError::InvalidResolution(resolution));
+    }
+
+    let pixel_count = width as usize * height as usize;
+    if pixel_count > MAX_PIXELS {
+        return Err(HdrError::InvalidResolution(resolution));
+    }
+
+    // every scanline takes at least one pixel or adaptive RLE header
+    let remaining = bytes.len() - reader.position;
+    if height as usize * 4 > remaining || pixel_count > remaining * MAX_PIXELS_PER_BYTE {
+        return Err(HdrError::InvalidPixels);
+    }
+    let mut pixels = Vec::with_capacity(pixel_count);
+    let mut scanline = vec![[0u8; 4]; width as usize];
+    for _ in 0..height {
+        reader.scanline(&mut scanline)?;
+        pixels.extend(scanline.iter().map(|&rgbe| rgbe_to_float(rgbe)));
+    }
+
+    Ok(HdrImage {
+        width,
+        height,
+        pixels,
+    })
+}
+
+fn rgbe_to_float([r, g, b, e]: [u8; 4]) -> [f32; 4] {
+    if e == 0 {
+        return [0.0, 0.0, 0.0, 1.0];
+    }
+    // the mantissas are fixed point with 8 bits, sampled at the middle of their range
+    let scale = 2.0f32.powi(e as i32 - (128 + 8));
+    [
+        (r as f32 + 0.5) * scale,
+        (g as f32 + 0.5) * scale,
+        (b as f32 + 0.5) * scale,
+        1.0,
+    ]
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl Reader<'_> {
+    fn byte(&mut self) -> Result<u8, HdrError> {
+        let byte = *self
+            .bytes
+            .get(self.position)
+            .ok_or(HdrError::InvalidPixels)?;
+        self.position += 1;
+        Ok(byte)
+    }
+
+    fn rgbe(&mut self) -> Result<[u8; 4], HdrError> {
+        Ok([self.byte()?, self.byte()?, self.byte()?, self.byte()?])
+    }
+
+    fn line(&mut self) -> Option<String> {
+        let rest = self.bytes.get(self.position..)?;
+        let end = rest.iter().position(|&b| b == b'\n')?;
+        self.position += end + 1;
+        Some(String::from_utf8_lossy(&rest[..end]).trim().to_owned())
+    }
+
+    fn scanline(&mut self, scanline: &mut [[u8; 4]]) -> Result<(), HdrError> {
+        let width = scanline.len();
+        // adaptive RLE starts with 2, 2 and the width, it is only used for these widths
+        if (8..0x8000).contains(&width) {
+            let start = self.position;
+            let header = self.rgbe()?;
+            if header[0] == 2 && header[1] == 2 && header[2] < 128 {
+                if (header[2] as usize) << 8 | header[3] as usize != width {
+                    return Err(HdrError::InvalidPixels);
+                }
+                return self.adaptive_scanline(scanline);
+            }
+            self.position = start;
+        }
+        self.flat_scanline(scanline)
+    }
+
+    /// Each channel is stored separately as runs and literal spans
+    fn adaptive_scanline(&mut self, scanline: &mut [[u8; 4]]) -> Result<(), HdrError> {
+        for channel in 0..4 {
+            let mut x = 0;
+            while x < scanline.len() {
+                let count = self.byte()? as usize;
+                let (count, run) = if count > 128 {
+                    (count - 128, true)
+                } else {
+                    (count, false)
+                };
+                if count == 0 || x + count > scanline.len() {
+                    return Err(HdrError::InvalidPixels);
+                }
+
+                let value = if run { self.byte()? } else { 0 };
+                for pixel in &mut scanline[x..x + count] {
+                    pixel[channel] = if run { value } else { self.byte()? };
+                }
+                x += count;
+            }
+        }
+        Ok(())
+    }
+
+    /// Plain pixels, where a (1, 1, 1, n) pixel repeats the previous one
+    fn flat_scanline(&mut self, scanline: &mut [[u8; 4]]) -> Result<(), HdrError> {
+        let mut x = 0;
+        let mut shift = 0;
+        while x < scanline.len() {
+            let rgbe = self.rgbe()?;
+            if rgbe[..3] == [1, 1, 1] {
+                let previous = *scanline[..x].last().ok_or(HdrError::InvalidPixels)?;
+                // consecutive markers hold ever higher bytes of the count, so a nonzero
+                // one overruns the scanline long before the shift overflows
+                let count = (rgbe[3] as usize)
+                    .checked_shl(shift)
+                    .filter(|&count| count > 0 && x + count <= scanline.len())
+                    .ok_or(HdrError::InvalidPixels)?;
+                scanline[x..x + count].fill(previous);
+                x += count;
+                shift += 8;
+            } else {
+                scanline[x] = rgbe;
+                x += 1;
+                shift = 0;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(width: u32, height: u32) -> Vec<u8> {
+        format!("#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y {height} +X {width}\n").into_bytes()
+    }
+
+    fn decoded(pixels: &[[u8; 4]]) -> Vec<[f32; 4]> {
+        pixels.iter().map(|&rgbe| rgbe_to_float(rgbe)).collect()
+    }
+
+    const A: [u8; 4] = [128, 64, 32, 129];
+    const B: [u8; 4] = [255, 0, 16, 120];
+
+    #[test]
+    fn flat() {
+        let mut bytes = header(2, 2);
+        for pixel in [A, B, B, [0, 0, 0, 0]] {
+            bytes.extend(pixel);
+        }
+
+        let image = parse_hdr(&bytes).unwrap();
+        assert_eq!((image.width, image.height), (2, 2));
+        assert_eq!(image.pixels, decoded(&[A, B, B, [0, 0, 0, 0]]));
+        assert_eq!(image.pixels[3], [0.0, 0.0, 0.0, 1.0]);
+    }
+
+    #[test]
+    fn old_style_rle() {
+        // 1 + 43 + (1 << 8) pixels, the second marker holds the next byte of the count
+        let mut bytes = header(300, 1);
+        bytes.extend(A);
+        bytes.extend([1, 1, 1, 43]);
+        bytes.extend([1, 1, 1, 1]);
+
+        let image = parse_hdr(&bytes).unwrap();
+        assert_eq!(image.pixels, decoded(&[A; 300]));
+    }
+
+    #[test]
+    fn adaptive_rle() {
+        let mut bytes = header(8, 1);
+        bytes.extend([2, 2, 0, 8]);
+        // red as literals, the other channels as runs, green split in two
+        bytes.extend([8, 1, 2, 3, 4, 5, 6, 7, 8]);
+        bytes.extend([128 + 3, 10, 128 + 5, 20]);
+        bytes.extend([128 + 8, 30]);
+        bytes.extend([128 + 8, 130]);
+
+        let image = parse_hdr(&bytes).unwrap();
+        let expected: Vec<_> = (0..8u8)
+            .map(|x| [x + 1, if x < 3 { 10 } else { 20 }, 30, 130])
+            .collect();
+        assert_eq!(image.pixels, decoded(&expected));
+    }
+
+    #[test]
+    fn truncated() {
+        let mut bytes = header(2, 2);
+        for pixel in [A, B, B, A] {
+            bytes.extend(pixel);
+        }
+        bytes.pop();
+        assert!(matches!(parse_hdr(&bytes), Err(HdrError::InvalidPixels)));
+
+        let mut bytes = header(8, 1);
+        bytes.extend([2, 2, 0, 8, 128 + 8, 1, 128 + 8, 2]);
+        assert!(matches!(parse_hdr(&bytes), Err(HdrError::InvalidPixels)));
+
+        let bytes = header(1 << 10, 1 << 16);
+        assert!(matches!(parse_hdr(&bytes), Err(HdrError::InvalidPixels)));
+    }
+
+    #[test]
+    fn endless_run_markers() {
+        let mut bytes = header(4, 1);
+        bytes.extend(A);
+        for _ in 0..16 {
+            bytes.extend([1, 1, 1, 0]);
+        }
+        assert!(matches!(parse_hdr(&bytes), Err(HdrError::InvalidPixels)));
+    }
+
+    #[test]
+    fn oversized() {
+        // old-style runs would fill these 4G pixels from about 1 MB
+        let mut bytes = header(1 << 16, 1 << 16);
+        bytes.resize(bytes.len() + (1 << 20), 1);
+        assert!(matches!(
+            parse_hdr(&bytes),
+            Err(HdrError::InvalidResolution(_))
+        ));
+
+        // few enough pixels, but far more than the data could hold
+        let mut bytes = header(1 << 12, 1 << 12);
+        bytes.resize(bytes.len() + (1 << 16), 1);
+        assert!(matches!(parse_hdr(&bytes), Err(HdrError::InvalidPixels)));
+    }
+}
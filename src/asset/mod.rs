@@ -1 +1,2 @@
 pub mod asset_loader;
+pub mod hdr;
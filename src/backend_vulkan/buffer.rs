@@ -53,6 +53,91 @@ impl Buffer {
         }
     }
 
+    pub fn new_with_data<T: Copy>(
+        allocator: &mut Allocator,
+        device: &Device,
+        data: &[T],
+        usage: vk::BufferUsageFlags,
+        name: impl Into<String>,
+    ) -> Self {
+        let mut buffer = Self::new(
+            allocator,
+            device,
+            std::mem::size_of_val(data).max(1),
+            usage,
+            name,
+        );
+        buffer.write(0, data);
+        buffer
+    }
+
+    /// Copies `data` into the mapped buffer memory, starting at element `offset`
+    pub fn write<T: Copy>(&mut self, offset: usize, data: &[T]) {
+        let alloc = self.allocation.as_ref().unwrap();
+        assert!(
+            (offset + data.len()) * std::mem::size_of::<T>() <= alloc.size() as usize,
+            "Write out of buffer bounds"
+        );
+
+        // get the underlying mapped pointer and copy the data inside
+        unsafe {
+            (alloc.mapped_ptr().unwrap().as_ptr() as *mut T)
+                .add(offset)
+                .copy_from_nonoverlapping(data.as_ptr(), data.len())
+        };
+    }
+
+    /// Reads element `offset` of the mapped buffer memory, which the GPU may have written
+    pub fn read<T: Copy>(&self, offset: usize) -> T {
+        let alloc = self.allocation.as_ref().unwrap();
+        assert!(
+            (offset + 1) * std::mem::size_of::<T>() <= alloc.size() as usize,
+            "Read out of buffer bounds"
+        );
+
+        unsafe {
+            (alloc.mapped_ptr().unwrap().as_ptr() as *const T)
+                .add(offset)
+                .read_unaligned()
+        }
+    }
+
+    /// Reads the first `len` elements of the mapped buffer memory
+    pub fn read_slice<T: Copy>(&self, len: usize) -> Vec<T> {
+        let alloc = self.allocation.as_ref().unwrap();
+        assert!(
+            len * std::mem::size_of::<T>() <= alloc.size() as usize,
+            "Read out of buffer bounds"
+        );
+
+        let mut data = Vec::with_capacity(len);
+        unsafe {
+            (alloc.mapped_ptr().unwrap().as_ptr() as *const u8).copy_to_nonoverlapping(
+                data.as_mut_ptr() as *mut u8,
+                len * std::mem::size_of::<T>(),
+            );
+            data.set_len(len);
+        }
+        data
+    }
+
+    /// Copies the first `size` bytes of `source` into this buffer through mapped memory
+    pub fn copy_from(&mut self, source: &Buffer, size: usize) {
+        let (alloc, source) = (
+            self.allocation.as_ref().unwrap(),
+            source.allocation.as_ref().unwrap(),
+        );
+        assert!(
+            size <= alloc.size() as usize && size <= source.size() as usize,
+            "Copy out of buffer bounds"
+        );
+
+        unsafe {
+            (alloc.mapped_ptr().unwrap().as_ptr() as *mut u8)
+                .copy_from_nonoverlapping(source.mapped_ptr().unwrap().as_ptr() as *const u8, size)
+        };
+    }
+
     pub fn destroy(&mut self, device: &Device, allocator: &mut Allocator) {
         allocator.free(self.allocation.take().unwrap()).unwrap();
         unsafe { device.raw.destroy_buffer(self.raw, None) }
@@ -0,0 +1,128 @@
+use super::{buffer::Buffer, device::Device};
+use anyhow::Result;
+use ash::vk;
+
+#[derive(Default)]
+pub struct DescriptorSetLayoutBuilder {
+    bindings: Vec<vk::DescriptorSetLayoutBinding>,
+}
+
+impl DescriptorSetLayoutBuilder {
+    pub fn binding(
+        mut self,
+        binding: u32,
+        ty: vk::DescriptorType,
+        stages: vk::ShaderStageFlags,
+    ) -> Self {
+        self.bindings.push(
+            vk::DescriptorSetLayoutBinding::builder()
+                .binding(binding)
+                .descriptor_type(ty)
+                .descriptor_count(1)
+                .stage_flags(stages)
+                .build(),
+        );
+        self
+    }
+
+    pub fn build(self, device: &Device) -> Result<vk::DescriptorSetLayout> {
+        let create_info = vk::DescriptorSetLayoutCreateInfo::builder().bindings(&self.bindings);
+        Ok(unsafe {
+            device
+                .raw
+                .create_descriptor_set_layout(&create_info, None)?
+        })
+    }
+}
+
+pub struct DescriptorPool {
+    pub raw: vk::DescriptorPool,
+}
+
+impl DescriptorPool {
+    pub fn new(device: &Device, max_sets: u32, sizes: &[vk::DescriptorPoolSize]) -> Result<Self> {
+        let create_info = vk::DescriptorPoolCreateInfo::builder()
+            .max_sets(max_sets)
+            .pool_sizes(sizes);
+
+        Ok(DescriptorPool {
+            raw: unsafe { device.raw.create_descriptor_pool(&create_info, None)? },
+        })
+    }
+
+    pub fn allocate(
+        &self,
+        device: &Device,
+        layout: vk::DescriptorSetLayout,
+    ) -> Result<vk::DescriptorSet> {
+        let layouts = [layout];
+        let allocate_info = vk::DescriptorSetAllocateInfo::builder()
+            .descriptor_pool(self.raw)
+            .set_layouts(&layouts);
+
+        Ok(unsafe { device.raw.allocate_descriptor_sets(&allocate_info)?[0] })
+    }
+
+    /// Frees every set allocated from the pool, none of them may still be in use
+    pub fn reset(&self, device: &Device) -> Result<()> {
+        unsafe {
+            device
+                .raw
+                .reset_descriptor_pool(self.raw, vk::DescriptorPoolResetFlags::empty())?
+        };
+        Ok(())
+    }
+
+    pub fn destroy(&mut self, device: &Device) {
+        unsafe { device.raw.destroy_descriptor_pool(self.raw, None) }
+    }
+}
+
+/// Points `binding` of `set` at the whole of `buffer`
+pub fn write_buffer_descriptor(
+    device: &Device,
+    set: vk::DescriptorSet,
+    binding: u32,
+    ty: vk::DescriptorType,
+    buffer: &Buffer,
+) {
+    let buffer_info = [vk::DescriptorBufferInfo::builder()
+        .buffer(buffer.raw)
+        .offset(0)
+        .range(vk::WHOLE_SIZE)
+        .build()];
+
+    let write = vk::WriteDescriptorSet::builder()
+        .dst_set(set)
+        .dst_binding(binding)
+        .descriptor_type(ty)
+        .buffer_info(&buffer_info);
+
+    unsafe { device.raw.update_descriptor_sets(&[write.build()], &[]) };
+}
+
+/// Points `binding` of `set` at an image view, `sampler` is only used by
+/// `SAMPLER` and `COMBINED_IMAGE_SAMPLER` descriptors
+pub fn write_image_descriptor(
+    device: &Device,
+    set: vk::DescriptorSet,
+    binding: u32,
+    ty: vk::DescriptorType,
+    view: vk::ImageView,
+    layout: vk::ImageLayout,
+    sampler: vk::Sampler,
+) {
+    let image_info = [vk::DescriptorImageInfo::builder()
+        .image_view(view)
+        .image_layout(layout)
+        .sampler(sampler)
+        .build()];
+
+    let write = vk::WriteDescriptorSet::builder()
+        .dst_set(set)
+        .dst_binding(binding)
+        .descriptor_type(ty)
+        .image_info(&image_info);
+
+    unsafe { device.raw.update_descriptor_sets(&[write.build()], &[]) };
+}
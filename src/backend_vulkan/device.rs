@@ -3,9 +3,15 @@ use super::{
     physical_device::{PhysicalDevice, QueueFamily},
 };
 use anyhow::Result;
-use ash::{extensions::khr, vk};
+use ash::{
+    extensions::{ext, khr},
+    vk,
+};
 use std::{collections::HashSet, ffi::CStr, os::raw::c_char, sync::Arc};
 
+/// Number of frames the CPU may record ahead of the GPU
+pub const FRAMES_IN_FLIGHT: usize = 2;
+
 pub struct Queue {
     pub raw: vk::Queue,
     pub family: QueueFamily,
@@ -48,7 +54,32 @@ impl CommandBuffer {
         })
     }
 
-    pub fn immediate_submit() {}
+    /// Records commands with `record`, submits them to `queue` and waits until they have finished
+    pub fn immediate_submit(
+        &self,
+        device: &ash::Device,
+        queue: &Queue,
+        record: impl FnOnce(vk::CommandBuffer),
+    ) -> Result<()> {
+        let begin_info = vk::CommandBufferBeginInfo::builder()
+            .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+        let command_buffers = [self.raw];
+        let submit_info = vk::SubmitInfo::builder().command_buffers(&command_buffers);
+
+        unsafe {
+            device.reset_fences(&[self.submit_done_fence])?;
+            device.reset_command_pool(self.pool, vk::CommandPoolResetFlags::empty())?;
+
+            device.begin_command_buffer(self.raw, &begin_info)?;
+            record(self.raw);
+            device.end_command_buffer(self.raw)?;
+
+            device.queue_submit(queue.raw, &[submit_info.build()], self.submit_done_fence)?;
+            device.wait_for_fences(&[self.submit_done_fence], true, u64::MAX)?;
+        }
+
+        Ok(())
+    }
 }
 
 pub struct Device {
@@ -59,12 +90,24 @@ pub struct Device {
     pub graphics_queue: Queue,
     pub transfer_queue: Option<Queue>, // TODO: currently unused
     // TODO: create an optional queue for compute as well
-    pub main_command_buffer: CommandBuffer,
-
-    pub render_fence: vk::Fence,
+    /// One command buffer per frame in flight, each signals its own fence on submit
+    pub frame_command_buffers: Vec<CommandBuffer>,
+    /// Used for uploads and other work that is waited on right away
+    pub immediate_command_buffer: CommandBuffer,
+    /// Loader of `VK_EXT_mesh_shader`, `None` unless it and its task and mesh shader
+    /// features are supported
+    pub mesh_shader: Option<ext::MeshShader>,
+    /// Whether the features the GPU-driven path draws with are enabled: indirect draws
+    /// with counts, several draws per call and a first instance
+    pub indirect_draws: bool,
+    pub buffer_device_address: bool,
+    /// Whether storage images may use formats such as `rg32float`, which the depth
+    /// pyramid of occlusion culling is written in
+    pub storage_image_extended_formats: bool,
 }
 
 impl Device {
+    #[allow(clippy::arc_with_non_send_sync)]
     pub fn new(pdevice: &Arc<PhysicalDevice>) -> Result<Arc<Self>> {
         let supported_extensions: HashSet<String> = unsafe {
             pdevice
@@ -81,7 +124,7 @@ impl Device {
                 .collect()
         };
 
-        let device_ext_names = vec![khr::Swapchain::name().as_ptr()];
+        let mut device_ext_names = vec![khr::Swapchain::name().as_ptr()];
 
         unsafe {
             for &ext in &device_ext_names {
@@ -134,13 +177,54 @@ impl Device {
             );
         };
 
+        let supported = &pdevice.features;
+        let supported12 = &pdevice.features12;
+        let buffer_device_address = supported12.buffer_device_address == vk::TRUE;
+        // one indirect draw per object, each reading its own instance
+        let indirect_draws = supported12.draw_indirect_count == vk::TRUE
+            && supported.multi_draw_indirect == vk::TRUE
+            && supported.draw_indirect_first_instance == vk::TRUE;
+        let storage_image_extended_formats =
+            supported.shader_storage_image_extended_formats == vk::TRUE;
+
+        // the features of an extension may only be queried when it is supported
+        let mesh_shader_supported =
+            supported_extensions.contains(ext::MeshShader::name().to_string_lossy().as_ref()) && {
+                let mut mesh_features = vk::PhysicalDeviceMeshShaderFeaturesEXT::default();
+                let mut features =
+                    vk::PhysicalDeviceFeatures2::builder().push_next(&mut mesh_features);
+                unsafe {
+                    pdevice
+                        .instance
+                        .raw
+                        .get_physical_device_features2(pdevice.raw, &mut features)
+                };
+                mesh_features.task_shader == vk::TRUE && mesh_features.mesh_shader == vk::TRUE
+            };
+        if mesh_shader_supported {
+            device_ext_names.push(ext::MeshShader::name().as_ptr());
+        }
+
         let mut features13 = vk::PhysicalDeviceVulkan13Features::builder().dynamic_rendering(true);
         let mut features12 = vk::PhysicalDeviceVulkan12Features::builder()
-            .buffer_device_address(true)
+            .buffer_device_address(buffer_device_address)
+            .draw_indirect_count(indirect_draws)
             .build();
+        let core_features = vk::PhysicalDeviceFeatures::builder()
+            .multi_draw_indirect(indirect_draws)
+            .draw_indirect_first_instance(indirect_draws)
+            .shader_storage_image_extended_formats(storage_image_extended_formats)
+            .build();
+        let mut mesh_features = vk::PhysicalDeviceMeshShaderFeaturesEXT::builder()
+            .task_shader(true)
+            .mesh_shader(true);
         let mut features = vk::PhysicalDeviceFeatures2::builder()
+            .features(core_features)
             .push_next(&mut features12)
             .push_next(&mut features13);
+        if mesh_shader_supported {
+            features = features.push_next(&mut mesh_features);
+        }
 
         let device_create_info = vk::DeviceCreateInfo::builder()
             .enabled_extension_names(&device_ext_names)
@@ -166,12 +250,13 @@ impl Device {
             family,
         });
 
-        let main_command_buffer = CommandBuffer::new(&device, &graphics_queue_family, 1)?;
+        let frame_command_buffers = (0..FRAMES_IN_FLIGHT)
+            .map(|_| CommandBuffer::new(&device, &graphics_queue_family, 1))
+            .collect::<Result<Vec<_>>>()?;
+        let immediate_command_buffer = CommandBuffer::new(&device, &graphics_queue_family, 1)?;
 
-        let fence_create_info =
-            vk::FenceCreateInfo::builder().flags(vk::FenceCreateFlags::SIGNALED);
-
-        let render_fence = unsafe { device.create_fence(&fence_create_info, None)? };
+        let mesh_shader =
+            mesh_shader_supported.then(|| ext::MeshShader::new(&pdevice.instance.raw, &device));
 
         Ok(Arc::new(Device {
             raw: device,
@@ -179,8 +264,18 @@ impl Device {
             instance: pdevice.instance.clone(),
             graphics_queue,
             transfer_queue,
-            main_command_buffer,
-            render_fence,
+            frame_command_buffers,
+            immediate_command_buffer,
+            mesh_shader,
+            indirect_draws,
+            buffer_device_address,
+            storage_image_extended_formats,
         }))
     }
+
+    /// Runs the commands recorded by `record` on the graphics queue and waits for them
+    pub fn immediate_submit(&self, record: impl FnOnce(vk::CommandBuffer)) -> Result<()> {
+        self.immediate_command_buffer
+            .immediate_submit(&self.raw, &self.graphics_queue, record)
+    }
 }
@@ -0,0 +1,210 @@
+use ash::vk;
+use gpu_allocator::{
+    vulkan::{Allocation, AllocationCreateDesc, Allocator},
+    MemoryLocation,
+};
+
+use super::device::Device;
+
+#[derive(Clone, Copy, Debug)]
+pub struct ImageDesc {
+    pub format: vk::Format,
+    pub extent: vk::Extent3D,
+    pub usage: vk::ImageUsageFlags,
+    pub aspect: vk::ImageAspectFlags,
+    pub mip_levels: u32,
+    pub array_layers: u32,
+    pub flags: vk::ImageCreateFlags,
+}
+
+impl ImageDesc {
+    pub fn new_2d(format: vk::Format, extent: vk::Extent2D, usage: vk::ImageUsageFlags) -> Self {
+        ImageDesc {
+            format,
+            extent: vk::Extent3D {
+                width: extent.width,
+                height: extent.height,
+                depth: 1,
+            },
+            usage,
+            aspect: aspect_mask_from_format(format),
+            mip_levels: 1,
+            array_layers: 1,
+            flags: vk::ImageCreateFlags::empty(),
+        }
+    }
+
+    /// Six square layers, viewed as a cube by default
+    pub fn new_cube(format: vk::Format, size: u32, usage: vk::ImageUsageFlags) -> Self {
+        ImageDesc {
+            array_layers: 6,
+            flags: vk::ImageCreateFlags::CUBE_COMPATIBLE,
+            ..Self::new_2d(
+                format,
+                vk::Extent2D {
+                    width: size,
+                    height: size,
+                },
+                usage,
+            )
+        }
+    }
+
+    pub fn mip_levels(mut self, mip_levels: u32) -> Self {
+        self.mip_levels = mip_levels;
+        self
+    }
+
+    pub fn array_layers(mut self, array_layers: u32) -> Self {
+        self.array_layers = array_layers;
+        self
+    }
+
+    pub fn extent_2d(&self) -> vk::Extent2D {
+        vk::Extent2D {
+            width: self.extent.width,
+            height: self.extent.height,
+        }
+    }
+
+    pub fn subresource_range(&self) -> vk::ImageSubresourceRange {
+        vk::ImageSubresourceRange::builder()
+            .aspect_mask(self.aspect)
+            .base_mip_level(0)
+            .level_count(self.mip_levels)
+            .base_array_layer(0)
+            .layer_count(self.array_layers)
+            .build()
+    }
+}
+
+pub fn aspect_mask_from_format(format: vk::Format) -> vk::ImageAspectFlags {
+    match format {
+        vk::Format::D16_UNORM | vk::Format::D32_SFLOAT | vk::Format::X8_D24_UNORM_PACK32 => {
+            vk::ImageAspectFlags::DEPTH
+        }
+        vk::Format::D16_UNORM_S8_UINT
+        | vk::Format::D24_UNORM_S8_UINT
+        | vk::Format::D32_SFLOAT_S8_UINT => {
+            vk::ImageAspectFlags::DEPTH | vk::ImageAspectFlags::STENCIL
+        }
+        _ => vk::ImageAspectFlags::COLOR,
+    }
+}
+
+#[derive(Debug)]
+pub struct Image {
+    pub raw: vk::Image,
+    pub view: vk::ImageView,
+    pub desc: ImageDesc,
+    pub allocation: Option<Allocation>,
+}
+
+impl Image {
+    pub fn new(
+        allocator: &mut Allocator,
+        device: &Device,
+        desc: ImageDesc,
+        name: impl Into<String>,
+    ) -> Self {
+        let name = name.into();
+
+        let vk_info = vk::ImageCreateInfo::builder()
+            .flags(desc.flags)
+            .image_type(vk::ImageType::TYPE_2D)
+            .format(desc.format)
+            .extent(desc.extent)
+            .mip_levels(desc.mip_levels)
+            .array_layers(desc.array_layers)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .tiling(vk::ImageTiling::OPTIMAL)
+            .usage(desc.usage)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE)
+            .initial_layout(vk::ImageLayout::UNDEFINED);
+
+        let raw = unsafe { device.raw.create_image(&vk_info, None) }.unwrap();
+        let requirements = unsafe { device.raw.get_image_memory_requirements(raw) };
+
+        let allocation = allocator
+            .allocate(&AllocationCreateDesc {
+                name: &name,
+                requirements,
+                location: MemoryLocation::GpuOnly,
+                linear: false,
+            })
+            .unwrap();
+
+        unsafe {
+            device
+                .raw
+                .bind_image_memory(raw, allocation.memory(), allocation.offset())
+                .unwrap()
+        };
+
+        let view_type = if desc.flags.contains(vk::ImageCreateFlags::CUBE_COMPATIBLE) {
+            vk::ImageViewType::CUBE
+        } else if desc.array_layers > 1 {
+            vk::ImageViewType::TYPE_2D_ARRAY
+        } else {
+            vk::ImageViewType::TYPE_2D
+        };
+
+        let view_info = vk::ImageViewCreateInfo::builder()
+            .image(raw)
+            .view_type(view_type)
+            .format(desc.format)
+            .subresource_range(desc.subresource_range());
+
+        let view = unsafe { device.raw.create_image_view(&view_info, None) }.unwrap();
+
+        Image {
+            raw,
+            view,
+            desc,
+            allocation: Some(allocation),
+        }
+    }
+
+    /// Creates a 2D view of a single array layer, e.g. to render into it
+    pub fn create_layer_view(&self, device: &Device, layer: u32) -> vk::ImageView {
+        let view_info = vk::ImageViewCreateInfo::builder()
+            .image(self.raw)
+            .view_type(vk::ImageViewType::TYPE_2D)
+            .format(self.desc.format)
+            .subresource_range(vk::ImageSubresourceRange {
+                base_array_layer: layer,
+                layer_count: 1,
+                ..self.desc.subresource_range()
+            });
+
+        unsafe { device.raw.create_image_view(&view_info, None) }.unwrap()
+    }
+
+    /// Creates a 2D (array) view of a single mip level, e.g. to write it as a storage image
+    pub fn create_mip_view(&self, device: &Device, mip: u32) -> vk::ImageView {
+        let view_type = if self.desc.array_layers > 1 {
+            vk::ImageViewType::TYPE_2D_ARRAY
+        } else {
+            vk::ImageViewType::TYPE_2D
+        };
+        let view_info = vk::ImageViewCreateInfo::builder()
+            .image(self.raw)
+            .view_type(view_type)
+            .format(self.desc.format)
+            .subresource_range(vk::ImageSubresourceRange {
+                base_mip_level: mip,
+                level_count: 1,
+                ..self.desc.subresource_range()
+            });
+
+        unsafe { device.raw.create_image_view(&view_info, None) }.unwrap()
+    }
+
+    pub fn destroy(&mut self, device: &Device, allocator: &mut Allocator) {
+        allocator.free(self.allocation.take().unwrap()).unwrap();
+        unsafe {
+            device.raw.destroy_image_view(self.view, None);
+            device.raw.destroy_image(self.raw, None);
+        }
+    }
+}
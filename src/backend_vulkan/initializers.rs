@@ -1,17 +1,24 @@
 use super::shader::{ShaderSource, ShaderStage};
 use ash::vk;
 
+#[inline(always)]
+pub fn shader_stage_flags(stage: ShaderStage) -> vk::ShaderStageFlags {
+    match stage {
+        ShaderStage::Vertex => vk::ShaderStageFlags::VERTEX,
+        ShaderStage::Fragment => vk::ShaderStageFlags::FRAGMENT,
+        ShaderStage::Compute => vk::ShaderStageFlags::COMPUTE,
+        ShaderStage::Task => vk::ShaderStageFlags::TASK_EXT,
+        ShaderStage::Mesh => vk::ShaderStageFlags::MESH_EXT,
+    }
+}
+
 #[inline(always)]
 pub fn pipeline_shader_stage_create_info(
     shader_module: vk::ShaderModule,
     shader_source: &ShaderSource,
-) -> vk::PipelineShaderStageCreateInfoBuilder {
+) -> vk::PipelineShaderStageCreateInfoBuilder<'_> {
     vk::PipelineShaderStageCreateInfo::builder()
-        .stage(match shader_source.stage {
-            ShaderStage::Vertex => vk::ShaderStageFlags::VERTEX,
-            ShaderStage::Fragment => vk::ShaderStageFlags::FRAGMENT,
-            ShaderStage::Compute => vk::ShaderStageFlags::COMPUTE,
-        })
+        .stage(shader_stage_flags(shader_source.stage))
         .module(shader_module)
 }
 
@@ -62,3 +69,23 @@ pub fn pipeline_color_blend_attachment_state<'a>(
         .color_write_mask(vk::ColorComponentFlags::RGBA)
         .blend_enable(false)
 }
+
+#[inline(always)]
+pub fn pipeline_depth_stencil_state_create_info<'a>(
+    depth_test: bool,
+    depth_write: bool,
+    compare_op: vk::CompareOp,
+) -> vk::PipelineDepthStencilStateCreateInfoBuilder<'a> {
+    vk::PipelineDepthStencilStateCreateInfo::builder()
+        .depth_test_enable(depth_test)
+        .depth_write_enable(depth_write)
+        .depth_compare_op(if depth_test {
+            compare_op
+        } else {
+            vk::CompareOp::ALWAYS
+        })
+        .depth_bounds_test_enable(false)
+        .min_depth_bounds(0.0)
+        .max_depth_bounds(1.0)
+        .stencil_test_enable(false)
+}
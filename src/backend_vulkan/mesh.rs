@@ -1,11 +1,12 @@
-use std::mem::size_of;
+use std::{fmt, mem::size_of, ops::Range};
 
 use ash::vk;
-use glam::{Mat4, Vec3, Vec4};
+use glam::Vec3;
 use gpu_allocator::vulkan::Allocator;
 use memoffset::offset_of;
 
 use super::{buffer::Buffer, device::Device};
+use crate::culling::{Aabb, BoundingSphere};
 
 #[derive(Debug)]
 pub struct VertexInputDescription {
@@ -18,7 +19,8 @@ pub trait HasVertexInputDescription {
     fn describe() -> VertexInputDescription;
 }
 
-#[derive(Debug)]
+#[derive(Clone, Copy, Debug, Default)]
+#[repr(C)]
 pub struct Vertex {
     pub position: Vec3,
     pub normal: Vec3,
@@ -41,12 +43,12 @@ impl HasVertexInputDescription for Vertex {
             .offset(offset_of!(Vertex, position) as u32)
             .build();
 
-        // let normal_attr = vk::VertexInputAttributeDescription::builder()
-        //     .binding(0)
-        //     .location(1)
-        //     .format(vk::Format::R32G32B32_SFLOAT)
-        //     .offset(offset_of!(Vertex, normal) as u32)
-        //     .build();
+        let normal_attr = vk::VertexInputAttributeDescription::builder()
+            .binding(0)
+            .location(1)
+            .format(vk::Format::R32G32B32_SFLOAT)
+            .offset(offset_of!(Vertex, normal) as u32)
+            .build();
 
         let color_attr = vk::VertexInputAttributeDescription::builder()
             .binding(0)
@@ -57,39 +59,154 @@ impl HasVertexInputDescription for Vertex {
 
         VertexInputDescription {
             bindings: vec![main_binding],
-            attributes: vec![position_attr, color_attr],
-            // attributes: vec![position_attr, normal_attr, color_attr],
+            attributes: vec![position_attr, normal_attr, color_attr],
             flags: vk::PipelineVertexInputStateCreateFlags::empty(),
         }
     }
 }
 
-#[derive(Debug, Default, Clone)]
-pub struct MeshPushConstants {
-    pub data: Vec4,
-    pub render_matrix: Mat4,
+/// Joints influencing a vertex and their weights, which add up to 1.
+/// Matches `SkinVertex` in `skinning.wgsl`
+#[derive(Clone, Copy, Debug, Default)]
+#[repr(C)]
+pub struct SkinVertex {
+    /// Indices into the joints of the skin the mesh is drawn with
+    pub joints: [u32; 4],
+    pub weights: [f32; 4],
 }
 
-#[derive(Debug)]
-pub struct Mesh {
+/// Offsets added to every vertex of a mesh, scaled by the target's weight
+#[derive(Clone, Debug, Default)]
+pub struct MorphTarget {
+    /// One per vertex
+    pub positions: Vec<Vec3>,
+    /// One per vertex, or empty when the target leaves normals unchanged
+    pub normals: Vec<Vec3>,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+#[repr(C)]
+struct MorphDelta {
+    position: Vec3,
+    normal: Vec3,
+}
+
+/// Largest error of each level generated by [`MeshData::generate_lods`],
+/// relative to the largest extent of the mesh
+const LOD_ERRORS: [f32; 4] = [0.002, 0.005, 0.01, 0.02];
+
+/// Levels keeping more of the previous level's indices than this are skipped
+const LOD_MAX_RATIO: f32 = 0.75;
+
+/// Size of the FIFO vertex cache that [`MeshData::analyze`] simulates
+const VERTEX_CACHE_SIZE: u32 = 16;
+
+/// How much [`MeshData::optimize`] lets the vertex cache efficiency degrade to reduce overdraw
+const OVERDRAW_THRESHOLD: f32 = 1.05;
+
+/// How efficiently the GPU processes the vertices and triangles of a mesh
+#[derive(Clone, Copy, Debug, Default)]
+pub struct MeshStats {
+    /// Average vertices shaded per triangle, from 3 down to about 0.5
+    pub acmr: f32,
+    /// Average times each vertex is shaded, 1 at best
+    pub atvr: f32,
+    /// Pixels shaded per pixel covered seen from around the mesh, 1 at best
+    pub overdraw: f32,
+    /// Bytes of vertices fetched per byte of vertices, 1 at best
+    pub overfetch: f32,
+}
+
+impl fmt::Display for MeshStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "ACMR {:.3}, ATVR {:.3}, overdraw {:.3}, overfetch {:.3}",
+            self.acmr, self.atvr, self.overdraw, self.overfetch
+        )
+    }
+}
+
+/// Most vertices in a [`Meshlet`]
+pub const MESHLET_MAX_VERTICES: usize = 64;
+/// Most triangles in a [`Meshlet`], the triangles of a meshlet fit in two per invocation
+/// of the 64 wide culling workgroups
+pub const MESHLET_MAX_TRIANGLES: usize = 124;
+
+/// A small cluster of a mesh's triangles, culled as a whole before its triangles are.
+/// Matches `Meshlet` in `meshlets.wgsl`
+#[derive(Clone, Copy, Debug, Default)]
+#[repr(C)]
+pub struct Meshlet {
+    /// Bounding sphere in mesh space
+    pub center: Vec3,
+    pub radius: f32,
+    /// Every triangle faces away from a viewer at `eye` when
+    /// `dot(center - eye, cone_axis) >= cone_cutoff * distance(center, eye) + radius`
+    pub cone_axis: Vec3,
+    pub cone_cutoff: f32,
+    /// Into [`MeshData::meshlet_vertices`]
+    pub first_vertex: u32,
+    pub vertex_count: u32,
+    /// Into [`MeshData::meshlet_triangles`]
+    pub first_triangle: u32,
+    pub triangle_count: u32,
+}
+
+/// The positions of `vertices` for meshoptimizer
+fn positions(vertices: &[Vertex]) -> meshopt::VertexDataAdapter<'_> {
+    meshopt::VertexDataAdapter::new(
+        meshopt::typed_to_bytes(vertices),
+        size_of::<Vertex>(),
+        offset_of!(Vertex, position),
+    )
+    .unwrap()
+}
+
+/// A simplified version of a mesh indexing the same vertices, drawn in its place from afar
+#[derive(Clone, Debug, Default)]
+pub struct MeshLod {
+    pub indices: Vec<u32>,
+    /// Largest distance between the simplified and the full surface, in mesh space
+    pub error: f32,
+}
+
+/// Where a level of detail lives in the index buffer of a [`Mesh`]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct LodRange {
+    pub first_index: u32,
+    pub index_count: u32,
+    /// See [`MeshLod::error`], zero for the full mesh
+    pub error: f32,
+}
+
+/// CPU-side geometry used to create or update a [`Mesh`]
+#[derive(Clone, Debug, Default)]
+pub struct MeshData {
     pub vertices: Vec<Vertex>,
-    pub vertex_buffer: Buffer,
+    pub indices: Vec<u32>,
+    /// One per vertex for skinned meshes, empty otherwise
+    pub skin: Vec<SkinVertex>,
+    /// Blended by the weights of the node drawing the mesh
+    pub morph_targets: Vec<MorphTarget>,
+    /// Increasingly coarse versions of the mesh, see [`Self::generate_lods`]
+    pub lods: Vec<MeshLod>,
+    /// Clusters of the full mesh, see [`Self::build_meshlets`]
+    pub meshlets: Vec<Meshlet>,
+    /// Indices into `vertices` of the vertices of each meshlet
+    pub meshlet_vertices: Vec<u32>,
+    /// Three 8 bit indices into the vertices of its meshlet per triangle, first in the lowest bits
+    pub meshlet_triangles: Vec<u32>,
 }
 
-impl Mesh {
-    pub fn new(allocator: &mut Allocator, device: &Device) -> Self {
+impl MeshData {
+    pub fn triangle() -> Self {
         let positions = [
             Vec3::new(0.6, -0.6, 0.0),
             Vec3::new(-0.6, -0.6, 0.0),
             Vec3::new(0.0, 0.6, 0.0),
         ];
 
-        let normals = [
-            Vec3::new(0.0, 0.0, 0.0),
-            Vec3::new(0.0, 0.0, 0.0),
-            Vec3::new(0.0, 0.0, 0.0),
-        ];
-
         let colors = [
             Vec3::new(1.0, 0.0, 0.0),
             Vec3::new(0.0, 1.0, 0.0),
@@ -98,34 +215,439 @@ impl Mesh {
 
         let vertices = positions
             .into_iter()
-            .zip(normals.into_iter())
-            .zip(colors.into_iter())
-            .map(|((position, normal), color)| Vertex {
+            .zip(colors)
+            .map(|(position, color)| Vertex {
                 position,
-                normal,
+                normal: Vec3::Z,
                 color,
             })
             .collect::<Vec<Vertex>>();
 
-        let vertex_buffer = Buffer::new(
+        MeshData {
+            vertices,
+            indices: vec![0, 1, 2],
+            ..Default::default()
+        }
+    }
+
+    /// Replaces the vertex normals with area-weighted averages of the face normals
+    pub fn compute_normals(&mut self) {
+        for vertex in &mut self.vertices {
+            vertex.normal = Vec3::ZERO;
+        }
+
+        for triangle in self.indices.chunks_exact(3) {
+            let [a, b, c] = [0, 1, 2].map(|i| self.vertices[triangle[i] as usize].position);
+            // the cross product's length is twice the triangle's area
+            let normal = (b - a).cross(c - a);
+            for &index in triangle {
+                self.vertices[index as usize].normal += normal;
+            }
+        }
+
+        for vertex in &mut self.vertices {
+            vertex.normal = vertex.normal.normalize_or_zero();
+        }
+    }
+
+    /// Replaces the levels of detail with simplifications of the mesh of increasing error,
+    /// each aiming for half the triangles of the previous one
+    pub fn generate_lods(&mut self) {
+        self.lods.clear();
+
+        let extent = Aabb::from_points(self.vertices.iter().map(|vertex| vertex.position))
+            .half_extents()
+            .max_element()
+            * 2.0;
+        let adapter = positions(&self.vertices);
+
+        let mut previous_count = self.indices.len();
+        for relative_error in LOD_ERRORS {
+            // always simplified from the full mesh so errors do not accumulate
+            let target_count = previous_count / 6 * 3;
+            let indices = meshopt::simplify(&self.indices, &adapter, target_count, relative_error);
+            if indices.is_empty() || indices.len() as f32 > previous_count as f32 * LOD_MAX_RATIO {
+                continue;
+            }
+
+            previous_count = indices.len();
+            self.lods.push(MeshLod {
+                indices: meshopt::optimize_vertex_cache(&indices, self.vertices.len()),
+                error: relative_error * extent,
+            });
+        }
+    }
+
+    /// Merges identical vertices, reorders the triangles for the vertex cache and then to
+    /// reduce overdraw, and the vertices in the order they are first drawn. Skins, morph
+    /// targets and levels of detail follow the vertices.
+    pub fn optimize(&mut self) {
+        if self.indices.is_empty() {
+            return;
+        }
+
+        // vertices are only identical if their skin and morph offsets are too
+        let mut streams = vec![meshopt::VertexStream::new(self.vertices.as_ptr())];
+        if !self.skin.is_empty() {
+            streams.push(meshopt::VertexStream::new(self.skin.as_ptr()));
+        }
+        for target in &self.morph_targets {
+            for offsets in [&target.positions, &target.normals] {
+                if !offsets.is_empty() {
+                    streams.push(meshopt::VertexStream::new(offsets.as_ptr()));
+                }
+            }
+        }
+        let (vertex_count, remap) = meshopt::generate_vertex_remap_multi::<Vertex>(
+            self.vertices.len(),
+            &streams,
+            Some(&self.indices),
+        );
+        self.remap_vertices(vertex_count, &remap);
+
+        let indices = meshopt::optimize_vertex_cache(&self.indices, vertex_count);
+        let positions = positions(&self.vertices);
+        unsafe {
+            meshopt::ffi::meshopt_optimizeOverdraw(
+                self.indices.as_mut_ptr(),
+                indices.as_ptr(),
+                indices.len(),
+                positions.pos_ptr(),
+                vertex_count,
+                size_of::<Vertex>(),
+                OVERDRAW_THRESHOLD,
+            );
+        }
+
+        // every vertex is drawn after merging, so none are dropped
+        let remap = meshopt::optimize_vertex_fetch_remap(&self.indices, vertex_count);
+        self.remap_vertices(remap.len(), &remap);
+    }
+
+    /// Moves vertex `i` to `remap[i]`, dropping the vertices remapped to `u32::MAX`
+    fn remap_vertices(&mut self, vertex_count: usize, remap: &[u32]) {
+        self.vertices = meshopt::remap_vertex_buffer(&self.vertices, vertex_count, remap);
+        if !self.skin.is_empty() {
+            self.skin = meshopt::remap_vertex_buffer(&self.skin, vertex_count, remap);
+        }
+        for target in &mut self.morph_targets {
+            for offsets in [&mut target.positions, &mut target.normals] {
+                if !offsets.is_empty() {
+                    *offsets = meshopt::remap_vertex_buffer(offsets, vertex_count, remap);
+                }
+            }
+        }
+
+        let lods = self.lods.iter_mut().map(|lod| &mut lod.indices);
+        let meshlets = std::iter::once(&mut self.meshlet_vertices);
+        for indices in std::iter::once(&mut self.indices)
+            .chain(lods)
+            .chain(meshlets)
+        {
+            *indices = meshopt::remap_index_buffer(Some(indices), vertex_count, remap);
+        }
+    }
+
+    /// Replaces the meshlets with clusters of the full mesh's triangles, in their order
+    pub fn build_meshlets(&mut self) {
+        self.meshlets.clear();
+        self.meshlet_vertices.clear();
+        self.meshlet_triangles.clear();
+        if self.indices.is_empty() {
+            return;
+        }
+
+        let positions = positions(&self.vertices);
+        for meshlet in meshopt::build_meshlets(
+            &self.indices,
+            self.vertices.len(),
+            MESHLET_MAX_VERTICES,
+            MESHLET_MAX_TRIANGLES,
+        ) {
+            let bounds = meshopt::compute_meshlet_bounds(&meshlet, &positions);
+            let (vertex_count, triangle_count) = (
+                meshlet.vertex_count as usize,
+                meshlet.triangle_count as usize,
+            );
+
+            self.meshlets.push(Meshlet {
+                center: Vec3::from(bounds.center),
+                radius: bounds.radius,
+                cone_axis: Vec3::from(bounds.cone_axis),
+                cone_cutoff: bounds.cone_cutoff,
+                first_vertex: self.meshlet_vertices.len() as u32,
+                vertex_count: vertex_count as u32,
+                first_triangle: self.meshlet_triangles.len() as u32,
+                triangle_count: triangle_count as u32,
+            });
+            self.meshlet_vertices
+                .extend_from_slice(&meshlet.vertices[..vertex_count]);
+            self.meshlet_triangles.extend(
+                meshlet.indices[..triangle_count]
+                    .iter()
+                    .map(|&[a, b, c]| a as u32 | (b as u32) << 8 | (c as u32) << 16),
+            );
+        }
+    }
+
+    /// Measures the full mesh, ignoring its levels of detail
+    pub fn analyze(&self) -> MeshStats {
+        if self.indices.is_empty() {
+            return MeshStats::default();
+        }
+
+        let vertex_count = self.vertices.len();
+        let cache =
+            meshopt::analyze_vertex_cache(&self.indices, vertex_count, VERTEX_CACHE_SIZE, 0, 0);
+        let overdraw = meshopt::analyze_overdraw(&self.indices, &positions(&self.vertices));
+        let fetch = meshopt::analyze_vertex_fetch(&self.indices, vertex_count, size_of::<Vertex>());
+
+        MeshStats {
+            acmr: cache.acmr,
+            atvr: cache.atvr,
+            overdraw: overdraw.overdraw,
+            overfetch: fetch.overfetch,
+        }
+    }
+
+    /// The indices of every level of detail one after the other, starting with the full mesh
+    pub fn lod_indices(&self) -> Vec<u32> {
+        let lods = self.lods.iter().map(|lod| &lod.indices);
+        std::iter::once(&self.indices)
+            .chain(lods)
+            .flatten()
+            .copied()
+            .collect()
+    }
+
+    /// Where each level lives in [`Self::lod_indices`]
+    pub fn lod_ranges(&self) -> Vec<LodRange> {
+        let mut first_index = 0;
+        std::iter::once((&self.indices, 0.0))
+            .chain(self.lods.iter().map(|lod| (&lod.indices, lod.error)))
+            .map(|(indices, error)| {
+                let range = LodRange {
+                    first_index,
+                    index_count: indices.len() as u32,
+                    error,
+                };
+                first_index += range.index_count;
+                range
+            })
+            .collect()
+    }
+
+    /// Sphere of radius 0.5 centered on the origin with `segments` around its equator and
+    /// `rings` from pole to pole, with smooth normals and white vertex colors
+    pub fn sphere(segments: u32, rings: u32) -> Self {
+        let vertices = (0..=rings)
+            .flat_map(|ring| {
+                let polar = ring as f32 / rings as f32 * std::f32::consts::PI;
+                (0..=segments).map(move |segment| {
+                    let azimuth = segment as f32 / segments as f32 * std::f32::consts::TAU;
+                    let normal = Vec3::new(
+                        polar.sin() * azimuth.cos(),
+                        polar.cos(),
+                        -polar.sin() * azimuth.sin(),
+                    );
+                    Vertex {
+                        position: normal * 0.5,
+                        normal,
+                        color: Vec3::ONE,
+                    }
+                })
+            })
+            .collect();
+
+        let row = segments + 1;
+        let indices = (0..rings)
+            .flat_map(|ring| {
+                (0..segments).flat_map(move |segment| {
+                    let (top, bottom) = (ring * row + segment, (ring + 1) * row + segment);
+                    [top, bottom, bottom + 1, top, bottom + 1, top + 1]
+                })
+            })
+            .collect();
+
+        MeshData {
+            vertices,
+            indices,
+            ..Default::default()
+        }
+    }
+
+    /// Unit cube centered on the origin with flat normals and white vertex colors
+    pub fn cube() -> Self {
+        let faces = [
+            Vec3::X,
+            Vec3::NEG_X,
+            Vec3::Y,
+            Vec3::NEG_Y,
+            Vec3::Z,
+            Vec3::NEG_Z,
+        ];
+
+        let mut vertices = Vec::with_capacity(24);
+        let mut indices = Vec::with_capacity(36);
+
+        for normal in faces {
+            // two axes spanning the face, the quad winds counter-clockwise seen from outside
+            let tangent = normal.any_orthonormal_vector();
+            let bitangent = normal.cross(tangent);
+
+            let base = vertices.len() as u32;
+            for (u, v) in [(-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)] {
+                vertices.push(Vertex {
+                    position: (normal + tangent * u + bitangent * v) * 0.5,
+                    normal,
+                    color: Vec3::ONE,
+                });
+            }
+            indices.extend_from_slice(&[base, base + 1, base + 2, base, base + 2, base + 3]);
+        }
+
+        MeshData {
+            vertices,
+            indices,
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Mesh {
+    pub vertex_buffer: Buffer,
+    pub index_buffer: Buffer,
+    /// The [`SkinVertex`] of every vertex, only for skinned meshes
+    pub skin_buffer: Option<Buffer>,
+    /// Position and normal offsets of every vertex, target after target.
+    /// Matches `MorphDelta` in `skinning.wgsl`
+    pub morph_buffer: Option<Buffer>,
+    pub morph_target_count: u32,
+    /// Bounds of the vertices in mesh space, ignoring skinning and morph targets
+    pub aabb: Aabb,
+    pub bounding_sphere: BoundingSphere,
+    pub vertex_count: u32,
+    /// The full mesh first, then increasingly coarse levels of detail
+    pub lods: Vec<LodRange>,
+    /// Kept on the CPU until the GPU-driven path copies the mesh into its arena
+    pub meshlets: Vec<Meshlet>,
+    pub meshlet_vertices: Vec<u32>,
+    pub meshlet_triangles: Vec<u32>,
+}
+
+impl Mesh {
+    pub fn new(
+        allocator: &mut Allocator,
+        device: &Device,
+        data: &MeshData,
+        name: impl Into<String>,
+    ) -> Self {
+        let name = name.into();
+
+        // deformed meshes are read by the skinning compute pass instead of being drawn directly
+        let vertex_usage = if data.skin.is_empty() && data.morph_targets.is_empty() {
+            vk::BufferUsageFlags::VERTEX_BUFFER
+        } else {
+            vk::BufferUsageFlags::VERTEX_BUFFER | vk::BufferUsageFlags::STORAGE_BUFFER
+        };
+        let vertex_buffer = Buffer::new_with_data(
             allocator,
             device,
-            vertices.len() * size_of::<Vertex>(),
-            vk::BufferUsageFlags::VERTEX_BUFFER,
-            "triangle",
+            &data.vertices,
+            vertex_usage,
+            format!("{name} vertices"),
         );
 
-        let alloc = vertex_buffer.allocation.as_ref().unwrap();
+        let index_buffer = Buffer::new_with_data(
+            allocator,
+            device,
+            &data.lod_indices(),
+            vk::BufferUsageFlags::INDEX_BUFFER,
+            format!("{name} indices"),
+        );
 
-        // get the underlying mapped pointer and copy vertex data inside
-        unsafe {
-            (alloc.mapped_ptr().unwrap().as_ptr() as *mut Vertex)
-                .copy_from_nonoverlapping(vertices.as_ptr(), vertices.len())
-        };
+        let skin_buffer = (!data.skin.is_empty()).then(|| {
+            Buffer::new_with_data(
+                allocator,
+                device,
+                &data.skin,
+                vk::BufferUsageFlags::STORAGE_BUFFER,
+                format!("{name} skin"),
+            )
+        });
+
+        let positions = data.vertices.iter().map(|vertex| vertex.position);
+
+        let morph_deltas = data
+            .morph_targets
+            .iter()
+            .flat_map(|target| {
+                (0..data.vertices.len()).map(|i| MorphDelta {
+                    position: target.positions.get(i).copied().unwrap_or_default(),
+                    normal: target.normals.get(i).copied().unwrap_or_default(),
+                })
+            })
+            .collect::<Vec<_>>();
+        let morph_buffer = (!morph_deltas.is_empty()).then(|| {
+            Buffer::new_with_data(
+                allocator,
+                device,
+                &morph_deltas,
+                vk::BufferUsageFlags::STORAGE_BUFFER,
+                format!("{name} morph targets"),
+            )
+        });
 
         Mesh {
-            vertices,
             vertex_buffer,
+            index_buffer,
+            skin_buffer,
+            morph_buffer,
+            morph_target_count: data.morph_targets.len() as u32,
+            aabb: Aabb::from_points(positions.clone()),
+            bounding_sphere: BoundingSphere::from_points(positions),
+            vertex_count: data.vertices.len() as u32,
+            lods: data.lod_ranges(),
+            meshlets: data.meshlets.clone(),
+            meshlet_vertices: data.meshlet_vertices.clone(),
+            meshlet_triangles: data.meshlet_triangles.clone(),
+        }
+    }
+
+    /// Binds the vertex and index buffers and draws every index once
+    pub fn draw(&self, device: &Device, cmd: vk::CommandBuffer) {
+        self.draw_instanced(device, cmd, self.vertex_buffer.raw, 0, 0, 0..1);
+    }
+
+    /// Like [`Self::draw`], but draws `instances` of level of detail `lod` and reads the
+    /// vertices from `offset` bytes into `vertex_buffer`, which must hold as many vertices
+    /// as this mesh
+    pub fn draw_instanced(
+        &self,
+        device: &Device,
+        cmd: vk::CommandBuffer,
+        vertex_buffer: vk::Buffer,
+        offset: vk::DeviceSize,
+        lod: usize,
+        instances: Range<u32>,
+    ) {
+        let lod = self.lods[lod];
+        unsafe {
+            device
+                .raw
+                .cmd_bind_vertex_buffers(cmd, 0, &[vertex_buffer], &[offset]);
+            device
+                .raw
+                .cmd_bind_index_buffer(cmd, self.index_buffer.raw, 0, vk::IndexType::UINT32);
+            device.raw.cmd_draw_indexed(
+                cmd,
+                lod.index_count,
+                instances.len() as u32,
+                lod.first_index,
+                0,
+                instances.start,
+            );
         }
     }
 }
@@ -1,10 +1,14 @@
 pub mod buffer;
+pub mod descriptor;
 pub mod device;
+pub mod image;
 pub mod initializers;
 pub mod instance;
 pub mod mesh;
 pub mod physical_device;
 pub mod pipeline;
+pub mod profiler;
+pub mod sampler;
 pub mod shader;
 pub mod surface;
 pub mod swapchain;
@@ -14,6 +14,9 @@ pub struct PhysicalDevice {
     pub instance: Arc<Instance>,
     pub properties: vk::PhysicalDeviceProperties,
     pub dyn_rendering_supported: vk::PhysicalDeviceDynamicRenderingFeatures,
+    /// Optional features are only enabled when supported
+    pub features: vk::PhysicalDeviceFeatures,
+    pub features12: vk::PhysicalDeviceVulkan12Features,
     pub(crate) queue_families: Vec<QueueFamily>,
     // pub(crate) presentation_requested: bool,
     // pub memory_properties: PhysicalDeviceMemoryProperties,
@@ -23,20 +26,26 @@ impl PhysicalDevice {
     pub fn enumerate_physical_devices(instance: &Arc<Instance>) -> Result<Vec<PhysicalDevice>> {
         let pdevices = unsafe { instance.raw.enumerate_physical_devices()? };
 
-        let mut dyn_rendering_supported = vk::PhysicalDeviceDynamicRenderingFeatures::default();
-        let mut features = vk::PhysicalDeviceFeatures2::builder()
-            .push_next(&mut dyn_rendering_supported)
-            .build();
-
         Ok(pdevices
             .into_iter()
             .map(|pdevice| {
                 let properties = unsafe { instance.raw.get_physical_device_properties(pdevice) };
+
+                let mut dyn_rendering_supported =
+                    vk::PhysicalDeviceDynamicRenderingFeatures::default();
+                let mut features12 = vk::PhysicalDeviceVulkan12Features::default();
+                let mut features = vk::PhysicalDeviceFeatures2::builder()
+                    .push_next(&mut dyn_rendering_supported)
+                    .push_next(&mut features12);
                 unsafe {
                     instance
                         .raw
                         .get_physical_device_features2(pdevice, &mut features)
                 };
+                let features = features.features;
+                // the chain only lives for the query
+                dyn_rendering_supported.p_next = std::ptr::null_mut();
+                features12.p_next = std::ptr::null_mut();
 
                 let queue_families = unsafe {
                     instance
@@ -56,6 +65,8 @@ impl PhysicalDevice {
                     instance: instance.clone(),
                     properties,
                     dyn_rendering_supported,
+                    features,
+                    features12,
                     queue_families,
                 }
             })
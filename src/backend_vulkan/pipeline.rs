@@ -1,48 +1,108 @@
-use std::{ffi::CString, mem::size_of};
+use std::ffi::CString;
 
 use super::{
     device::Device,
     initializers::{
-        self, pipeline_color_blend_attachment_state, pipeline_input_assembly_create_info,
-        pipeline_rasterization_state_create_info,
+        self, pipeline_color_blend_attachment_state, pipeline_depth_stencil_state_create_info,
+        pipeline_input_assembly_create_info, pipeline_rasterization_state_create_info,
     },
-    mesh::{HasVertexInputDescription, MeshPushConstants, Vertex},
+    mesh::VertexInputDescription,
     shader::ShaderSource,
-    swapchain::Swapchain,
 };
 use anyhow::Result;
 use ash::vk;
 
-pub struct GraphicsPipeline {
-    pub pipeline: vk::Pipeline,
-    pub layout: vk::PipelineLayout,
+pub struct GraphicsPipelineBuilder {
+    color_formats: Vec<vk::Format>,
+    depth_format: vk::Format,
+    descriptor_set_layouts: Vec<vk::DescriptorSetLayout>,
+    push_constant_size: u32,
+    vertex_input: Option<VertexInputDescription>,
+    cull_mode: vk::CullModeFlags,
+    depth_bias: bool,
+    depth_test: bool,
+    depth_write: bool,
+    depth_compare_op: vk::CompareOp,
 }
 
-impl GraphicsPipeline {
-    pub fn create_pipeline(
-        device: &Device,
-        swapchain: &Swapchain,
-        shader_sources: &[ShaderSource],
-    ) -> Result<Self> {
-        let viewports = [vk::Viewport::builder()
-            .x(0.0)
-            .y(0.0)
-            .width(swapchain.desc.extent.width as f32)
-            .height(swapchain.desc.extent.height as f32)
-            .min_depth(0.0)
-            .max_depth(1.0)
-            .build()];
+impl Default for GraphicsPipelineBuilder {
+    fn default() -> Self {
+        GraphicsPipelineBuilder {
+            color_formats: vec![],
+            depth_format: vk::Format::UNDEFINED,
+            descriptor_set_layouts: vec![],
+            push_constant_size: 0,
+            vertex_input: None,
+            cull_mode: vk::CullModeFlags::NONE,
+            depth_bias: false,
+            depth_test: false,
+            depth_write: false,
+            depth_compare_op: vk::CompareOp::LESS_OR_EQUAL,
+        }
+    }
+}
 
-        let scissors = [vk::Rect2D::builder()
-            .offset(vk::Offset2D::builder().x(0).y(0).build())
-            .extent(swapchain.desc.extent)
-            .build()];
+impl GraphicsPipelineBuilder {
+    pub fn color_formats(mut self, color_formats: &[vk::Format]) -> Self {
+        self.color_formats = color_formats.to_vec();
+        self
+    }
+
+    /// Enables depth testing and writing against an attachment of `depth_format`
+    pub fn depth_format(mut self, depth_format: vk::Format) -> Self {
+        self.depth_format = depth_format;
+        self.depth_test = true;
+        self.depth_write = true;
+        self
+    }
+
+    pub fn depth_write(mut self, depth_write: bool) -> Self {
+        self.depth_write = depth_write;
+        self
+    }
+
+    pub fn depth_compare_op(mut self, depth_compare_op: vk::CompareOp) -> Self {
+        self.depth_compare_op = depth_compare_op;
+        self
+    }
+
+    pub fn descriptor_set_layouts(mut self, layouts: &[vk::DescriptorSetLayout]) -> Self {
+        self.descriptor_set_layouts = layouts.to_vec();
+        self
+    }
+
+    /// Size in bytes of the push constant block, visible to all graphics stages and the
+    /// task and mesh shaders of the pipeline
+    pub fn push_constant_size(mut self, size: usize) -> Self {
+        self.push_constant_size = size as u32;
+        self
+    }
+
+    pub fn vertex_input(mut self, vertex_input: VertexInputDescription) -> Self {
+        self.vertex_input = Some(vertex_input);
+        self
+    }
 
+    pub fn cull_mode(mut self, cull_mode: vk::CullModeFlags) -> Self {
+        self.cull_mode = cull_mode;
+        self
+    }
+
+    /// Enables depth biasing, the bias itself is dynamic state
+    pub fn depth_bias(mut self, depth_bias: bool) -> Self {
+        self.depth_bias = depth_bias;
+        self
+    }
+
+    pub fn build(
+        self,
+        device: &Device,
+        shader_sources: &[ShaderSource],
+    ) -> Result<GraphicsPipeline> {
+        // viewport and scissor are dynamic, only their count matters here
         let viewport_state = vk::PipelineViewportStateCreateInfo::builder()
             .viewport_count(1)
-            .viewports(&viewports)
-            .scissor_count(1)
-            .scissors(&scissors);
+            .scissor_count(1);
 
         let mut entry_points = vec![];
 
@@ -65,7 +125,11 @@ impl GraphicsPipeline {
             })
             .collect::<Vec<vk::PipelineShaderStageCreateInfo>>();
 
-        let vertex_desc = Vertex::describe();
+        let vertex_desc = self.vertex_input.unwrap_or(VertexInputDescription {
+            bindings: vec![],
+            attributes: vec![],
+            flags: vk::PipelineVertexInputStateCreateFlags::empty(),
+        });
 
         let vertex_input_state = vk::PipelineVertexInputStateCreateInfo::builder()
             .vertex_attribute_descriptions(&vertex_desc.attributes)
@@ -74,31 +138,52 @@ impl GraphicsPipeline {
 
         let input_assembly_state =
             pipeline_input_assembly_create_info(vk::PrimitiveTopology::TRIANGLE_LIST);
-        let rasterizer = pipeline_rasterization_state_create_info(
-            vk::PolygonMode::FILL,
-            vk::CullModeFlags::NONE,
-        );
+        let rasterizer =
+            pipeline_rasterization_state_create_info(vk::PolygonMode::FILL, self.cull_mode)
+                .depth_bias_enable(self.depth_bias);
         let multisampling = initializers::pipeline_multisampling_state_create_info();
+        let depth_stencil_state = pipeline_depth_stencil_state_create_info(
+            self.depth_test,
+            self.depth_write,
+            self.depth_compare_op,
+        );
 
-        let color_blend_attachments = [pipeline_color_blend_attachment_state().build()];
+        let color_blend_attachments = self
+            .color_formats
+            .iter()
+            .map(|_| pipeline_color_blend_attachment_state().build())
+            .collect::<Vec<_>>();
 
         let color_blend_state = vk::PipelineColorBlendStateCreateInfo::builder()
             .logic_op_enable(false)
             .logic_op(vk::LogicOp::COPY)
             .attachments(&color_blend_attachments);
 
-        let dynamic_state = vk::PipelineDynamicStateCreateInfo::builder()
-            .dynamic_states(&[vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR]);
+        let mut dynamic_states = vec![vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR];
+        if self.depth_bias {
+            dynamic_states.push(vk::DynamicState::DEPTH_BIAS);
+        }
 
-        let push_constant = vk::PushConstantRange::builder()
+        let dynamic_state =
+            vk::PipelineDynamicStateCreateInfo::builder().dynamic_states(&dynamic_states);
+
+        // `ALL_GRAPHICS` leaves out the task and mesh stages
+        let push_constant_stages = shader_sources
+            .iter()
+            .fold(vk::ShaderStageFlags::ALL_GRAPHICS, |stages, source| {
+                stages | initializers::shader_stage_flags(source.stage)
+            });
+        let push_constants = [vk::PushConstantRange::builder()
             .offset(0)
-            .size(size_of::<MeshPushConstants>() as u32)
-            .stage_flags(vk::ShaderStageFlags::VERTEX)
-            .build();
+            .size(self.push_constant_size)
+            .stage_flags(push_constant_stages)
+            .build()];
 
-        let push_constants = [push_constant];
-        let layout_create_info =
-            vk::PipelineLayoutCreateInfo::builder().push_constant_ranges(&push_constants);
+        let mut layout_create_info =
+            vk::PipelineLayoutCreateInfo::builder().set_layouts(&self.descriptor_set_layouts);
+        if self.push_constant_size > 0 {
+            layout_create_info = layout_create_info.push_constant_ranges(&push_constants);
+        }
 
         let layout = unsafe {
             device
@@ -106,9 +191,9 @@ impl GraphicsPipeline {
                 .create_pipeline_layout(&layout_create_info, None)?
         };
 
-        let formats = [swapchain.desc.surface_format.format];
-        let mut rendering_info =
-            vk::PipelineRenderingCreateInfo::builder().color_attachment_formats(&formats);
+        let mut rendering_info = vk::PipelineRenderingCreateInfo::builder()
+            .color_attachment_formats(&self.color_formats)
+            .depth_attachment_format(self.depth_format);
 
         let pipeline_create_info = vk::GraphicsPipelineCreateInfo::builder()
             .stages(&stages)
@@ -117,6 +202,7 @@ impl GraphicsPipeline {
             .rasterization_state(&rasterizer)
             .multisample_state(&multisampling)
             .viewport_state(&viewport_state)
+            .depth_stencil_state(&depth_stencil_state)
             .color_blend_state(&color_blend_state)
             .dynamic_state(&dynamic_state)
             .layout(layout)
@@ -133,6 +219,152 @@ impl GraphicsPipeline {
                 .map_err(|e| e.1)?[0]
         };
 
-        Ok(GraphicsPipeline { pipeline, layout })
+        // modules are no longer needed once the pipeline is created
+        for stage in &stages {
+            unsafe { device.raw.destroy_shader_module(stage.module, None) };
+        }
+
+        Ok(GraphicsPipeline {
+            pipeline,
+            layout,
+            push_constant_stages,
+        })
+    }
+}
+
+pub struct GraphicsPipeline {
+    pub pipeline: vk::Pipeline,
+    pub layout: vk::PipelineLayout,
+    pub push_constant_stages: vk::ShaderStageFlags,
+}
+
+impl GraphicsPipeline {
+    pub fn builder() -> GraphicsPipelineBuilder {
+        GraphicsPipelineBuilder::default()
+    }
+
+    pub fn push_constants<T: Copy>(&self, device: &Device, cmd: vk::CommandBuffer, constants: &T) {
+        unsafe {
+            device.raw.cmd_push_constants(
+                cmd,
+                self.layout,
+                self.push_constant_stages,
+                0,
+                std::slice::from_raw_parts(
+                    constants as *const T as *const u8,
+                    std::mem::size_of::<T>(),
+                ),
+            )
+        };
+    }
+
+    pub fn destroy(&self, device: &Device) {
+        unsafe {
+            device.raw.destroy_pipeline(self.pipeline, None);
+            device.raw.destroy_pipeline_layout(self.layout, None);
+        }
+    }
+}
+
+pub struct ComputePipeline {
+    pub pipeline: vk::Pipeline,
+    pub layout: vk::PipelineLayout,
+}
+
+impl ComputePipeline {
+    /// `push_constant_size` is in bytes, the push constants are only visible to the compute stage
+    pub fn new(
+        device: &Device,
+        shader_source: &ShaderSource,
+        descriptor_set_layouts: &[vk::DescriptorSetLayout],
+        push_constant_size: usize,
+    ) -> Result<Self> {
+        let module = shader_source
+            .clone()
+            .create_shader()?
+            .create_module(device)?;
+        let entry_point = CString::new(shader_source.entry.clone()).expect("Invalid entrypoint");
+
+        let stage = initializers::pipeline_shader_stage_create_info(module, shader_source)
+            .name(&entry_point)
+            .build();
+
+        let push_constants = [vk::PushConstantRange::builder()
+            .offset(0)
+            .size(push_constant_size as u32)
+            .stage_flags(vk::ShaderStageFlags::COMPUTE)
+            .build()];
+
+        let mut layout_create_info =
+            vk::PipelineLayoutCreateInfo::builder().set_layouts(descriptor_set_layouts);
+        if push_constant_size > 0 {
+            layout_create_info = layout_create_info.push_constant_ranges(&push_constants);
+        }
+
+        let layout = unsafe {
+            device
+                .raw
+                .create_pipeline_layout(&layout_create_info, None)?
+        };
+
+        let pipeline_create_info = vk::ComputePipelineCreateInfo::builder()
+            .stage(stage)
+            .layout(layout);
+
+        let pipeline = unsafe {
+            device
+                .raw
+                .create_compute_pipelines(
+                    vk::PipelineCache::null(),
+                    &[pipeline_create_info.build()],
+                    None,
+                )
+                .map_err(|e| e.1)?[0]
+        };
+
+        unsafe { device.raw.destroy_shader_module(module, None) };
+
+        Ok(ComputePipeline { pipeline, layout })
+    }
+
+    pub fn push_constants<T: Copy>(&self, device: &Device, cmd: vk::CommandBuffer, constants: &T) {
+        unsafe {
+            device.raw.cmd_push_constants(
+                cmd,
+                self.layout,
+                vk::ShaderStageFlags::COMPUTE,
+                0,
+                std::slice::from_raw_parts(
+                    constants as *const T as *const u8,
+                    std::mem::size_of::<T>(),
+                ),
+            )
+        };
+    }
+
+    pub fn destroy(&self, device: &Device) {
+        unsafe {
+            device.raw.destroy_pipeline(self.pipeline, None);
+            device.raw.destroy_pipeline_layout(self.layout, None);
+        }
+    }
+}
+
+/// Makes storage writes of earlier dispatches visible to later ones
+pub fn compute_write_barrier(device: &Device, cmd: vk::CommandBuffer) {
+    let barrier = vk::MemoryBarrier::builder()
+        .src_access_mask(vk::AccessFlags::SHADER_WRITE)
+        .dst_access_mask(vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE);
+
+    unsafe {
+        device.raw.cmd_pipeline_barrier(
+            cmd,
+            vk::PipelineStageFlags::COMPUTE_SHADER,
+            vk::PipelineStageFlags::COMPUTE_SHADER,
+            vk::DependencyFlags::empty(),
+            &[barrier.build()],
+            &[],
+            &[],
+        );
     }
 }
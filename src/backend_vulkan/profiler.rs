@@ -0,0 +1,153 @@
+use super::device::{Device, FRAMES_IN_FLIGHT};
+use anyhow::Result;
+use ash::vk;
+use std::time::Duration;
+
+/// Maximum number of scopes recorded per frame, later scopes are ignored
+const MAX_SCOPES: u32 = 32;
+
+/// GPU time spent in a scope of the last finished frame
+#[derive(Clone, Copy, Debug)]
+pub struct GpuTiming {
+    pub name: &'static str,
+    pub duration: Duration,
+}
+
+struct FrameQueries {
+    pool: vk::QueryPool,
+    scopes: Vec<&'static str>,
+}
+
+/// Measures named scopes of the command buffer with timestamp queries.
+///
+/// Each frame in flight has its own query pool, which is read back once the frame's
+/// fence has been waited on, so results lag [`FRAMES_IN_FLIGHT`] frames behind.
+/// Scopes cannot be nested.
+pub struct GpuProfiler {
+    frames: Vec<FrameQueries>,
+    current: usize,
+    open_scope: bool,
+    /// Nanoseconds per timestamp tick, `None` when the graphics queue has no timestamps
+    timestamp_period: Option<f64>,
+    timings: Vec<GpuTiming>,
+}
+
+impl GpuProfiler {
+    pub fn new(device: &Device) -> Result<Self> {
+        let timestamp_period = (device.graphics_queue.family.properties.timestamp_valid_bits > 0)
+            .then_some(device.pdevice.properties.limits.timestamp_period as f64);
+
+        let create_info = vk::QueryPoolCreateInfo::builder()
+            .query_type(vk::QueryType::TIMESTAMP)
+            .query_count(2 * MAX_SCOPES);
+
+        let frames = (0..FRAMES_IN_FLIGHT)
+            .map(|_| {
+                Ok(FrameQueries {
+                    pool: unsafe { device.raw.create_query_pool(&create_info, None)? },
+                    scopes: vec![],
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(GpuProfiler {
+            frames,
+            current: 0,
+            open_scope: false,
+            timestamp_period,
+            timings: vec![],
+        })
+    }
+
+    /// Collects the timings the frame at `frame_index` recorded last time and resets its
+    /// queries, must be called after waiting on its fence and before any scope is recorded
+    pub fn begin_frame(&mut self, device: &Device, cmd: vk::CommandBuffer, frame_index: usize) {
+        let Some(period) = self.timestamp_period else {
+            return;
+        };
+
+        self.current = frame_index;
+        self.open_scope = false;
+        let frame = &mut self.frames[frame_index];
+
+        if !frame.scopes.is_empty() {
+            let mut timestamps = vec![0u64; 2 * frame.scopes.len()];
+            let result = unsafe {
+                device.raw.get_query_pool_results(
+                    frame.pool,
+                    0,
+                    timestamps.len() as u32,
+                    &mut timestamps,
+                    vk::QueryResultFlags::TYPE_64,
+                )
+            };
+
+            if result.is_ok() {
+                self.timings = frame
+                    .scopes
+                    .iter()
+                    .zip(timestamps.chunks_exact(2))
+                    .map(|(&name, ticks)| GpuTiming {
+                        name,
+                        duration: Duration::from_nanos(
+                            (ticks[1].saturating_sub(ticks[0]) as f64 * period) as u64,
+                        ),
+                    })
+                    .collect();
+            }
+            frame.scopes.clear();
+        }
+
+        unsafe {
+            device
+                .raw
+                .cmd_reset_query_pool(cmd, frame.pool, 0, 2 * MAX_SCOPES)
+        };
+    }
+
+    pub fn begin_scope(&mut self, device: &Device, cmd: vk::CommandBuffer, name: &'static str) {
+        let frame = &mut self.frames[self.current];
+        if self.timestamp_period.is_none() || frame.scopes.len() == MAX_SCOPES as usize {
+            return;
+        }
+
+        unsafe {
+            device.raw.cmd_write_timestamp(
+                cmd,
+                vk::PipelineStageFlags::TOP_OF_PIPE,
+                frame.pool,
+                2 * frame.scopes.len() as u32,
+            )
+        };
+        frame.scopes.push(name);
+        self.open_scope = true;
+    }
+
+    pub fn end_scope(&mut self, device: &Device, cmd: vk::CommandBuffer) {
+        if !self.open_scope {
+            return;
+        }
+
+        let frame = &self.frames[self.current];
+        unsafe {
+            device.raw.cmd_write_timestamp(
+                cmd,
+                vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                frame.pool,
+                2 * frame.scopes.len() as u32 - 1,
+            )
+        };
+        self.open_scope = false;
+    }
+
+    /// Timings of the most recent frame the GPU has finished, in recording order
+    pub fn timings(&self) -> &[GpuTiming] {
+        &self.timings
+    }
+
+    pub fn destroy(&mut self, device: &Device) {
+        for frame in self.frames.drain(..) {
+            unsafe { device.raw.destroy_query_pool(frame.pool, None) };
+        }
+    }
+}
@@ -0,0 +1,38 @@
+use super::device::Device;
+use anyhow::Result;
+use ash::vk;
+
+/// Creates a sampler without mipmapping that filters and addresses all axes the same way
+pub fn create_sampler(
+    device: &Device,
+    filter: vk::Filter,
+    address_mode: vk::SamplerAddressMode,
+) -> Result<vk::Sampler> {
+    let create_info = vk::SamplerCreateInfo::builder()
+        .mag_filter(filter)
+        .min_filter(filter)
+        .mipmap_mode(vk::SamplerMipmapMode::NEAREST)
+        .address_mode_u(address_mode)
+        .address_mode_v(address_mode)
+        .address_mode_w(address_mode)
+        .max_lod(vk::LOD_CLAMP_NONE);
+
+    Ok(unsafe { device.raw.create_sampler(&create_info, None)? })
+}
+
+/// Creates a linear sampler that also blends between mip levels
+pub fn create_trilinear_sampler(
+    device: &Device,
+    address_mode: vk::SamplerAddressMode,
+) -> Result<vk::Sampler> {
+    let create_info = vk::SamplerCreateInfo::builder()
+        .mag_filter(vk::Filter::LINEAR)
+        .min_filter(vk::Filter::LINEAR)
+        .mipmap_mode(vk::SamplerMipmapMode::LINEAR)
+        .address_mode_u(address_mode)
+        .address_mode_v(address_mode)
+        .address_mode_w(address_mode)
+        .max_lod(vk::LOD_CLAMP_NONE);
+
+    Ok(unsafe { device.raw.create_sampler(&create_info, None)? })
+}
@@ -1,6 +1,6 @@
 use super::device::Device;
-use anyhow::Result;
-use ash::vk;
+use anyhow::{bail, Result};
+use ash::{util::read_spv, vk};
 use naga::{
     back::spv::{self, PipelineOptions},
     front::glsl,
@@ -13,6 +13,8 @@ use std::{fs, path::PathBuf};
 pub enum ShaderLanguage {
     GLSL,
     WGSL,
+    /// Precompiled, for stages naga cannot compile
+    SPIRV,
 }
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
@@ -20,6 +22,10 @@ pub enum ShaderStage {
     Vertex,
     Fragment,
     Compute,
+    /// Task shader of `VK_EXT_mesh_shader`, only loaded from SPIR-V
+    Task,
+    /// Mesh shader of `VK_EXT_mesh_shader`, only loaded from SPIR-V
+    Mesh,
 }
 
 #[derive(Clone, Debug)]
@@ -27,13 +33,15 @@ pub struct ShaderSourceBuilder {
     pub entry: String,
 }
 
-impl ShaderSourceBuilder {
-    pub fn default() -> Self {
+impl Default for ShaderSourceBuilder {
+    fn default() -> Self {
         ShaderSourceBuilder {
             entry: String::from("main"),
         }
     }
+}
 
+impl ShaderSourceBuilder {
     pub fn entry(mut self, entry: impl Into<String>) -> Self {
         self.entry = entry.into();
         self
@@ -68,12 +76,25 @@ impl ShaderSource {
     }
 
     pub fn create_shader(self) -> Result<Shader> {
+        if self.language == ShaderLanguage::SPIRV {
+            let code = read_spv(&mut fs::File::open(&self.path)?)?;
+            let shader = Shader { code, source: self };
+            log::debug!("Loaded shader {:?}", shader.source);
+            return Ok(shader);
+        }
+
         let buf = fs::read_to_string(&self.path)?;
 
         let naga_stage = match self.stage {
             ShaderStage::Vertex => naga::ShaderStage::Vertex,
             ShaderStage::Fragment => naga::ShaderStage::Fragment,
             ShaderStage::Compute => naga::ShaderStage::Compute,
+            ShaderStage::Task | ShaderStage::Mesh => {
+                bail!(
+                    "naga cannot compile {:?} shaders, load them from SPIR-V",
+                    self.stage
+                )
+            }
         };
 
         let module = match self.language {
@@ -88,6 +109,7 @@ impl ShaderSource {
                 let mut parser = wgsl::Parser::new();
                 parser.parse(&buf).expect("Failed to parse WGSL shader")
             }
+            ShaderLanguage::SPIRV => unreachable!(),
         };
 
         let module_info =
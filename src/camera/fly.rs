@@ -0,0 +1,151 @@
+use super::{Camera, CameraController};
+use glam::{EulerRot, Quat, Vec2, Vec3};
+use std::f32::consts::FRAC_PI_2;
+use winit::{
+    dpi::PhysicalPosition,
+    event::{ElementState, KeyboardInput, MouseButton, VirtualKeyCode, WindowEvent},
+    window::Window,
+};
+
+// keep the pitch just short of straight up/down to avoid flipping over
+const MAX_PITCH: f32 = FRAC_PI_2 - 0.01;
+
+#[derive(Clone, Copy, Debug, Default)]
+struct MovementKeys {
+    forward: bool,
+    backward: bool,
+    left: bool,
+    right: bool,
+    up: bool,
+    down: bool,
+    boost: bool,
+}
+
+/// First-person camera, WASD to move, E/Q to go up/down and
+/// hold the right mouse button to grab the cursor and look around.
+#[derive(Clone, Debug)]
+pub struct FlyCameraController {
+    pub move_speed: f32,
+    pub boost_multiplier: f32,
+    /// Radians per pixel of mouse movement
+    pub look_sensitivity: f32,
+    yaw: f32,
+    pitch: f32,
+    keys: MovementKeys,
+    looking: bool,
+    last_cursor: Option<PhysicalPosition<f64>>,
+    mouse_delta: Vec2,
+}
+
+impl FlyCameraController {
+    pub fn new(camera: &Camera) -> Self {
+        let forward = camera.forward();
+
+        FlyCameraController {
+            move_speed: 2.0,
+            boost_multiplier: 4.0,
+            look_sensitivity: 0.003,
+            yaw: (-forward.x).atan2(-forward.z),
+            pitch: forward.y.clamp(-1.0, 1.0).asin(),
+            keys: MovementKeys::default(),
+            looking: false,
+            last_cursor: None,
+            mouse_delta: Vec2::ZERO,
+        }
+    }
+
+    fn set_looking(&mut self, window: &Window, looking: bool) {
+        if self.looking == looking {
+            return;
+        }
+
+        if let Err(e) = window.set_cursor_grab(looking) {
+            log::warn!("Failed to change cursor grab: {e}");
+        }
+        window.set_cursor_visible(!looking);
+
+        self.looking = looking;
+        self.last_cursor = None;
+    }
+}
+
+impl CameraController for FlyCameraController {
+    fn handle_window_event(&mut self, window: &Window, event: &WindowEvent<'_>) -> bool {
+        match event {
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state,
+                        virtual_keycode: Some(key),
+                        ..
+                    },
+                ..
+            } => {
+                let pressed = *state == ElementState::Pressed;
+                match key {
+                    VirtualKeyCode::W => self.keys.forward = pressed,
+                    VirtualKeyCode::S => self.keys.backward = pressed,
+                    VirtualKeyCode::A => self.keys.left = pressed,
+                    VirtualKeyCode::D => self.keys.right = pressed,
+                    VirtualKeyCode::E | VirtualKeyCode::Space => self.keys.up = pressed,
+                    VirtualKeyCode::Q | VirtualKeyCode::LControl => self.keys.down = pressed,
+                    VirtualKeyCode::LShift => self.keys.boost = pressed,
+                    _ => return false,
+                }
+                true
+            }
+            WindowEvent::MouseInput {
+                state,
+                button: MouseButton::Right,
+                ..
+            } => {
+                self.set_looking(window, *state == ElementState::Pressed);
+                true
+            }
+            WindowEvent::CursorMoved { position, .. } if self.looking => {
+                if let Some(last) = self.last_cursor {
+                    self.mouse_delta +=
+                        Vec2::new((position.x - last.x) as f32, (position.y - last.y) as f32);
+                }
+
+                // re-center the cursor so it never hits the window border,
+                // not every platform supports this so fall back to tracking
+                let size = window.inner_size();
+                let center =
+                    PhysicalPosition::new(size.width as f64 / 2.0, size.height as f64 / 2.0);
+                self.last_cursor = match window.set_cursor_position(center) {
+                    Ok(_) => Some(center),
+                    Err(_) => Some(*position),
+                };
+                true
+            }
+            WindowEvent::Focused(false) => {
+                self.set_looking(window, false);
+                self.keys = MovementKeys::default();
+                false
+            }
+            _ => false,
+        }
+    }
+
+    fn update(&mut self, camera: &mut Camera, delta_time: f32) {
+        self.yaw -= self.mouse_delta.x * self.look_sensitivity;
+        self.pitch =
+            (self.pitch - self.mouse_delta.y * self.look_sensitivity).clamp(-MAX_PITCH, MAX_PITCH);
+        self.mouse_delta = Vec2::ZERO;
+
+        camera.rotation = Quat::from_euler(EulerRot::YXZ, self.yaw, self.pitch, 0.0);
+
+        let axis = |positive: bool, negative: bool| positive as i32 as f32 - negative as i32 as f32;
+        let direction = camera.forward() * axis(self.keys.forward, self.keys.backward)
+            + camera.right() * axis(self.keys.right, self.keys.left)
+            + Vec3::Y * axis(self.keys.up, self.keys.down);
+
+        let mut speed = self.move_speed;
+        if self.keys.boost {
+            speed *= self.boost_multiplier;
+        }
+
+        camera.position += direction.normalize_or_zero() * speed * delta_time;
+    }
+}
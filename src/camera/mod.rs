@@ -0,0 +1,71 @@
+pub mod fly;
+pub mod orbit;
+
+pub use fly::FlyCameraController;
+pub use orbit::OrbitCameraController;
+
+use glam::{Mat4, Quat, Vec3};
+use winit::{event::WindowEvent, window::Window};
+
+#[derive(Clone, Copy, Debug)]
+pub struct Camera {
+    pub position: Vec3,
+    pub rotation: Quat,
+    /// Vertical field of view in radians
+    pub fov_y: f32,
+    pub aspect_ratio: f32,
+    pub z_near: f32,
+    pub z_far: f32,
+}
+
+impl Default for Camera {
+    fn default() -> Self {
+        Camera {
+            position: Vec3::new(0.0, 0.0, 2.0),
+            rotation: Quat::IDENTITY,
+            fov_y: 70.0_f32.to_radians(),
+            aspect_ratio: 16.0 / 9.0,
+            z_near: 0.1,
+            z_far: 200.0,
+        }
+    }
+}
+
+impl Camera {
+    pub fn look_at(&mut self, target: Vec3) {
+        let view = Mat4::look_at_rh(self.position, target, Vec3::Y);
+        self.rotation = Quat::from_mat4(&view.inverse());
+    }
+
+    pub fn forward(&self) -> Vec3 {
+        self.rotation * Vec3::NEG_Z
+    }
+
+    pub fn right(&self) -> Vec3 {
+        self.rotation * Vec3::X
+    }
+
+    pub fn up(&self) -> Vec3 {
+        self.rotation * Vec3::Y
+    }
+
+    pub fn view(&self) -> Mat4 {
+        Mat4::from_rotation_translation(self.rotation, self.position).inverse()
+    }
+
+    pub fn projection(&self) -> Mat4 {
+        Mat4::perspective_rh(self.fov_y, self.aspect_ratio, self.z_near, self.z_far)
+    }
+
+    pub fn view_projection(&self) -> Mat4 {
+        self.projection() * self.view()
+    }
+}
+
+pub trait CameraController {
+    /// Feeds a window event to the controller, returns `true` if the event was consumed
+    fn handle_window_event(&mut self, window: &Window, event: &WindowEvent<'_>) -> bool;
+
+    /// Applies the accumulated input to `camera`, `delta_time` is in seconds
+    fn update(&mut self, camera: &mut Camera, delta_time: f32);
+}
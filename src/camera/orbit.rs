@@ -0,0 +1,126 @@
+use super::{Camera, CameraController};
+use glam::{EulerRot, Quat, Vec2, Vec3};
+use std::f32::consts::FRAC_PI_2;
+use winit::{
+    dpi::PhysicalPosition,
+    event::{ElementState, MouseButton, MouseScrollDelta, WindowEvent},
+    window::Window,
+};
+
+const MAX_PITCH: f32 = FRAC_PI_2 - 0.01;
+
+/// Orbits around `target`, drag with the left mouse button to rotate,
+/// the middle mouse button to pan and scroll to zoom.
+#[derive(Clone, Debug)]
+pub struct OrbitCameraController {
+    pub target: Vec3,
+    pub distance: f32,
+    pub min_distance: f32,
+    pub max_distance: f32,
+    /// Radians per pixel of mouse movement
+    pub rotate_sensitivity: f32,
+    /// Fraction of the distance to the target per scrolled line
+    pub zoom_sensitivity: f32,
+    yaw: f32,
+    pitch: f32,
+    rotating: bool,
+    panning: bool,
+    last_cursor: Option<PhysicalPosition<f64>>,
+    rotate_delta: Vec2,
+    pan_delta: Vec2,
+    zoom_delta: f32,
+    viewport_height: f32,
+}
+
+impl OrbitCameraController {
+    pub fn new(camera: &Camera, target: Vec3) -> Self {
+        let offset = camera.position - target;
+        let distance = offset.length().max(f32::EPSILON);
+        let direction = offset / distance;
+
+        OrbitCameraController {
+            target,
+            distance,
+            min_distance: 0.05,
+            max_distance: 1000.0,
+            rotate_sensitivity: 0.005,
+            zoom_sensitivity: 0.1,
+            yaw: direction.x.atan2(direction.z),
+            pitch: (-direction.y).clamp(-1.0, 1.0).asin(),
+            rotating: false,
+            panning: false,
+            last_cursor: None,
+            rotate_delta: Vec2::ZERO,
+            pan_delta: Vec2::ZERO,
+            zoom_delta: 0.0,
+            viewport_height: 720.0,
+        }
+    }
+}
+
+impl CameraController for OrbitCameraController {
+    fn handle_window_event(&mut self, window: &Window, event: &WindowEvent<'_>) -> bool {
+        match event {
+            WindowEvent::MouseInput { state, button, .. } => {
+                let pressed = *state == ElementState::Pressed;
+                match button {
+                    MouseButton::Left => self.rotating = pressed,
+                    MouseButton::Middle => self.panning = pressed,
+                    _ => return false,
+                }
+                true
+            }
+            WindowEvent::CursorMoved { position, .. } => {
+                let delta = self.last_cursor.map_or(Vec2::ZERO, |last| {
+                    Vec2::new((position.x - last.x) as f32, (position.y - last.y) as f32)
+                });
+                self.last_cursor = Some(*position);
+                self.viewport_height = window.inner_size().height as f32;
+
+                if self.rotating {
+                    self.rotate_delta += delta;
+                } else if self.panning {
+                    self.pan_delta += delta;
+                }
+                self.rotating || self.panning
+            }
+            WindowEvent::MouseWheel { delta, .. } => {
+                self.zoom_delta += match delta {
+                    MouseScrollDelta::LineDelta(_, y) => *y,
+                    // roughly what most platforms report for a single line
+                    MouseScrollDelta::PixelDelta(pos) => pos.y as f32 / 20.0,
+                };
+                true
+            }
+            WindowEvent::Focused(false) | WindowEvent::CursorLeft { .. } => {
+                self.rotating = false;
+                self.panning = false;
+                self.last_cursor = None;
+                false
+            }
+            _ => false,
+        }
+    }
+
+    fn update(&mut self, camera: &mut Camera, _delta_time: f32) {
+        self.yaw -= self.rotate_delta.x * self.rotate_sensitivity;
+        self.pitch = (self.pitch - self.rotate_delta.y * self.rotate_sensitivity)
+            .clamp(-MAX_PITCH, MAX_PITCH);
+        self.rotate_delta = Vec2::ZERO;
+
+        self.distance = (self.distance * (1.0 - self.zoom_sensitivity).powf(self.zoom_delta))
+            .clamp(self.min_distance, self.max_distance);
+        self.zoom_delta = 0.0;
+
+        camera.rotation = Quat::from_euler(EulerRot::YXZ, self.yaw, self.pitch, 0.0);
+
+        // pan in the view plane, scaled so the target follows the cursor
+        let world_per_pixel =
+            2.0 * self.distance * (camera.fov_y * 0.5).tan() / self.viewport_height.max(1.0);
+        self.target +=
+            (camera.up() * self.pan_delta.y - camera.right() * self.pan_delta.x) * world_per_pixel;
+        self.pan_delta = Vec2::ZERO;
+
+        camera.position = self.target - camera.forward() * self.distance;
+    }
+}
@@ -0,0 +1,88 @@
+use glam::{Mat4, Vec3};
+
+/// Axis-aligned bounding box
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Aabb {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl Aabb {
+    /// Smallest box holding every point, or an empty box at the origin without any
+    pub fn from_points(points: impl IntoIterator<Item = Vec3>) -> Self {
+        let mut points = points.into_iter();
+        let Some(first) = points.next() else {
+            return Aabb::default();
+        };
+        points.fold(
+            Aabb {
+                min: first,
+                max: first,
+            },
+            |aabb, point| Aabb {
+                min: aabb.min.min(point),
+                max: aabb.max.max(point),
+            },
+        )
+    }
+
+    pub fn center(&self) -> Vec3 {
+        (self.min + self.max) * 0.5
+    }
+
+    pub fn half_extents(&self) -> Vec3 {
+        (self.max - self.min) * 0.5
+    }
+
+    /// Box holding this one after it is transformed by `matrix`
+    pub fn transform(&self, matrix: Mat4) -> Self {
+        let center = matrix.transform_point3(self.center());
+        let half_extents = self.half_extents();
+        // every axis of the new box gets the extents projected onto it
+        let extents = Vec3::new(
+            matrix.row(0).truncate().abs().dot(half_extents),
+            matrix.row(1).truncate().abs().dot(half_extents),
+            matrix.row(2).truncate().abs().dot(half_extents),
+        );
+        Aabb {
+            min: center - extents,
+            max: center + extents,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct BoundingSphere {
+    pub center: Vec3,
+    pub radius: f32,
+}
+
+impl BoundingSphere {
+    /// Sphere around the center of the points' bounding box, not necessarily the smallest
+    pub fn from_points(points: impl IntoIterator<Item = Vec3> + Clone) -> Self {
+        let center = Aabb::from_points(points.clone()).center();
+        let radius = points
+            .into_iter()
+            .map(|point| point.distance_squared(center))
+            .fold(0.0, f32::max)
+            .sqrt();
+        BoundingSphere { center, radius }
+    }
+
+    /// Sphere holding this one after it is transformed by `matrix`
+    pub fn transform(&self, matrix: Mat4) -> Self {
+        BoundingSphere {
+            center: matrix.transform_point3(self.center),
+            radius: self.radius * max_scale(matrix),
+        }
+    }
+}
+
+/// Largest factor `matrix` scales lengths by along any of its axes
+pub fn max_scale(matrix: Mat4) -> f32 {
+    [matrix.x_axis, matrix.y_axis, matrix.z_axis]
+        .map(|axis| axis.truncate().length_squared())
+        .into_iter()
+        .fold(0.0, f32::max)
+        .sqrt()
+}
@@ -0,0 +1,144 @@
+use super::{Aabb, BoundingSphere};
+use glam::{Mat4, Vec4};
+
+/// The six planes bounding the volume seen through a projection, their normals point inside
+#[derive(Clone, Copy, Debug)]
+pub struct Frustum {
+    pub planes: [Vec4; 6],
+}
+
+impl Frustum {
+    /// Extracts the planes from a view projection matrix with depth from 0 to 1
+    pub fn from_view_projection(view_projection: Mat4) -> Self {
+        let [x, y, z, w] = [0, 1, 2, 3].map(|i| view_projection.row(i));
+        let planes =
+            [w + x, w - x, w + y, w - y, z, w - z].map(|plane| plane / plane.truncate().length());
+        Frustum { planes }
+    }
+
+    pub fn intersects_sphere(&self, sphere: &BoundingSphere) -> bool {
+        self.planes
+            .iter()
+            .all(|plane| plane.truncate().dot(sphere.center) + plane.w >= -sphere.radius)
+    }
+
+    pub fn intersects_aabb(&self, aabb: &Aabb) -> bool {
+        let (center, half_extents) = (aabb.center(), aabb.half_extents());
+        self.planes.iter().all(|plane| {
+            let normal = plane.truncate();
+            normal.dot(center) + plane.w >= -normal.abs().dot(half_extents)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use glam::Vec3;
+    use std::f32::consts::FRAC_PI_2;
+
+    fn assert_planes(frustum: &Frustum, expected: [Vec4; 6]) {
+        for (plane, expected) in frustum.planes.iter().zip(expected) {
+            assert!(
+                (*plane - expected).length() < 1e-5,
+                "{plane} instead of {expected}"
+            );
+        }
+    }
+
+    fn distances(frustum: &Frustum, point: Vec3) -> [f32; 6] {
+        frustum
+            .planes
+            .map(|plane| plane.truncate().dot(point) + plane.w)
+    }
+
+    #[test]
+    fn perspective_planes() {
+        // 90 degrees wide, looking down -z from 1 to 10
+        let frustum =
+            Frustum::from_view_projection(Mat4::perspective_rh(FRAC_PI_2, 1.0, 1.0, 10.0));
+        let side = std::f32::consts::FRAC_1_SQRT_2;
+        // left, right, bottom, top, near and far
+        assert_planes(
+            &frustum,
+            [
+                Vec4::new(side, 0.0, -side, 0.0),
+                Vec4::new(-side, 0.0, -side, 0.0),
+                Vec4::new(0.0, side, -side, 0.0),
+                Vec4::new(0.0, -side, -side, 0.0),
+                Vec4::new(0.0, 0.0, -1.0, -1.0),
+                Vec4::new(0.0, 0.0, 1.0, 10.0),
+            ],
+        );
+
+        assert!(distances(&frustum, Vec3::new(0.0, 0.0, -5.0))
+            .iter()
+            .all(|&distance| distance > 0.0));
+        // just outside of each plane in turn
+        let outside = [
+            Vec3::new(-5.1, 0.0, -5.0),
+            Vec3::new(5.1, 0.0, -5.0),
+            Vec3::new(0.0, -5.1, -5.0),
+            Vec3::new(0.0, 5.1, -5.0),
+            Vec3::new(0.0, 0.0, -0.9),
+            Vec3::new(0.0, 0.0, -10.1),
+        ];
+        for (i, point) in outside.into_iter().enumerate() {
+            let distances = distances(&frustum, point);
+            assert!(distances[i] < 0.0, "{point} is inside plane {i}");
+        }
+    }
+
+    #[test]
+    fn view_and_orthographic_planes() {
+        // looking down +x from x = 10 through a 4 by 2 box from 1 to 5 away
+        let view = Mat4::look_at_rh(
+            Vec3::new(10.0, 0.0, 0.0),
+            Vec3::new(20.0, 0.0, 0.0),
+            Vec3::Y,
+        );
+        let projection = Mat4::orthographic_rh(-2.0, 2.0, -1.0, 1.0, 1.0, 5.0);
+        let frustum = Frustum::from_view_projection(projection * view);
+        assert_planes(
+            &frustum,
+            [
+                // the camera's right is +z
+                Vec4::new(0.0, 0.0, 1.0, 2.0),
+                Vec4::new(0.0, 0.0, -1.0, 2.0),
+                Vec4::new(0.0, 1.0, 0.0, 1.0),
+                Vec4::new(0.0, -1.0, 0.0, 1.0),
+                Vec4::new(1.0, 0.0, 0.0, -11.0),
+                Vec4::new(-1.0, 0.0, 0.0, 15.0),
+            ],
+        );
+    }
+
+    #[test]
+    fn intersections() {
+        let frustum =
+            Frustum::from_view_projection(Mat4::perspective_rh(FRAC_PI_2, 1.0, 1.0, 10.0));
+        let sphere = |x, z, radius| BoundingSphere {
+            center: Vec3::new(x, 0.0, z),
+            radius,
+        };
+        assert!(frustum.intersects_sphere(&sphere(0.0, -5.0, 0.5)));
+        assert!(frustum.intersects_sphere(&sphere(0.0, 0.0, 1.5)));
+        assert!(!frustum.intersects_sphere(&sphere(0.0, 0.0, 0.5)));
+        assert!(!frustum.intersects_sphere(&sphere(0.0, -12.0, 1.0)));
+        assert!(!frustum.intersects_sphere(&sphere(-8.0, -5.0, 1.0)));
+
+        let aabb = |min, max| Aabb { min, max };
+        assert!(frustum.intersects_aabb(&aabb(Vec3::splat(-1.0), Vec3::new(1.0, 1.0, -2.0))));
+        assert!(frustum.intersects_aabb(&aabb(
+            Vec3::new(-9.0, -1.0, -6.0),
+            Vec3::new(-5.0, 1.0, -5.0)
+        )));
+        assert!(!frustum.intersects_aabb(&aabb(
+            Vec3::new(-9.0, -1.0, -6.0),
+            Vec3::new(-7.0, 1.0, -5.0)
+        )));
+        assert!(
+            !frustum.intersects_aabb(&aabb(Vec3::new(-1.0, -1.0, 1.0), Vec3::new(1.0, 1.0, 2.0)))
+        );
+    }
+}
@@ -0,0 +1,41 @@
+pub mod bounds;
+pub mod frustum;
+
+pub use bounds::{max_scale, Aabb, BoundingSphere};
+pub use frustum::Frustum;
+
+#[derive(Clone, Copy, Debug)]
+pub struct CullingSettings {
+    /// Skips objects outside of the camera's view before recording any draws
+    pub frustum: bool,
+    /// Culls the undeformed meshes in a compute pass and draws them with indirect draws
+    /// instead, always against the frustum. Culled counts then lag a few frames behind.
+    /// Stays off on devices without `drawIndirectCount` and multi-draw indirect.
+    pub gpu: bool,
+    /// Also skips objects hidden behind others when culling on the GPU, which always
+    /// renders a depth prepass. See [`crate::gpu_driven::GpuCullingPass`]. Stays off on
+    /// devices without `shaderStorageImageExtendedFormats`.
+    pub occlusion: bool,
+    /// Draws the objects skipped by occlusion culling in red on top of the scene
+    pub show_occluded: bool,
+    /// Culls the meshlets of meshes drawn at full detail, and then their triangles unless
+    /// mesh shaders draw them, instead of whole objects when culling on the GPU. See
+    /// [`crate::gpu_driven::MeshletCullingPass`].
+    pub meshlets: bool,
+    /// Also culls meshlets and triangles facing away from the camera, only correct for
+    /// closed meshes since the scene is drawn two-sided
+    pub backface: bool,
+}
+
+impl Default for CullingSettings {
+    fn default() -> Self {
+        CullingSettings {
+            frustum: true,
+            gpu: false,
+            occlusion: true,
+            show_occluded: false,
+            meshlets: true,
+            backface: false,
+        }
+    }
+}
@@ -0,0 +1,116 @@
+use crate::backend_vulkan::{
+    descriptor::{write_image_descriptor, DescriptorPool, DescriptorSetLayoutBuilder},
+    device::Device,
+    image::{Image, ImageDesc},
+};
+use anyhow::Result;
+use ash::vk;
+use gpu_allocator::vulkan::Allocator;
+
+/// Formats of the color attachments, in the order of the `GBufferOut` locations
+pub const GBUFFER_FORMATS: [vk::Format; 4] = [
+    // albedo
+    vk::Format::R8G8B8A8_SRGB,
+    // world-space normal
+    vk::Format::R16G16B16A16_SFLOAT,
+    // metallic and roughness
+    vk::Format::R8G8_UNORM,
+    // emissive radiance, not limited to 1
+    vk::Format::R16G16B16A16_SFLOAT,
+];
+
+const GBUFFER_NAMES: [&str; 4] = [
+    "gbuffer albedo",
+    "gbuffer normal",
+    "gbuffer metallic roughness",
+    "gbuffer emissive",
+];
+
+/// Surface attributes written by the geometry pass, sampled together with the
+/// depth buffer through [`Self::descriptor_set`]
+pub struct GBuffer {
+    pub images: Vec<Image>,
+    pub layout: vk::DescriptorSetLayout,
+    descriptor_pool: DescriptorPool,
+    pub descriptor_set: vk::DescriptorSet,
+}
+
+impl GBuffer {
+    pub fn new(allocator: &mut Allocator, device: &Device, depth: &Image) -> Result<Self> {
+        let layout = (0..=GBUFFER_FORMATS.len() as u32)
+            .fold(DescriptorSetLayoutBuilder::default(), |builder, binding| {
+                builder.binding(
+                    binding,
+                    vk::DescriptorType::SAMPLED_IMAGE,
+                    vk::ShaderStageFlags::FRAGMENT,
+                )
+            })
+            .build(device)?;
+
+        let descriptor_pool = DescriptorPool::new(
+            device,
+            1,
+            &[vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::SAMPLED_IMAGE,
+                descriptor_count: GBUFFER_FORMATS.len() as u32 + 1,
+            }],
+        )?;
+        let descriptor_set = descriptor_pool.allocate(device, layout)?;
+
+        let gbuffer = GBuffer {
+            images: Self::create_images(allocator, device, depth),
+            layout,
+            descriptor_pool,
+            descriptor_set,
+        };
+        gbuffer.write_descriptors(device, depth);
+
+        Ok(gbuffer)
+    }
+
+    fn create_images(allocator: &mut Allocator, device: &Device, depth: &Image) -> Vec<Image> {
+        GBUFFER_FORMATS
+            .iter()
+            .zip(GBUFFER_NAMES)
+            .map(|(&format, name)| {
+                let desc = ImageDesc::new_2d(
+                    format,
+                    depth.desc.extent_2d(),
+                    vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::SAMPLED,
+                );
+                Image::new(allocator, device, desc, name)
+            })
+            .collect()
+    }
+
+    fn write_descriptors(&self, device: &Device, depth: &Image) {
+        for (binding, image) in self.images.iter().chain([depth]).enumerate() {
+            write_image_descriptor(
+                device,
+                self.descriptor_set,
+                binding as u32,
+                vk::DescriptorType::SAMPLED_IMAGE,
+                image.view,
+                vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                vk::Sampler::null(),
+            );
+        }
+    }
+
+    /// Recreates the images at the size of the new `depth` image, the device must be idle
+    pub fn resize(&mut self, allocator: &mut Allocator, device: &Device, depth: &Image) {
+        for image in &mut self.images {
+            image.destroy(device, allocator);
+        }
+        self.images = Self::create_images(allocator, device, depth);
+        self.write_descriptors(device, depth);
+    }
+
+    pub fn destroy(&mut self, device: &Device, allocator: &mut Allocator) {
+        for image in &mut self.images {
+            image.destroy(device, allocator);
+        }
+        self.descriptor_pool.destroy(device);
+        unsafe { device.raw.destroy_descriptor_set_layout(self.layout, None) };
+    }
+}
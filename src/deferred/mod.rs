@@ -0,0 +1,5 @@
+pub mod gbuffer;
+pub mod pass;
+
+pub use gbuffer::{GBuffer, GBUFFER_FORMATS};
+pub use pass::{DeferredPass, GBufferView, RenderPath};
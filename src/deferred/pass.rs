@@ -0,0 +1,390 @@
+use super::gbuffer::{GBuffer, GBUFFER_FORMATS};
+use crate::{
+    backend_vulkan::{
+        device::Device,
+        image::Image,
+        mesh::{HasVertexInputDescription, Mesh, Vertex},
+        pipeline::GraphicsPipeline,
+        shader::{ShaderLanguage, ShaderSource, ShaderStage},
+    },
+    gpu_driven::meshlets::MeshletTarget,
+    resource::Pool,
+    DrawList, HDR_FORMAT,
+};
+use anyhow::Result;
+use ash::vk;
+use gpu_allocator::vulkan::Allocator;
+use std::mem::size_of;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RenderPath {
+    /// Every draw is lit while it is rasterized
+    #[default]
+    Forward,
+    /// Draws only write a G-buffer, which is lit once per pixel afterwards
+    Deferred,
+}
+
+/// What the deferred lighting pass outputs, matches the constants in `shader_new.wgsl`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[repr(u32)]
+pub enum GBufferView {
+    #[default]
+    Lit = 0,
+    Albedo = 1,
+    Normal = 2,
+    MetallicRoughness = 3,
+    Emissive = 4,
+    Depth = 5,
+}
+
+impl GBufferView {
+    pub const ALL: [GBufferView; 6] = [
+        GBufferView::Lit,
+        GBufferView::Albedo,
+        GBufferView::Normal,
+        GBufferView::MetallicRoughness,
+        GBufferView::Emissive,
+        GBufferView::Depth,
+    ];
+
+    /// The next view, wrapping around after the last one
+    pub fn next(self) -> Self {
+        let index = Self::ALL.iter().position(|&v| v == self).unwrap();
+        Self::ALL[(index + 1) % Self::ALL.len()]
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+#[repr(C)]
+struct DeferredPushConstants {
+    view: u32,
+}
+
+/// Geometry pass into a [`GBuffer`] followed by a fullscreen lighting pass into the HDR target
+pub struct DeferredPass {
+    pub gbuffer: GBuffer,
+    gbuffer_pipeline: GraphicsPipeline,
+    lighting_pipeline: GraphicsPipeline,
+}
+
+impl DeferredPass {
+    pub fn new(
+        allocator: &mut Allocator,
+        device: &Device,
+        frame_descriptor_layout: vk::DescriptorSetLayout,
+        depth: &Image,
+    ) -> Result<Self> {
+        let gbuffer = GBuffer::new(allocator, device, depth)?;
+
+        let shader = |stage: ShaderStage, entry: &str, path: &str| {
+            ShaderSource::builder()
+                .entry(entry)
+                .build(stage, ShaderLanguage::WGSL, path)
+        };
+
+        let gbuffer_pipeline = GraphicsPipeline::builder()
+            .color_formats(&GBUFFER_FORMATS)
+            .depth_format(depth.desc.format)
+            .vertex_input(Vertex::describe())
+            .descriptor_set_layouts(&[frame_descriptor_layout])
+            .build(
+                device,
+                &[
+                    shader(
+                        ShaderStage::Vertex,
+                        "vs_main",
+                        "./src/shaders/shader_new.wgsl",
+                    ),
+                    shader(
+                        ShaderStage::Fragment,
+                        "fs_gbuffer",
+                        "./src/shaders/shader_new.wgsl",
+                    ),
+                ],
+            )?;
+
+        let lighting_pipeline = GraphicsPipeline::builder()
+            .color_formats(&[HDR_FORMAT])
+            .descriptor_set_layouts(&[frame_descriptor_layout, gbuffer.layout])
+            .push_constant_size(size_of::<DeferredPushConstants>())
+            .build(
+                device,
+                &[
+                    shader(
+                        ShaderStage::Vertex,
+                        "vs_fullscreen",
+                        "./src/shaders/fullscreen.wgsl",
+                    ),
+                    shader(
+                        ShaderStage::Fragment,
+                        "fs_deferred",
+                        "./src/shaders/shader_new.wgsl",
+                    ),
+                ],
+            )?;
+
+        Ok(DeferredPass {
+            gbuffer,
+            gbuffer_pipeline,
+            lighting_pipeline,
+        })
+    }
+
+    /// Recreates the G-buffer at the size of the new `depth` image, the device must be idle
+    pub fn resize(&mut self, allocator: &mut Allocator, device: &Device, depth: &Image) {
+        self.gbuffer.resize(allocator, device, depth);
+    }
+
+    /// Fills the G-buffer with `draws` and lights it on top of the background in `hdr`,
+    /// which has to be in `COLOR_ATTACHMENT_OPTIMAL` already.
+    ///
+    /// `depth` is cleared unless it was `depth_prepassed`, in which case it must
+    /// be in `DEPTH_ATTACHMENT_OPTIMAL`.
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn record(
+        &self,
+        device: &Device,
+        cmd: vk::CommandBuffer,
+        frame_descriptor_set: vk::DescriptorSet,
+        view: GBufferView,
+        hdr: &Image,
+        depth: &Image,
+        depth_prepassed: bool,
+        meshes: &Pool<Mesh>,
+        draws: &DrawList,
+    ) {
+        // every target is fully rewritten, but the previous frame may still be using them
+        let mut barriers = self
+            .gbuffer
+            .images
+            .iter()
+            .map(|image| {
+                vk::ImageMemoryBarrier::builder()
+                    .old_layout(vk::ImageLayout::UNDEFINED)
+                    .new_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+                    .src_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
+                    .dst_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
+                    .image(image.raw)
+                    .subresource_range(image.desc.subresource_range())
+                    .build()
+            })
+            .collect::<Vec<_>>();
+
+        if !depth_prepassed {
+            barriers.push(
+                vk::ImageMemoryBarrier::builder()
+                    .old_layout(vk::ImageLayout::UNDEFINED)
+                    .new_layout(vk::ImageLayout::DEPTH_ATTACHMENT_OPTIMAL)
+                    .src_access_mask(vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE)
+                    .dst_access_mask(vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE)
+                    .image(depth.raw)
+                    .subresource_range(depth.desc.subresource_range())
+                    .build(),
+            );
+        }
+
+        unsafe {
+            device.raw.cmd_pipeline_barrier(
+                cmd,
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                    | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS
+                    | vk::PipelineStageFlags::FRAGMENT_SHADER
+                    | vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                    | vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &barriers,
+            );
+        }
+
+        self.record_gbuffer(
+            device,
+            cmd,
+            frame_descriptor_set,
+            depth,
+            depth_prepassed,
+            meshes,
+            draws,
+        );
+
+        let mut barriers = self
+            .gbuffer
+            .images
+            .iter()
+            .map(|image| {
+                vk::ImageMemoryBarrier::builder()
+                    .old_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+                    .new_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+                    .src_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
+                    .dst_access_mask(vk::AccessFlags::SHADER_READ)
+                    .image(image.raw)
+                    .subresource_range(image.desc.subresource_range())
+                    .build()
+            })
+            .collect::<Vec<_>>();
+        barriers.push(
+            vk::ImageMemoryBarrier::builder()
+                .old_layout(vk::ImageLayout::DEPTH_ATTACHMENT_OPTIMAL)
+                .new_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+                .src_access_mask(vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE)
+                .dst_access_mask(vk::AccessFlags::SHADER_READ)
+                .image(depth.raw)
+                .subresource_range(depth.desc.subresource_range())
+                .build(),
+        );
+
+        unsafe {
+            device.raw.cmd_pipeline_barrier(
+                cmd,
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                    | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
+                vk::PipelineStageFlags::FRAGMENT_SHADER,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &barriers,
+            );
+        }
+
+        self.record_lighting(device, cmd, frame_descriptor_set, view, hdr);
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn record_gbuffer(
+        &self,
+        device: &Device,
+        cmd: vk::CommandBuffer,
+        frame_descriptor_set: vk::DescriptorSet,
+        depth: &Image,
+        depth_prepassed: bool,
+        meshes: &Pool<Mesh>,
+        draws: &DrawList,
+    ) {
+        let color_attachments = self
+            .gbuffer
+            .images
+            .iter()
+            .map(|image| {
+                vk::RenderingAttachmentInfo::builder()
+                    .image_view(image.view)
+                    .image_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+                    .load_op(vk::AttachmentLoadOp::CLEAR)
+                    .store_op(vk::AttachmentStoreOp::STORE)
+                    .clear_value(vk::ClearValue {
+                        color: vk::ClearColorValue { float32: [0.0; 4] },
+                    })
+                    .build()
+            })
+            .collect::<Vec<_>>();
+
+        // the lighting pass reconstructs positions from depth, so it has to be stored
+        let depth_attachment_info = vk::RenderingAttachmentInfo::builder()
+            .image_view(depth.view)
+            .image_layout(vk::ImageLayout::DEPTH_ATTACHMENT_OPTIMAL)
+            .load_op(if depth_prepassed {
+                vk::AttachmentLoadOp::LOAD
+            } else {
+                vk::AttachmentLoadOp::CLEAR
+            })
+            .store_op(vk::AttachmentStoreOp::STORE)
+            .clear_value(vk::ClearValue {
+                depth_stencil: vk::ClearDepthStencilValue {
+                    depth: 1.0,
+                    stencil: 0,
+                },
+            });
+
+        let rendering_info = vk::RenderingInfo::builder()
+            .render_area(vk::Rect2D {
+                extent: depth.desc.extent_2d(),
+                ..Default::default()
+            })
+            .layer_count(1)
+            .color_attachments(&color_attachments)
+            .depth_attachment(&depth_attachment_info);
+
+        unsafe {
+            device.raw.cmd_begin_rendering(cmd, &rendering_info);
+
+            device.raw.cmd_bind_pipeline(
+                cmd,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.gbuffer_pipeline.pipeline,
+            );
+            device.raw.cmd_bind_descriptor_sets(
+                cmd,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.gbuffer_pipeline.layout,
+                0,
+                &[frame_descriptor_set],
+                &[],
+            );
+        }
+
+        draws.record(device, cmd, meshes, MeshletTarget::GBuffer);
+
+        unsafe { device.raw.cmd_end_rendering(cmd) };
+    }
+
+    fn record_lighting(
+        &self,
+        device: &Device,
+        cmd: vk::CommandBuffer,
+        frame_descriptor_set: vk::DescriptorSet,
+        view: GBufferView,
+        hdr: &Image,
+    ) {
+        let color_attachments = [vk::RenderingAttachmentInfo::builder()
+            .image_view(hdr.view)
+            .image_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+            // pixels without geometry keep the background
+            .load_op(vk::AttachmentLoadOp::LOAD)
+            .store_op(vk::AttachmentStoreOp::STORE)
+            .build()];
+
+        let rendering_info = vk::RenderingInfo::builder()
+            .render_area(vk::Rect2D {
+                extent: hdr.desc.extent_2d(),
+                ..Default::default()
+            })
+            .layer_count(1)
+            .color_attachments(&color_attachments);
+
+        let constants = DeferredPushConstants { view: view as u32 };
+
+        unsafe {
+            device.raw.cmd_begin_rendering(cmd, &rendering_info);
+
+            device.raw.cmd_bind_pipeline(
+                cmd,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.lighting_pipeline.pipeline,
+            );
+            device.raw.cmd_bind_descriptor_sets(
+                cmd,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.lighting_pipeline.layout,
+                0,
+                &[frame_descriptor_set, self.gbuffer.descriptor_set],
+                &[],
+            );
+        }
+
+        self.lighting_pipeline
+            .push_constants(device, cmd, &constants);
+
+        unsafe {
+            device.raw.cmd_draw(cmd, 3, 1, 0, 0);
+            device.raw.cmd_end_rendering(cmd);
+        }
+    }
+
+    pub fn destroy(&mut self, device: &Device, allocator: &mut Allocator) {
+        self.gbuffer.destroy(device, allocator);
+        self.gbuffer_pipeline.destroy(device);
+        self.lighting_pipeline.destroy(device);
+    }
+}
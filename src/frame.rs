@@ -0,0 +1,509 @@
+use crate::{
+    backend_vulkan::{
+        buffer::Buffer,
+        descriptor::{
+            write_buffer_descriptor, write_image_descriptor, DescriptorPool,
+            DescriptorSetLayoutBuilder,
+        },
+        device::{Device, FRAMES_IN_FLIGHT},
+    },
+    lighting::{ImageBasedLighting, LightData, ShadowData, ShadowMaps},
+    resource::DeletionQueue,
+    scene::MaterialData,
+};
+use anyhow::Result;
+use ash::vk;
+use glam::{Mat4, Vec2, Vec3, Vec4};
+use gpu_allocator::vulkan::Allocator;
+use std::mem::size_of;
+
+pub const GLOBAL_UNIFORMS_BINDING: u32 = 0;
+pub const INSTANCES_BINDING: u32 = 1;
+pub const MATERIALS_BINDING: u32 = 2;
+pub const LIGHTS_BINDING: u32 = 3;
+pub const SHADOWS_BINDING: u32 = 4;
+pub const SHADOW_MAPS_BINDING: u32 = 5;
+pub const SHADOW_SAMPLER_BINDING: u32 = 6;
+pub const AMBIENT_OCCLUSION_BINDING: u32 = 7;
+pub const CLUSTERS_BINDING: u32 = 8;
+pub const IRRADIANCE_BINDING: u32 = 9;
+pub const PREFILTERED_BINDING: u32 = 10;
+pub const BRDF_LUT_BINDING: u32 = 11;
+pub const ENVIRONMENT_SAMPLER_BINDING: u32 = 12;
+
+/// Data shared by every draw in a frame, matches `GlobalUniforms` in the shaders
+#[derive(Clone, Copy, Debug, Default)]
+#[repr(C)]
+pub struct GlobalUniforms {
+    pub view: Mat4,
+    pub projection: Mat4,
+    pub view_projection: Mat4,
+    /// `w` is unused
+    pub camera_position: Vec4,
+    pub resolution: Vec2,
+    /// Seconds since the renderer was created
+    pub time: f32,
+    pub frame_number: u32,
+    /// Constant ambient radiance, `w` is unused
+    pub ambient_light: Vec4,
+    pub light_count: u32,
+    /// Number of cascades of each directional light's shadows
+    pub cascade_count: u32,
+    /// Whether the ambient light is multiplied by the ambient occlusion map
+    pub ambient_occlusion: u32,
+    /// Whether lights are read from the clusters, see `ClusterSettings`
+    pub light_culling: u32,
+    /// View distance at which each shadow cascade ends
+    pub cascade_splits: Vec4,
+    pub inverse_projection: Mat4,
+    pub inverse_view_projection: Mat4,
+    pub z_near: f32,
+    pub z_far: f32,
+    /// Whether the ambient light comes from the environment maps instead of `ambient_light`
+    pub environment: u32,
+    pub environment_intensity: f32,
+}
+
+/// Per-instance transforms and materials, matches `InstanceData` in the shaders
+#[derive(Clone, Copy, Debug, Default)]
+#[repr(C)]
+pub struct InstanceData {
+    pub model: Mat4,
+    /// Inverse transpose of `model`, keeps normals perpendicular under non-uniform scaling
+    pub normal_matrix: Mat4,
+    /// Multiplies the base color of the material
+    pub color: Vec3,
+    pub material_index: u32,
+    /// While cross-fading between two levels of detail, the fraction of pixels drawn with
+    /// the finer level, negated on the instance drawing the coarser one. Zero draws every pixel
+    pub lod_fade: f32,
+    pub _padding: [u32; 3],
+}
+
+impl InstanceData {
+    pub fn new(model: Mat4, color: Vec3, material_index: u32) -> Self {
+        InstanceData {
+            model,
+            normal_matrix: model.inverse().transpose(),
+            color,
+            material_index,
+            lod_fade: 0.0,
+            _padding: [0; 3],
+        }
+    }
+
+    pub fn with_lod_fade(mut self, lod_fade: f32) -> Self {
+        self.lod_fade = lod_fade;
+        self
+    }
+}
+
+/// What the last call to [`crate::PoogieRenderer::draw`] submitted
+#[derive(Clone, Copy, Debug, Default)]
+pub struct FrameStats {
+    /// Scene nodes drawing a mesh
+    pub objects: u32,
+    /// Objects outside of the camera's view, they are still drawn into shadow maps
+    pub culled_objects: u32,
+    /// Objects hidden behind others, only known when culling on the GPU
+    /// and then counted a few frames late like `culled_objects`
+    pub occluded_objects: u32,
+    /// Meshlets of objects drawn at full detail when culling on the GPU
+    pub meshlets: u32,
+    /// Meshlets without any visible triangle, counted a few frames late like `culled_objects`,
+    /// always zero when mesh shaders cull them
+    pub culled_meshlets: u32,
+    /// Instanced draw calls of the scene pass
+    pub draw_calls: u32,
+}
+
+/// A growable storage buffer of `T`, resized buffers are retired to the deletion queue
+pub(crate) struct StorageArray<T> {
+    pub(crate) buffer: Buffer,
+    capacity: usize,
+    usage: vk::BufferUsageFlags,
+    name: &'static str,
+    _marker: std::marker::PhantomData<T>,
+}
+
+impl<T: Copy> StorageArray<T> {
+    pub(crate) fn new(
+        allocator: &mut Allocator,
+        device: &Device,
+        capacity: usize,
+        name: &'static str,
+    ) -> Self {
+        Self::with_usage(
+            allocator,
+            device,
+            capacity,
+            vk::BufferUsageFlags::empty(),
+            name,
+        )
+    }
+
+    /// `usage` is added to `STORAGE_BUFFER`
+    pub(crate) fn with_usage(
+        allocator: &mut Allocator,
+        device: &Device,
+        capacity: usize,
+        usage: vk::BufferUsageFlags,
+        name: &'static str,
+    ) -> Self {
+        let usage = usage | vk::BufferUsageFlags::STORAGE_BUFFER;
+        StorageArray {
+            buffer: Self::create_buffer(allocator, device, capacity, usage, name),
+            capacity,
+            usage,
+            name,
+            _marker: std::marker::PhantomData,
+        }
+    }
+
+    fn create_buffer(
+        allocator: &mut Allocator,
+        device: &Device,
+        capacity: usize,
+        usage: vk::BufferUsageFlags,
+        name: &str,
+    ) -> Buffer {
+        Buffer::new(allocator, device, capacity * size_of::<T>(), usage, name)
+    }
+
+    /// Uploads `data`, growing the buffer and repointing `binding` of `set` at it when needed
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn upload(
+        &mut self,
+        allocator: &mut Allocator,
+        device: &Device,
+        deletion_queue: &mut DeletionQueue,
+        frame_number: u64,
+        set: vk::DescriptorSet,
+        binding: u32,
+        data: &[T],
+    ) {
+        self.reserve(
+            allocator,
+            device,
+            deletion_queue,
+            frame_number,
+            set,
+            binding,
+            data.len(),
+        );
+        self.buffer.write(0, data);
+    }
+
+    /// Grows the buffer to hold at least `len` elements, for arrays written on the GPU
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn reserve(
+        &mut self,
+        allocator: &mut Allocator,
+        device: &Device,
+        deletion_queue: &mut DeletionQueue,
+        frame_number: u64,
+        set: vk::DescriptorSet,
+        binding: u32,
+        len: usize,
+    ) {
+        if len <= self.capacity {
+            return;
+        }
+
+        self.capacity = len.next_power_of_two();
+        let buffer = Self::create_buffer(allocator, device, self.capacity, self.usage, self.name);
+        deletion_queue.push(frame_number, std::mem::replace(&mut self.buffer, buffer));
+
+        write_buffer_descriptor(
+            device,
+            set,
+            binding,
+            vk::DescriptorType::STORAGE_BUFFER,
+            &self.buffer,
+        );
+    }
+}
+
+/// Everything written to a [`FrameData`] before recording a frame
+pub struct FrameContents<'a> {
+    pub globals: GlobalUniforms,
+    pub instances: &'a [InstanceData],
+    pub materials: &'a [MaterialData],
+    pub lights: &'a [LightData],
+    pub shadows: &'a [ShadowData],
+}
+
+/// Buffers and descriptors owned by a single frame in flight
+pub struct FrameData {
+    pub descriptor_set: vk::DescriptorSet,
+    global_buffer: Buffer,
+    instances: StorageArray<InstanceData>,
+    materials: StorageArray<MaterialData>,
+    lights: StorageArray<LightData>,
+    shadows: StorageArray<ShadowData>,
+}
+
+impl FrameData {
+    pub fn descriptor_set_layout(device: &Device) -> Result<vk::DescriptorSetLayout> {
+        let mut stages = vk::ShaderStageFlags::ALL_GRAPHICS | vk::ShaderStageFlags::COMPUTE;
+        if device.mesh_shader.is_some() {
+            stages |= vk::ShaderStageFlags::TASK_EXT | vk::ShaderStageFlags::MESH_EXT;
+        }
+
+        DescriptorSetLayoutBuilder::default()
+            .binding(
+                GLOBAL_UNIFORMS_BINDING,
+                vk::DescriptorType::UNIFORM_BUFFER,
+                stages,
+            )
+            .binding(
+                INSTANCES_BINDING,
+                vk::DescriptorType::STORAGE_BUFFER,
+                stages,
+            )
+            .binding(
+                MATERIALS_BINDING,
+                vk::DescriptorType::STORAGE_BUFFER,
+                stages,
+            )
+            .binding(LIGHTS_BINDING, vk::DescriptorType::STORAGE_BUFFER, stages)
+            .binding(SHADOWS_BINDING, vk::DescriptorType::STORAGE_BUFFER, stages)
+            .binding(
+                SHADOW_MAPS_BINDING,
+                vk::DescriptorType::SAMPLED_IMAGE,
+                stages,
+            )
+            .binding(SHADOW_SAMPLER_BINDING, vk::DescriptorType::SAMPLER, stages)
+            .binding(
+                AMBIENT_OCCLUSION_BINDING,
+                vk::DescriptorType::SAMPLED_IMAGE,
+                stages,
+            )
+            .binding(CLUSTERS_BINDING, vk::DescriptorType::STORAGE_BUFFER, stages)
+            .binding(
+                IRRADIANCE_BINDING,
+                vk::DescriptorType::SAMPLED_IMAGE,
+                stages,
+            )
+            .binding(
+                PREFILTERED_BINDING,
+                vk::DescriptorType::SAMPLED_IMAGE,
+                stages,
+            )
+            .binding(BRDF_LUT_BINDING, vk::DescriptorType::SAMPLED_IMAGE, stages)
+            .binding(
+                ENVIRONMENT_SAMPLER_BINDING,
+                vk::DescriptorType::SAMPLER,
+                stages,
+            )
+            .build(device)
+    }
+
+    pub fn descriptor_pool(device: &Device) -> Result<DescriptorPool> {
+        let frames = FRAMES_IN_FLIGHT as u32;
+        DescriptorPool::new(
+            device,
+            frames,
+            &[
+                vk::DescriptorPoolSize {
+                    ty: vk::DescriptorType::UNIFORM_BUFFER,
+                    descriptor_count: frames,
+                },
+                vk::DescriptorPoolSize {
+                    ty: vk::DescriptorType::STORAGE_BUFFER,
+                    descriptor_count: 5 * frames,
+                },
+                vk::DescriptorPoolSize {
+                    ty: vk::DescriptorType::SAMPLED_IMAGE,
+                    descriptor_count: 5 * frames,
+                },
+                vk::DescriptorPoolSize {
+                    ty: vk::DescriptorType::SAMPLER,
+                    descriptor_count: 2 * frames,
+                },
+            ],
+        )
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        allocator: &mut Allocator,
+        device: &Device,
+        pool: &DescriptorPool,
+        layout: vk::DescriptorSetLayout,
+        shadow_maps: &ShadowMaps,
+        ambient_occlusion: vk::ImageView,
+        clusters: &Buffer,
+        ibl: &ImageBasedLighting,
+    ) -> Result<Self> {
+        let global_buffer = Buffer::new(
+            allocator,
+            device,
+            size_of::<GlobalUniforms>(),
+            vk::BufferUsageFlags::UNIFORM_BUFFER,
+            "global uniforms",
+        );
+        let instances = StorageArray::new(allocator, device, 1024, "instances");
+        let materials = StorageArray::new(allocator, device, 256, "materials");
+        let lights = StorageArray::new(allocator, device, 64, "lights");
+        let shadows = StorageArray::new(
+            allocator,
+            device,
+            shadow_maps.layer_count() as usize,
+            "shadows",
+        );
+
+        let descriptor_set = pool.allocate(device, layout)?;
+
+        write_buffer_descriptor(
+            device,
+            descriptor_set,
+            GLOBAL_UNIFORMS_BINDING,
+            vk::DescriptorType::UNIFORM_BUFFER,
+            &global_buffer,
+        );
+        for (binding, buffer) in [
+            (INSTANCES_BINDING, &instances.buffer),
+            (MATERIALS_BINDING, &materials.buffer),
+            (LIGHTS_BINDING, &lights.buffer),
+            (SHADOWS_BINDING, &shadows.buffer),
+            (CLUSTERS_BINDING, clusters),
+        ] {
+            write_buffer_descriptor(
+                device,
+                descriptor_set,
+                binding,
+                vk::DescriptorType::STORAGE_BUFFER,
+                buffer,
+            );
+        }
+
+        write_image_descriptor(
+            device,
+            descriptor_set,
+            SHADOW_MAPS_BINDING,
+            vk::DescriptorType::SAMPLED_IMAGE,
+            shadow_maps.image.view,
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            vk::Sampler::null(),
+        );
+        write_image_descriptor(
+            device,
+            descriptor_set,
+            SHADOW_SAMPLER_BINDING,
+            vk::DescriptorType::SAMPLER,
+            vk::ImageView::null(),
+            vk::ImageLayout::UNDEFINED,
+            shadow_maps.sampler,
+        );
+        for (binding, image) in [
+            (IRRADIANCE_BINDING, &ibl.irradiance),
+            (PREFILTERED_BINDING, &ibl.prefiltered),
+            (BRDF_LUT_BINDING, &ibl.brdf_lut),
+        ] {
+            write_image_descriptor(
+                device,
+                descriptor_set,
+                binding,
+                vk::DescriptorType::SAMPLED_IMAGE,
+                image.view,
+                vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                vk::Sampler::null(),
+            );
+        }
+        write_image_descriptor(
+            device,
+            descriptor_set,
+            ENVIRONMENT_SAMPLER_BINDING,
+            vk::DescriptorType::SAMPLER,
+            vk::ImageView::null(),
+            vk::ImageLayout::UNDEFINED,
+            ibl.sampler,
+        );
+
+        let frame = FrameData {
+            descriptor_set,
+            global_buffer,
+            instances,
+            materials,
+            lights,
+            shadows,
+        };
+        frame.set_ambient_occlusion(device, ambient_occlusion);
+
+        Ok(frame)
+    }
+
+    /// Points the frame at a new ambient occlusion map, which is sampled in `GENERAL` layout
+    pub fn set_ambient_occlusion(&self, device: &Device, view: vk::ImageView) {
+        write_image_descriptor(
+            device,
+            self.descriptor_set,
+            AMBIENT_OCCLUSION_BINDING,
+            vk::DescriptorType::SAMPLED_IMAGE,
+            view,
+            vk::ImageLayout::GENERAL,
+            vk::Sampler::null(),
+        );
+    }
+
+    /// Writes this frame's data, the GPU must have finished the previous
+    /// frame that used this `FrameData`
+    pub fn upload(
+        &mut self,
+        allocator: &mut Allocator,
+        device: &Device,
+        deletion_queue: &mut DeletionQueue,
+        frame_number: u64,
+        contents: &FrameContents,
+    ) {
+        let set = self.descriptor_set;
+
+        self.global_buffer
+            .write(0, std::slice::from_ref(&contents.globals));
+        self.instances.upload(
+            allocator,
+            device,
+            deletion_queue,
+            frame_number,
+            set,
+            INSTANCES_BINDING,
+            contents.instances,
+        );
+        self.materials.upload(
+            allocator,
+            device,
+            deletion_queue,
+            frame_number,
+            set,
+            MATERIALS_BINDING,
+            contents.materials,
+        );
+        self.lights.upload(
+            allocator,
+            device,
+            deletion_queue,
+            frame_number,
+            set,
+            LIGHTS_BINDING,
+            contents.lights,
+        );
+        self.shadows.upload(
+            allocator,
+            device,
+            deletion_queue,
+            frame_number,
+            set,
+            SHADOWS_BINDING,
+            contents.shadows,
+        );
+    }
+
+    pub fn destroy(&mut self, device: &Device, allocator: &mut Allocator) {
+        self.global_buffer.destroy(device, allocator);
+        self.instances.buffer.destroy(device, allocator);
+        self.materials.buffer.destroy(device, allocator);
+        self.lights.buffer.destroy(device, allocator);
+        self.shadows.buffer.destroy(device, allocator);
+    }
+}
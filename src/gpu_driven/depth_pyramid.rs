@@ -0,0 +1,312 @@
+use crate::backend_vulkan::{
+    descriptor::{write_image_descriptor, DescriptorPool, DescriptorSetLayoutBuilder},
+    device::Device,
+    image::{Image, ImageDesc},
+    pipeline::{compute_write_barrier, ComputePipeline},
+    shader::{ShaderLanguage, ShaderSource, ShaderStage},
+};
+use anyhow::Result;
+use ash::vk;
+use gpu_allocator::vulkan::Allocator;
+
+/// Nearest depth in `r`, farthest in `g`
+pub const DEPTH_PYRAMID_FORMAT: vk::Format = vk::Format::R32G32_SFLOAT;
+
+/// Workgroup size of the reduction shader in both dimensions
+const GROUP_SIZE: u32 = 8;
+
+/// Enough for depth buffers up to 65536 pixels wide
+const MAX_LEVELS: u32 = 16;
+
+const DEPTH_BINDING: u32 = 0;
+const SOURCE_BINDING: u32 = 1;
+const DESTINATION_BINDING: u32 = 2;
+
+/// Mip chain of the depth buffer reduced to its minimum and maximum, every level halves
+/// the previous one rounding up, with level 0 at half the depth buffer's resolution.
+/// The image stays in `GENERAL` layout.
+pub struct DepthPyramid {
+    pub image: Image,
+    mip_views: Vec<vk::ImageView>,
+    layout: vk::DescriptorSetLayout,
+    descriptor_pool: DescriptorPool,
+    /// One per level, writing it and reading the previous one
+    sets: Vec<vk::DescriptorSet>,
+    depth_pipeline: ComputePipeline,
+    reduce_pipeline: ComputePipeline,
+    /// Whether the image has left `UNDEFINED`
+    initialized: bool,
+    built: bool,
+}
+
+impl DepthPyramid {
+    pub fn new(allocator: &mut Allocator, device: &Device, depth: &Image) -> Result<Self> {
+        let layout = DescriptorSetLayoutBuilder::default()
+            .binding(
+                DEPTH_BINDING,
+                vk::DescriptorType::SAMPLED_IMAGE,
+                vk::ShaderStageFlags::COMPUTE,
+            )
+            .binding(
+                SOURCE_BINDING,
+                vk::DescriptorType::SAMPLED_IMAGE,
+                vk::ShaderStageFlags::COMPUTE,
+            )
+            .binding(
+                DESTINATION_BINDING,
+                vk::DescriptorType::STORAGE_IMAGE,
+                vk::ShaderStageFlags::COMPUTE,
+            )
+            .build(device)?;
+
+        let pipeline = |entry: &str| {
+            let shader = ShaderSource::builder().entry(entry).build(
+                ShaderStage::Compute,
+                ShaderLanguage::WGSL,
+                "./src/shaders/depth_pyramid.wgsl",
+            );
+            ComputePipeline::new(device, &shader, &[layout], 0)
+        };
+        let depth_pipeline = pipeline("cs_reduce_depth")?;
+        let reduce_pipeline = pipeline("cs_reduce")?;
+
+        let descriptor_pool = DescriptorPool::new(
+            device,
+            MAX_LEVELS,
+            &[
+                vk::DescriptorPoolSize {
+                    ty: vk::DescriptorType::SAMPLED_IMAGE,
+                    descriptor_count: MAX_LEVELS,
+                },
+                vk::DescriptorPoolSize {
+                    ty: vk::DescriptorType::STORAGE_IMAGE,
+                    descriptor_count: MAX_LEVELS,
+                },
+            ],
+        )?;
+        let sets = (0..MAX_LEVELS)
+            .map(|_| descriptor_pool.allocate(device, layout))
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut pyramid = DepthPyramid {
+            image: Self::create_image(allocator, device, depth),
+            mip_views: vec![],
+            layout,
+            descriptor_pool,
+            sets,
+            depth_pipeline,
+            reduce_pipeline,
+            initialized: false,
+            built: false,
+        };
+        pyramid.write_descriptors(device, depth);
+
+        Ok(pyramid)
+    }
+
+    fn create_image(allocator: &mut Allocator, device: &Device, depth: &Image) -> Image {
+        let depth_extent = depth.desc.extent_2d();
+        let extent = vk::Extent2D {
+            width: depth_extent.width.div_ceil(2),
+            height: depth_extent.height.div_ceil(2),
+        };
+        let mip_levels = u32::BITS - extent.width.max(extent.height).leading_zeros();
+
+        let desc = ImageDesc::new_2d(
+            DEPTH_PYRAMID_FORMAT,
+            extent,
+            vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::STORAGE,
+        )
+        .mip_levels(mip_levels.min(MAX_LEVELS));
+        Image::new(allocator, device, desc, "depth pyramid")
+    }
+
+    fn write_descriptors(&mut self, device: &Device, depth: &Image) {
+        self.mip_views = (0..self.image.desc.mip_levels)
+            .map(|mip| self.image.create_mip_view(device, mip))
+            .collect();
+
+        for (level, &view) in self.mip_views.iter().enumerate() {
+            let set = self.sets[level];
+
+            // level 0 reduces the depth buffer, every other level the one before it
+            if level == 0 {
+                write_image_descriptor(
+                    device,
+                    set,
+                    DEPTH_BINDING,
+                    vk::DescriptorType::SAMPLED_IMAGE,
+                    depth.view,
+                    vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                    vk::Sampler::null(),
+                );
+            } else {
+                write_image_descriptor(
+                    device,
+                    set,
+                    SOURCE_BINDING,
+                    vk::DescriptorType::SAMPLED_IMAGE,
+                    self.mip_views[level - 1],
+                    vk::ImageLayout::GENERAL,
+                    vk::Sampler::null(),
+                );
+            }
+            write_image_descriptor(
+                device,
+                set,
+                DESTINATION_BINDING,
+                vk::DescriptorType::STORAGE_IMAGE,
+                view,
+                vk::ImageLayout::GENERAL,
+                vk::Sampler::null(),
+            );
+        }
+    }
+
+    /// Whether the pyramid holds the depth of an earlier frame
+    pub fn is_built(&self) -> bool {
+        self.built
+    }
+
+    /// Moves the image out of `UNDEFINED` the first time, so it can be bound before it is built
+    pub fn prepare(&mut self, device: &Device, cmd: vk::CommandBuffer) {
+        if self.initialized {
+            return;
+        }
+        self.initialized = true;
+
+        let barrier = vk::ImageMemoryBarrier::builder()
+            .old_layout(vk::ImageLayout::UNDEFINED)
+            .new_layout(vk::ImageLayout::GENERAL)
+            .dst_access_mask(vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE)
+            .image(self.image.raw)
+            .subresource_range(self.image.desc.subresource_range());
+
+        unsafe {
+            device.raw.cmd_pipeline_barrier(
+                cmd,
+                vk::PipelineStageFlags::TOP_OF_PIPE,
+                vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[barrier.build()],
+            );
+        }
+    }
+
+    /// Reduces `depth`, which must be in `DEPTH_ATTACHMENT_OPTIMAL` and is left there
+    pub fn record(&mut self, device: &Device, cmd: vk::CommandBuffer, depth: &Image) {
+        self.prepare(device, cmd);
+        self.built = true;
+
+        let to_shader_read = vk::ImageMemoryBarrier::builder()
+            .old_layout(vk::ImageLayout::DEPTH_ATTACHMENT_OPTIMAL)
+            .new_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            .src_access_mask(vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE)
+            .dst_access_mask(vk::AccessFlags::SHADER_READ)
+            .image(depth.raw)
+            .subresource_range(depth.desc.subresource_range());
+
+        unsafe {
+            // the culling pass may still be reading the previous pyramid
+            device.raw.cmd_pipeline_barrier(
+                cmd,
+                vk::PipelineStageFlags::LATE_FRAGMENT_TESTS
+                    | vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[to_shader_read.build()],
+            );
+        }
+
+        let mut extent = self.image.desc.extent_2d();
+        for (level, &set) in self.sets[..self.mip_views.len()].iter().enumerate() {
+            let pipeline = if level == 0 {
+                &self.depth_pipeline
+            } else {
+                compute_write_barrier(device, cmd);
+                &self.reduce_pipeline
+            };
+
+            unsafe {
+                device.raw.cmd_bind_pipeline(
+                    cmd,
+                    vk::PipelineBindPoint::COMPUTE,
+                    pipeline.pipeline,
+                );
+                device.raw.cmd_bind_descriptor_sets(
+                    cmd,
+                    vk::PipelineBindPoint::COMPUTE,
+                    pipeline.layout,
+                    0,
+                    &[set],
+                    &[],
+                );
+                device.raw.cmd_dispatch(
+                    cmd,
+                    extent.width.div_ceil(GROUP_SIZE),
+                    extent.height.div_ceil(GROUP_SIZE),
+                    1,
+                );
+            }
+
+            extent = vk::Extent2D {
+                width: extent.width.div_ceil(2),
+                height: extent.height.div_ceil(2),
+            };
+        }
+
+        let to_attachment = vk::ImageMemoryBarrier::builder()
+            .old_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            .new_layout(vk::ImageLayout::DEPTH_ATTACHMENT_OPTIMAL)
+            .dst_access_mask(
+                vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ
+                    | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+            )
+            .image(depth.raw)
+            .subresource_range(depth.desc.subresource_range());
+        let pyramid_barrier = vk::MemoryBarrier::builder()
+            .src_access_mask(vk::AccessFlags::SHADER_WRITE)
+            .dst_access_mask(vk::AccessFlags::SHADER_READ);
+
+        unsafe {
+            device.raw.cmd_pipeline_barrier(
+                cmd,
+                vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::PipelineStageFlags::COMPUTE_SHADER
+                    | vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS,
+                vk::DependencyFlags::empty(),
+                &[pyramid_barrier.build()],
+                &[],
+                &[to_attachment.build()],
+            );
+        }
+    }
+
+    /// Recreates the pyramid for the new `depth` image, the device must be idle
+    pub fn resize(&mut self, allocator: &mut Allocator, device: &Device, depth: &Image) {
+        self.destroy_image(device, allocator);
+        self.image = Self::create_image(allocator, device, depth);
+        self.write_descriptors(device, depth);
+        self.initialized = false;
+        self.built = false;
+    }
+
+    fn destroy_image(&mut self, device: &Device, allocator: &mut Allocator) {
+        for view in self.mip_views.drain(..) {
+            unsafe { device.raw.destroy_image_view(view, None) };
+        }
+        self.image.destroy(device, allocator);
+    }
+
+    pub fn destroy(&mut self, device: &Device, allocator: &mut Allocator) {
+        self.destroy_image(device, allocator);
+        self.descriptor_pool.destroy(device);
+        self.depth_pipeline.destroy(device);
+        self.reduce_pipeline.destroy(device);
+        unsafe { device.raw.destroy_descriptor_set_layout(self.layout, None) };
+    }
+}
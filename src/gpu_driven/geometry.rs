@@ -0,0 +1,342 @@
+use crate::{
+    backend_vulkan::{
+        buffer::Buffer,
+        device::Device,
+        mesh::{Mesh, Meshlet, Vertex},
+    },
+    resource::{DeletionQueue, MeshHandle},
+};
+use ash::vk;
+use gpu_allocator::vulkan::Allocator;
+use std::{collections::HashMap, mem::size_of, ops::Range};
+
+/// Where a mesh lives in the [`GeometryArena`], in elements of each buffer
+#[derive(Clone, Copy, Debug)]
+pub struct GeometryRange {
+    pub first_vertex: u32,
+    pub vertex_count: u32,
+    pub first_index: u32,
+    pub index_count: u32,
+    pub first_meshlet: u32,
+    pub meshlet_count: u32,
+    /// The offsets of the meshlets in the arena already include these
+    pub first_meshlet_vertex: u32,
+    pub meshlet_vertex_count: u32,
+    pub first_meshlet_triangle: u32,
+    pub meshlet_triangle_count: u32,
+}
+
+/// First-fit allocator of element ranges
+#[derive(Debug, Default)]
+struct RangeAllocator {
+    /// Sorted and never adjacent
+    free: Vec<Range<u32>>,
+    /// Everything past it is free
+    end: u32,
+}
+
+impl RangeAllocator {
+    /// Returns `None` when `count` elements do not fit below `capacity`
+    fn allocate(&mut self, count: u32, capacity: u32) -> Option<u32> {
+        if let Some(i) = self
+            .free
+            .iter()
+            .position(|range| range.len() >= count as usize)
+        {
+            let start = self.free[i].start;
+            self.free[i].start += count;
+            if self.free[i].is_empty() {
+                self.free.remove(i);
+            }
+            return Some(start);
+        }
+
+        (self.end + count <= capacity).then(|| {
+            self.end += count;
+            self.end - count
+        })
+    }
+
+    fn free(&mut self, range: Range<u32>) {
+        if range.is_empty() {
+            return;
+        }
+
+        let i = self.free.partition_point(|free| free.start < range.start);
+        self.free.insert(i, range);
+
+        // merge with the neighbours, and give the tail back to `end`
+        if i + 1 < self.free.len() && self.free[i].end == self.free[i + 1].start {
+            self.free[i].end = self.free.remove(i + 1).end;
+        }
+        if i > 0 && self.free[i - 1].end == self.free[i].start {
+            self.free[i - 1].end = self.free.remove(i).end;
+        }
+        if self.free.last().is_some_and(|last| last.end == self.end) {
+            self.end = self.free.pop().unwrap().start;
+        }
+    }
+}
+
+/// A growable buffer suballocated by a [`RangeAllocator`]
+struct ArenaBuffer {
+    buffer: Buffer,
+    capacity: u32,
+    ranges: RangeAllocator,
+    usage: vk::BufferUsageFlags,
+    stride: usize,
+    name: &'static str,
+}
+
+impl ArenaBuffer {
+    fn new<T>(
+        allocator: &mut Allocator,
+        device: &Device,
+        capacity: u32,
+        usage: vk::BufferUsageFlags,
+        name: &'static str,
+    ) -> Self {
+        let stride = size_of::<T>();
+        ArenaBuffer {
+            buffer: Buffer::new(allocator, device, capacity as usize * stride, usage, name),
+            capacity,
+            ranges: RangeAllocator::default(),
+            usage,
+            stride,
+            name,
+        }
+    }
+
+    /// Copies `data` into a free range, growing the buffer when there is none
+    fn insert<T: Copy>(
+        &mut self,
+        allocator: &mut Allocator,
+        device: &Device,
+        deletion_queue: &mut DeletionQueue,
+        frame_number: u64,
+        data: &[T],
+    ) -> u32 {
+        let count = data.len() as u32;
+        let start = match self.ranges.allocate(count, self.capacity) {
+            Some(start) => start,
+            None => {
+                // earlier frames keep drawing from the old buffer until they finish
+                self.capacity = (self.ranges.end + count).next_power_of_two();
+                let mut buffer = Buffer::new(
+                    allocator,
+                    device,
+                    self.capacity as usize * self.stride,
+                    self.usage,
+                    self.name,
+                );
+                buffer.copy_from(&self.buffer, self.ranges.end as usize * self.stride);
+                deletion_queue.push(frame_number, std::mem::replace(&mut self.buffer, buffer));
+                self.ranges.allocate(count, self.capacity).unwrap()
+            }
+        };
+
+        self.buffer.write(start as usize, data);
+        start
+    }
+}
+
+/// The vertices, indices and meshlets of the meshes drawn by the GPU-driven path in a single
+/// set of buffers, so any of them can be drawn without binding other buffers. Removed ranges
+/// are only reused once the frames that may still draw them have finished.
+pub struct GeometryArena {
+    vertices: ArenaBuffer,
+    indices: ArenaBuffer,
+    meshlets: ArenaBuffer,
+    meshlet_vertices: ArenaBuffer,
+    meshlet_triangles: ArenaBuffer,
+    meshes: HashMap<MeshHandle, GeometryRange>,
+    /// Freed ranges and the first frame no longer using them
+    pending: Vec<(u64, GeometryRange)>,
+}
+
+impl GeometryArena {
+    pub fn new(allocator: &mut Allocator, device: &Device) -> Self {
+        GeometryArena {
+            // the meshlet culling pass reads vertex positions
+            vertices: ArenaBuffer::new::<Vertex>(
+                allocator,
+                device,
+                1 << 16,
+                vk::BufferUsageFlags::VERTEX_BUFFER | vk::BufferUsageFlags::STORAGE_BUFFER,
+                "arena vertices",
+            ),
+            indices: ArenaBuffer::new::<u32>(
+                allocator,
+                device,
+                1 << 18,
+                vk::BufferUsageFlags::INDEX_BUFFER,
+                "arena indices",
+            ),
+            meshlets: ArenaBuffer::new::<Meshlet>(
+                allocator,
+                device,
+                1 << 10,
+                vk::BufferUsageFlags::STORAGE_BUFFER,
+                "arena meshlets",
+            ),
+            meshlet_vertices: ArenaBuffer::new::<u32>(
+                allocator,
+                device,
+                1 << 16,
+                vk::BufferUsageFlags::STORAGE_BUFFER,
+                "arena meshlet vertices",
+            ),
+            meshlet_triangles: ArenaBuffer::new::<u32>(
+                allocator,
+                device,
+                1 << 17,
+                vk::BufferUsageFlags::STORAGE_BUFFER,
+                "arena meshlet triangles",
+            ),
+            meshes: HashMap::new(),
+            pending: vec![],
+        }
+    }
+
+    /// Copies the geometry of `mesh` from its own buffers, replacing what it had before
+    pub fn insert(
+        &mut self,
+        allocator: &mut Allocator,
+        device: &Device,
+        deletion_queue: &mut DeletionQueue,
+        frame_number: u64,
+        handle: MeshHandle,
+        mesh: &Mesh,
+    ) {
+        self.remove(handle, frame_number);
+
+        let vertices = mesh
+            .vertex_buffer
+            .read_slice::<Vertex>(mesh.vertex_count as usize);
+        let first_vertex =
+            self.vertices
+                .insert(allocator, device, deletion_queue, frame_number, &vertices);
+        let index_count = mesh
+            .lods
+            .last()
+            .map_or(0, |lod| lod.first_index + lod.index_count);
+        let indices = mesh.index_buffer.read_slice::<u32>(index_count as usize);
+        let first_index =
+            self.indices
+                .insert(allocator, device, deletion_queue, frame_number, &indices);
+
+        let first_meshlet_vertex = self.meshlet_vertices.insert(
+            allocator,
+            device,
+            deletion_queue,
+            frame_number,
+            &mesh.meshlet_vertices,
+        );
+        let first_meshlet_triangle = self.meshlet_triangles.insert(
+            allocator,
+            device,
+            deletion_queue,
+            frame_number,
+            &mesh.meshlet_triangles,
+        );
+        let meshlets = mesh
+            .meshlets
+            .iter()
+            .map(|meshlet| Meshlet {
+                first_vertex: meshlet.first_vertex + first_meshlet_vertex,
+                first_triangle: meshlet.first_triangle + first_meshlet_triangle,
+                ..*meshlet
+            })
+            .collect::<Vec<_>>();
+        let first_meshlet =
+            self.meshlets
+                .insert(allocator, device, deletion_queue, frame_number, &meshlets);
+
+        self.meshes.insert(
+            handle,
+            GeometryRange {
+                first_vertex,
+                vertex_count: vertices.len() as u32,
+                first_index,
+                index_count: indices.len() as u32,
+                first_meshlet,
+                meshlet_count: meshlets.len() as u32,
+                first_meshlet_vertex,
+                meshlet_vertex_count: mesh.meshlet_vertices.len() as u32,
+                first_meshlet_triangle,
+                meshlet_triangle_count: mesh.meshlet_triangles.len() as u32,
+            },
+        );
+    }
+
+    /// `frame_number` is the first frame that no longer draws the mesh
+    pub fn remove(&mut self, mesh: MeshHandle, frame_number: u64) {
+        if let Some(range) = self.meshes.remove(&mesh) {
+            self.pending.push((frame_number, range));
+        }
+    }
+
+    /// Frees the ranges removed at or before `first_pending_frame`, which must be
+    /// the oldest frame the GPU has not finished yet
+    pub fn collect(&mut self, first_pending_frame: u64) {
+        self.pending.retain(|(frame, range)| {
+            if *frame > first_pending_frame {
+                return true;
+            }
+            let buffers = [
+                (&mut self.vertices, range.first_vertex, range.vertex_count),
+                (&mut self.indices, range.first_index, range.index_count),
+                (&mut self.meshlets, range.first_meshlet, range.meshlet_count),
+                (
+                    &mut self.meshlet_vertices,
+                    range.first_meshlet_vertex,
+                    range.meshlet_vertex_count,
+                ),
+                (
+                    &mut self.meshlet_triangles,
+                    range.first_meshlet_triangle,
+                    range.meshlet_triangle_count,
+                ),
+            ];
+            for (buffer, first, count) in buffers {
+                buffer.ranges.free(first..first + count);
+            }
+            false
+        });
+    }
+
+    pub fn get(&self, mesh: MeshHandle) -> Option<&GeometryRange> {
+        self.meshes.get(&mesh)
+    }
+
+    pub fn vertex_buffer(&self) -> vk::Buffer {
+        self.vertices.buffer.raw
+    }
+
+    pub fn index_buffer(&self) -> vk::Buffer {
+        self.indices.buffer.raw
+    }
+
+    /// The vertex, meshlet, meshlet vertex and meshlet triangle buffers read when culling
+    /// meshlets, they are replaced whenever they grow
+    pub(crate) fn meshlet_buffers(&self) -> [&Buffer; 4] {
+        [
+            &self.vertices.buffer,
+            &self.meshlets.buffer,
+            &self.meshlet_vertices.buffer,
+            &self.meshlet_triangles.buffer,
+        ]
+    }
+
+    pub fn destroy(&mut self, device: &Device, allocator: &mut Allocator) {
+        for buffer in [
+            &mut self.vertices,
+            &mut self.indices,
+            &mut self.meshlets,
+            &mut self.meshlet_vertices,
+            &mut self.meshlet_triangles,
+        ] {
+            buffer.buffer.destroy(device, allocator);
+        }
+    }
+}
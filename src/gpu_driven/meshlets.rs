@@ -0,0 +1,527 @@
+use super::{geometry::GeometryArena, pass::IndirectDraws};
+use crate::{
+    backend_vulkan::{
+        buffer::Buffer,
+        descriptor::{write_buffer_descriptor, DescriptorPool, DescriptorSetLayoutBuilder},
+        device::{Device, FRAMES_IN_FLIGHT},
+        pipeline::{ComputePipeline, GraphicsPipeline},
+        shader::{ShaderLanguage, ShaderSource, ShaderStage},
+    },
+    culling::Frustum,
+    deferred::GBUFFER_FORMATS,
+    frame::StorageArray,
+    resource::DeletionQueue,
+    HDR_FORMAT,
+};
+use anyhow::Result;
+use ash::vk;
+use glam::Vec4;
+use gpu_allocator::vulkan::Allocator;
+use std::mem::size_of;
+
+const TASKS_BINDING: u32 = 0;
+const MESHLETS_BINDING: u32 = 1;
+const MESHLET_VERTICES_BINDING: u32 = 2;
+const MESHLET_TRIANGLES_BINDING: u32 = 3;
+const VERTICES_BINDING: u32 = 4;
+const INDICES_BINDING: u32 = 5;
+const COMMANDS_BINDING: u32 = 6;
+const COUNTS_BINDING: u32 = 7;
+const BINDING_COUNT: u32 = 8;
+
+/// Most workgroups dispatched along x, the smallest `maxComputeWorkGroupCount` allowed
+const MAX_GROUPS_X: u32 = 65535;
+/// Most task shader workgroups along x, the smallest `maxTaskWorkGroupCount` allowed
+const MAX_TASK_GROUPS_X: u32 = 65535;
+/// Tasks culled by each task shader workgroup, its `LocalSize`
+const TASK_GROUP_SIZE: u32 = 32;
+
+/// A meshlet of an instance to cull, matches `MeshletTask` in `meshlets.wgsl`
+#[derive(Clone, Copy, Debug, Default)]
+#[repr(C)]
+pub(crate) struct MeshletTask {
+    /// Index into the frame's instance buffer
+    pub(crate) instance: u32,
+    /// Index into the meshlets of the [`GeometryArena`]
+    pub(crate) meshlet: u32,
+    /// First vertex of the meshlet's mesh in the [`GeometryArena`]
+    pub(crate) vertex_offset: u32,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+#[repr(C)]
+struct MeshletPushConstants {
+    planes: [Vec4; 6],
+    task_count: u32,
+    backface: u32,
+    group_count_x: u32,
+    _padding: u32,
+}
+
+/// Pass a [`DrawList`](crate::DrawList) is recorded in, which decides the fragment shader
+/// meshlets drawn by mesh shaders use
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum MeshletTarget {
+    /// `fs_main` into the HDR target
+    Forward,
+    /// `fs_gbuffer` into the gbuffer
+    GBuffer,
+    /// `fs_depth` into the depth buffer alone
+    Depth,
+}
+
+/// How the meshlets of a frame are drawn
+pub(crate) enum MeshletDraws {
+    /// The triangles [`MeshletCullingPass::record`] keeps, one indirect draw per meshlet
+    Indirect(IndirectDraws),
+    /// Meshlets culled and drawn by the task and mesh shaders
+    MeshShader(MeshShaderDraws),
+}
+
+/// Everything needed to draw the meshlets of a frame with task and mesh shaders
+pub(crate) struct MeshShaderDraws {
+    /// Indexed by [`MeshletTarget`]
+    pipelines: [vk::Pipeline; 3],
+    layout: vk::PipelineLayout,
+    push_constant_stages: vk::ShaderStageFlags,
+    /// The frame's descriptor set and the meshlet one
+    descriptor_sets: [vk::DescriptorSet; 2],
+    constants: MeshletPushConstants,
+}
+
+impl MeshShaderDraws {
+    /// Binds the pipeline of `target` and its descriptor sets, the caller has to rebind
+    /// its own before drawing anything else
+    pub(crate) fn record(&self, device: &Device, cmd: vk::CommandBuffer, target: MeshletTarget) {
+        let Some(mesh_shader) = &device.mesh_shader else {
+            return;
+        };
+        let groups = self.constants.task_count.div_ceil(TASK_GROUP_SIZE);
+        if groups == 0 {
+            return;
+        }
+
+        unsafe {
+            device.raw.cmd_bind_pipeline(
+                cmd,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipelines[target as usize],
+            );
+            device.raw.cmd_bind_descriptor_sets(
+                cmd,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.layout,
+                0,
+                &self.descriptor_sets,
+                &[],
+            );
+            device.raw.cmd_push_constants(
+                cmd,
+                self.layout,
+                self.push_constant_stages,
+                0,
+                std::slice::from_raw_parts(
+                    &self.constants as *const MeshletPushConstants as *const u8,
+                    size_of::<MeshletPushConstants>(),
+                ),
+            );
+            mesh_shader.cmd_draw_mesh_tasks(
+                cmd,
+                self.constants.group_count_x,
+                groups.div_ceil(self.constants.group_count_x),
+                1,
+            );
+        }
+    }
+}
+
+/// Buffers owned by a single frame in flight, they grow to fit all of its tasks
+struct MeshletFrame {
+    tasks: StorageArray<MeshletTask>,
+    /// The visible triangles, with room for all of them
+    indices: StorageArray<u32>,
+    /// One draw per meshlet with visible triangles
+    commands: StorageArray<vk::DrawIndexedIndirectCommand>,
+    /// Number of commands and of indices written, read back once the frame has finished
+    counts: Buffer,
+    descriptor_set: vk::DescriptorSet,
+    constants: MeshletPushConstants,
+    /// Whether `counts` holds the result of the frame's last submission
+    counted: bool,
+}
+
+/// Compute pass culling the meshlets of instances against the view frustum and, for
+/// meshes only seen from the outside, their normal cones, then the triangles of the
+/// remaining meshlets the same way. The visible triangles are written to an index buffer
+/// drawn with one indirect draw per meshlet.
+///
+/// With `VK_EXT_mesh_shader` the meshlets are instead culled by a task shader and drawn
+/// by a mesh shader, without the index buffer or the triangle culling. naga has no mesh
+/// shader stages, so `build.rs` assembles these two from the `.spvasm` files next to
+/// `meshlets.wgsl`.
+pub struct MeshletCullingPass {
+    layout: vk::DescriptorSetLayout,
+    descriptor_pool: DescriptorPool,
+    pipeline: ComputePipeline,
+    /// Indexed by [`MeshletTarget`], only created when mesh shaders are supported
+    mesh_pipelines: Option<[GraphicsPipeline; 3]>,
+    frames: Vec<MeshletFrame>,
+}
+
+impl MeshletCullingPass {
+    pub fn new(
+        allocator: &mut Allocator,
+        device: &Device,
+        frame_descriptor_layout: vk::DescriptorSetLayout,
+        depth_format: vk::Format,
+    ) -> Result<Self> {
+        let stages = if device.mesh_shader.is_some() {
+            vk::ShaderStageFlags::COMPUTE
+                | vk::ShaderStageFlags::TASK_EXT
+                | vk::ShaderStageFlags::MESH_EXT
+        } else {
+            vk::ShaderStageFlags::COMPUTE
+        };
+        let layout = (0..BINDING_COUNT)
+            .fold(DescriptorSetLayoutBuilder::default(), |builder, binding| {
+                builder.binding(binding, vk::DescriptorType::STORAGE_BUFFER, stages)
+            })
+            .build(device)?;
+
+        let descriptor_pool = DescriptorPool::new(
+            device,
+            FRAMES_IN_FLIGHT as u32,
+            &[vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::STORAGE_BUFFER,
+                descriptor_count: BINDING_COUNT * FRAMES_IN_FLIGHT as u32,
+            }],
+        )?;
+
+        let shader = ShaderSource::builder().entry("cs_cull_meshlets").build(
+            ShaderStage::Compute,
+            ShaderLanguage::WGSL,
+            "./src/shaders/meshlets.wgsl",
+        );
+        let pipeline = ComputePipeline::new(
+            device,
+            &shader,
+            &[frame_descriptor_layout, layout],
+            size_of::<MeshletPushConstants>(),
+        )?;
+
+        let mesh_pipelines = match device.mesh_shader {
+            Some(_) => {
+                log::info!("VK_EXT_mesh_shader is supported, meshlets are drawn by mesh shaders");
+                let shader = |stage, entry: &str, path: &str| {
+                    let language = if stage == ShaderStage::Fragment {
+                        ShaderLanguage::WGSL
+                    } else {
+                        ShaderLanguage::SPIRV
+                    };
+                    ShaderSource::builder()
+                        .entry(entry)
+                        .build(stage, language, path)
+                };
+                let pipeline = |color_formats: &[vk::Format], fragment_entry: &str| {
+                    GraphicsPipeline::builder()
+                        .color_formats(color_formats)
+                        .depth_format(depth_format)
+                        .descriptor_set_layouts(&[frame_descriptor_layout, layout])
+                        .push_constant_size(size_of::<MeshletPushConstants>())
+                        .build(
+                            device,
+                            &[
+                                shader(
+                                    ShaderStage::Task,
+                                    "ts_meshlets",
+                                    concat!(env!("OUT_DIR"), "/meshlets_task.spv"),
+                                ),
+                                shader(
+                                    ShaderStage::Mesh,
+                                    "ms_meshlets",
+                                    concat!(env!("OUT_DIR"), "/meshlets_mesh.spv"),
+                                ),
+                                shader(
+                                    ShaderStage::Fragment,
+                                    fragment_entry,
+                                    "./src/shaders/shader_new.wgsl",
+                                ),
+                            ],
+                        )
+                };
+                Some([
+                    pipeline(&[HDR_FORMAT], "fs_main")?,
+                    pipeline(&GBUFFER_FORMATS, "fs_gbuffer")?,
+                    pipeline(&[], "fs_depth")?,
+                ])
+            }
+            None => None,
+        };
+
+        let frames = (0..FRAMES_IN_FLIGHT)
+            .map(|_| {
+                let frame = MeshletFrame {
+                    tasks: StorageArray::new(allocator, device, 1024, "meshlet tasks"),
+                    indices: StorageArray::with_usage(
+                        allocator,
+                        device,
+                        1 << 16,
+                        vk::BufferUsageFlags::INDEX_BUFFER,
+                        "meshlet indices",
+                    ),
+                    commands: StorageArray::with_usage(
+                        allocator,
+                        device,
+                        1024,
+                        vk::BufferUsageFlags::INDIRECT_BUFFER,
+                        "meshlet draws",
+                    ),
+                    counts: Buffer::new(
+                        allocator,
+                        device,
+                        2 * size_of::<u32>(),
+                        vk::BufferUsageFlags::STORAGE_BUFFER
+                            | vk::BufferUsageFlags::INDIRECT_BUFFER
+                            | vk::BufferUsageFlags::TRANSFER_DST,
+                        "meshlet draw counts",
+                    ),
+                    descriptor_set: descriptor_pool.allocate(device, layout)?,
+                    constants: MeshletPushConstants::default(),
+                    counted: false,
+                };
+
+                for (binding, buffer) in [
+                    (TASKS_BINDING, &frame.tasks.buffer),
+                    (INDICES_BINDING, &frame.indices.buffer),
+                    (COMMANDS_BINDING, &frame.commands.buffer),
+                    (COUNTS_BINDING, &frame.counts),
+                ] {
+                    write_buffer_descriptor(
+                        device,
+                        frame.descriptor_set,
+                        binding,
+                        vk::DescriptorType::STORAGE_BUFFER,
+                        buffer,
+                    );
+                }
+
+                Ok(frame)
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(MeshletCullingPass {
+            layout,
+            descriptor_pool,
+            pipeline,
+            mesh_pipelines,
+            frames,
+        })
+    }
+
+    /// Meshlets rejected the last time frame `frame_index` was drawn, `None` when that
+    /// frame culled none or did so in task shaders, which count nothing. Must be called
+    /// before uploading the frame again.
+    pub fn last_culled(&self, frame_index: usize) -> Option<u32> {
+        let frame = &self.frames[frame_index];
+        frame.counted.then(|| {
+            let [draws, _indices]: [u32; 2] = frame.counts.read(0);
+            frame.constants.task_count - draws
+        })
+    }
+
+    /// Uploads the meshlets of frame `frame_index` and returns how to draw them, either the
+    /// draws the culling pass will produce for them or the mesh shaders culling them
+    /// as they are drawn. `triangle_count` is the sum of the triangles of every task.
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn upload(
+        &mut self,
+        allocator: &mut Allocator,
+        device: &Device,
+        deletion_queue: &mut DeletionQueue,
+        frame_number: u64,
+        frame_index: usize,
+        frame_descriptor_set: vk::DescriptorSet,
+        arena: &GeometryArena,
+        frustum: &Frustum,
+        backface: bool,
+        tasks: &[MeshletTask],
+        triangle_count: usize,
+    ) -> MeshletDraws {
+        let frame = &mut self.frames[frame_index];
+        let set = frame.descriptor_set;
+
+        frame.tasks.upload(
+            allocator,
+            device,
+            deletion_queue,
+            frame_number,
+            set,
+            TASKS_BINDING,
+            tasks,
+        );
+        let [vertices, meshlets, meshlet_vertices, meshlet_triangles] = arena.meshlet_buffers();
+        for (binding, buffer) in [
+            (VERTICES_BINDING, vertices),
+            (MESHLETS_BINDING, meshlets),
+            (MESHLET_VERTICES_BINDING, meshlet_vertices),
+            (MESHLET_TRIANGLES_BINDING, meshlet_triangles),
+        ] {
+            // the arena replaces its buffers when they grow
+            write_buffer_descriptor(
+                device,
+                set,
+                binding,
+                vk::DescriptorType::STORAGE_BUFFER,
+                buffer,
+            );
+        }
+
+        if let Some(pipelines) = &self.mesh_pipelines {
+            let groups = (tasks.len() as u32).div_ceil(TASK_GROUP_SIZE);
+            frame.constants = MeshletPushConstants {
+                planes: frustum.planes,
+                task_count: tasks.len() as u32,
+                backface: backface as u32,
+                group_count_x: groups.clamp(1, MAX_TASK_GROUPS_X),
+                _padding: 0,
+            };
+            return MeshletDraws::MeshShader(MeshShaderDraws {
+                pipelines: pipelines.each_ref().map(|pipeline| pipeline.pipeline),
+                layout: pipelines[0].layout,
+                push_constant_stages: pipelines[0].push_constant_stages,
+                descriptor_sets: [frame_descriptor_set, set],
+                constants: frame.constants,
+            });
+        }
+
+        frame.indices.reserve(
+            allocator,
+            device,
+            deletion_queue,
+            frame_number,
+            set,
+            INDICES_BINDING,
+            3 * triangle_count,
+        );
+        frame.commands.reserve(
+            allocator,
+            device,
+            deletion_queue,
+            frame_number,
+            set,
+            COMMANDS_BINDING,
+            tasks.len(),
+        );
+
+        frame.constants = MeshletPushConstants {
+            planes: frustum.planes,
+            task_count: tasks.len() as u32,
+            backface: backface as u32,
+            group_count_x: 0,
+            _padding: 0,
+        };
+
+        MeshletDraws::Indirect(IndirectDraws {
+            vertex_buffer: arena.vertex_buffer(),
+            index_buffer: frame.indices.buffer.raw,
+            commands: frame.commands.buffer.raw,
+            counts: frame.counts.raw,
+            max_draw_count: tasks.len() as u32,
+            lists: 0..1,
+        })
+    }
+
+    /// Culls the meshlets uploaded for frame `frame_index`, their draws can be read by
+    /// indirect draws afterwards
+    pub fn record(
+        &mut self,
+        device: &Device,
+        cmd: vk::CommandBuffer,
+        frame_index: usize,
+        frame_descriptor_set: vk::DescriptorSet,
+    ) {
+        let frame = &mut self.frames[frame_index];
+        frame.counted = true;
+        let constants = frame.constants;
+
+        unsafe {
+            device
+                .raw
+                .cmd_fill_buffer(cmd, frame.counts.raw, 0, vk::WHOLE_SIZE, 0);
+
+            let barrier = vk::MemoryBarrier::builder()
+                .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+                .dst_access_mask(vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE);
+            device.raw.cmd_pipeline_barrier(
+                cmd,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::DependencyFlags::empty(),
+                &[barrier.build()],
+                &[],
+                &[],
+            );
+
+            device.raw.cmd_bind_pipeline(
+                cmd,
+                vk::PipelineBindPoint::COMPUTE,
+                self.pipeline.pipeline,
+            );
+            device.raw.cmd_bind_descriptor_sets(
+                cmd,
+                vk::PipelineBindPoint::COMPUTE,
+                self.pipeline.layout,
+                0,
+                &[frame_descriptor_set, frame.descriptor_set],
+                &[],
+            );
+        }
+
+        self.pipeline.push_constants(device, cmd, &constants);
+
+        // one workgroup per task, wrapping into rows past the dispatch limit
+        let groups_x = constants.task_count.min(MAX_GROUPS_X);
+        if groups_x > 0 {
+            unsafe {
+                device
+                    .raw
+                    .cmd_dispatch(cmd, groups_x, constants.task_count.div_ceil(groups_x), 1);
+            }
+        }
+
+        let barrier = vk::MemoryBarrier::builder()
+            .src_access_mask(vk::AccessFlags::SHADER_WRITE)
+            .dst_access_mask(vk::AccessFlags::INDIRECT_COMMAND_READ | vk::AccessFlags::INDEX_READ);
+        unsafe {
+            device.raw.cmd_pipeline_barrier(
+                cmd,
+                vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::PipelineStageFlags::DRAW_INDIRECT | vk::PipelineStageFlags::VERTEX_INPUT,
+                vk::DependencyFlags::empty(),
+                &[barrier.build()],
+                &[],
+                &[],
+            );
+        }
+    }
+
+    /// Forgets the counts of frames recorded without culling meshlets
+    pub fn skip(&mut self, frame_index: usize) {
+        self.frames[frame_index].counted = false;
+    }
+
+    pub fn destroy(&mut self, device: &Device, allocator: &mut Allocator) {
+        for frame in &mut self.frames {
+            frame.tasks.buffer.destroy(device, allocator);
+            frame.indices.buffer.destroy(device, allocator);
+            frame.commands.buffer.destroy(device, allocator);
+            frame.counts.destroy(device, allocator);
+        }
+        self.descriptor_pool.destroy(device);
+        self.pipeline.destroy(device);
+        for pipeline in self.mesh_pipelines.iter().flatten() {
+            pipeline.destroy(device);
+        }
+        unsafe { device.raw.destroy_descriptor_set_layout(self.layout, None) };
+    }
+}
@@ -0,0 +1,9 @@
+pub mod depth_pyramid;
+pub mod geometry;
+pub mod meshlets;
+pub mod pass;
+
+pub use depth_pyramid::DepthPyramid;
+pub use geometry::{GeometryArena, GeometryRange};
+pub use meshlets::MeshletCullingPass;
+pub use pass::{CullingResults, GpuCullingPass};
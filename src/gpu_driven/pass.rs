@@ -0,0 +1,627 @@
+use super::{
+    depth_pyramid::DepthPyramid,
+    geometry::{GeometryArena, GeometryRange},
+};
+use crate::{
+    backend_vulkan::{
+        buffer::Buffer,
+        descriptor::{
+            write_buffer_descriptor, write_image_descriptor, DescriptorPool,
+            DescriptorSetLayoutBuilder,
+        },
+        device::{Device, FRAMES_IN_FLIGHT},
+        image::Image,
+        mesh::{HasVertexInputDescription, Mesh, Vertex},
+        pipeline::{ComputePipeline, GraphicsPipeline},
+        shader::{ShaderLanguage, ShaderSource, ShaderStage},
+    },
+    culling::Frustum,
+    frame::StorageArray,
+    resource::DeletionQueue,
+    HDR_FORMAT,
+};
+use anyhow::Result;
+use ash::vk;
+use glam::{Vec3, Vec4};
+use gpu_allocator::vulkan::Allocator;
+use std::{mem::size_of, ops::Range};
+
+const OBJECTS_BINDING: u32 = 0;
+const MESHES_BINDING: u32 = 1;
+const COMMANDS_BINDING: u32 = 2;
+const COUNTS_BINDING: u32 = 3;
+const VISIBILITY_BINDING: u32 = 4;
+const PYRAMID_BINDING: u32 = 5;
+
+/// Command lists written by the culling shader, see `LIST_*` in `culling.wgsl`
+pub(crate) const VISIBLE_LIST: u32 = 0;
+/// Objects found visible by the second phase
+pub(crate) const DISOCCLUDED_LIST: u32 = 1;
+/// Objects hidden behind the depth pyramid, only drawn for debugging
+pub(crate) const OCCLUDED_LIST: u32 = 2;
+const LIST_COUNT: u32 = 3;
+
+/// Workgroup size of the culling shader
+const GROUP_SIZE: u32 = 64;
+
+/// An instance to cull, matches `GpuObject` in `culling.wgsl`
+#[derive(Clone, Copy, Debug, Default)]
+#[repr(C)]
+pub(crate) struct GpuObject {
+    /// Index into the frame's instance buffer
+    pub(crate) instance: u32,
+    /// Index into the meshes passed to [`GpuCullingPass::upload`]
+    pub(crate) mesh: u32,
+}
+
+/// Bounds and arena location of a mesh, matches `GpuMesh` in `culling.wgsl`
+#[derive(Clone, Copy, Debug, Default)]
+#[repr(C)]
+pub(crate) struct GpuMesh {
+    center: Vec3,
+    radius: f32,
+    index_count: u32,
+    first_index: u32,
+    vertex_offset: i32,
+    _padding: u32,
+}
+
+impl GpuMesh {
+    /// Draws level of detail `lod` of `mesh`
+    pub(crate) fn new(mesh: &Mesh, range: &GeometryRange, lod: usize) -> Self {
+        let lod = mesh.lods[lod];
+        GpuMesh {
+            center: mesh.bounding_sphere.center,
+            radius: mesh.bounding_sphere.radius,
+            index_count: lod.index_count,
+            first_index: range.first_index + lod.first_index,
+            vertex_offset: range.first_vertex as i32,
+            _padding: 0,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+#[repr(C)]
+struct CullingPushConstants {
+    planes: [Vec4; 6],
+    object_count: u32,
+    phase: u32,
+    occlusion: u32,
+    _padding: u32,
+}
+
+/// Draws whose commands were written by the culling pass, all from the geometry arena
+#[derive(Clone)]
+pub(crate) struct IndirectDraws {
+    pub(super) vertex_buffer: vk::Buffer,
+    pub(super) index_buffer: vk::Buffer,
+    pub(super) commands: vk::Buffer,
+    pub(super) counts: vk::Buffer,
+    /// Capacity of each command list
+    pub(super) max_draw_count: u32,
+    /// Command lists drawn, one indirect draw each
+    pub(super) lists: Range<u32>,
+}
+
+impl IndirectDraws {
+    /// The same draws restricted to `lists`
+    pub(crate) fn lists(&self, lists: Range<u32>) -> Self {
+        IndirectDraws {
+            lists,
+            ..self.clone()
+        }
+    }
+
+    pub(crate) fn record(&self, device: &Device, cmd: vk::CommandBuffer) {
+        let stride = size_of::<vk::DrawIndexedIndirectCommand>() as u32;
+        unsafe {
+            device
+                .raw
+                .cmd_bind_vertex_buffers(cmd, 0, &[self.vertex_buffer], &[0]);
+            device
+                .raw
+                .cmd_bind_index_buffer(cmd, self.index_buffer, 0, vk::IndexType::UINT32);
+            for list in self.lists.clone() {
+                device.raw.cmd_draw_indexed_indirect_count(
+                    cmd,
+                    self.commands,
+                    (list * self.max_draw_count * stride) as vk::DeviceSize,
+                    self.counts,
+                    (list as usize * size_of::<u32>()) as vk::DeviceSize,
+                    self.max_draw_count,
+                    stride,
+                );
+            }
+        }
+    }
+}
+
+/// Buffers owned by a single frame in flight, they grow to fit all of its objects
+struct CullingFrame {
+    objects: StorageArray<GpuObject>,
+    meshes: StorageArray<GpuMesh>,
+    /// `LIST_COUNT` lists with room for every object
+    commands: StorageArray<vk::DrawIndexedIndirectCommand>,
+    /// Number of commands in each list, read back once the frame has finished
+    counts: Buffer,
+    /// Result of the first phase for each object
+    visibility: StorageArray<u32>,
+    descriptor_set: vk::DescriptorSet,
+    constants: CullingPushConstants,
+    /// Whether the second phase runs
+    occlusion: bool,
+    /// Whether `counts` holds the result of the frame's last submission
+    counted: bool,
+}
+
+/// Objects rejected by the [`GpuCullingPass`] in a frame
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CullingResults {
+    /// Outside of the view frustum
+    pub culled: u32,
+    /// Inside the frustum but hidden behind the depth pyramid
+    pub occluded: u32,
+}
+
+/// Compute pass culling every object against the view frustum and writing a compacted
+/// list of indirect draw commands for the visible ones.
+///
+/// With occlusion culling it runs in two phases: the first one also tests against the
+/// [`DepthPyramid`] of the previous frame, and after the objects it kept have been drawn
+/// and the pyramid rebuilt from their depth, the second one retests the objects it
+/// rejected. Objects wrongly rejected by the first phase are then drawn all the same.
+pub struct GpuCullingPass {
+    layout: vk::DescriptorSetLayout,
+    descriptor_pool: DescriptorPool,
+    pipeline: ComputePipeline,
+    /// Draws the occluded objects on top of the scene
+    debug_pipeline: GraphicsPipeline,
+    frames: Vec<CullingFrame>,
+}
+
+impl GpuCullingPass {
+    pub fn new(
+        allocator: &mut Allocator,
+        device: &Device,
+        frame_descriptor_layout: vk::DescriptorSetLayout,
+    ) -> Result<Self> {
+        let layout = [
+            OBJECTS_BINDING,
+            MESHES_BINDING,
+            COMMANDS_BINDING,
+            COUNTS_BINDING,
+            VISIBILITY_BINDING,
+        ]
+        .into_iter()
+        .fold(DescriptorSetLayoutBuilder::default(), |builder, binding| {
+            builder.binding(
+                binding,
+                vk::DescriptorType::STORAGE_BUFFER,
+                vk::ShaderStageFlags::COMPUTE,
+            )
+        })
+        .binding(
+            PYRAMID_BINDING,
+            vk::DescriptorType::SAMPLED_IMAGE,
+            vk::ShaderStageFlags::COMPUTE,
+        )
+        .build(device)?;
+
+        let descriptor_pool = DescriptorPool::new(
+            device,
+            FRAMES_IN_FLIGHT as u32,
+            &[
+                vk::DescriptorPoolSize {
+                    ty: vk::DescriptorType::STORAGE_BUFFER,
+                    descriptor_count: 5 * FRAMES_IN_FLIGHT as u32,
+                },
+                vk::DescriptorPoolSize {
+                    ty: vk::DescriptorType::SAMPLED_IMAGE,
+                    descriptor_count: FRAMES_IN_FLIGHT as u32,
+                },
+            ],
+        )?;
+
+        let shader = ShaderSource::builder().entry("cs_cull").build(
+            ShaderStage::Compute,
+            ShaderLanguage::WGSL,
+            "./src/shaders/culling.wgsl",
+        );
+        let pipeline = ComputePipeline::new(
+            device,
+            &shader,
+            &[frame_descriptor_layout, layout],
+            size_of::<CullingPushConstants>(),
+        )?;
+
+        let debug_shaders = [
+            (ShaderStage::Vertex, "vs_occluded"),
+            (ShaderStage::Fragment, "fs_occluded"),
+        ]
+        .map(|(stage, entry)| {
+            ShaderSource::builder().entry(entry).build(
+                stage,
+                ShaderLanguage::WGSL,
+                "./src/shaders/occlusion_debug.wgsl",
+            )
+        });
+        let debug_pipeline = GraphicsPipeline::builder()
+            .color_formats(&[HDR_FORMAT])
+            .vertex_input(Vertex::describe())
+            .descriptor_set_layouts(&[frame_descriptor_layout])
+            .build(device, &debug_shaders)?;
+
+        let frames = (0..FRAMES_IN_FLIGHT)
+            .map(|_| {
+                let frame = CullingFrame {
+                    objects: StorageArray::new(allocator, device, 1024, "culling objects"),
+                    meshes: StorageArray::new(allocator, device, 64, "culling meshes"),
+                    commands: StorageArray::with_usage(
+                        allocator,
+                        device,
+                        LIST_COUNT as usize * 1024,
+                        vk::BufferUsageFlags::INDIRECT_BUFFER,
+                        "indirect draws",
+                    ),
+                    counts: Buffer::new(
+                        allocator,
+                        device,
+                        LIST_COUNT as usize * size_of::<u32>(),
+                        vk::BufferUsageFlags::STORAGE_BUFFER
+                            | vk::BufferUsageFlags::INDIRECT_BUFFER
+                            | vk::BufferUsageFlags::TRANSFER_DST,
+                        "draw counts",
+                    ),
+                    visibility: StorageArray::new(allocator, device, 1024, "culling visibility"),
+                    descriptor_set: descriptor_pool.allocate(device, layout)?,
+                    constants: CullingPushConstants::default(),
+                    occlusion: false,
+                    counted: false,
+                };
+
+                for (binding, buffer) in [
+                    (OBJECTS_BINDING, &frame.objects.buffer),
+                    (MESHES_BINDING, &frame.meshes.buffer),
+                    (COMMANDS_BINDING, &frame.commands.buffer),
+                    (COUNTS_BINDING, &frame.counts),
+                    (VISIBILITY_BINDING, &frame.visibility.buffer),
+                ] {
+                    write_buffer_descriptor(
+                        device,
+                        frame.descriptor_set,
+                        binding,
+                        vk::DescriptorType::STORAGE_BUFFER,
+                        buffer,
+                    );
+                }
+
+                Ok(frame)
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(GpuCullingPass {
+            layout,
+            descriptor_pool,
+            pipeline,
+            debug_pipeline,
+            frames,
+        })
+    }
+
+    /// Objects rejected the last time frame `frame_index` was drawn, `None` when that
+    /// frame was not culled on the GPU. Must be called before uploading the frame again.
+    pub fn last_results(&self, frame_index: usize) -> Option<CullingResults> {
+        let frame = &self.frames[frame_index];
+        frame.counted.then(|| {
+            let counts: [u32; LIST_COUNT as usize] = frame.counts.read(0);
+            let occluded = counts[OCCLUDED_LIST as usize];
+            CullingResults {
+                culled: frame.constants.object_count - counts.iter().sum::<u32>(),
+                occluded,
+            }
+        })
+    }
+
+    /// Uploads the objects of frame `frame_index` and returns the draws the culling
+    /// pass will produce for them, without the occluded ones.
+    /// `occlusion` enables the second phase, see [`Self::record_second_phase`].
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn upload(
+        &mut self,
+        allocator: &mut Allocator,
+        device: &Device,
+        deletion_queue: &mut DeletionQueue,
+        frame_number: u64,
+        frame_index: usize,
+        arena: &GeometryArena,
+        pyramid: &DepthPyramid,
+        frustum: &Frustum,
+        occlusion: bool,
+        objects: &[GpuObject],
+        meshes: &[GpuMesh],
+    ) -> IndirectDraws {
+        let frame = &mut self.frames[frame_index];
+        let set = frame.descriptor_set;
+
+        frame.objects.upload(
+            allocator,
+            device,
+            deletion_queue,
+            frame_number,
+            set,
+            OBJECTS_BINDING,
+            objects,
+        );
+        frame.meshes.upload(
+            allocator,
+            device,
+            deletion_queue,
+            frame_number,
+            set,
+            MESHES_BINDING,
+            meshes,
+        );
+        frame.commands.reserve(
+            allocator,
+            device,
+            deletion_queue,
+            frame_number,
+            set,
+            COMMANDS_BINDING,
+            LIST_COUNT as usize * objects.len(),
+        );
+        frame.visibility.reserve(
+            allocator,
+            device,
+            deletion_queue,
+            frame_number,
+            set,
+            VISIBILITY_BINDING,
+            objects.len(),
+        );
+
+        // the pyramid is recreated with the depth buffer
+        write_image_descriptor(
+            device,
+            set,
+            PYRAMID_BINDING,
+            vk::DescriptorType::SAMPLED_IMAGE,
+            pyramid.image.view,
+            vk::ImageLayout::GENERAL,
+            vk::Sampler::null(),
+        );
+
+        frame.occlusion = occlusion;
+        frame.constants = CullingPushConstants {
+            planes: frustum.planes,
+            object_count: objects.len() as u32,
+            phase: 0,
+            occlusion: (occlusion && pyramid.is_built()) as u32,
+            _padding: 0,
+        };
+
+        let last_list = if occlusion {
+            DISOCCLUDED_LIST
+        } else {
+            VISIBLE_LIST
+        };
+        IndirectDraws {
+            vertex_buffer: arena.vertex_buffer(),
+            index_buffer: arena.index_buffer(),
+            commands: frame.commands.buffer.raw,
+            counts: frame.counts.raw,
+            max_draw_count: objects.len() as u32,
+            lists: VISIBLE_LIST..last_list + 1,
+        }
+    }
+
+    /// Culls the objects uploaded for frame `frame_index`, the draw commands of the
+    /// visible list can be read by indirect draws afterwards. The depth pyramid must
+    /// have been prepared.
+    pub fn record(
+        &mut self,
+        device: &Device,
+        cmd: vk::CommandBuffer,
+        frame_index: usize,
+        frame_descriptor_set: vk::DescriptorSet,
+    ) {
+        let frame = &mut self.frames[frame_index];
+        frame.counted = true;
+
+        unsafe {
+            device
+                .raw
+                .cmd_fill_buffer(cmd, frame.counts.raw, 0, vk::WHOLE_SIZE, 0);
+
+            // the previous frame may still be building the depth pyramid
+            let barrier = vk::MemoryBarrier::builder()
+                .src_access_mask(vk::AccessFlags::TRANSFER_WRITE | vk::AccessFlags::SHADER_WRITE)
+                .dst_access_mask(vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE);
+            device.raw.cmd_pipeline_barrier(
+                cmd,
+                vk::PipelineStageFlags::TRANSFER | vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::DependencyFlags::empty(),
+                &[barrier.build()],
+                &[],
+                &[],
+            );
+        }
+
+        let constants = frame.constants;
+        self.dispatch(device, cmd, frame_index, frame_descriptor_set, &constants);
+    }
+
+    /// Retests the objects the first phase found occluded against the rebuilt depth
+    /// pyramid, filling the disoccluded and occluded lists
+    pub fn record_second_phase(
+        &mut self,
+        device: &Device,
+        cmd: vk::CommandBuffer,
+        frame_index: usize,
+        frame_descriptor_set: vk::DescriptorSet,
+    ) {
+        let frame = &self.frames[frame_index];
+        if !frame.occlusion {
+            return;
+        }
+
+        // the depth prepass may still be reading the counts
+        let barrier = vk::MemoryBarrier::builder()
+            .src_access_mask(vk::AccessFlags::SHADER_WRITE)
+            .dst_access_mask(vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE);
+        unsafe {
+            device.raw.cmd_pipeline_barrier(
+                cmd,
+                vk::PipelineStageFlags::DRAW_INDIRECT | vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::DependencyFlags::empty(),
+                &[barrier.build()],
+                &[],
+                &[],
+            );
+        }
+
+        let constants = CullingPushConstants {
+            phase: 1,
+            ..frame.constants
+        };
+        self.dispatch(device, cmd, frame_index, frame_descriptor_set, &constants);
+    }
+
+    fn dispatch(
+        &self,
+        device: &Device,
+        cmd: vk::CommandBuffer,
+        frame_index: usize,
+        frame_descriptor_set: vk::DescriptorSet,
+        constants: &CullingPushConstants,
+    ) {
+        unsafe {
+            device.raw.cmd_bind_pipeline(
+                cmd,
+                vk::PipelineBindPoint::COMPUTE,
+                self.pipeline.pipeline,
+            );
+            device.raw.cmd_bind_descriptor_sets(
+                cmd,
+                vk::PipelineBindPoint::COMPUTE,
+                self.pipeline.layout,
+                0,
+                &[
+                    frame_descriptor_set,
+                    self.frames[frame_index].descriptor_set,
+                ],
+                &[],
+            );
+        }
+
+        self.pipeline.push_constants(device, cmd, constants);
+
+        unsafe {
+            device
+                .raw
+                .cmd_dispatch(cmd, constants.object_count.div_ceil(GROUP_SIZE), 1, 1);
+        }
+
+        let barrier = vk::MemoryBarrier::builder()
+            .src_access_mask(vk::AccessFlags::SHADER_WRITE)
+            .dst_access_mask(vk::AccessFlags::INDIRECT_COMMAND_READ);
+        unsafe {
+            device.raw.cmd_pipeline_barrier(
+                cmd,
+                vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::PipelineStageFlags::DRAW_INDIRECT,
+                vk::DependencyFlags::empty(),
+                &[barrier.build()],
+                &[],
+                &[],
+            );
+        }
+    }
+
+    /// Draws the objects of `draws` hidden behind the depth pyramid in a flat color on
+    /// top of `target`, which must be in `COLOR_ATTACHMENT_OPTIMAL`
+    pub(crate) fn record_occluded(
+        &self,
+        device: &Device,
+        cmd: vk::CommandBuffer,
+        frame_descriptor_set: vk::DescriptorSet,
+        target: &Image,
+        draws: &IndirectDraws,
+    ) {
+        let color_barrier = vk::MemoryBarrier::builder()
+            .src_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
+            .dst_access_mask(
+                vk::AccessFlags::COLOR_ATTACHMENT_READ | vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+            );
+
+        let color_attachments = [vk::RenderingAttachmentInfo::builder()
+            .image_view(target.view)
+            .image_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+            .load_op(vk::AttachmentLoadOp::LOAD)
+            .store_op(vk::AttachmentStoreOp::STORE)
+            .build()];
+
+        let rendering_info = vk::RenderingInfo::builder()
+            .render_area(vk::Rect2D {
+                extent: target.desc.extent_2d(),
+                ..Default::default()
+            })
+            .layer_count(1)
+            .color_attachments(&color_attachments);
+
+        unsafe {
+            device.raw.cmd_pipeline_barrier(
+                cmd,
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+                vk::DependencyFlags::empty(),
+                &[color_barrier.build()],
+                &[],
+                &[],
+            );
+
+            device.raw.cmd_begin_rendering(cmd, &rendering_info);
+            device.raw.cmd_bind_pipeline(
+                cmd,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.debug_pipeline.pipeline,
+            );
+            device.raw.cmd_bind_descriptor_sets(
+                cmd,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.debug_pipeline.layout,
+                0,
+                &[frame_descriptor_set],
+                &[],
+            );
+        }
+
+        draws
+            .lists(OCCLUDED_LIST..OCCLUDED_LIST + 1)
+            .record(device, cmd);
+
+        unsafe { device.raw.cmd_end_rendering(cmd) };
+    }
+
+    /// Forgets the counts of frames recorded without GPU culling
+    pub fn skip(&mut self, frame_index: usize) {
+        self.frames[frame_index].counted = false;
+    }
+
+    pub fn destroy(&mut self, device: &Device, allocator: &mut Allocator) {
+        for frame in &mut self.frames {
+            frame.objects.buffer.destroy(device, allocator);
+            frame.meshes.buffer.destroy(device, allocator);
+            frame.commands.buffer.destroy(device, allocator);
+            frame.counts.destroy(device, allocator);
+            frame.visibility.buffer.destroy(device, allocator);
+        }
+        self.descriptor_pool.destroy(device);
+        self.pipeline.destroy(device);
+        self.debug_pipeline.destroy(device);
+        unsafe { device.raw.destroy_descriptor_set_layout(self.layout, None) };
+    }
+}
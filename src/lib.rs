@@ -1,26 +1,69 @@
 pub mod asset;
 pub mod backend_vulkan;
+pub mod camera;
+pub mod culling;
+pub mod deferred;
+pub mod frame;
+pub mod gpu_driven;
+pub mod lighting;
+pub mod lod;
+pub mod post;
+pub mod resource;
+pub mod scene;
+pub mod skinning;
 
 use anyhow::Result;
 use ash::vk;
+use asset::{
+    asset_loader::{GltfAsset, GltfInstance},
+    hdr::load_hdr,
+};
 use backend_vulkan::{
-    device::Device,
+    descriptor::DescriptorPool,
+    device::{Device, FRAMES_IN_FLIGHT},
+    image::{Image, ImageDesc},
     instance::Instance,
-    mesh::{Mesh, MeshPushConstants},
+    mesh::{HasVertexInputDescription, Mesh, MeshData, Vertex},
     physical_device::PhysicalDevice,
     pipeline::GraphicsPipeline,
+    profiler::{GpuProfiler, GpuTiming},
     shader::{ShaderLanguage, ShaderSource, ShaderStage},
     surface::Surface,
     swapchain::{CreateSwapchainError, Swapchain, SwapchainDesc},
 };
-use glam::{vec3, Mat4};
+use camera::Camera;
+use culling::{max_scale, CullingSettings, Frustum};
+use deferred::{DeferredPass, GBufferView, RenderPath};
+use frame::{FrameContents, FrameData, FrameStats, GlobalUniforms, InstanceData};
+use glam::{Vec2, Vec3};
 use gpu_allocator::{
     vulkan::{Allocator, AllocatorCreateDesc},
     AllocatorDebugSettings,
 };
-use std::{ffi::CStr, mem::size_of, sync::Arc};
+use gpu_driven::{
+    meshlets::{MeshletDraws, MeshletTarget, MeshletTask},
+    pass::{GpuMesh, GpuObject, IndirectDraws, DISOCCLUDED_LIST},
+    DepthPyramid, GeometryArena, GpuCullingPass, MeshletCullingPass,
+};
+use lighting::{
+    shadow::shadow_views, shadow_maps::ShadowPass, Background, BackgroundPass, CascadeSettings,
+    ClusterSettings, IblSettings, ImageBasedLighting, Light, LightClusters, LightData, LightHandle,
+    ShadowMaps, SsaoPass, SsaoSettings,
+};
+use lod::{LodSelector, LodSettings};
+use post::{
+    targets::SCENE_TARGET, BloomPass, BloomSettings, BuiltinPostEffects, PostEffect,
+    PostEffectDesc, PostEffectHandle, PostStack, PostTargets, TonemapPass, TonemapSettings,
+};
+use resource::{DeletionQueue, MaterialHandle, MeshHandle, Pool, ResourceError};
+use scene::{Material, MaterialData, Node, NodeHandle, Scene, Skin, Transform};
+use skinning::{pass::DeformedMesh, SkinningPass};
+use std::{collections::HashMap, ffi::CStr, ops::Range, path::Path, sync::Arc, time::Instant};
 use thiserror::Error;
 
+/// Format the scene is rendered in before it is tonemapped to the swapchain
+pub const HDR_FORMAT: vk::Format = vk::Format::R16G16B16A16_SFLOAT;
+
 #[derive(Error, Debug)]
 pub enum DrawError {
     #[error("Height or width of the window is zero")]
@@ -43,27 +86,163 @@ pub struct PoogieRenderer {
     #[allow(dead_code)]
     pub shader_sources: Vec<ShaderSource>,
     // pub pipeline: GraphicsPipeline,
-    pub allocator: Allocator,
+    allocator: Allocator,
     pub mesh_pipeline_temp: GraphicsPipeline,
-    pub meshes: Vec<Mesh>,
-    // pub triangle_mesh_temp: Mesh,
+    meshes: Pool<Mesh>,
+    materials: Pool<Material>,
+    default_material: Material,
+    deletion_queue: DeletionQueue,
+    lights: Pool<Light>,
+    pub scene: Scene,
+    pub camera: Camera,
+    /// Constant radiance added to every surface
+    pub ambient_light: Vec3,
+    ibl: ImageBasedLighting,
+    /// Ambient lighting from the environment set with [`Self::load_environment`]
+    pub environment: IblSettings,
+    background_pass: BackgroundPass,
+    /// Drawn wherever the scene leaves the HDR target empty
+    pub background: Background,
+    pub depth_image: Image,
+    deferred_pass: DeferredPass,
+    pub render_path: RenderPath,
+    /// G-buffer channel shown instead of the lit scene on the deferred path
+    pub gbuffer_view: GBufferView,
+    ssao_pass: SsaoPass,
+    pub ssao: SsaoSettings,
+    post_targets: PostTargets,
+    post_stack: PostStack,
+    pub builtin_post_effects: BuiltinPostEffects,
+    bloom_pass: BloomPass,
+    pub bloom: BloomSettings,
+    tonemap_pass: TonemapPass,
+    pub tonemap: TonemapSettings,
+    shadow_maps: ShadowMaps,
+    /// Split of the view frustum between the shadow cascades of directional lights
+    pub cascades: CascadeSettings,
+    light_clusters: LightClusters,
+    pub clusters: ClusterSettings,
+    skinning_pass: SkinningPass,
+    pub culling: CullingSettings,
+    pub lod: LodSettings,
+    stats: FrameStats,
+    /// Copies of the geometry of the meshes the GPU-driven path has drawn since they
+    /// were last updated
+    geometry: GeometryArena,
+    gpu_culling: GpuCullingPass,
+    meshlet_culling: MeshletCullingPass,
+    depth_pyramid: DepthPyramid,
+    frame_descriptor_layout: vk::DescriptorSetLayout,
+    frame_descriptor_pool: DescriptorPool,
+    frames: Vec<FrameData>,
+    profiler: GpuProfiler,
+    start_time: Instant,
+}
+
+/// Instances of a mesh drawn with a single call, the shaders read their transforms and
+/// materials from the frame's instance buffer at `instance_index`
+#[derive(Clone)]
+pub(crate) struct MeshDraw {
+    pub(crate) mesh: MeshHandle,
+    /// Index into [`Mesh::lods`]
+    pub(crate) lod: usize,
+    /// Range in the frame's instance buffer
+    pub(crate) instances: Range<u32>,
+    /// Buffer and byte offset of the skinned vertices replacing the mesh's own
+    pub(crate) vertices: Option<(vk::Buffer, vk::DeviceSize)>,
+}
+
+impl MeshDraw {
+    pub(crate) fn draw(&self, device: &Device, cmd: vk::CommandBuffer, meshes: &Pool<Mesh>) {
+        let mesh = &meshes[self.mesh];
+        let (buffer, offset) = self.vertices.unwrap_or((mesh.vertex_buffer.raw, 0));
+        mesh.draw_instanced(
+            device,
+            cmd,
+            buffer,
+            offset,
+            self.lod,
+            self.instances.clone(),
+        );
+    }
+}
+
+/// The draws of a camera pass
+pub(crate) struct DrawList {
+    pub(crate) draws: Vec<MeshDraw>,
+    /// Objects culled on the GPU, drawn before `draws`
+    pub(crate) indirect: Option<IndirectDraws>,
+    /// Meshlets culled on the GPU, drawn after `indirect`, or after everything else when
+    /// mesh shaders draw them
+    pub(crate) meshlets: Option<MeshletDraws>,
+}
+
+impl DrawList {
+    /// Draws with the pipeline bound by the caller, except for meshlets drawn by mesh
+    /// shaders, which use the pipeline of `target` and leave it bound
+    pub(crate) fn record(
+        &self,
+        device: &Device,
+        cmd: vk::CommandBuffer,
+        meshes: &Pool<Mesh>,
+        target: MeshletTarget,
+    ) {
+        if let Some(indirect) = &self.indirect {
+            indirect.record(device, cmd);
+        }
+        if let Some(MeshletDraws::Indirect(indirect)) = &self.meshlets {
+            indirect.record(device, cmd);
+        }
+        for draw in &self.draws {
+            draw.draw(device, cmd, meshes);
+        }
+        if let Some(MeshletDraws::MeshShader(meshlets)) = &self.meshlets {
+            meshlets.record(device, cmd, target);
+        }
+    }
+}
+
+/// One of the nodes added by [`PoogieRenderer::add_instances`]
+#[derive(Clone, Copy, Debug)]
+pub struct InstanceDesc {
+    pub transform: Transform,
+    /// The renderer's default material is used when unset
+    pub material: Option<MaterialHandle>,
+    /// Linear RGB multiplying the material's base color
+    pub color: Vec3,
+}
+
+impl Default for InstanceDesc {
+    fn default() -> Self {
+        InstanceDesc {
+            transform: Transform::IDENTITY,
+            material: None,
+            color: Vec3::ONE,
+        }
+    }
 }
 
 pub struct PoogieRendererBuilder {
     app_name: String,
     debug_graphics: bool,
     vsync: bool,
+    shadow_map_resolution: u32,
+    shadow_map_layers: u32,
 }
 
-impl PoogieRendererBuilder {
-    pub fn default() -> Self {
+impl Default for PoogieRendererBuilder {
+    fn default() -> Self {
         PoogieRendererBuilder {
             app_name: "PoogieApp".to_string(),
             debug_graphics: false,
             vsync: true,
+            shadow_map_resolution: 2048,
+            shadow_map_layers: 16,
         }
     }
+}
 
+impl PoogieRendererBuilder {
     pub fn debug_graphics(mut self, debug_graphics: bool) -> Self {
         self.debug_graphics = debug_graphics;
         self
@@ -74,6 +253,18 @@ impl PoogieRendererBuilder {
         self
     }
 
+    pub fn shadow_map_resolution(mut self, resolution: u32) -> Self {
+        self.shadow_map_resolution = resolution;
+        self
+    }
+
+    /// Number of shadow maps shared by all lights, directional lights need one per cascade
+    /// and point lights six, lights that do not fit cast no shadows
+    pub fn shadow_map_layers(mut self, layers: u32) -> Self {
+        self.shadow_map_layers = layers;
+        self
+    }
+
     pub fn build(self, window: Arc<winit::window::Window>) -> Result<PoogieRenderer> {
         PoogieRenderer::create(self, window)
     }
@@ -101,6 +292,7 @@ impl PoogieRenderer {
             .build()?;
 
         let pdevices = PhysicalDevice::enumerate_physical_devices(&instance)?;
+        #[allow(clippy::arc_with_non_send_sync)]
         let pdevice = Arc::new(
             pdevices
                 .into_iter()
@@ -122,7 +314,7 @@ impl PoogieRenderer {
         let allocator_desc = AllocatorCreateDesc {
             instance: instance.raw.clone(),
             device: device.raw.clone(),
-            physical_device: pdevice.raw.clone(),
+            physical_device: pdevice.raw,
             debug_settings: AllocatorDebugSettings {
                 log_memory_information: true,
                 log_leaks_on_shutdown: true,
@@ -131,7 +323,7 @@ impl PoogieRenderer {
                 log_frees: true,
                 log_stack_traces: true,
             },
-            buffer_device_address: true, // Ideally, check the BufferDeviceAddressFeatures struct.
+            buffer_device_address: device.buffer_device_address,
         };
 
         let mut allocator = Allocator::new(&allocator_desc)?;
@@ -190,10 +382,98 @@ impl PoogieRenderer {
 
         let shader_sources = vec![vertex_shader, fragment_shader];
 
-        let triangle_mesh_temp = Mesh::new(&mut allocator, &device);
-        let mesh_pipeline_temp =
-            GraphicsPipeline::create_pipeline(&device, &swapchain, &shader_sources)?;
+        let depth_image = Self::create_depth_image(&mut allocator, &device, &swapchain);
+        let post_targets = PostTargets::new(&mut allocator, &device, swapchain.desc.extent)?;
+        let bloom_pass = BloomPass::new(&mut allocator, &device, &post_targets)?;
+        let tonemap_pass = TonemapPass::new(
+            &device,
+            swapchain.desc.surface_format.format,
+            post_targets.input_layout,
+        )?;
+
+        let frame_descriptor_layout = FrameData::descriptor_set_layout(&device)?;
+        let ssao_pass = SsaoPass::new(
+            &mut allocator,
+            &device,
+            frame_descriptor_layout,
+            &depth_image,
+        )?;
+        let deferred_pass = DeferredPass::new(
+            &mut allocator,
+            &device,
+            frame_descriptor_layout,
+            &depth_image,
+        )?;
+        let ibl = ImageBasedLighting::new(&mut allocator, &device)?;
+        let background_pass = BackgroundPass::new(&device, frame_descriptor_layout, &ibl)?;
+        let light_clusters = LightClusters::new(&mut allocator, &device, frame_descriptor_layout)?;
+        let skinning_pass = SkinningPass::new(&mut allocator, &device)?;
+        let frame_descriptor_pool = FrameData::descriptor_pool(&device)?;
+        let geometry = GeometryArena::new(&mut allocator, &device);
+        let gpu_culling = GpuCullingPass::new(&mut allocator, &device, frame_descriptor_layout)?;
+        let meshlet_culling = MeshletCullingPass::new(
+            &mut allocator,
+            &device,
+            frame_descriptor_layout,
+            depth_image.desc.format,
+        )?;
+        let depth_pyramid = DepthPyramid::new(&mut allocator, &device, &depth_image)?;
+        let shadow_maps = ShadowMaps::new(
+            &mut allocator,
+            &device,
+            frame_descriptor_layout,
+            builder.shadow_map_resolution,
+            // the shadow maps are sampled through an array view
+            builder.shadow_map_layers.max(2),
+        )?;
+        let frames = (0..FRAMES_IN_FLIGHT)
+            .map(|_| {
+                FrameData::new(
+                    &mut allocator,
+                    &device,
+                    &frame_descriptor_pool,
+                    frame_descriptor_layout,
+                    &shadow_maps,
+                    ssao_pass.occlusion.view,
+                    &light_clusters.buffer,
+                    &ibl,
+                )
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut post_stack = PostStack::default();
+        let mut add_builtin = |desc: PostEffectDesc| {
+            post_stack.add(
+                &device,
+                &post_targets,
+                frame_descriptor_layout,
+                desc.enabled(false),
+            )
+        };
+        let builtin_post_effects = BuiltinPostEffects {
+            color_grade: add_builtin(PostEffectDesc::color_grade())?,
+            chromatic_aberration: add_builtin(PostEffectDesc::chromatic_aberration())?,
+            film_grain: add_builtin(PostEffectDesc::film_grain())?,
+            vignette: add_builtin(PostEffectDesc::vignette())?,
+        };
+
+        let mesh_pipeline_temp = GraphicsPipeline::builder()
+            .color_formats(&[HDR_FORMAT])
+            .depth_format(depth_image.desc.format)
+            .vertex_input(Vertex::describe())
+            .descriptor_set_layouts(&[frame_descriptor_layout])
+            .build(&device, &shader_sources)?;
 
+        let profiler = GpuProfiler::new(&device)?;
+
+        if !device.indirect_draws {
+            log::warn!("Indirect draws with counts are not supported, GPU culling is disabled");
+        }
+        if !device.storage_image_extended_formats {
+            log::warn!(
+                "Extended storage image formats are not supported, occlusion culling is disabled"
+            );
+        }
         log::info!("Successfully created renderer!");
 
         Ok(PoogieRenderer {
@@ -207,16 +487,93 @@ impl PoogieRenderer {
             // pipeline,
             allocator,
             mesh_pipeline_temp,
-            meshes: vec![triangle_mesh_temp],
+            meshes: Pool::new(),
+            materials: Pool::new(),
+            default_material: Material::default(),
+            deletion_queue: DeletionQueue::default(),
+            lights: Pool::new(),
+            scene: Scene::new(),
+            camera: Camera::default(),
+            ambient_light: Vec3::splat(0.03),
+            ibl,
+            environment: IblSettings::default(),
+            background_pass,
+            background: Background::default(),
+            depth_image,
+            deferred_pass,
+            render_path: RenderPath::default(),
+            gbuffer_view: GBufferView::default(),
+            ssao_pass,
+            ssao: SsaoSettings::default(),
+            post_targets,
+            post_stack,
+            builtin_post_effects,
+            bloom_pass,
+            bloom: BloomSettings::default(),
+            tonemap_pass,
+            tonemap: TonemapSettings::default(),
+            shadow_maps,
+            cascades: CascadeSettings::default(),
+            light_clusters,
+            clusters: ClusterSettings::default(),
+            skinning_pass,
+            culling: CullingSettings::default(),
+            lod: LodSettings::default(),
+            stats: FrameStats::default(),
+            geometry,
+            gpu_culling,
+            meshlet_culling,
+            depth_pyramid,
+            frame_descriptor_layout,
+            frame_descriptor_pool,
+            frames,
+            profiler,
+            start_time: Instant::now(),
         })
     }
 
+    fn create_depth_image(
+        allocator: &mut Allocator,
+        device: &Device,
+        swapchain: &Swapchain,
+    ) -> Image {
+        let desc = ImageDesc::new_2d(
+            vk::Format::D32_SFLOAT,
+            swapchain.desc.extent,
+            vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT | vk::ImageUsageFlags::SAMPLED,
+        );
+        Image::new(allocator, device, desc, "depth")
+    }
+
     pub fn recreate_swapchain(&mut self) -> Result<(), CreateSwapchainError> {
         let window_size = self.window.inner_size();
         if window_size.width == 0 || window_size.height == 0 {
             return Err(CreateSwapchainError::ZeroSizedExtent);
         }
-        self.swapchain.recreate(&window_size)
+        self.swapchain.recreate(&window_size)?;
+
+        self.depth_image.destroy(&self.device, &mut self.allocator);
+        self.depth_image =
+            Self::create_depth_image(&mut self.allocator, &self.device, &self.swapchain);
+        self.ssao_pass
+            .resize(&mut self.allocator, &self.device, &self.depth_image);
+        for frame in &self.frames {
+            frame.set_ambient_occlusion(&self.device, self.ssao_pass.occlusion.view);
+        }
+        self.deferred_pass
+            .resize(&mut self.allocator, &self.device, &self.depth_image);
+        self.depth_pyramid
+            .resize(&mut self.allocator, &self.device, &self.depth_image);
+
+        self.post_targets.resize(
+            &mut self.allocator,
+            &self.device,
+            self.swapchain.desc.extent,
+        );
+        self.bloom_pass
+            .resize(&mut self.allocator, &self.device, &self.post_targets);
+
+        Ok(())
     }
 
     pub fn draw(&mut self) -> Result<std::time::Duration, DrawError> {
@@ -227,35 +584,48 @@ impl PoogieRenderer {
             return Err(DrawError::ZeroSizedExtent);
         }
 
+        let frame_index = self.frame_number as usize % FRAMES_IN_FLIGHT;
+        let command_buffer = &self.device.frame_command_buffers[frame_index];
+        let render_fence = command_buffer.submit_done_fence;
+
         unsafe {
             self.device
                 .raw
-                .wait_for_fences(&[self.device.render_fence], true, u64::MAX)
+                .wait_for_fences(&[render_fence], true, u64::MAX)
                 .unwrap();
         }
 
+        // the last frame that used this frame's resources has finished now,
+        // as has everything retired before the oldest frame still in flight
+        self.deletion_queue.collect(
+            (self.frame_number + 1).saturating_sub(FRAMES_IN_FLIGHT as u64),
+            &self.device,
+            &mut self.allocator,
+        );
+        self.geometry
+            .collect((self.frame_number + 1).saturating_sub(FRAMES_IN_FLIGHT as u64));
+
         let swapchain_image = match self.swapchain.acquire_next_image() {
             Some(img) => img,
             None => return Err(DrawError::NoSwapchainImage),
         };
 
-        let raw_cmd_buffer = self.device.main_command_buffer.raw;
+        let raw_cmd_buffer = command_buffer.raw;
 
         unsafe {
-            self.device
-                .raw
-                .reset_fences(&[self.device.render_fence])
-                .unwrap();
+            self.device.raw.reset_fences(&[render_fence]).unwrap();
 
             self.device
                 .raw
                 .reset_command_pool(
-                    self.device.main_command_buffer.pool,
+                    command_buffer.pool,
                     vk::CommandPoolResetFlags::RELEASE_RESOURCES,
                 )
                 .unwrap();
         }
 
+        let (draws, shadow_draws, shadow_passes) = self.upload_frame_data(frame_index);
+
         let cmd_info = vk::CommandBufferBeginInfo::builder()
             .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
 
@@ -278,7 +648,67 @@ impl PoogieRenderer {
                 .raw
                 .begin_command_buffer(raw_cmd_buffer, &cmd_info)
                 .unwrap();
+        }
+
+        self.profiler
+            .begin_frame(&self.device, raw_cmd_buffer, frame_index);
+
+        self.profiler
+            .begin_scope(&self.device, raw_cmd_buffer, "skinning");
+        self.skinning_pass
+            .record(&self.device, raw_cmd_buffer, frame_index);
+        self.profiler.end_scope(&self.device, raw_cmd_buffer);
+
+        if draws.indirect.is_some() {
+            self.profiler
+                .begin_scope(&self.device, raw_cmd_buffer, "gpu culling");
+            self.depth_pyramid.prepare(&self.device, raw_cmd_buffer);
+            self.gpu_culling.record(
+                &self.device,
+                raw_cmd_buffer,
+                frame_index,
+                self.frames[frame_index].descriptor_set,
+            );
+            // mesh shaders cull meshlets as they draw them
+            if let Some(MeshletDraws::Indirect(_)) = draws.meshlets {
+                self.meshlet_culling.record(
+                    &self.device,
+                    raw_cmd_buffer,
+                    frame_index,
+                    self.frames[frame_index].descriptor_set,
+                );
+            } else {
+                self.meshlet_culling.skip(frame_index);
+            }
+            self.profiler.end_scope(&self.device, raw_cmd_buffer);
+        } else {
+            self.gpu_culling.skip(frame_index);
+            self.meshlet_culling.skip(frame_index);
+        }
+
+        self.profiler
+            .begin_scope(&self.device, raw_cmd_buffer, "shadows");
+        self.shadow_maps.record(
+            &self.device,
+            raw_cmd_buffer,
+            self.frames[frame_index].descriptor_set,
+            &shadow_passes,
+            &self.meshes,
+            &shadow_draws,
+        );
+        self.profiler.end_scope(&self.device, raw_cmd_buffer);
+
+        self.profiler
+            .begin_scope(&self.device, raw_cmd_buffer, "light culling");
+        self.light_clusters.record(
+            &self.device,
+            raw_cmd_buffer,
+            &self.clusters,
+            self.frames[frame_index].descriptor_set,
+        );
+        self.profiler.end_scope(&self.device, raw_cmd_buffer);
 
+        unsafe {
             // set dynamic states
             self.device
                 .raw
@@ -289,10 +719,151 @@ impl PoogieRenderer {
                 .cmd_set_scissor(raw_cmd_buffer, 0, &scissors);
         }
 
+        // draws what the first phase kept, then what the second one finds visible in its depth
+        let occlusion = draws.indirect.as_ref().filter(|_| self.culling.occlusion);
+        if let Some(indirect) = occlusion {
+            self.profiler
+                .begin_scope(&self.device, raw_cmd_buffer, "occlusion culling");
+            self.ssao_pass.record_prepass(
+                &self.device,
+                raw_cmd_buffer,
+                self.frames[frame_index].descriptor_set,
+                &self.depth_image,
+                &self.meshes,
+                &draws,
+                false,
+            );
+            self.depth_pyramid
+                .record(&self.device, raw_cmd_buffer, &self.depth_image);
+            self.gpu_culling.record_second_phase(
+                &self.device,
+                raw_cmd_buffer,
+                frame_index,
+                self.frames[frame_index].descriptor_set,
+            );
+            self.ssao_pass.record_prepass(
+                &self.device,
+                raw_cmd_buffer,
+                self.frames[frame_index].descriptor_set,
+                &self.depth_image,
+                &self.meshes,
+                &DrawList {
+                    draws: vec![],
+                    indirect: Some(indirect.lists(DISOCCLUDED_LIST..DISOCCLUDED_LIST + 1)),
+                    meshlets: None,
+                },
+                true,
+            );
+            self.profiler.end_scope(&self.device, raw_cmd_buffer);
+        }
+
+        self.profiler
+            .begin_scope(&self.device, raw_cmd_buffer, "ssao");
+        let depth_prepassed = self.ssao_pass.record(
+            &self.device,
+            raw_cmd_buffer,
+            &self.ssao,
+            self.frames[frame_index].descriptor_set,
+            &self.depth_image,
+            &self.meshes,
+            &draws,
+            occlusion.is_some(),
+        );
+        self.profiler.end_scope(&self.device, raw_cmd_buffer);
+
+        let hdr_image = &self.post_targets.images[SCENE_TARGET];
+
+        self.profiler
+            .begin_scope(&self.device, raw_cmd_buffer, "background");
+        self.background_pass.record(
+            &self.device,
+            raw_cmd_buffer,
+            self.frames[frame_index].descriptor_set,
+            self.background,
+            &self.ibl,
+            &self.environment,
+            hdr_image,
+        );
+        self.profiler.end_scope(&self.device, raw_cmd_buffer);
+
+        self.profiler
+            .begin_scope(&self.device, raw_cmd_buffer, "scene");
+
+        match self.render_path {
+            RenderPath::Forward => {
+                self.record_forward(raw_cmd_buffer, frame_index, &draws, depth_prepassed)
+            }
+            RenderPath::Deferred => self.deferred_pass.record(
+                &self.device,
+                raw_cmd_buffer,
+                self.frames[frame_index].descriptor_set,
+                self.gbuffer_view,
+                hdr_image,
+                &self.depth_image,
+                depth_prepassed,
+                &self.meshes,
+                &draws,
+            ),
+        }
+
+        if let Some(indirect) = occlusion.filter(|_| self.culling.show_occluded) {
+            self.gpu_culling.record_occluded(
+                &self.device,
+                raw_cmd_buffer,
+                self.frames[frame_index].descriptor_set,
+                hdr_image,
+                indirect,
+            );
+        }
+
+        self.profiler.end_scope(&self.device, raw_cmd_buffer);
+
+        let hdr_memory_barrier = vk::ImageMemoryBarrier::builder()
+            .old_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+            .new_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            .src_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
+            .dst_access_mask(vk::AccessFlags::SHADER_READ)
+            .image(hdr_image.raw)
+            .subresource_range(hdr_image.desc.subresource_range());
+
+        unsafe {
+            self.device.raw.cmd_pipeline_barrier(
+                raw_cmd_buffer,
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+                vk::PipelineStageFlags::FRAGMENT_SHADER | vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[hdr_memory_barrier.build()],
+            );
+        }
+
+        self.profiler
+            .begin_scope(&self.device, raw_cmd_buffer, "bloom");
+        let bloom_output = self.bloom_pass.record(
+            &self.device,
+            raw_cmd_buffer,
+            &self.bloom,
+            &self.post_targets,
+        );
+        self.profiler.end_scope(&self.device, raw_cmd_buffer);
+
+        self.profiler
+            .begin_scope(&self.device, raw_cmd_buffer, "post effects");
+        let post_output = self.post_stack.record(
+            &self.device,
+            raw_cmd_buffer,
+            &self.post_targets,
+            self.frames[frame_index].descriptor_set,
+            bloom_output,
+        );
+        self.profiler.end_scope(&self.device, raw_cmd_buffer);
+
         // manually set image to a renderable layout
         let img_memory_barrier = vk::ImageMemoryBarrier::builder()
             .old_layout(vk::ImageLayout::UNDEFINED)
             .new_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+            .dst_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
             .image(*swapchain_image.image)
             .subresource_range(
                 vk::ImageSubresourceRange::builder()
@@ -305,9 +876,10 @@ impl PoogieRenderer {
             );
 
         unsafe {
+            // color output waits on the acquire semaphore, chain the layout transition onto it
             self.device.raw.cmd_pipeline_barrier(
                 raw_cmd_buffer,
-                vk::PipelineStageFlags::TOP_OF_PIPE,
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
                 vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
                 vk::DependencyFlags::empty(),
                 &[],
@@ -316,82 +888,17 @@ impl PoogieRenderer {
             );
         }
 
-        let color_attachment_info = vk::RenderingAttachmentInfo::builder()
-            .image_view(self.swapchain.image_views[swapchain_image.index as usize])
-            .image_layout(vk::ImageLayout::ATTACHMENT_OPTIMAL_KHR)
-            .load_op(vk::AttachmentLoadOp::CLEAR)
-            .store_op(vk::AttachmentStoreOp::STORE)
-            .clear_value(vk::ClearValue {
-                color: vk::ClearColorValue {
-                    float32: [0.0, 0.0, 0.0, 1.0],
-                },
-            })
-            .build();
-
-        let color_attachments = vec![color_attachment_info];
-
-        let rendering_info = vk::RenderingInfo::builder()
-            .render_area(vk::Rect2D {
-                extent: vk::Extent2D::builder()
-                    .width(self.swapchain.desc.extent.width)
-                    .height(self.swapchain.desc.extent.height)
-                    .build(),
-                ..Default::default()
-            })
-            .layer_count(1)
-            .color_attachments(&color_attachments);
-
-        unsafe {
-            self.device
-                .raw
-                .cmd_begin_rendering(raw_cmd_buffer, &rendering_info);
-
-            self.device.raw.cmd_bind_pipeline(
-                raw_cmd_buffer,
-                vk::PipelineBindPoint::GRAPHICS,
-                // self.pipeline.pipeline,
-                self.mesh_pipeline_temp.pipeline,
-            );
-
-            let cam_pos = vec3(0.0, 0.0, -2.0);
-            let view = Mat4::from_translation(cam_pos) * Mat4::from_scale(vec3(1.0, 1.0, 1.0));
-
-            let projection = Mat4::perspective_rh(70.0, 16.0 / 9.0, 0.1, 200.0);
-            let model = Mat4::from_rotation_y(self.frame_number() as f32 * 0.004)
-                * Mat4::from_scale(vec3(1.0, 1.0, 1.0));
-
-            let mesh_matrix = projection * view * model;
-            let constants = MeshPushConstants {
-                render_matrix: mesh_matrix,
-                ..Default::default()
-            };
-
-            self.device.raw.cmd_push_constants(
-                raw_cmd_buffer,
-                self.mesh_pipeline_temp.layout,
-                vk::ShaderStageFlags::VERTEX,
-                0,
-                std::slice::from_raw_parts(
-                    &constants as *const MeshPushConstants as *const u8,
-                    size_of::<MeshPushConstants>(),
-                ),
-            );
-
-            for mesh in &self.meshes {
-                self.device.raw.cmd_bind_vertex_buffers(
-                    raw_cmd_buffer,
-                    0,
-                    &[mesh.vertex_buffer.raw],
-                    &[0],
-                );
-
-                self.device
-                    .raw
-                    .cmd_draw(raw_cmd_buffer, mesh.vertices.len() as u32, 1, 0, 0);
-            }
-
-            self.device.raw.cmd_end_rendering(raw_cmd_buffer);
-        }
+        self.profiler
+            .begin_scope(&self.device, raw_cmd_buffer, "tonemap");
+        self.tonemap_pass.record(
+            &self.device,
+            raw_cmd_buffer,
+            &self.tonemap,
+            self.post_targets.input_sets[post_output],
+            self.swapchain.image_views[swapchain_image.index as usize],
+            self.swapchain.desc.extent,
+        );
+        self.profiler.end_scope(&self.device, raw_cmd_buffer);
 
         // manually set image to a presentable layout
         let img_memory_barrier = vk::ImageMemoryBarrier::builder()
@@ -435,7 +942,7 @@ impl PoogieRenderer {
                 .queue_submit(
                     self.device.graphics_queue.raw,
                     std::slice::from_ref(&submit_info),
-                    self.device.render_fence,
+                    render_fence,
                 )
                 .unwrap();
         }
@@ -472,12 +979,818 @@ impl PoogieRenderer {
         unsafe {
             self.device.raw.device_wait_idle().unwrap();
 
-            for mesh in &mut self.meshes {
+            for mut mesh in self.meshes.drain() {
                 mesh.vertex_buffer
                     .destroy(&self.device, &mut self.allocator);
+                mesh.index_buffer.destroy(&self.device, &mut self.allocator);
+                if let Some(skin_buffer) = &mut mesh.skin_buffer {
+                    skin_buffer.destroy(&self.device, &mut self.allocator);
+                }
+                if let Some(morph_buffer) = &mut mesh.morph_buffer {
+                    morph_buffer.destroy(&self.device, &mut self.allocator);
+                }
+            }
+            self.deletion_queue.flush(&self.device, &mut self.allocator);
+
+            for frame in &mut self.frames {
+                frame.destroy(&self.device, &mut self.allocator);
+            }
+            self.frame_descriptor_pool.destroy(&self.device);
+            self.device
+                .raw
+                .destroy_descriptor_set_layout(self.frame_descriptor_layout, None);
+
+            self.depth_image.destroy(&self.device, &mut self.allocator);
+            self.ssao_pass.destroy(&self.device, &mut self.allocator);
+            self.deferred_pass
+                .destroy(&self.device, &mut self.allocator);
+            self.post_stack.destroy(&self.device);
+            self.bloom_pass.destroy(&self.device, &mut self.allocator);
+            self.post_targets.destroy(&self.device, &mut self.allocator);
+            self.tonemap_pass.destroy(&self.device);
+            self.shadow_maps.destroy(&self.device, &mut self.allocator);
+            self.light_clusters
+                .destroy(&self.device, &mut self.allocator);
+            self.skinning_pass
+                .destroy(&self.device, &mut self.allocator);
+            self.geometry.destroy(&self.device, &mut self.allocator);
+            self.gpu_culling.destroy(&self.device, &mut self.allocator);
+            self.meshlet_culling
+                .destroy(&self.device, &mut self.allocator);
+            self.depth_pyramid
+                .destroy(&self.device, &mut self.allocator);
+            self.background_pass
+                .destroy(&self.device, &mut self.allocator);
+            self.ibl.destroy(&self.device, &mut self.allocator);
+            self.profiler.destroy(&self.device);
+        }
+    }
+
+    /// Shades `draws` on top of the background in the HDR target,
+    /// which has to be in `COLOR_ATTACHMENT_OPTIMAL` already
+    fn record_forward(
+        &self,
+        cmd: vk::CommandBuffer,
+        frame_index: usize,
+        draws: &DrawList,
+        depth_prepassed: bool,
+    ) {
+        let hdr = &self.post_targets.images[SCENE_TARGET];
+
+        if !depth_prepassed {
+            // without a prepass the depth buffer is cleared, so its previous contents can be
+            // discarded, but the previous frame may still be writing to it
+            let depth_memory_barrier = vk::ImageMemoryBarrier::builder()
+                .old_layout(vk::ImageLayout::UNDEFINED)
+                .new_layout(vk::ImageLayout::DEPTH_ATTACHMENT_OPTIMAL)
+                .src_access_mask(vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE)
+                .dst_access_mask(vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE)
+                .image(self.depth_image.raw)
+                .subresource_range(self.depth_image.desc.subresource_range());
+
+            unsafe {
+                self.device.raw.cmd_pipeline_barrier(
+                    cmd,
+                    vk::PipelineStageFlags::LATE_FRAGMENT_TESTS
+                        | vk::PipelineStageFlags::FRAGMENT_SHADER
+                        | vk::PipelineStageFlags::COMPUTE_SHADER,
+                    vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS,
+                    vk::DependencyFlags::empty(),
+                    &[],
+                    &[],
+                    &[depth_memory_barrier.build()],
+                );
+            }
+        }
+
+        let color_attachment_info = vk::RenderingAttachmentInfo::builder()
+            .image_view(hdr.view)
+            .image_layout(vk::ImageLayout::ATTACHMENT_OPTIMAL_KHR)
+            .load_op(vk::AttachmentLoadOp::LOAD)
+            .store_op(vk::AttachmentStoreOp::STORE)
+            .build();
+
+        let color_attachments = vec![color_attachment_info];
+
+        let depth_attachment_info = vk::RenderingAttachmentInfo::builder()
+            .image_view(self.depth_image.view)
+            .image_layout(vk::ImageLayout::DEPTH_ATTACHMENT_OPTIMAL)
+            .load_op(if depth_prepassed {
+                vk::AttachmentLoadOp::LOAD
+            } else {
+                vk::AttachmentLoadOp::CLEAR
+            })
+            .store_op(vk::AttachmentStoreOp::DONT_CARE)
+            .clear_value(vk::ClearValue {
+                depth_stencil: vk::ClearDepthStencilValue {
+                    depth: 1.0,
+                    stencil: 0,
+                },
+            });
+
+        let rendering_info = vk::RenderingInfo::builder()
+            .render_area(vk::Rect2D {
+                extent: vk::Extent2D::builder()
+                    .width(self.swapchain.desc.extent.width)
+                    .height(self.swapchain.desc.extent.height)
+                    .build(),
+                ..Default::default()
+            })
+            .layer_count(1)
+            .color_attachments(&color_attachments)
+            .depth_attachment(&depth_attachment_info);
+
+        unsafe {
+            self.device.raw.cmd_begin_rendering(cmd, &rendering_info);
+
+            self.device.raw.cmd_bind_pipeline(
+                cmd,
+                vk::PipelineBindPoint::GRAPHICS,
+                // self.pipeline.pipeline,
+                self.mesh_pipeline_temp.pipeline,
+            );
+
+            self.device.raw.cmd_bind_descriptor_sets(
+                cmd,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.mesh_pipeline_temp.layout,
+                0,
+                &[self.frames[frame_index].descriptor_set],
+                &[],
+            );
+
+            draws.record(&self.device, cmd, &self.meshes, MeshletTarget::Forward);
+
+            self.device.raw.cmd_end_rendering(cmd);
+        }
+    }
+
+    /// Fills the uniform and storage buffers of frame `frame_index` and returns the draws
+    /// of the camera and of the shadow maps, and the shadow passes, that refer to them
+    fn upload_frame_data(
+        &mut self,
+        frame_index: usize,
+    ) -> (DrawList, Vec<MeshDraw>, Vec<ShadowPass>) {
+        let extent = self.swapchain.desc.extent;
+        self.camera.aspect_ratio = extent.width as f32 / extent.height as f32;
+
+        let projection = self.camera.projection();
+        let view_projection = self.camera.view_projection();
+        let globals = GlobalUniforms {
+            view: self.camera.view(),
+            projection,
+            view_projection,
+            camera_position: self.camera.position.extend(1.0),
+            resolution: Vec2::new(extent.width as f32, extent.height as f32),
+            time: self.start_time.elapsed().as_secs_f32(),
+            frame_number: self.frame_number as u32,
+            ambient_light: self.ambient_light.extend(0.0),
+            light_count: self.lights.len() as u32,
+            cascade_count: self.cascades.count(),
+            ambient_occlusion: self.ssao.enabled as u32,
+            light_culling: self.clusters.light_culling(),
+            cascade_splits: self.cascades.split_distances(&self.camera),
+            inverse_projection: projection.inverse(),
+            inverse_view_projection: view_projection.inverse(),
+            z_near: self.camera.z_near,
+            z_far: self.camera.z_far,
+            environment: (self.environment.enabled && self.ibl.is_loaded()) as u32,
+            environment_intensity: self.environment.intensity,
+        };
+
+        let mut lights = vec![];
+        let mut shadows = vec![];
+        let mut shadow_passes = vec![];
+
+        for (_, light) in self.lights.iter() {
+            let mut data = LightData::from(light);
+
+            if let Some(settings) = light.shadow {
+                let views = shadow_views(
+                    light,
+                    &self.camera,
+                    &self.cascades,
+                    self.shadow_maps.resolution(),
+                );
+
+                // lights that do not fit anymore simply stay unshadowed
+                if shadows.len() + views.len() <= self.shadow_maps.layer_count() as usize {
+                    data.shadow_index = shadows.len() as i32;
+                    shadow_passes.extend((0..views.len()).map(|i| ShadowPass {
+                        layer: (shadows.len() + i) as u32,
+                        settings,
+                    }));
+                    shadows.extend(views);
+                }
+            }
+
+            lights.push(data);
+        }
+
+        // material 0 is the default, the others are offset by one from their slot
+        let mut materials = vec![MaterialData::default(); self.materials.slot_count() + 1];
+        materials[0] = (&self.default_material).into();
+        for (handle, material) in self.materials.iter() {
+            materials[handle.index() as usize + 1] = material.into();
+        }
+
+        self.scene.update_world_matrices();
+
+        let mut instances: Vec<InstanceData> = vec![];
+        let mut draws = vec![];
+        let mut joints = vec![];
+        let mut morph_weights = vec![];
+        let mut deformed = vec![];
+        let mut deformed_draws = vec![];
+        // nodes sharing a mesh and its level of detail are drawn together, unless they deform
+        // it. Visible instances come first so the camera draws a prefix of what the shadow
+        // maps draw.
+        let mut batches: Vec<(MeshHandle, usize, Vec<InstanceData>, Vec<InstanceData>)> = vec![];
+        let mut mesh_batches = HashMap::new();
+        let frustum = Frustum::from_view_projection(view_projection);
+        let mut stats = FrameStats::default();
+        // the settings are public, the device may not support what they ask for
+        self.culling.gpu &= self.device.indirect_draws;
+        self.culling.occlusion &= self.device.storage_image_extended_formats;
+        let gpu_culling = self.culling.gpu;
+        let meshlet_culling = gpu_culling && self.culling.meshlets;
+        let lod_selector = LodSelector::new(self.lod, &self.camera, extent.height);
+
+        for (_, node) in self.scene.nodes() {
+            // nodes may still refer to meshes that have since been removed
+            let mesh = match node.mesh.filter(|&mesh| self.meshes.contains(mesh)) {
+                Some(mesh) => mesh,
+                None => continue,
+            };
+
+            let material_index = node
+                .material
+                .filter(|&material| self.materials.contains(material))
+                .map_or(0, |material| material.index() + 1);
+            let instance = InstanceData::new(node.world_matrix(), node.color, material_index);
+            stats.objects += 1;
+
+            let (bounds, world) = (&self.meshes[mesh], node.world_matrix());
+            let sphere = bounds.bounding_sphere.transform(world);
+            let lod = lod_selector.select(&bounds.lods, &sphere, max_scale(world));
+
+            // skinned meshes without a skin are drawn in their bind pose
+            let skin = node
+                .skin
+                .and_then(|skin| self.scene.skin(skin))
+                .filter(|_| self.meshes[mesh].skin_buffer.is_some());
+            let morph_target_count = self.meshes[mesh].morph_target_count as usize;
+            if skin.is_some() || morph_target_count > 0 {
+                deformed_draws.push(draws.len());
+                deformed.push(DeformedMesh {
+                    mesh,
+                    joint_offset: skin.map(|_| joints.len() as u32),
+                    weight_offset: morph_weights.len() as u32,
+                });
+                if let Some(skin) = skin {
+                    skin.joint_matrices(&self.scene, node.world_matrix(), &mut joints);
+                }
+
+                let weights = match node.parent().and_then(|parent| self.scene.node(parent)) {
+                    Some(parent) if node.morph_weights.is_empty() => &parent.morph_weights,
+                    _ => &node.morph_weights,
+                };
+                let start = morph_weights.len();
+                morph_weights.extend(weights.iter().take(morph_target_count));
+                morph_weights.resize(start + morph_target_count, 0.0);
+
+                let first = instances.len() as u32;
+                // deformed meshes switch levels without fading
+                draws.push(MeshDraw {
+                    mesh,
+                    lod: lod.level,
+                    instances: first..first + 1,
+                    vertices: None,
+                });
+                instances.push(instance);
+                continue;
+            }
+
+            // deformed meshes are never culled, their bounds only hold the bind pose
+            let visible = gpu_culling
+                || !self.culling.frustum
+                || frustum.intersects_sphere(&sphere)
+                    && frustum.intersects_aabb(&bounds.aabb.transform(world));
+            if !visible {
+                stats.culled_objects += 1;
+            }
+
+            // instances fading between two levels are drawn with both, dithered so that
+            // every pixel is covered by exactly one of them
+            let levels = [
+                Some((lod.level, lod.fade.unwrap_or(0.0))),
+                lod.fade.map(|fade| (lod.level + 1, -fade)),
+            ];
+            for (level, fade) in levels.into_iter().flatten() {
+                let batch = *mesh_batches.entry((mesh, level)).or_insert_with(|| {
+                    batches.push((mesh, level, vec![], vec![]));
+                    batches.len() - 1
+                });
+                let instance = instance.with_lod_fade(fade);
+                if visible {
+                    batches[batch].2.push(instance);
+                } else {
+                    batches[batch].3.push(instance);
+                }
+            }
+        }
+
+        let mut shadow_draws = vec![];
+        let mut objects = vec![];
+        let mut gpu_meshes = vec![];
+        let mut meshlet_tasks = vec![];
+        let mut meshlet_triangles = 0;
+        for (mesh, lod, visible, culled) in batches {
+            let first = instances.len() as u32;
+            instances.extend(visible);
+            let visible_end = instances.len() as u32;
+            instances.extend(culled);
+
+            // meshes only take space in the arena once the GPU-driven path draws them
+            if gpu_culling && self.geometry.get(mesh).is_none() {
+                self.geometry.insert(
+                    &mut self.allocator,
+                    &self.device,
+                    &mut self.deletion_queue,
+                    self.frame_number,
+                    mesh,
+                    &self.meshes[mesh],
+                );
+            }
+            match self.geometry.get(mesh).filter(|_| gpu_culling) {
+                // only the full mesh is split into meshlets
+                Some(range) if meshlet_culling && lod == 0 && range.meshlet_count > 0 => {
+                    for instance in first..visible_end {
+                        meshlet_tasks.extend((0..range.meshlet_count).map(|meshlet| MeshletTask {
+                            instance,
+                            meshlet: range.first_meshlet + meshlet,
+                            vertex_offset: range.first_vertex,
+                        }));
+                    }
+                    meshlet_triangles +=
+                        (visible_end - first) as usize * range.meshlet_triangle_count as usize;
+                }
+                Some(range) => {
+                    let mesh_index = gpu_meshes.len() as u32;
+                    objects.extend((first..visible_end).map(|instance| GpuObject {
+                        instance,
+                        mesh: mesh_index,
+                    }));
+                    gpu_meshes.push(GpuMesh::new(&self.meshes[mesh], range, lod));
+                }
+                None if visible_end > first => draws.push(MeshDraw {
+                    mesh,
+                    lod,
+                    instances: first..visible_end,
+                    vertices: None,
+                }),
+                None => {}
+            }
+            shadow_draws.push(MeshDraw {
+                mesh,
+                lod,
+                instances: first..instances.len() as u32,
+                vertices: None,
+            });
+        }
+
+        let (deformed_vertices, offsets) = self
+            .skinning_pass
+            .upload(
+                &mut self.allocator,
+                &self.device,
+                &mut self.deletion_queue,
+                self.frame_number,
+                frame_index,
+                &self.meshes,
+                &joints,
+                &morph_weights,
+                &deformed,
+            )
+            .unwrap();
+        for (&draw, offset) in deformed_draws.iter().zip(offsets) {
+            draws[draw].vertices = Some((deformed_vertices, offset));
+        }
+        shadow_draws.extend(deformed_draws.iter().map(|&draw| draws[draw].clone()));
+
+        let indirect = gpu_culling.then(|| {
+            if let Some(results) = self.gpu_culling.last_results(frame_index) {
+                stats.culled_objects += results.culled;
+                stats.occluded_objects = results.occluded;
+            }
+            self.gpu_culling.upload(
+                &mut self.allocator,
+                &self.device,
+                &mut self.deletion_queue,
+                self.frame_number,
+                frame_index,
+                &self.geometry,
+                &self.depth_pyramid,
+                &frustum,
+                self.culling.occlusion,
+                &objects,
+                &gpu_meshes,
+            )
+        });
+
+        if let Some(culled) = self.meshlet_culling.last_culled(frame_index) {
+            stats.culled_meshlets = culled;
+        }
+        stats.meshlets = meshlet_tasks.len() as u32;
+        let meshlets = (!meshlet_tasks.is_empty()).then(|| {
+            self.meshlet_culling.upload(
+                &mut self.allocator,
+                &self.device,
+                &mut self.deletion_queue,
+                self.frame_number,
+                frame_index,
+                self.frames[frame_index].descriptor_set,
+                &self.geometry,
+                &frustum,
+                self.culling.backface,
+                &meshlet_tasks,
+                meshlet_triangles,
+            )
+        });
+
+        stats.draw_calls =
+            draws.len() as u32 + indirect.is_some() as u32 + meshlets.is_some() as u32;
+        self.stats = stats;
+
+        self.frames[frame_index].upload(
+            &mut self.allocator,
+            &self.device,
+            &mut self.deletion_queue,
+            self.frame_number,
+            &FrameContents {
+                globals,
+                instances: &instances,
+                materials: &materials,
+                lights: &lights,
+                shadows: &shadows,
+            },
+        );
+
+        (
+            DrawList {
+                draws,
+                indirect,
+                meshlets,
+            },
+            shadow_draws,
+            shadow_passes,
+        )
+    }
+
+    /// Counts of the last frame drawn
+    pub fn stats(&self) -> FrameStats {
+        self.stats
+    }
+
+    pub fn add_mesh(&mut self, data: &MeshData, name: impl Into<String>) -> MeshHandle {
+        let mesh = Mesh::new(&mut self.allocator, &self.device, data, name);
+        self.meshes.insert(mesh)
+    }
+
+    /// Replaces the geometry of a mesh, the old buffers stay alive until
+    /// the frames using them have finished rendering
+    pub fn update_mesh(
+        &mut self,
+        handle: MeshHandle,
+        data: &MeshData,
+        name: impl Into<String>,
+    ) -> Result<(), ResourceError> {
+        if !self.meshes.contains(handle) {
+            return Err(ResourceError::InvalidHandle);
+        }
+
+        let mesh = Mesh::new(&mut self.allocator, &self.device, data, name);
+        let old = std::mem::replace(&mut self.meshes[handle], mesh);
+        self.retire_mesh(old);
+        // copied into the arena again the next time the GPU-driven path draws it
+        self.geometry.remove(handle, self.frame_number);
+
+        Ok(())
+    }
+
+    /// Removes a mesh, nodes still referring to it are skipped when drawing
+    pub fn remove_mesh(&mut self, handle: MeshHandle) -> Result<(), ResourceError> {
+        let mesh = self
+            .meshes
+            .remove(handle)
+            .ok_or(ResourceError::InvalidHandle)?;
+        self.retire_mesh(mesh);
+        self.geometry.remove(handle, self.frame_number);
+
+        Ok(())
+    }
+
+    fn retire_mesh(&mut self, mesh: Mesh) {
+        self.deletion_queue
+            .push(self.frame_number, mesh.vertex_buffer);
+        self.deletion_queue
+            .push(self.frame_number, mesh.index_buffer);
+        if let Some(skin_buffer) = mesh.skin_buffer {
+            self.deletion_queue.push(self.frame_number, skin_buffer);
+        }
+        if let Some(morph_buffer) = mesh.morph_buffer {
+            self.deletion_queue.push(self.frame_number, morph_buffer);
+        }
+    }
+
+    pub fn add_material(&mut self, material: Material) -> MaterialHandle {
+        self.materials.insert(material)
+    }
+
+    pub fn material(&self, handle: MaterialHandle) -> Option<&Material> {
+        self.materials.get(handle)
+    }
+
+    pub fn update_material(
+        &mut self,
+        handle: MaterialHandle,
+        material: Material,
+    ) -> Result<(), ResourceError> {
+        let slot = self
+            .materials
+            .get_mut(handle)
+            .ok_or(ResourceError::InvalidHandle)?;
+        *slot = material;
+
+        Ok(())
+    }
+
+    /// Removes a material, nodes still referring to it fall back to the default material
+    pub fn remove_material(&mut self, handle: MaterialHandle) -> Result<(), ResourceError> {
+        self.materials
+            .remove(handle)
+            .map(|_| ())
+            .ok_or(ResourceError::InvalidHandle)
+    }
+
+    /// Adds a scene node drawing `mesh`, optionally attached to `parent`
+    pub fn add_instance(
+        &mut self,
+        mesh: MeshHandle,
+        material: Option<MaterialHandle>,
+        transform: Transform,
+        parent: Option<NodeHandle>,
+    ) -> Result<NodeHandle, ResourceError> {
+        if !self.meshes.contains(mesh)
+            || material.is_some_and(|material| !self.materials.contains(material))
+            || parent.is_some_and(|parent| !self.scene.contains(parent))
+        {
+            return Err(ResourceError::InvalidHandle);
+        }
+
+        let mut node = Node::new("instance")
+            .with_mesh(mesh)
+            .with_transform(transform);
+        node.material = material;
+
+        Ok(self.scene.add_node(node, parent))
+    }
+
+    /// Adds a scene node drawing `mesh` for every instance, nodes sharing a mesh
+    /// are drawn with a single instanced call
+    pub fn add_instances(
+        &mut self,
+        mesh: MeshHandle,
+        instances: &[InstanceDesc],
+        parent: Option<NodeHandle>,
+    ) -> Result<Vec<NodeHandle>, ResourceError> {
+        if instances.iter().any(|instance| {
+            instance
+                .material
+                .is_some_and(|material| !self.materials.contains(material))
+        }) {
+            return Err(ResourceError::InvalidHandle);
+        }
+
+        instances
+            .iter()
+            .map(|instance| {
+                let node =
+                    self.add_instance(mesh, instance.material, instance.transform, parent)?;
+                self.scene.node_mut(node).unwrap().color = instance.color;
+                Ok(node)
+            })
+            .collect()
+    }
+
+    pub fn update_instance(
+        &mut self,
+        handle: NodeHandle,
+        transform: Transform,
+    ) -> Result<(), ResourceError> {
+        if !self.scene.contains(handle) {
+            return Err(ResourceError::InvalidHandle);
+        }
+        self.scene.set_transform(handle, transform);
+
+        Ok(())
+    }
+
+    /// Removes an instance and every node attached below it
+    pub fn remove_instance(&mut self, handle: NodeHandle) -> Result<(), ResourceError> {
+        if !self.scene.remove_node(handle) {
+            return Err(ResourceError::InvalidHandle);
+        }
+
+        Ok(())
+    }
+
+    /// Loads an equirectangular Radiance `.hdr` file and bakes the ambient lighting from it,
+    /// replacing the previous environment
+    pub fn load_environment(&mut self, path: impl AsRef<Path>) -> Result<()> {
+        let hdr = load_hdr(path)?;
+        // the maps are rewritten in place
+        unsafe { self.device.raw.device_wait_idle()? };
+        self.ibl.bake(&mut self.allocator, &self.device, &hdr)
+    }
+
+    /// Loads an equirectangular Radiance `.hdr` file as the cube shown by [`Background::Skybox`],
+    /// replacing the previous skybox
+    pub fn load_skybox(&mut self, path: impl AsRef<Path>) -> Result<()> {
+        let hdr = load_hdr(path)?;
+        unsafe { self.device.raw.device_wait_idle()? };
+        self.background_pass
+            .load_skybox(&mut self.allocator, &self.device, &self.ibl, &hdr)
+    }
+
+    pub fn add_light(&mut self, light: Light) -> LightHandle {
+        self.lights.insert(light)
+    }
+
+    pub fn light(&self, handle: LightHandle) -> Option<&Light> {
+        self.lights.get(handle)
+    }
+
+    pub fn update_light(&mut self, handle: LightHandle, light: Light) -> Result<(), ResourceError> {
+        let slot = self
+            .lights
+            .get_mut(handle)
+            .ok_or(ResourceError::InvalidHandle)?;
+        *slot = light;
+
+        Ok(())
+    }
+
+    pub fn remove_light(&mut self, handle: LightHandle) -> Result<(), ResourceError> {
+        self.lights
+            .remove(handle)
+            .map(|_| ())
+            .ok_or(ResourceError::InvalidHandle)
+    }
+
+    /// Adds the meshes, materials, node hierarchy and animations of a glTF asset,
+    /// all of its root nodes are placed under a single new node
+    pub fn add_gltf(&mut self, asset: &GltfAsset, parent: Option<NodeHandle>) -> GltfInstance {
+        let materials = asset
+            .materials
+            .iter()
+            .map(|material| self.add_material(material.clone()))
+            .collect::<Vec<_>>();
+
+        let meshes = asset
+            .meshes
+            .iter()
+            .map(|mesh| {
+                mesh.primitives
+                    .iter()
+                    .enumerate()
+                    .map(|(i, primitive)| {
+                        let handle = self.add_mesh(&primitive.data, format!("{} #{i}", mesh.name));
+                        (
+                            handle,
+                            primitive.material.map(|material| materials[material]),
+                        )
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+
+        let root = self.scene.add_node(Node::new("glTF root"), parent);
+        let mut nodes = vec![None; asset.nodes.len()];
+        let mut skinned_primitives = vec![];
+
+        let mut stack = asset
+            .roots
+            .iter()
+            .map(|&node| (node, root))
+            .collect::<Vec<_>>();
+
+        while let Some((index, parent)) = stack.pop() {
+            let gltf_node = &asset.nodes[index];
+            let node = self.scene.add_node(
+                Node::new(gltf_node.name.clone())
+                    .with_transform(gltf_node.transform)
+                    .with_morph_weights(gltf_node.morph_weights.clone()),
+                Some(parent),
+            );
+            nodes[index] = Some(node);
+
+            // every primitive becomes a child so each can carry its own material
+            for &(mesh, material) in gltf_node.mesh.map_or(&[][..], |mesh| &meshes[mesh]) {
+                let mut primitive = Node::new(gltf_node.name.clone()).with_mesh(mesh);
+                primitive.material = material;
+                let primitive = self.scene.add_node(primitive, Some(node));
+                if let Some(skin) = gltf_node.skin {
+                    skinned_primitives.push((primitive, skin));
+                }
             }
-            self.meshes.clear();
+
+            stack.extend(gltf_node.children.iter().map(|&child| (child, node)));
+        }
+
+        // skins can only be created once all of their joints exist,
+        // those with joints outside of the default scene are left out
+        let skins = asset
+            .skins
+            .iter()
+            .map(|skin| {
+                let joints = skin
+                    .joints
+                    .iter()
+                    .map(|&joint| nodes[joint])
+                    .collect::<Option<Vec<_>>>()?;
+                Some(self.scene.add_skin(Skin {
+                    name: skin.name.clone(),
+                    joints,
+                    inverse_bind_matrices: skin.inverse_bind_matrices.clone(),
+                }))
+            })
+            .collect::<Vec<_>>();
+        for (primitive, skin) in skinned_primitives {
+            self.scene.node_mut(primitive).unwrap().skin = skins[skin];
         }
+
+        // nodes outside of the default scene were not added
+        let animations = asset
+            .animations
+            .iter()
+            .map(|clip| clip.map_targets(|&index| nodes[index]))
+            .collect();
+
+        GltfInstance { root, animations }
+    }
+
+    /// Appends an effect to the end of the post-processing stack
+    pub fn add_post_effect(&mut self, desc: PostEffectDesc) -> Result<PostEffectHandle> {
+        self.post_stack.add(
+            &self.device,
+            &self.post_targets,
+            self.frame_descriptor_layout,
+            desc,
+        )
+    }
+
+    pub fn post_effect(&self, handle: PostEffectHandle) -> Option<&PostEffect> {
+        self.post_stack.get(handle)
+    }
+
+    pub fn post_effect_mut(&mut self, handle: PostEffectHandle) -> Option<&mut PostEffect> {
+        self.post_stack.get_mut(handle)
+    }
+
+    /// Handles of all post effects in the order they are applied
+    pub fn post_effects(&self) -> &[PostEffectHandle] {
+        self.post_stack.order()
+    }
+
+    pub fn set_post_effect_enabled(
+        &mut self,
+        handle: PostEffectHandle,
+        enabled: bool,
+    ) -> Result<(), ResourceError> {
+        let effect = self
+            .post_stack
+            .get_mut(handle)
+            .ok_or(ResourceError::InvalidHandle)?;
+        effect.enabled = enabled;
+
+        Ok(())
+    }
+
+    pub fn remove_post_effect(&mut self, handle: PostEffectHandle) -> Result<(), ResourceError> {
+        let effect = self
+            .post_stack
+            .remove(handle)
+            .ok_or(ResourceError::InvalidHandle)?;
+        self.deletion_queue
+            .push(self.frame_number, effect.into_retired());
+
+        Ok(())
+    }
+
+    /// GPU time of each pass of the most recent finished frame
+    pub fn gpu_timings(&self) -> &[GpuTiming] {
+        self.profiler.timings()
     }
 
     pub fn frame_number(&self) -> u64 {
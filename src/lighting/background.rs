@@ -0,0 +1,365 @@
+use super::ibl::{IblSettings, ImageBasedLighting, CUBE_FORMAT};
+use crate::{
+    asset::hdr::HdrImage,
+    backend_vulkan::{
+        descriptor::{write_image_descriptor, DescriptorPool, DescriptorSetLayoutBuilder},
+        device::Device,
+        image::{Image, ImageDesc},
+        pipeline::GraphicsPipeline,
+        sampler::create_trilinear_sampler,
+        shader::{ShaderLanguage, ShaderSource, ShaderStage},
+    },
+    HDR_FORMAT,
+};
+use anyhow::Result;
+use ash::vk;
+use glam::Vec3;
+use gpu_allocator::vulkan::Allocator;
+use std::mem::size_of;
+
+/// Resolution of each face of a skybox loaded with [`BackgroundPass::load_skybox`]
+pub const SKYBOX_SIZE: u32 = 1024;
+
+const SKYBOX_BINDING: u32 = 0;
+const ENVIRONMENT_BINDING: u32 = 1;
+const SAMPLER_BINDING: u32 = 2;
+
+/// Values of `BackgroundPushConstants::mode`
+const BACKGROUND_SKYBOX: u32 = 1;
+const BACKGROUND_ENVIRONMENT: u32 = 2;
+const BACKGROUND_SKY: u32 = 3;
+
+/// Single scattering atmosphere lit by the first directional light
+#[derive(Clone, Copy, Debug)]
+pub struct SkySettings {
+    /// Multiplies the radiance of the sky
+    pub intensity: f32,
+    /// Forward scattering of aerosols, between -1 and 1
+    pub mie_anisotropy: f32,
+    /// Draws the sun itself
+    pub sun_disc: bool,
+}
+
+impl Default for SkySettings {
+    fn default() -> Self {
+        SkySettings {
+            intensity: 1.0,
+            mie_anisotropy: 0.76,
+            sun_disc: true,
+        }
+    }
+}
+
+/// What is visible wherever no geometry was drawn
+#[derive(Clone, Copy, Debug)]
+pub enum Background {
+    /// Linear RGB radiance
+    Color(Vec3),
+    /// The cube set with [`BackgroundPass::load_skybox`]
+    Skybox,
+    /// The environment used for image based lighting, scaled by its intensity
+    Environment,
+    Sky(SkySettings),
+}
+
+impl Default for Background {
+    fn default() -> Self {
+        Background::Color(Vec3::ZERO)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+#[repr(C)]
+struct BackgroundPushConstants {
+    mode: u32,
+    intensity: f32,
+    mie_anisotropy: f32,
+    sun_disc: u32,
+}
+
+/// Clears the HDR target and fills it with the [`Background`] before the scene is drawn on top
+pub struct BackgroundPass {
+    skybox: Option<Image>,
+    sampler: vk::Sampler,
+    layout: vk::DescriptorSetLayout,
+    descriptor_pool: DescriptorPool,
+    descriptor_set: vk::DescriptorSet,
+    pipeline: GraphicsPipeline,
+}
+
+impl BackgroundPass {
+    pub fn new(
+        device: &Device,
+        frame_descriptor_layout: vk::DescriptorSetLayout,
+        ibl: &ImageBasedLighting,
+    ) -> Result<Self> {
+        let fragment = vk::ShaderStageFlags::FRAGMENT;
+        let layout = DescriptorSetLayoutBuilder::default()
+            .binding(SKYBOX_BINDING, vk::DescriptorType::SAMPLED_IMAGE, fragment)
+            .binding(
+                ENVIRONMENT_BINDING,
+                vk::DescriptorType::SAMPLED_IMAGE,
+                fragment,
+            )
+            .binding(SAMPLER_BINDING, vk::DescriptorType::SAMPLER, fragment)
+            .build(device)?;
+
+        let descriptor_pool = DescriptorPool::new(
+            device,
+            1,
+            &[
+                vk::DescriptorPoolSize {
+                    ty: vk::DescriptorType::SAMPLED_IMAGE,
+                    descriptor_count: 2,
+                },
+                vk::DescriptorPoolSize {
+                    ty: vk::DescriptorType::SAMPLER,
+                    descriptor_count: 1,
+                },
+            ],
+        )?;
+        let descriptor_set = descriptor_pool.allocate(device, layout)?;
+        let sampler = create_trilinear_sampler(device, vk::SamplerAddressMode::CLAMP_TO_EDGE)?;
+
+        write_image_descriptor(
+            device,
+            descriptor_set,
+            SAMPLER_BINDING,
+            vk::DescriptorType::SAMPLER,
+            vk::ImageView::null(),
+            vk::ImageLayout::UNDEFINED,
+            sampler,
+        );
+        // the skybox binding needs a valid cube until one is loaded
+        for binding in [SKYBOX_BINDING, ENVIRONMENT_BINDING] {
+            write_image_descriptor(
+                device,
+                descriptor_set,
+                binding,
+                vk::DescriptorType::SAMPLED_IMAGE,
+                ibl.environment.view,
+                vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                vk::Sampler::null(),
+            );
+        }
+
+        let shader = |stage: ShaderStage, entry: &str, path: &str| {
+            ShaderSource::builder()
+                .entry(entry)
+                .build(stage, ShaderLanguage::WGSL, path)
+        };
+
+        let pipeline = GraphicsPipeline::builder()
+            .color_formats(&[HDR_FORMAT])
+            .descriptor_set_layouts(&[frame_descriptor_layout, layout])
+            .push_constant_size(size_of::<BackgroundPushConstants>())
+            .build(
+                device,
+                &[
+                    shader(
+                        ShaderStage::Vertex,
+                        "vs_fullscreen",
+                        "./src/shaders/fullscreen.wgsl",
+                    ),
+                    shader(
+                        ShaderStage::Fragment,
+                        "fs_background",
+                        "./src/shaders/background.wgsl",
+                    ),
+                ],
+            )?;
+
+        Ok(BackgroundPass {
+            skybox: None,
+            sampler,
+            layout,
+            descriptor_pool,
+            descriptor_set,
+            pipeline,
+        })
+    }
+
+    /// Whether a skybox has been loaded yet
+    pub fn has_skybox(&self) -> bool {
+        self.skybox.is_some()
+    }
+
+    /// Converts the equirectangular `hdr` into the skybox cube, replacing the previous one.
+    /// Waits for the GPU to finish, the skybox must not be in use.
+    pub fn load_skybox(
+        &mut self,
+        allocator: &mut Allocator,
+        device: &Device,
+        ibl: &ImageBasedLighting,
+        hdr: &HdrImage,
+    ) -> Result<()> {
+        let skybox = Image::new(
+            allocator,
+            device,
+            ImageDesc::new_cube(
+                CUBE_FORMAT,
+                SKYBOX_SIZE,
+                vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::STORAGE,
+            ),
+            "skybox",
+        );
+        ibl.convert_equirect(allocator, device, hdr, &skybox)?;
+
+        write_image_descriptor(
+            device,
+            self.descriptor_set,
+            SKYBOX_BINDING,
+            vk::DescriptorType::SAMPLED_IMAGE,
+            skybox.view,
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            vk::Sampler::null(),
+        );
+        if let Some(mut previous) = self.skybox.replace(skybox) {
+            previous.destroy(device, allocator);
+        }
+
+        Ok(())
+    }
+
+    /// Transitions `hdr` to `COLOR_ATTACHMENT_OPTIMAL`, discarding its previous contents,
+    /// and fills it with `background`. Backgrounds that have not been loaded fall back to black.
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn record(
+        &self,
+        device: &Device,
+        cmd: vk::CommandBuffer,
+        frame_descriptor_set: vk::DescriptorSet,
+        background: Background,
+        ibl: &ImageBasedLighting,
+        environment: &IblSettings,
+        hdr: &Image,
+    ) {
+        let constants = match background {
+            Background::Skybox if self.has_skybox() => Some(BackgroundPushConstants {
+                mode: BACKGROUND_SKYBOX,
+                intensity: 1.0,
+                ..Default::default()
+            }),
+            Background::Environment if ibl.is_loaded() => Some(BackgroundPushConstants {
+                mode: BACKGROUND_ENVIRONMENT,
+                intensity: environment.intensity,
+                ..Default::default()
+            }),
+            Background::Sky(sky) => Some(BackgroundPushConstants {
+                mode: BACKGROUND_SKY,
+                intensity: sky.intensity,
+                mie_anisotropy: sky.mie_anisotropy,
+                sun_disc: sky.sun_disc as u32,
+            }),
+            _ => None,
+        };
+        let clear_color = match background {
+            Background::Color(color) => color,
+            _ => Vec3::ZERO,
+        };
+
+        // the HDR target is fully redrawn, but the previous frame may still be reading it
+        let hdr_memory_barrier = vk::ImageMemoryBarrier::builder()
+            .old_layout(vk::ImageLayout::UNDEFINED)
+            .new_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+            .src_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
+            .dst_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
+            .image(hdr.raw)
+            .subresource_range(hdr.desc.subresource_range());
+
+        unsafe {
+            device.raw.cmd_pipeline_barrier(
+                cmd,
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                    | vk::PipelineStageFlags::FRAGMENT_SHADER
+                    | vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[hdr_memory_barrier.build()],
+            );
+        }
+
+        let color_attachments = [vk::RenderingAttachmentInfo::builder()
+            .image_view(hdr.view)
+            .image_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+            .load_op(if constants.is_some() {
+                vk::AttachmentLoadOp::DONT_CARE
+            } else {
+                vk::AttachmentLoadOp::CLEAR
+            })
+            .store_op(vk::AttachmentStoreOp::STORE)
+            .clear_value(vk::ClearValue {
+                color: vk::ClearColorValue {
+                    float32: clear_color.extend(1.0).to_array(),
+                },
+            })
+            .build()];
+
+        let rendering_info = vk::RenderingInfo::builder()
+            .render_area(vk::Rect2D {
+                extent: hdr.desc.extent_2d(),
+                ..Default::default()
+            })
+            .layer_count(1)
+            .color_attachments(&color_attachments);
+
+        unsafe { device.raw.cmd_begin_rendering(cmd, &rendering_info) };
+
+        if let Some(constants) = constants {
+            unsafe {
+                device.raw.cmd_bind_pipeline(
+                    cmd,
+                    vk::PipelineBindPoint::GRAPHICS,
+                    self.pipeline.pipeline,
+                );
+                device.raw.cmd_bind_descriptor_sets(
+                    cmd,
+                    vk::PipelineBindPoint::GRAPHICS,
+                    self.pipeline.layout,
+                    0,
+                    &[frame_descriptor_set, self.descriptor_set],
+                    &[],
+                );
+            }
+
+            self.pipeline.push_constants(device, cmd, &constants);
+
+            unsafe { device.raw.cmd_draw(cmd, 3, 1, 0, 0) };
+        }
+
+        unsafe {
+            device.raw.cmd_end_rendering(cmd);
+
+            // the scene is drawn on top in a separate rendering pass
+            let barrier = vk::MemoryBarrier::builder()
+                .src_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
+                .dst_access_mask(
+                    vk::AccessFlags::COLOR_ATTACHMENT_READ
+                        | vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+                );
+            device.raw.cmd_pipeline_barrier(
+                cmd,
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+                vk::DependencyFlags::empty(),
+                &[barrier.build()],
+                &[],
+                &[],
+            );
+        }
+    }
+
+    pub fn destroy(&mut self, device: &Device, allocator: &mut Allocator) {
+        if let Some(skybox) = &mut self.skybox {
+            skybox.destroy(device, allocator);
+        }
+        self.descriptor_pool.destroy(device);
+        self.pipeline.destroy(device);
+        unsafe {
+            device.raw.destroy_sampler(self.sampler, None);
+            device.raw.destroy_descriptor_set_layout(self.layout, None);
+        }
+    }
+}
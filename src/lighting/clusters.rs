@@ -0,0 +1,156 @@
+use crate::backend_vulkan::{
+    buffer::Buffer,
+    device::Device,
+    pipeline::ComputePipeline,
+    shader::{ShaderLanguage, ShaderSource, ShaderStage},
+};
+use anyhow::Result;
+use ash::vk;
+use gpu_allocator::vulkan::Allocator;
+use std::mem::size_of;
+
+/// Number of clusters along the screen width, height and view depth
+pub const CLUSTER_GRID: [u32; 3] = [16, 9, 24];
+/// Lights beyond this many in a single cluster are dropped
+pub const MAX_LIGHTS_PER_CLUSTER: usize = 127;
+pub const CLUSTER_COUNT: usize = (CLUSTER_GRID[0] * CLUSTER_GRID[1] * CLUSTER_GRID[2]) as usize;
+
+/// Workgroup size of the binning shader in all three dimensions
+const GROUP_SIZE: u32 = 4;
+
+/// Matches `Cluster` in the shaders
+#[derive(Clone, Copy)]
+#[repr(C)]
+struct Cluster {
+    count: u32,
+    lights: [u32; MAX_LIGHTS_PER_CLUSTER],
+}
+
+/// Values of `GlobalUniforms::light_culling`
+const LIGHT_CULLING_NONE: u32 = 0;
+const LIGHT_CULLING_CLUSTERED: u32 = 1;
+const LIGHT_CULLING_HEATMAP: u32 = 2;
+
+/// Clustered light culling, lets every fragment only iterate the lights that can reach it
+#[derive(Clone, Copy, Debug)]
+pub struct ClusterSettings {
+    pub enabled: bool,
+    /// Tints the scene by the number of lights in each cluster
+    pub heatmap: bool,
+}
+
+impl Default for ClusterSettings {
+    fn default() -> Self {
+        ClusterSettings {
+            enabled: true,
+            heatmap: false,
+        }
+    }
+}
+
+impl ClusterSettings {
+    pub(crate) fn light_culling(&self) -> u32 {
+        match (self.enabled, self.heatmap) {
+            (false, _) => LIGHT_CULLING_NONE,
+            (true, false) => LIGHT_CULLING_CLUSTERED,
+            (true, true) => LIGHT_CULLING_HEATMAP,
+        }
+    }
+}
+
+/// Bins the frame's lights into a grid of view-space froxels, the result is
+/// read through `CLUSTERS_BINDING` of the frame descriptor set
+pub struct LightClusters {
+    pub buffer: Buffer,
+    pipeline: ComputePipeline,
+}
+
+impl LightClusters {
+    pub fn new(
+        allocator: &mut Allocator,
+        device: &Device,
+        frame_descriptor_layout: vk::DescriptorSetLayout,
+    ) -> Result<Self> {
+        let buffer = Buffer::new(
+            allocator,
+            device,
+            CLUSTER_COUNT * size_of::<Cluster>(),
+            vk::BufferUsageFlags::STORAGE_BUFFER,
+            "light clusters",
+        );
+
+        let shader = ShaderSource::builder().entry("cs_cluster_lights").build(
+            ShaderStage::Compute,
+            ShaderLanguage::WGSL,
+            "./src/shaders/clusters.wgsl",
+        );
+        let pipeline = ComputePipeline::new(device, &shader, &[frame_descriptor_layout], 0)?;
+
+        Ok(LightClusters { buffer, pipeline })
+    }
+
+    /// Rebuilds the clusters from the lights and camera of `frame_descriptor_set`,
+    /// does nothing when culling is disabled
+    pub fn record(
+        &self,
+        device: &Device,
+        cmd: vk::CommandBuffer,
+        settings: &ClusterSettings,
+        frame_descriptor_set: vk::DescriptorSet,
+    ) {
+        if !settings.enabled {
+            return;
+        }
+
+        unsafe {
+            // the previous frame may still be shading with the old clusters
+            device.raw.cmd_pipeline_barrier(
+                cmd,
+                vk::PipelineStageFlags::FRAGMENT_SHADER,
+                vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[],
+            );
+
+            device.raw.cmd_bind_pipeline(
+                cmd,
+                vk::PipelineBindPoint::COMPUTE,
+                self.pipeline.pipeline,
+            );
+            device.raw.cmd_bind_descriptor_sets(
+                cmd,
+                vk::PipelineBindPoint::COMPUTE,
+                self.pipeline.layout,
+                0,
+                &[frame_descriptor_set],
+                &[],
+            );
+            device.raw.cmd_dispatch(
+                cmd,
+                CLUSTER_GRID[0].div_ceil(GROUP_SIZE),
+                CLUSTER_GRID[1].div_ceil(GROUP_SIZE),
+                CLUSTER_GRID[2].div_ceil(GROUP_SIZE),
+            );
+
+            let barrier = vk::MemoryBarrier::builder()
+                .src_access_mask(vk::AccessFlags::SHADER_WRITE)
+                .dst_access_mask(vk::AccessFlags::SHADER_READ);
+            device.raw.cmd_pipeline_barrier(
+                cmd,
+                vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::PipelineStageFlags::FRAGMENT_SHADER,
+                vk::DependencyFlags::empty(),
+                &[barrier.build()],
+                &[],
+                &[],
+            );
+        }
+    }
+
+    pub fn destroy(&mut self, device: &Device, allocator: &mut Allocator) {
+        self.buffer.destroy(device, allocator);
+        self.pipeline.destroy(device);
+    }
+}
@@ -0,0 +1,681 @@
+use crate::{
+    asset::hdr::HdrImage,
+    backend_vulkan::{
+        buffer::Buffer,
+        descriptor::{write_image_descriptor, DescriptorPool, DescriptorSetLayoutBuilder},
+        device::Device,
+        image::{Image, ImageDesc},
+        pipeline::{compute_write_barrier, ComputePipeline},
+        sampler::create_trilinear_sampler,
+        shader::{ShaderLanguage, ShaderSource, ShaderStage},
+    },
+};
+use anyhow::Result;
+use ash::vk;
+use gpu_allocator::vulkan::Allocator;
+use std::mem::size_of;
+
+/// Resolution of each face of the environment cube, which has a full mip chain
+pub const ENVIRONMENT_SIZE: u32 = 512;
+pub const IRRADIANCE_SIZE: u32 = 32;
+pub const PREFILTERED_SIZE: u32 = 128;
+/// Mips of the prefiltered cube, spanning roughness 0 to 1 in even steps.
+/// Matches `PREFILTERED_MIPS` in the shaders
+pub const PREFILTERED_MIPS: u32 = 5;
+pub const BRDF_LUT_SIZE: u32 = 256;
+
+pub(crate) const CUBE_FORMAT: vk::Format = vk::Format::R16G16B16A16_SFLOAT;
+const PREFILTER_SAMPLES: u32 = 1024;
+const BRDF_LUT_SAMPLES: u32 = 1024;
+/// Workgroup size of the baking shaders in x and y
+const GROUP_SIZE: u32 = 8;
+
+const EQUIRECT_BINDING: u32 = 0;
+const SOURCE_CUBE_BINDING: u32 = 1;
+const SOURCE_MIP_BINDING: u32 = 2;
+const SAMPLER_BINDING: u32 = 3;
+const OUTPUT_CUBE_BINDING: u32 = 4;
+const OUTPUT_LUT_BINDING: u32 = 5;
+
+/// Ambient lighting from an environment map, replaces the constant ambient light once
+/// an environment has been loaded
+#[derive(Clone, Copy, Debug)]
+pub struct IblSettings {
+    pub enabled: bool,
+    /// Multiplies the radiance of the environment
+    pub intensity: f32,
+}
+
+impl Default for IblSettings {
+    fn default() -> Self {
+        IblSettings {
+            enabled: true,
+            intensity: 1.0,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+#[repr(C)]
+struct BakeParams {
+    roughness: f32,
+    sample_count: u32,
+    source_lod: f32,
+}
+
+struct BakeDispatch<'a> {
+    pipeline: &'a ComputePipeline,
+    set: vk::DescriptorSet,
+    params: BakeParams,
+    size: u32,
+    layers: u32,
+}
+
+/// Split sum image based lighting: a diffuse irradiance cube, a specular cube prefiltered
+/// per roughness and the BRDF lookup table, all in `SHADER_READ_ONLY_OPTIMAL`
+pub struct ImageBasedLighting {
+    /// The environment itself, as loaded
+    pub environment: Image,
+    pub irradiance: Image,
+    pub prefiltered: Image,
+    pub brdf_lut: Image,
+    /// Trilinear and clamped to the edges
+    pub sampler: vk::Sampler,
+    loaded: bool,
+    layout: vk::DescriptorSetLayout,
+    equirect_pipeline: ComputePipeline,
+    downsample_pipeline: ComputePipeline,
+    irradiance_pipeline: ComputePipeline,
+    prefilter_pipeline: ComputePipeline,
+    brdf_lut_pipeline: ComputePipeline,
+}
+
+impl ImageBasedLighting {
+    /// Generates the BRDF lookup table, the cubes stay empty until [`Self::bake`]
+    pub fn new(allocator: &mut Allocator, device: &Device) -> Result<Self> {
+        let usage = vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::STORAGE;
+        let environment = Image::new(
+            allocator,
+            device,
+            ImageDesc::new_cube(CUBE_FORMAT, ENVIRONMENT_SIZE, usage)
+                .mip_levels(ENVIRONMENT_SIZE.ilog2() + 1),
+            "environment",
+        );
+        let irradiance = Image::new(
+            allocator,
+            device,
+            ImageDesc::new_cube(CUBE_FORMAT, IRRADIANCE_SIZE, usage),
+            "irradiance",
+        );
+        let prefiltered = Image::new(
+            allocator,
+            device,
+            ImageDesc::new_cube(CUBE_FORMAT, PREFILTERED_SIZE, usage).mip_levels(PREFILTERED_MIPS),
+            "prefiltered environment",
+        );
+        let brdf_lut = Image::new(
+            allocator,
+            device,
+            ImageDesc::new_2d(
+                vk::Format::R16G16B16A16_SFLOAT,
+                vk::Extent2D {
+                    width: BRDF_LUT_SIZE,
+                    height: BRDF_LUT_SIZE,
+                },
+                usage,
+            ),
+            "brdf lut",
+        );
+
+        let compute = vk::ShaderStageFlags::COMPUTE;
+        let layout = DescriptorSetLayoutBuilder::default()
+            .binding(EQUIRECT_BINDING, vk::DescriptorType::SAMPLED_IMAGE, compute)
+            .binding(
+                SOURCE_CUBE_BINDING,
+                vk::DescriptorType::SAMPLED_IMAGE,
+                compute,
+            )
+            .binding(
+                SOURCE_MIP_BINDING,
+                vk::DescriptorType::SAMPLED_IMAGE,
+                compute,
+            )
+            .binding(SAMPLER_BINDING, vk::DescriptorType::SAMPLER, compute)
+            .binding(
+                OUTPUT_CUBE_BINDING,
+                vk::DescriptorType::STORAGE_IMAGE,
+                compute,
+            )
+            .binding(
+                OUTPUT_LUT_BINDING,
+                vk::DescriptorType::STORAGE_IMAGE,
+                compute,
+            )
+            .build(device)?;
+
+        let pipeline = |entry: &str| {
+            let shader = ShaderSource::builder().entry(entry).build(
+                ShaderStage::Compute,
+                ShaderLanguage::WGSL,
+                "./src/shaders/ibl.wgsl",
+            );
+            ComputePipeline::new(device, &shader, &[layout], size_of::<BakeParams>())
+        };
+
+        let ibl = ImageBasedLighting {
+            environment,
+            irradiance,
+            prefiltered,
+            brdf_lut,
+            sampler: create_trilinear_sampler(device, vk::SamplerAddressMode::CLAMP_TO_EDGE)?,
+            loaded: false,
+            layout,
+            equirect_pipeline: pipeline("cs_equirect_to_cube")?,
+            downsample_pipeline: pipeline("cs_downsample")?,
+            irradiance_pipeline: pipeline("cs_irradiance")?,
+            prefilter_pipeline: pipeline("cs_prefilter")?,
+            brdf_lut_pipeline: pipeline("cs_brdf_lut")?,
+        };
+
+        let mut pool = Self::bake_pool(device, 1)?;
+        let set = ibl.allocate_set(
+            device,
+            &pool,
+            &[(
+                OUTPUT_LUT_BINDING,
+                ibl.brdf_lut.view,
+                vk::ImageLayout::GENERAL,
+            )],
+        )?;
+        let cubes = [&ibl.environment, &ibl.irradiance, &ibl.prefiltered];
+
+        device.immediate_submit(|cmd| {
+            let mut barriers = cubes
+                .iter()
+                .map(|image| {
+                    layout_barrier(
+                        image,
+                        vk::ImageLayout::UNDEFINED,
+                        vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                        vk::AccessFlags::empty(),
+                        vk::AccessFlags::SHADER_READ,
+                    )
+                })
+                .collect::<Vec<_>>();
+            barriers.push(layout_barrier(
+                &ibl.brdf_lut,
+                vk::ImageLayout::UNDEFINED,
+                vk::ImageLayout::GENERAL,
+                vk::AccessFlags::empty(),
+                vk::AccessFlags::SHADER_WRITE,
+            ));
+            pipeline_barrier(
+                device,
+                cmd,
+                vk::PipelineStageFlags::TOP_OF_PIPE,
+                vk::PipelineStageFlags::COMPUTE_SHADER,
+                &barriers,
+            );
+
+            ibl.dispatch(
+                device,
+                cmd,
+                &BakeDispatch {
+                    pipeline: &ibl.brdf_lut_pipeline,
+                    set,
+                    params: BakeParams {
+                        sample_count: BRDF_LUT_SAMPLES,
+                        ..Default::default()
+                    },
+                    size: BRDF_LUT_SIZE,
+                    layers: 1,
+                },
+            );
+
+            pipeline_barrier(
+                device,
+                cmd,
+                vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::PipelineStageFlags::FRAGMENT_SHADER | vk::PipelineStageFlags::COMPUTE_SHADER,
+                &[layout_barrier(
+                    &ibl.brdf_lut,
+                    vk::ImageLayout::GENERAL,
+                    vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                    vk::AccessFlags::SHADER_WRITE,
+                    vk::AccessFlags::SHADER_READ,
+                )],
+            );
+        })?;
+
+        pool.destroy(device);
+
+        Ok(ibl)
+    }
+
+    /// Whether an environment has been baked yet
+    pub fn is_loaded(&self) -> bool {
+        self.loaded
+    }
+
+    /// Converts the equirectangular `hdr` into the environment cube and bakes the
+    /// lighting from it, waits for the GPU to finish. The maps must not be in use.
+    pub fn bake(
+        &mut self,
+        allocator: &mut Allocator,
+        device: &Device,
+        hdr: &HdrImage,
+    ) -> Result<()> {
+        self.convert_equirect(allocator, device, hdr, &self.environment)?;
+
+        let prefiltered_views = (0..PREFILTERED_MIPS)
+            .map(|mip| self.prefiltered.create_mip_view(device, mip))
+            .collect::<Vec<_>>();
+        let irradiance_view = self.irradiance.create_mip_view(device, 0);
+
+        let mut pool = Self::bake_pool(device, 1 + PREFILTERED_MIPS)?;
+        let general = vk::ImageLayout::GENERAL;
+        let environment = (
+            SOURCE_CUBE_BINDING,
+            self.environment.view,
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        );
+        let mut dispatches = vec![];
+
+        dispatches.push(BakeDispatch {
+            pipeline: &self.irradiance_pipeline,
+            set: self.allocate_set(
+                device,
+                &pool,
+                &[environment, (OUTPUT_CUBE_BINDING, irradiance_view, general)],
+            )?,
+            params: BakeParams {
+                // about as detailed as the irradiance cube itself
+                source_lod: (ENVIRONMENT_SIZE / IRRADIANCE_SIZE).ilog2() as f32,
+                ..Default::default()
+            },
+            size: IRRADIANCE_SIZE,
+            layers: 6,
+        });
+
+        for (mip, &view) in prefiltered_views.iter().enumerate() {
+            dispatches.push(BakeDispatch {
+                pipeline: &self.prefilter_pipeline,
+                set: self.allocate_set(
+                    device,
+                    &pool,
+                    &[environment, (OUTPUT_CUBE_BINDING, view, general)],
+                )?,
+                params: BakeParams {
+                    roughness: mip as f32 / (PREFILTERED_MIPS - 1) as f32,
+                    sample_count: PREFILTER_SAMPLES,
+                    source_lod: (ENVIRONMENT_SIZE / PREFILTERED_SIZE).ilog2() as f32,
+                },
+                size: PREFILTERED_SIZE >> mip,
+                layers: 6,
+            });
+        }
+
+        let cubes = [&self.irradiance, &self.prefiltered];
+
+        device.immediate_submit(|cmd| {
+            // the previous contents of the cubes are replaced entirely
+            let barriers = cubes
+                .iter()
+                .map(|image| {
+                    layout_barrier(
+                        image,
+                        vk::ImageLayout::UNDEFINED,
+                        general,
+                        vk::AccessFlags::empty(),
+                        vk::AccessFlags::SHADER_WRITE,
+                    )
+                })
+                .collect::<Vec<_>>();
+            pipeline_barrier(
+                device,
+                cmd,
+                vk::PipelineStageFlags::TOP_OF_PIPE,
+                vk::PipelineStageFlags::COMPUTE_SHADER,
+                &barriers,
+            );
+
+            // the dispatches write to separate images, so they need no barriers in between
+            for dispatch in &dispatches {
+                self.dispatch(device, cmd, dispatch);
+            }
+
+            let barriers = cubes
+                .iter()
+                .map(|image| {
+                    layout_barrier(
+                        image,
+                        general,
+                        vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                        vk::AccessFlags::SHADER_WRITE,
+                        vk::AccessFlags::SHADER_READ,
+                    )
+                })
+                .collect::<Vec<_>>();
+            pipeline_barrier(
+                device,
+                cmd,
+                vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::PipelineStageFlags::FRAGMENT_SHADER | vk::PipelineStageFlags::COMPUTE_SHADER,
+                &barriers,
+            );
+        })?;
+
+        pool.destroy(device);
+        unsafe {
+            for view in prefiltered_views.into_iter().chain([irradiance_view]) {
+                device.raw.destroy_image_view(view, None);
+            }
+        }
+
+        self.loaded = true;
+        Ok(())
+    }
+
+    /// Resamples the equirectangular `hdr` onto every face of `cube` and fills its mip chain,
+    /// waits for the GPU to finish and leaves `cube` in `SHADER_READ_ONLY_OPTIMAL`.
+    /// `cube` needs storage usage and the format of the IBL cubes, and must not be in use.
+    pub fn convert_equirect(
+        &self,
+        allocator: &mut Allocator,
+        device: &Device,
+        hdr: &HdrImage,
+        cube: &Image,
+    ) -> Result<()> {
+        let mut staging = Buffer::new_with_data(
+            allocator,
+            device,
+            &hdr.pixels,
+            vk::BufferUsageFlags::TRANSFER_SRC,
+            "environment staging",
+        );
+        // 32-bit floats keep the full range of RGBE
+        let mut equirect = Image::new(
+            allocator,
+            device,
+            ImageDesc::new_2d(
+                vk::Format::R32G32B32A32_SFLOAT,
+                vk::Extent2D {
+                    width: hdr.width,
+                    height: hdr.height,
+                },
+                vk::ImageUsageFlags::TRANSFER_DST | vk::ImageUsageFlags::SAMPLED,
+            ),
+            "environment equirect",
+        );
+
+        let size = cube.desc.extent.width;
+        let mips = cube.desc.mip_levels;
+        let views = (0..mips)
+            .map(|mip| cube.create_mip_view(device, mip))
+            .collect::<Vec<_>>();
+
+        let mut pool = Self::bake_pool(device, mips)?;
+        let general = vk::ImageLayout::GENERAL;
+        let mut dispatches = vec![];
+
+        dispatches.push(BakeDispatch {
+            pipeline: &self.equirect_pipeline,
+            set: self.allocate_set(
+                device,
+                &pool,
+                &[
+                    (
+                        EQUIRECT_BINDING,
+                        equirect.view,
+                        vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                    ),
+                    (OUTPUT_CUBE_BINDING, views[0], general),
+                ],
+            )?,
+            params: BakeParams::default(),
+            size,
+            layers: 6,
+        });
+
+        for mip in 1..mips as usize {
+            dispatches.push(BakeDispatch {
+                pipeline: &self.downsample_pipeline,
+                set: self.allocate_set(
+                    device,
+                    &pool,
+                    &[
+                        (SOURCE_MIP_BINDING, views[mip - 1], general),
+                        (OUTPUT_CUBE_BINDING, views[mip], general),
+                    ],
+                )?,
+                params: BakeParams::default(),
+                size: size >> mip,
+                layers: 6,
+            });
+        }
+
+        device.immediate_submit(|cmd| {
+            pipeline_barrier(
+                device,
+                cmd,
+                vk::PipelineStageFlags::TOP_OF_PIPE,
+                vk::PipelineStageFlags::TRANSFER,
+                &[layout_barrier(
+                    &equirect,
+                    vk::ImageLayout::UNDEFINED,
+                    vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                    vk::AccessFlags::empty(),
+                    vk::AccessFlags::TRANSFER_WRITE,
+                )],
+            );
+
+            let region = vk::BufferImageCopy::builder()
+                .image_subresource(vk::ImageSubresourceLayers {
+                    aspect_mask: vk::ImageAspectFlags::COLOR,
+                    mip_level: 0,
+                    base_array_layer: 0,
+                    layer_count: 1,
+                })
+                .image_extent(equirect.desc.extent);
+            unsafe {
+                device.raw.cmd_copy_buffer_to_image(
+                    cmd,
+                    staging.raw,
+                    equirect.raw,
+                    vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                    &[region.build()],
+                );
+            }
+
+            // the previous contents of the cube are replaced entirely
+            let barriers = [
+                layout_barrier(
+                    cube,
+                    vk::ImageLayout::UNDEFINED,
+                    general,
+                    vk::AccessFlags::empty(),
+                    vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE,
+                ),
+                layout_barrier(
+                    &equirect,
+                    vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                    vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                    vk::AccessFlags::TRANSFER_WRITE,
+                    vk::AccessFlags::SHADER_READ,
+                ),
+            ];
+            pipeline_barrier(
+                device,
+                cmd,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::COMPUTE_SHADER,
+                &barriers,
+            );
+
+            for (i, dispatch) in dispatches.iter().enumerate() {
+                if i > 0 {
+                    compute_write_barrier(device, cmd);
+                }
+                self.dispatch(device, cmd, dispatch);
+            }
+
+            pipeline_barrier(
+                device,
+                cmd,
+                vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::PipelineStageFlags::FRAGMENT_SHADER | vk::PipelineStageFlags::COMPUTE_SHADER,
+                &[layout_barrier(
+                    cube,
+                    general,
+                    vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                    vk::AccessFlags::SHADER_WRITE,
+                    vk::AccessFlags::SHADER_READ,
+                )],
+            );
+        })?;
+
+        pool.destroy(device);
+        unsafe {
+            for view in views {
+                device.raw.destroy_image_view(view, None);
+            }
+        }
+        staging.destroy(device, allocator);
+        equirect.destroy(device, allocator);
+
+        Ok(())
+    }
+
+    fn bake_pool(device: &Device, max_sets: u32) -> Result<DescriptorPool> {
+        DescriptorPool::new(
+            device,
+            max_sets,
+            &[
+                vk::DescriptorPoolSize {
+                    ty: vk::DescriptorType::SAMPLED_IMAGE,
+                    descriptor_count: 3 * max_sets,
+                },
+                vk::DescriptorPoolSize {
+                    ty: vk::DescriptorType::SAMPLER,
+                    descriptor_count: max_sets,
+                },
+                vk::DescriptorPoolSize {
+                    ty: vk::DescriptorType::STORAGE_IMAGE,
+                    descriptor_count: 2 * max_sets,
+                },
+            ],
+        )
+    }
+
+    /// Allocates a set pointing at the sampler and the given `(binding, view, layout)` images,
+    /// the other bindings are left empty
+    fn allocate_set(
+        &self,
+        device: &Device,
+        pool: &DescriptorPool,
+        images: &[(u32, vk::ImageView, vk::ImageLayout)],
+    ) -> Result<vk::DescriptorSet> {
+        let set = pool.allocate(device, self.layout)?;
+
+        write_image_descriptor(
+            device,
+            set,
+            SAMPLER_BINDING,
+            vk::DescriptorType::SAMPLER,
+            vk::ImageView::null(),
+            vk::ImageLayout::UNDEFINED,
+            self.sampler,
+        );
+        for &(binding, view, layout) in images {
+            let ty = match binding {
+                OUTPUT_CUBE_BINDING | OUTPUT_LUT_BINDING => vk::DescriptorType::STORAGE_IMAGE,
+                _ => vk::DescriptorType::SAMPLED_IMAGE,
+            };
+            write_image_descriptor(device, set, binding, ty, view, layout, vk::Sampler::null());
+        }
+
+        Ok(set)
+    }
+
+    fn dispatch(&self, device: &Device, cmd: vk::CommandBuffer, dispatch: &BakeDispatch) {
+        unsafe {
+            device.raw.cmd_bind_pipeline(
+                cmd,
+                vk::PipelineBindPoint::COMPUTE,
+                dispatch.pipeline.pipeline,
+            );
+            device.raw.cmd_bind_descriptor_sets(
+                cmd,
+                vk::PipelineBindPoint::COMPUTE,
+                dispatch.pipeline.layout,
+                0,
+                &[dispatch.set],
+                &[],
+            );
+        }
+
+        dispatch
+            .pipeline
+            .push_constants(device, cmd, &dispatch.params);
+
+        let groups = dispatch.size.div_ceil(GROUP_SIZE);
+        unsafe {
+            device
+                .raw
+                .cmd_dispatch(cmd, groups, groups, dispatch.layers)
+        };
+    }
+
+    pub fn destroy(&mut self, device: &Device, allocator: &mut Allocator) {
+        self.environment.destroy(device, allocator);
+        self.irradiance.destroy(device, allocator);
+        self.prefiltered.destroy(device, allocator);
+        self.brdf_lut.destroy(device, allocator);
+        unsafe {
+            device.raw.destroy_sampler(self.sampler, None);
+            device.raw.destroy_descriptor_set_layout(self.layout, None);
+        }
+        self.equirect_pipeline.destroy(device);
+        self.downsample_pipeline.destroy(device);
+        self.irradiance_pipeline.destroy(device);
+        self.prefilter_pipeline.destroy(device);
+        self.brdf_lut_pipeline.destroy(device);
+    }
+}
+
+fn layout_barrier(
+    image: &Image,
+    old_layout: vk::ImageLayout,
+    new_layout: vk::ImageLayout,
+    src_access_mask: vk::AccessFlags,
+    dst_access_mask: vk::AccessFlags,
+) -> vk::ImageMemoryBarrier {
+    vk::ImageMemoryBarrier::builder()
+        .old_layout(old_layout)
+        .new_layout(new_layout)
+        .src_access_mask(src_access_mask)
+        .dst_access_mask(dst_access_mask)
+        .image(image.raw)
+        .subresource_range(image.desc.subresource_range())
+        .build()
+}
+
+fn pipeline_barrier(
+    device: &Device,
+    cmd: vk::CommandBuffer,
+    src_stage_mask: vk::PipelineStageFlags,
+    dst_stage_mask: vk::PipelineStageFlags,
+    barriers: &[vk::ImageMemoryBarrier],
+) {
+    unsafe {
+        device.raw.cmd_pipeline_barrier(
+            cmd,
+            src_stage_mask,
+            dst_stage_mask,
+            vk::DependencyFlags::empty(),
+            &[],
+            &[],
+            barriers,
+        );
+    }
+}
@@ -0,0 +1,150 @@
+use super::ShadowSettings;
+use glam::Vec3;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LightKind {
+    /// Infinitely far away, lights everything along `direction`
+    Directional,
+    /// Radiates in every direction from `position`
+    Point,
+    /// Cone around `direction` from `position`, angles are in radians from the axis
+    Spot {
+        inner_cone_angle: f32,
+        outer_cone_angle: f32,
+    },
+}
+
+/// A punctual light, following the conventions of `KHR_lights_punctual`
+#[derive(Clone, Copy, Debug)]
+pub struct Light {
+    pub kind: LightKind,
+    pub position: Vec3,
+    pub direction: Vec3,
+    /// Linear RGB
+    pub color: Vec3,
+    /// Lux for directional lights, candela for point and spot lights
+    pub intensity: f32,
+    /// Distance at which point and spot lights fade out completely,
+    /// `None` falls back to the inverse square law only
+    pub range: Option<f32>,
+    /// Lights without shadow settings do not cast shadows
+    pub shadow: Option<ShadowSettings>,
+}
+
+impl Light {
+    pub fn directional(direction: Vec3, color: Vec3, intensity: f32) -> Self {
+        Light {
+            kind: LightKind::Directional,
+            position: Vec3::ZERO,
+            direction: direction.normalize(),
+            color,
+            intensity,
+            range: None,
+            shadow: None,
+        }
+    }
+
+    pub fn point(position: Vec3, color: Vec3, intensity: f32) -> Self {
+        Light {
+            kind: LightKind::Point,
+            position,
+            direction: Vec3::NEG_Z,
+            color,
+            intensity,
+            range: None,
+            shadow: None,
+        }
+    }
+
+    pub fn spot(
+        position: Vec3,
+        direction: Vec3,
+        color: Vec3,
+        intensity: f32,
+        inner_cone_angle: f32,
+        outer_cone_angle: f32,
+    ) -> Self {
+        Light {
+            kind: LightKind::Spot {
+                inner_cone_angle,
+                outer_cone_angle,
+            },
+            position,
+            direction: direction.normalize(),
+            color,
+            intensity,
+            range: None,
+            shadow: None,
+        }
+    }
+
+    pub fn with_range(mut self, range: f32) -> Self {
+        self.range = Some(range);
+        self
+    }
+
+    pub fn with_shadows(mut self, shadow: ShadowSettings) -> Self {
+        self.shadow = Some(shadow);
+        self
+    }
+}
+
+pub const LIGHT_KIND_DIRECTIONAL: u32 = 0;
+pub const LIGHT_KIND_POINT: u32 = 1;
+pub const LIGHT_KIND_SPOT: u32 = 2;
+
+/// Layout of a light in the per-frame light buffer, matches `LightData` in the shaders
+#[derive(Clone, Copy, Debug, Default)]
+#[repr(C)]
+pub struct LightData {
+    pub position: Vec3,
+    pub kind: u32,
+    pub direction: Vec3,
+    /// Zero when the light has no range
+    pub range: f32,
+    pub color: Vec3,
+    pub intensity: f32,
+    pub inner_cone_cos: f32,
+    pub outer_cone_cos: f32,
+    /// Index of the first `ShadowData` of this light, negative when it casts no shadows
+    pub shadow_index: i32,
+    /// Offset along the surface normal in shadow map texels
+    pub normal_bias: f32,
+    /// Half-size of the PCF kernel in texels
+    pub pcf_radius: u32,
+    pub _padding: [u32; 3],
+}
+
+impl From<&Light> for LightData {
+    fn from(light: &Light) -> Self {
+        let (kind, inner_cone_cos, outer_cone_cos) = match light.kind {
+            LightKind::Directional => (LIGHT_KIND_DIRECTIONAL, 0.0, 0.0),
+            LightKind::Point => (LIGHT_KIND_POINT, 0.0, 0.0),
+            LightKind::Spot {
+                inner_cone_angle,
+                outer_cone_angle,
+            } => (
+                LIGHT_KIND_SPOT,
+                inner_cone_angle.cos(),
+                outer_cone_angle.cos(),
+            ),
+        };
+
+        let shadow = light.shadow.unwrap_or_default();
+
+        LightData {
+            position: light.position,
+            kind,
+            direction: light.direction.normalize_or_zero(),
+            range: light.range.unwrap_or(0.0),
+            color: light.color,
+            intensity: light.intensity,
+            inner_cone_cos,
+            outer_cone_cos,
+            shadow_index: -1,
+            normal_bias: shadow.normal_bias,
+            pcf_radius: shadow.pcf_radius,
+            _padding: [0; 3],
+        }
+    }
+}
@@ -0,0 +1,19 @@
+pub mod background;
+pub mod clusters;
+pub mod ibl;
+pub mod light;
+pub mod shadow;
+pub mod shadow_maps;
+pub mod ssao;
+
+pub use background::{Background, BackgroundPass, SkySettings};
+pub use clusters::{ClusterSettings, LightClusters};
+pub use ibl::{IblSettings, ImageBasedLighting};
+pub use light::{Light, LightData, LightKind};
+pub use shadow::{CascadeSettings, ShadowData, ShadowSettings};
+pub use shadow_maps::ShadowMaps;
+pub use ssao::{SsaoPass, SsaoQuality, SsaoSettings};
+
+use crate::resource::Handle;
+
+pub type LightHandle = Handle<Light>;
@@ -0,0 +1,218 @@
+use super::{Light, LightKind};
+use crate::camera::Camera;
+use glam::{Mat4, Vec3, Vec4};
+use std::f32::consts::FRAC_PI_2;
+
+pub const MAX_CASCADES: u32 = 4;
+
+/// Near plane of spot and point light shadow frusta
+const SHADOW_NEAR: f32 = 0.05;
+/// Far plane of spot and point light shadow frusta when the light has no range
+const SHADOW_FAR: f32 = 100.0;
+
+/// Per-light shadow filtering and biasing
+#[derive(Clone, Copy, Debug)]
+pub struct ShadowSettings {
+    /// Constant depth bias applied while rasterizing the shadow map
+    pub depth_bias: f32,
+    /// Depth bias scaled by the slope of the caster
+    pub slope_bias: f32,
+    /// Offset of the receiver along its normal in shadow map texels
+    pub normal_bias: f32,
+    /// Half-size of the PCF kernel in texels, zero only uses the hardware 2x2 filter
+    pub pcf_radius: u32,
+}
+
+impl Default for ShadowSettings {
+    fn default() -> Self {
+        ShadowSettings {
+            depth_bias: 1.25,
+            slope_bias: 1.75,
+            normal_bias: 1.0,
+            pcf_radius: 1,
+        }
+    }
+}
+
+/// How the view frustum is split up between the cascades of directional lights
+#[derive(Clone, Copy, Debug)]
+pub struct CascadeSettings {
+    /// Number of cascades, at most [`MAX_CASCADES`]
+    pub count: u32,
+    /// View distance up to which directional lights cast shadows
+    pub max_distance: f32,
+    /// Blend between uniform (0) and logarithmic (1) split distances
+    pub split_lambda: f32,
+}
+
+impl Default for CascadeSettings {
+    fn default() -> Self {
+        CascadeSettings {
+            count: 4,
+            max_distance: 50.0,
+            split_lambda: 0.75,
+        }
+    }
+}
+
+impl CascadeSettings {
+    pub fn count(&self) -> u32 {
+        self.count.clamp(1, MAX_CASCADES)
+    }
+
+    /// Far distance of every cascade, unused cascades repeat the last split
+    pub fn split_distances(&self, camera: &Camera) -> Vec4 {
+        let near = camera.z_near;
+        let far = self.max_distance.min(camera.z_far);
+        let count = self.count();
+
+        let mut splits = [far; MAX_CASCADES as usize];
+        for (i, split) in splits.iter_mut().enumerate().take(count as usize) {
+            let p = (i + 1) as f32 / count as f32;
+            let logarithmic = near * (far / near).powf(p);
+            let uniform = near + (far - near) * p;
+            *split = self.split_lambda * logarithmic + (1.0 - self.split_lambda) * uniform;
+        }
+
+        Vec4::from(splits)
+    }
+}
+
+/// A single layer of the shadow map array, matches `ShadowData` in the shaders
+#[derive(Clone, Copy, Debug, Default)]
+#[repr(C)]
+pub struct ShadowData {
+    pub view_projection: Mat4,
+    /// World-space size of a texel, per unit of distance to the light for perspective shadows
+    pub texel_size: f32,
+    pub perspective: u32,
+    pub _padding: [u32; 2],
+}
+
+/// Shadow map layers needed by `light`, in the order the shaders expect them:
+/// one per cascade for directional lights, one for spot lights
+/// and the +X, -X, +Y, -Y, +Z, -Z cube faces for point lights
+pub fn shadow_views(
+    light: &Light,
+    camera: &Camera,
+    cascades: &CascadeSettings,
+    resolution: u32,
+) -> Vec<ShadowData> {
+    match light.kind {
+        LightKind::Directional => {
+            let light_view =
+                Mat4::look_to_rh(Vec3::ZERO, light.direction, up_vector(light.direction));
+            let splits = cascades.split_distances(camera);
+
+            (0..cascades.count() as usize)
+                .map(|i| {
+                    let near = if i == 0 { camera.z_near } else { splits[i - 1] };
+                    fit_cascade(
+                        camera,
+                        near,
+                        splits[i],
+                        light_view,
+                        cascades.max_distance,
+                        resolution,
+                    )
+                })
+                .collect()
+        }
+        LightKind::Spot {
+            outer_cone_angle, ..
+        } => {
+            let fov = (2.0 * outer_cone_angle).clamp(0.01, 3.1);
+            vec![perspective_view(light, light.direction, fov, resolution)]
+        }
+        LightKind::Point => [
+            Vec3::X,
+            Vec3::NEG_X,
+            Vec3::Y,
+            Vec3::NEG_Y,
+            Vec3::Z,
+            Vec3::NEG_Z,
+        ]
+        .into_iter()
+        .map(|direction| perspective_view(light, direction, FRAC_PI_2, resolution))
+        .collect(),
+    }
+}
+
+fn up_vector(direction: Vec3) -> Vec3 {
+    if direction.y.abs() > 0.99 {
+        Vec3::Z
+    } else {
+        Vec3::Y
+    }
+}
+
+fn perspective_view(light: &Light, direction: Vec3, fov: f32, resolution: u32) -> ShadowData {
+    let view = Mat4::look_to_rh(light.position, direction, up_vector(direction));
+    let far = light.range.unwrap_or(SHADOW_FAR);
+    let projection = Mat4::perspective_rh(fov, 1.0, SHADOW_NEAR, far);
+
+    ShadowData {
+        view_projection: projection * view,
+        texel_size: 2.0 * (fov * 0.5).tan() / resolution as f32,
+        perspective: 1,
+        _padding: [0; 2],
+    }
+}
+
+/// Fits an orthographic projection around the `near..far` slice of the camera frustum.
+///
+/// The projection bounds a sphere around the slice, which keeps its size constant as the
+/// camera rotates, and its center is snapped to whole texels so moving the camera does not
+/// make the shadow edges shimmer.
+fn fit_cascade(
+    camera: &Camera,
+    near: f32,
+    far: f32,
+    light_view: Mat4,
+    caster_distance: f32,
+    resolution: u32,
+) -> ShadowData {
+    let tan_y = (camera.fov_y * 0.5).tan();
+    let tan_x = tan_y * camera.aspect_ratio;
+    let (forward, right, up) = (camera.forward(), camera.right(), camera.up());
+
+    let corners = [near, far].into_iter().flat_map(|distance| {
+        [(-1.0, -1.0), (1.0, -1.0), (-1.0, 1.0), (1.0, 1.0)].map(|(x, y)| {
+            camera.position
+                + forward * distance
+                + right * (x * tan_x * distance)
+                + up * (y * tan_y * distance)
+        })
+    });
+    let corners = corners.collect::<Vec<_>>();
+
+    let center = corners.iter().sum::<Vec3>() / corners.len() as f32;
+    let radius = corners
+        .iter()
+        .map(|corner| corner.distance(center))
+        .fold(0.0, f32::max);
+    // round up so floating point noise does not change the texel size
+    let radius = (radius * 16.0).ceil() / 16.0;
+
+    let texel_size = 2.0 * radius / resolution as f32;
+    let mut center = light_view.transform_point3(center);
+    center.x = (center.x / texel_size).floor() * texel_size;
+    center.y = (center.y / texel_size).floor() * texel_size;
+
+    // casters between the light and the slice must still end up in the shadow map
+    let projection = Mat4::orthographic_rh(
+        center.x - radius,
+        center.x + radius,
+        center.y - radius,
+        center.y + radius,
+        -center.z - radius - caster_distance,
+        -center.z + radius,
+    );
+
+    ShadowData {
+        view_projection: projection * light_view,
+        texel_size,
+        perspective: 0,
+        _padding: [0; 2],
+    }
+}
@@ -0,0 +1,259 @@
+use super::ShadowSettings;
+use crate::{
+    backend_vulkan::{
+        device::Device,
+        image::{Image, ImageDesc},
+        mesh::{HasVertexInputDescription, Mesh, Vertex},
+        pipeline::GraphicsPipeline,
+        shader::{ShaderLanguage, ShaderSource, ShaderStage},
+    },
+    resource::Pool,
+    MeshDraw,
+};
+use anyhow::Result;
+use ash::vk;
+use gpu_allocator::vulkan::Allocator;
+use std::mem::size_of;
+
+pub const SHADOW_MAP_FORMAT: vk::Format = vk::Format::D32_SFLOAT;
+
+#[derive(Debug, Default, Clone, Copy)]
+#[repr(C)]
+struct ShadowPushConstants {
+    shadow_index: u32,
+}
+
+/// A depth-only render of every draw into one layer of the shadow maps
+#[derive(Clone, Copy, Debug)]
+pub struct ShadowPass {
+    pub layer: u32,
+    pub settings: ShadowSettings,
+}
+
+/// Depth array shared by all shadow casting lights, each light claims
+/// consecutive layers which are described by the frame's `ShadowData`
+pub struct ShadowMaps {
+    pub image: Image,
+    layer_views: Vec<vk::ImageView>,
+    /// Comparison sampler for hardware PCF
+    pub sampler: vk::Sampler,
+    pipeline: GraphicsPipeline,
+}
+
+impl ShadowMaps {
+    pub fn new(
+        allocator: &mut Allocator,
+        device: &Device,
+        frame_descriptor_layout: vk::DescriptorSetLayout,
+        resolution: u32,
+        layers: u32,
+    ) -> Result<Self> {
+        let desc = ImageDesc::new_2d(
+            SHADOW_MAP_FORMAT,
+            vk::Extent2D {
+                width: resolution,
+                height: resolution,
+            },
+            vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT | vk::ImageUsageFlags::SAMPLED,
+        )
+        .array_layers(layers);
+
+        let image = Image::new(allocator, device, desc, "shadow maps");
+        let layer_views = (0..layers)
+            .map(|layer| image.create_layer_view(device, layer))
+            .collect();
+
+        let sampler_info = vk::SamplerCreateInfo::builder()
+            .mag_filter(vk::Filter::LINEAR)
+            .min_filter(vk::Filter::LINEAR)
+            .mipmap_mode(vk::SamplerMipmapMode::NEAREST)
+            .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_BORDER)
+            .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_BORDER)
+            .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_BORDER)
+            .border_color(vk::BorderColor::FLOAT_OPAQUE_WHITE)
+            .compare_enable(true)
+            .compare_op(vk::CompareOp::LESS_OR_EQUAL)
+            .max_lod(vk::LOD_CLAMP_NONE);
+        let sampler = unsafe { device.raw.create_sampler(&sampler_info, None)? };
+
+        let vertex_shader = ShaderSource::builder().entry("vs_shadow").build(
+            ShaderStage::Vertex,
+            ShaderLanguage::WGSL,
+            "./src/shaders/shadow.wgsl",
+        );
+        // only discards the dithered pixels of instances fading between levels of detail
+        let fragment_shader = ShaderSource::builder().entry("fs_shadow").build(
+            ShaderStage::Fragment,
+            ShaderLanguage::WGSL,
+            "./src/shaders/shadow.wgsl",
+        );
+
+        let pipeline = GraphicsPipeline::builder()
+            .depth_format(SHADOW_MAP_FORMAT)
+            .depth_bias(true)
+            .vertex_input(Vertex::describe())
+            .descriptor_set_layouts(&[frame_descriptor_layout])
+            .push_constant_size(size_of::<ShadowPushConstants>())
+            .build(device, &[vertex_shader, fragment_shader])?;
+
+        Ok(ShadowMaps {
+            image,
+            layer_views,
+            sampler,
+            pipeline,
+        })
+    }
+
+    pub fn resolution(&self) -> u32 {
+        self.image.desc.extent.width
+    }
+
+    pub fn layer_count(&self) -> u32 {
+        self.image.desc.array_layers
+    }
+
+    /// Renders `passes` and leaves all layers ready to be sampled by fragment shaders
+    pub(crate) fn record(
+        &self,
+        device: &Device,
+        cmd: vk::CommandBuffer,
+        frame_descriptor_set: vk::DescriptorSet,
+        passes: &[ShadowPass],
+        meshes: &Pool<Mesh>,
+        draws: &[MeshDraw],
+    ) {
+        let subresource_range = self.image.desc.subresource_range();
+
+        // previous frames may still be sampling the shadow maps,
+        // their contents are overwritten so they can be discarded
+        let to_attachment = vk::ImageMemoryBarrier::builder()
+            .old_layout(vk::ImageLayout::UNDEFINED)
+            .new_layout(vk::ImageLayout::DEPTH_ATTACHMENT_OPTIMAL)
+            .dst_access_mask(vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE)
+            .image(self.image.raw)
+            .subresource_range(subresource_range);
+
+        unsafe {
+            device.raw.cmd_pipeline_barrier(
+                cmd,
+                vk::PipelineStageFlags::FRAGMENT_SHADER,
+                vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS
+                    | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[to_attachment.build()],
+            );
+        }
+
+        let resolution = self.resolution();
+        let extent = vk::Extent2D {
+            width: resolution,
+            height: resolution,
+        };
+
+        let viewports = [vk::Viewport::builder()
+            .width(resolution as f32)
+            .height(resolution as f32)
+            .min_depth(0.0)
+            .max_depth(1.0)
+            .build()];
+        let scissors = [vk::Rect2D {
+            extent,
+            ..Default::default()
+        }];
+
+        for pass in passes {
+            let depth_attachment_info = vk::RenderingAttachmentInfo::builder()
+                .image_view(self.layer_views[pass.layer as usize])
+                .image_layout(vk::ImageLayout::DEPTH_ATTACHMENT_OPTIMAL)
+                .load_op(vk::AttachmentLoadOp::CLEAR)
+                .store_op(vk::AttachmentStoreOp::STORE)
+                .clear_value(vk::ClearValue {
+                    depth_stencil: vk::ClearDepthStencilValue {
+                        depth: 1.0,
+                        stencil: 0,
+                    },
+                });
+
+            let rendering_info = vk::RenderingInfo::builder()
+                .render_area(vk::Rect2D {
+                    extent,
+                    ..Default::default()
+                })
+                .layer_count(1)
+                .depth_attachment(&depth_attachment_info);
+
+            unsafe {
+                device.raw.cmd_begin_rendering(cmd, &rendering_info);
+
+                device.raw.cmd_set_viewport(cmd, 0, &viewports);
+                device.raw.cmd_set_scissor(cmd, 0, &scissors);
+                device.raw.cmd_set_depth_bias(
+                    cmd,
+                    pass.settings.depth_bias,
+                    0.0,
+                    pass.settings.slope_bias,
+                );
+
+                device.raw.cmd_bind_pipeline(
+                    cmd,
+                    vk::PipelineBindPoint::GRAPHICS,
+                    self.pipeline.pipeline,
+                );
+                device.raw.cmd_bind_descriptor_sets(
+                    cmd,
+                    vk::PipelineBindPoint::GRAPHICS,
+                    self.pipeline.layout,
+                    0,
+                    &[frame_descriptor_set],
+                    &[],
+                );
+            }
+
+            self.pipeline.push_constants(
+                device,
+                cmd,
+                &ShadowPushConstants {
+                    shadow_index: pass.layer,
+                },
+            );
+            for draw in draws {
+                draw.draw(device, cmd, meshes);
+            }
+
+            unsafe { device.raw.cmd_end_rendering(cmd) };
+        }
+
+        let to_shader_read = vk::ImageMemoryBarrier::builder()
+            .old_layout(vk::ImageLayout::DEPTH_ATTACHMENT_OPTIMAL)
+            .new_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            .src_access_mask(vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE)
+            .dst_access_mask(vk::AccessFlags::SHADER_READ)
+            .image(self.image.raw)
+            .subresource_range(subresource_range);
+
+        unsafe {
+            device.raw.cmd_pipeline_barrier(
+                cmd,
+                vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
+                vk::PipelineStageFlags::FRAGMENT_SHADER,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[to_shader_read.build()],
+            );
+        }
+    }
+
+    pub fn destroy(&mut self, device: &Device, allocator: &mut Allocator) {
+        unsafe {
+            for &view in &self.layer_views {
+                device.raw.destroy_image_view(view, None);
+            }
+            device.raw.destroy_sampler(self.sampler, None);
+        }
+        self.pipeline.destroy(device);
+        self.image.destroy(device, allocator);
+    }
+}
@@ -0,0 +1,539 @@
+use crate::{
+    backend_vulkan::{
+        descriptor::{write_image_descriptor, DescriptorPool, DescriptorSetLayoutBuilder},
+        device::Device,
+        image::{Image, ImageDesc},
+        mesh::{HasVertexInputDescription, Mesh, Vertex},
+        pipeline::{compute_write_barrier, ComputePipeline, GraphicsPipeline},
+        shader::{ShaderLanguage, ShaderSource, ShaderStage},
+    },
+    gpu_driven::meshlets::MeshletTarget,
+    resource::Pool,
+    DrawList,
+};
+use anyhow::Result;
+use ash::vk;
+use gpu_allocator::vulkan::Allocator;
+use std::mem::size_of;
+
+pub const AMBIENT_OCCLUSION_FORMAT: vk::Format = vk::Format::R32_SFLOAT;
+
+/// Workgroup size of the occlusion and blur shaders in both dimensions
+const GROUP_SIZE: u32 = 8;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SsaoQuality {
+    Low,
+    #[default]
+    Medium,
+    High,
+}
+
+impl SsaoQuality {
+    pub const ALL: [SsaoQuality; 3] = [SsaoQuality::Low, SsaoQuality::Medium, SsaoQuality::High];
+
+    /// The next preset, wrapping around after the last one
+    pub fn next(self) -> Self {
+        let index = Self::ALL.iter().position(|&q| q == self).unwrap();
+        Self::ALL[(index + 1) % Self::ALL.len()]
+    }
+
+    fn sample_count(self) -> u32 {
+        match self {
+            SsaoQuality::Low => 8,
+            SsaoQuality::Medium => 16,
+            SsaoQuality::High => 32,
+        }
+    }
+
+    fn blur_radius(self) -> u32 {
+        match self {
+            SsaoQuality::Low => 2,
+            SsaoQuality::Medium => 3,
+            SsaoQuality::High => 4,
+        }
+    }
+}
+
+/// Screen-space ambient occlusion, only darkens the ambient light
+#[derive(Clone, Copy, Debug)]
+pub struct SsaoSettings {
+    pub enabled: bool,
+    pub quality: SsaoQuality,
+    /// World-space radius of the sampled hemisphere
+    pub radius: f32,
+    /// View-space depth difference below which samples do not occlude
+    pub bias: f32,
+    /// Exponent applied to the visibility, higher values darken occluded areas more
+    pub intensity: f32,
+    /// How strongly the blur ignores texels at a different depth
+    pub blur_sharpness: f32,
+}
+
+impl Default for SsaoSettings {
+    fn default() -> Self {
+        SsaoSettings {
+            enabled: true,
+            quality: SsaoQuality::default(),
+            radius: 0.5,
+            bias: 0.025,
+            intensity: 1.5,
+            blur_sharpness: 16.0,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+#[repr(C)]
+struct SsaoPushConstants {
+    radius: f32,
+    bias: f32,
+    intensity: f32,
+    sample_count: u32,
+    blur_radius: u32,
+    blur_sharpness: f32,
+    blur_direction: [i32; 2],
+}
+
+/// Depth prepass followed by hemisphere SSAO with normals reconstructed from depth
+/// and a separable bilateral blur. The result stays in `GENERAL` layout in [`Self::occlusion`].
+pub struct SsaoPass {
+    pub occlusion: Image,
+    scratch: Image,
+    layout: vk::DescriptorSetLayout,
+    descriptor_pool: DescriptorPool,
+    /// Writes `occlusion`, reading `scratch` when blurring
+    to_occlusion_set: vk::DescriptorSet,
+    /// Writes `scratch`, reading `occlusion`
+    to_scratch_set: vk::DescriptorSet,
+    depth_pipeline: GraphicsPipeline,
+    occlusion_pipeline: ComputePipeline,
+    blur_pipeline: ComputePipeline,
+}
+
+impl SsaoPass {
+    pub fn new(
+        allocator: &mut Allocator,
+        device: &Device,
+        frame_descriptor_layout: vk::DescriptorSetLayout,
+        depth: &Image,
+    ) -> Result<Self> {
+        let layout = DescriptorSetLayoutBuilder::default()
+            .binding(
+                0,
+                vk::DescriptorType::SAMPLED_IMAGE,
+                vk::ShaderStageFlags::COMPUTE,
+            )
+            .binding(
+                1,
+                vk::DescriptorType::SAMPLED_IMAGE,
+                vk::ShaderStageFlags::COMPUTE,
+            )
+            .binding(
+                2,
+                vk::DescriptorType::STORAGE_IMAGE,
+                vk::ShaderStageFlags::COMPUTE,
+            )
+            .build(device)?;
+
+        let descriptor_pool = DescriptorPool::new(
+            device,
+            2,
+            &[
+                vk::DescriptorPoolSize {
+                    ty: vk::DescriptorType::SAMPLED_IMAGE,
+                    descriptor_count: 4,
+                },
+                vk::DescriptorPoolSize {
+                    ty: vk::DescriptorType::STORAGE_IMAGE,
+                    descriptor_count: 2,
+                },
+            ],
+        )?;
+        let to_occlusion_set = descriptor_pool.allocate(device, layout)?;
+        let to_scratch_set = descriptor_pool.allocate(device, layout)?;
+
+        let depth_shader = |stage, entry: &str| {
+            ShaderSource::builder().entry(entry).build(
+                stage,
+                ShaderLanguage::WGSL,
+                "./src/shaders/shader_new.wgsl",
+            )
+        };
+        // the fragment shader only dithers meshes fading between levels of detail
+        let depth_pipeline = GraphicsPipeline::builder()
+            .depth_format(depth.desc.format)
+            .vertex_input(Vertex::describe())
+            .descriptor_set_layouts(&[frame_descriptor_layout])
+            .build(
+                device,
+                &[
+                    depth_shader(ShaderStage::Vertex, "vs_depth"),
+                    depth_shader(ShaderStage::Fragment, "fs_depth"),
+                ],
+            )?;
+
+        let compute_pipeline = |entry: &str| {
+            let shader = ShaderSource::builder().entry(entry).build(
+                ShaderStage::Compute,
+                ShaderLanguage::WGSL,
+                "./src/shaders/ssao.wgsl",
+            );
+            ComputePipeline::new(
+                device,
+                &shader,
+                &[frame_descriptor_layout, layout],
+                size_of::<SsaoPushConstants>(),
+            )
+        };
+
+        let (occlusion, scratch) = Self::create_images(allocator, device, depth);
+
+        let ssao = SsaoPass {
+            occlusion,
+            scratch,
+            layout,
+            descriptor_pool,
+            to_occlusion_set,
+            to_scratch_set,
+            depth_pipeline,
+            occlusion_pipeline: compute_pipeline("cs_occlusion")?,
+            blur_pipeline: compute_pipeline("cs_blur")?,
+        };
+        ssao.write_descriptors(device, depth);
+
+        Ok(ssao)
+    }
+
+    fn create_images(allocator: &mut Allocator, device: &Device, depth: &Image) -> (Image, Image) {
+        let desc = ImageDesc::new_2d(
+            AMBIENT_OCCLUSION_FORMAT,
+            depth.desc.extent_2d(),
+            vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::STORAGE,
+        );
+
+        (
+            Image::new(allocator, device, desc, "ambient occlusion"),
+            Image::new(allocator, device, desc, "ambient occlusion scratch"),
+        )
+    }
+
+    fn write_descriptors(&self, device: &Device, depth: &Image) {
+        for (set, input, output) in [
+            (self.to_occlusion_set, &self.scratch, &self.occlusion),
+            (self.to_scratch_set, &self.occlusion, &self.scratch),
+        ] {
+            write_image_descriptor(
+                device,
+                set,
+                0,
+                vk::DescriptorType::SAMPLED_IMAGE,
+                depth.view,
+                vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                vk::Sampler::null(),
+            );
+            write_image_descriptor(
+                device,
+                set,
+                1,
+                vk::DescriptorType::SAMPLED_IMAGE,
+                input.view,
+                vk::ImageLayout::GENERAL,
+                vk::Sampler::null(),
+            );
+            write_image_descriptor(
+                device,
+                set,
+                2,
+                vk::DescriptorType::STORAGE_IMAGE,
+                output.view,
+                vk::ImageLayout::GENERAL,
+                vk::Sampler::null(),
+            );
+        }
+    }
+
+    /// Recreates the images at the size of the new `depth` image, the device must be idle
+    pub fn resize(&mut self, allocator: &mut Allocator, device: &Device, depth: &Image) {
+        self.occlusion.destroy(device, allocator);
+        self.scratch.destroy(device, allocator);
+        (self.occlusion, self.scratch) = Self::create_images(allocator, device, depth);
+        self.write_descriptors(device, depth);
+    }
+
+    /// Renders `draws` into `depth` and computes the occlusion from it. When `prepassed`
+    /// `depth` already holds the scene in `DEPTH_ATTACHMENT_OPTIMAL`, see [`Self::record_prepass`].
+    ///
+    /// Returns whether `depth` was filled, in which case it is left in
+    /// `DEPTH_ATTACHMENT_OPTIMAL` to be loaded by the main pass. When disabled
+    /// only the layout of the occlusion image is prepared.
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn record(
+        &self,
+        device: &Device,
+        cmd: vk::CommandBuffer,
+        settings: &SsaoSettings,
+        frame_descriptor_set: vk::DescriptorSet,
+        depth: &Image,
+        meshes: &Pool<Mesh>,
+        draws: &DrawList,
+        prepassed: bool,
+    ) -> bool {
+        // both images are fully rewritten, but the previous frame may still be reading them
+        let occlusion_barriers = [&self.occlusion, &self.scratch].map(|image| {
+            vk::ImageMemoryBarrier::builder()
+                .old_layout(vk::ImageLayout::UNDEFINED)
+                .new_layout(vk::ImageLayout::GENERAL)
+                .dst_access_mask(vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE)
+                .image(image.raw)
+                .subresource_range(image.desc.subresource_range())
+                .build()
+        });
+        let occlusion_count = if settings.enabled { 2 } else { 1 };
+
+        unsafe {
+            device.raw.cmd_pipeline_barrier(
+                cmd,
+                vk::PipelineStageFlags::FRAGMENT_SHADER | vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::PipelineStageFlags::FRAGMENT_SHADER | vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &occlusion_barriers[..occlusion_count],
+            );
+        }
+
+        if !settings.enabled {
+            return prepassed;
+        }
+
+        if !prepassed {
+            self.record_prepass(
+                device,
+                cmd,
+                frame_descriptor_set,
+                depth,
+                meshes,
+                draws,
+                false,
+            );
+        }
+
+        let to_shader_read = vk::ImageMemoryBarrier::builder()
+            .old_layout(vk::ImageLayout::DEPTH_ATTACHMENT_OPTIMAL)
+            .new_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            .src_access_mask(vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE)
+            .dst_access_mask(vk::AccessFlags::SHADER_READ)
+            .image(depth.raw)
+            .subresource_range(depth.desc.subresource_range());
+
+        unsafe {
+            device.raw.cmd_pipeline_barrier(
+                cmd,
+                vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
+                vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[to_shader_read.build()],
+            );
+        }
+
+        let mut constants = SsaoPushConstants {
+            radius: settings.radius,
+            bias: settings.bias,
+            intensity: settings.intensity,
+            sample_count: settings.quality.sample_count(),
+            blur_radius: settings.quality.blur_radius(),
+            blur_sharpness: settings.blur_sharpness,
+            blur_direction: [0; 2],
+        };
+        let extent = depth.desc.extent_2d();
+
+        self.dispatch(
+            device,
+            cmd,
+            &self.occlusion_pipeline,
+            &constants,
+            [frame_descriptor_set, self.to_occlusion_set],
+            extent,
+        );
+
+        for (direction, set) in [
+            ([1, 0], self.to_scratch_set),
+            ([0, 1], self.to_occlusion_set),
+        ] {
+            constants.blur_direction = direction;
+            compute_write_barrier(device, cmd);
+            self.dispatch(
+                device,
+                cmd,
+                &self.blur_pipeline,
+                &constants,
+                [frame_descriptor_set, set],
+                extent,
+            );
+        }
+
+        // hand the depth back to the main pass and the occlusion to its fragment shader
+        let to_attachment = vk::ImageMemoryBarrier::builder()
+            .old_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            .new_layout(vk::ImageLayout::DEPTH_ATTACHMENT_OPTIMAL)
+            .dst_access_mask(
+                vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ
+                    | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+            )
+            .image(depth.raw)
+            .subresource_range(depth.desc.subresource_range());
+        let occlusion_barrier = vk::MemoryBarrier::builder()
+            .src_access_mask(vk::AccessFlags::SHADER_WRITE)
+            .dst_access_mask(vk::AccessFlags::SHADER_READ);
+
+        unsafe {
+            device.raw.cmd_pipeline_barrier(
+                cmd,
+                vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS
+                    | vk::PipelineStageFlags::FRAGMENT_SHADER,
+                vk::DependencyFlags::empty(),
+                &[occlusion_barrier.build()],
+                &[],
+                &[to_attachment.build()],
+            );
+        }
+
+        true
+    }
+
+    /// Renders `draws` into `depth`, which is cleared first unless `load` is set, in which
+    /// case it must already be in `DEPTH_ATTACHMENT_OPTIMAL`. Leaves it in that layout.
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn record_prepass(
+        &self,
+        device: &Device,
+        cmd: vk::CommandBuffer,
+        frame_descriptor_set: vk::DescriptorSet,
+        depth: &Image,
+        meshes: &Pool<Mesh>,
+        draws: &DrawList,
+        load: bool,
+    ) {
+        if !load {
+            // the previous frame may still be testing against the depth buffer
+            let depth_barrier = vk::ImageMemoryBarrier::builder()
+                .old_layout(vk::ImageLayout::UNDEFINED)
+                .new_layout(vk::ImageLayout::DEPTH_ATTACHMENT_OPTIMAL)
+                .src_access_mask(vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE)
+                .dst_access_mask(vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE)
+                .image(depth.raw)
+                .subresource_range(depth.desc.subresource_range());
+
+            unsafe {
+                device.raw.cmd_pipeline_barrier(
+                    cmd,
+                    vk::PipelineStageFlags::LATE_FRAGMENT_TESTS
+                        | vk::PipelineStageFlags::FRAGMENT_SHADER
+                        | vk::PipelineStageFlags::COMPUTE_SHADER,
+                    vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS,
+                    vk::DependencyFlags::empty(),
+                    &[],
+                    &[],
+                    &[depth_barrier.build()],
+                );
+            }
+        }
+
+        let extent = depth.desc.extent_2d();
+
+        let depth_attachment_info = vk::RenderingAttachmentInfo::builder()
+            .image_view(depth.view)
+            .image_layout(vk::ImageLayout::DEPTH_ATTACHMENT_OPTIMAL)
+            .load_op(if load {
+                vk::AttachmentLoadOp::LOAD
+            } else {
+                vk::AttachmentLoadOp::CLEAR
+            })
+            .store_op(vk::AttachmentStoreOp::STORE)
+            .clear_value(vk::ClearValue {
+                depth_stencil: vk::ClearDepthStencilValue {
+                    depth: 1.0,
+                    stencil: 0,
+                },
+            });
+
+        let rendering_info = vk::RenderingInfo::builder()
+            .render_area(vk::Rect2D {
+                extent,
+                ..Default::default()
+            })
+            .layer_count(1)
+            .depth_attachment(&depth_attachment_info);
+
+        unsafe {
+            device.raw.cmd_begin_rendering(cmd, &rendering_info);
+
+            device.raw.cmd_bind_pipeline(
+                cmd,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.depth_pipeline.pipeline,
+            );
+            device.raw.cmd_bind_descriptor_sets(
+                cmd,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.depth_pipeline.layout,
+                0,
+                &[frame_descriptor_set],
+                &[],
+            );
+        }
+
+        draws.record(device, cmd, meshes, MeshletTarget::Depth);
+
+        unsafe { device.raw.cmd_end_rendering(cmd) };
+    }
+
+    fn dispatch(
+        &self,
+        device: &Device,
+        cmd: vk::CommandBuffer,
+        pipeline: &ComputePipeline,
+        constants: &SsaoPushConstants,
+        sets: [vk::DescriptorSet; 2],
+        extent: vk::Extent2D,
+    ) {
+        unsafe {
+            device
+                .raw
+                .cmd_bind_pipeline(cmd, vk::PipelineBindPoint::COMPUTE, pipeline.pipeline);
+            device.raw.cmd_bind_descriptor_sets(
+                cmd,
+                vk::PipelineBindPoint::COMPUTE,
+                pipeline.layout,
+                0,
+                &sets,
+                &[],
+            );
+        }
+
+        pipeline.push_constants(device, cmd, constants);
+
+        unsafe {
+            device.raw.cmd_dispatch(
+                cmd,
+                extent.width.div_ceil(GROUP_SIZE),
+                extent.height.div_ceil(GROUP_SIZE),
+                1,
+            );
+        }
+    }
+
+    pub fn destroy(&mut self, device: &Device, allocator: &mut Allocator) {
+        self.occlusion.destroy(device, allocator);
+        self.scratch.destroy(device, allocator);
+        self.descriptor_pool.destroy(device);
+        unsafe { device.raw.destroy_descriptor_set_layout(self.layout, None) };
+        self.depth_pipeline.destroy(device);
+        self.occlusion_pipeline.destroy(device);
+        self.blur_pipeline.destroy(device);
+    }
+}
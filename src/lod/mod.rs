@@ -0,0 +1,84 @@
+use crate::{backend_vulkan::mesh::LodRange, camera::Camera, culling::BoundingSphere};
+use glam::Vec3;
+
+#[derive(Clone, Copy, Debug)]
+pub struct LodSettings {
+    /// Draws the coarser levels of detail of meshes once they are far enough from the camera
+    pub enabled: bool,
+    /// Largest error in pixels a level of detail may show on screen to be drawn
+    pub max_error: f32,
+    /// Dithers instances between two levels of detail instead of switching at once
+    pub cross_fade: bool,
+    /// Fraction of `max_error` over which the next level fades in before it is picked
+    pub fade_range: f32,
+}
+
+impl Default for LodSettings {
+    fn default() -> Self {
+        LodSettings {
+            enabled: true,
+            max_error: 1.0,
+            cross_fade: true,
+            fade_range: 0.5,
+        }
+    }
+}
+
+/// The level of detail an instance is drawn with
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LodSelection {
+    /// Index into [`crate::backend_vulkan::mesh::Mesh::lods`]
+    pub level: usize,
+    /// While fading over to `level + 1`, the fraction of pixels still drawn with `level`
+    pub fade: Option<f32>,
+}
+
+/// Picks levels of detail by projecting their errors onto the screen
+pub struct LodSelector {
+    settings: LodSettings,
+    camera_position: Vec3,
+    z_near: f32,
+    /// Pixels covered by a length of one seen from a distance of one
+    pixels_per_unit: f32,
+}
+
+impl LodSelector {
+    pub fn new(settings: LodSettings, camera: &Camera, screen_height: u32) -> Self {
+        LodSelector {
+            settings,
+            camera_position: camera.position,
+            z_near: camera.z_near,
+            pixels_per_unit: screen_height as f32 * 0.5 / (camera.fov_y * 0.5).tan(),
+        }
+    }
+
+    /// Picks the coarsest of `lods` whose error stays below [`LodSettings::max_error`],
+    /// `sphere` bounds the instance in world space and its transform scales the mesh by `scale`
+    pub fn select(&self, lods: &[LodRange], sphere: &BoundingSphere, scale: f32) -> LodSelection {
+        if !self.settings.enabled || lods.len() < 2 {
+            return LodSelection {
+                level: 0,
+                fade: None,
+            };
+        }
+
+        // the closest point of the bounds shows the largest error
+        let distance =
+            (sphere.center.distance(self.camera_position) - sphere.radius).max(self.z_near);
+        let pixels = |lod: &LodRange| lod.error * scale * self.pixels_per_unit / distance;
+        let max_error = self.settings.max_error;
+
+        // the full mesh has no error, so there always is one
+        let level = lods
+            .iter()
+            .rposition(|lod| pixels(lod) <= max_error)
+            .unwrap_or(0);
+        let fade = lods
+            .get(level + 1)
+            .filter(|_| self.settings.cross_fade && self.settings.fade_range > 0.0)
+            .map(|next| (pixels(next) / max_error - 1.0) / self.settings.fade_range)
+            .filter(|&fade| fade > 0.0 && fade < 1.0);
+
+        LodSelection { level, fade }
+    }
+}
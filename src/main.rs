@@ -1,5 +1,17 @@
-use poogie::PoogieRenderer;
-use std::{borrow::BorrowMut, sync::Arc};
+use glam::{vec3, vec4, Quat, Vec3};
+use poogie::{
+    asset::asset_loader::load_gltf,
+    backend_vulkan::mesh::MeshData,
+    camera::{CameraController, FlyCameraController, OrbitCameraController},
+    deferred::RenderPath,
+    lighting::{Background, Light, ShadowSettings, SkySettings},
+    scene::{
+        AnimationGraph, AnimationLayer, AnimationPlayer, AnimationState, Condition, Material,
+        Transform, Transition,
+    },
+    InstanceDesc, PoogieRenderer,
+};
+use std::{borrow::BorrowMut, sync::Arc, time::Instant};
 use winit::{
     event::{ElementState, Event, KeyboardInput, VirtualKeyCode, WindowEvent},
     event_loop::{ControlFlow, EventLoop},
@@ -25,6 +37,150 @@ fn main() {
         .build(window.clone())
         .unwrap();
 
+    let mut camera_controller: Box<dyn CameraController> =
+        Box::new(FlyCameraController::new(&poogie.camera));
+    let mut orbiting = false;
+    // a spinning triangle with a smaller, tinted one orbiting around it
+    let triangle = poogie.add_mesh(&MeshData::triangle(), "triangle");
+    let tint = poogie.add_material(Material {
+        name: String::from("tint"),
+        base_color: vec4(1.0, 0.8, 0.2, 1.0),
+        metallic: 1.0,
+        roughness: 0.3,
+        ..Default::default()
+    });
+    let spinning_node = poogie
+        .add_instance(triangle, None, Transform::IDENTITY, None)
+        .unwrap();
+    poogie
+        .add_instance(
+            triangle,
+            Some(tint),
+            Transform::from_translation(Vec3::X).with_scale(Vec3::splat(0.4)),
+            Some(spinning_node),
+        )
+        .unwrap();
+
+    let cube = poogie.add_mesh(&MeshData::cube(), "cube");
+    // a small emissive cube to show off bloom
+    let glow = poogie.add_material(Material {
+        name: String::from("glow"),
+        base_color: vec4(0.1, 0.1, 0.1, 1.0),
+        emissive: vec3(8.0, 3.0, 1.0),
+        ..Default::default()
+    });
+    poogie
+        .add_instance(
+            cube,
+            Some(glow),
+            Transform::from_translation(vec3(-1.5, -0.6, 1.0)).with_scale(Vec3::splat(0.3)),
+            None,
+        )
+        .unwrap();
+    poogie
+        .add_instance(
+            cube,
+            None,
+            Transform::from_translation(vec3(0.0, -1.0, 0.0)).with_scale(vec3(4.0, 0.1, 4.0)),
+            None,
+        )
+        .unwrap();
+
+    // a wall of tinted cubes drawn with a single instanced call
+    let wall = (0..8)
+        .flat_map(|y| {
+            (0..16).map(move |x| InstanceDesc {
+                transform: Transform::from_translation(vec3(
+                    x as f32 * 0.25 - 1.875,
+                    y as f32 * 0.25 - 0.875,
+                    -2.5,
+                ))
+                .with_scale(Vec3::splat(0.2)),
+                color: vec3(x as f32 / 15.0, y as f32 / 7.0, 1.0 - x as f32 / 15.0),
+                ..Default::default()
+            })
+        })
+        .collect::<Vec<_>>();
+    poogie.add_instances(cube, &wall, None).unwrap();
+
+    // a row of detailed spheres receding into the distance, Y toggles their levels of detail
+    // and U their cross-fades
+    let mut sphere = MeshData::sphere(96, 48);
+    sphere.optimize();
+    sphere.build_meshlets();
+    sphere.generate_lods();
+    let sphere = poogie.add_mesh(&sphere, "sphere");
+    let spheres = (0..12)
+        .map(|i| InstanceDesc {
+            transform: Transform::from_translation(vec3(3.0, -0.5, 1.0 - i as f32 * 4.0)),
+            ..Default::default()
+        })
+        .collect::<Vec<_>>();
+    poogie.add_instances(sphere, &spheres, None).unwrap();
+
+    // the animations of every glTF file, only the first one is controlled and played
+    let mut players = vec![];
+    // the first glTF file with several animations is driven by a graph instead,
+    // N cross-fades to its next clip
+    let mut graph = None;
+    let mut clip_count = 0;
+    // optionally show glTF files and light the scene with `.hdr` environments passed on the command line,
+    // `--skybox=<path>` loads an `.hdr` as the skybox instead
+    for path in std::env::args().skip(1) {
+        if let Some(path) = path.strip_prefix("--skybox=") {
+            if let Err(e) = poogie.load_skybox(path) {
+                log::error!("Failed to load {path}: {e}");
+            }
+            continue;
+        }
+        if path.ends_with(".hdr") {
+            if let Err(e) = poogie.load_environment(&path) {
+                log::error!("Failed to load {path}: {e}");
+            }
+            continue;
+        }
+        match load_gltf(&path) {
+            Ok(asset) => {
+                let instance = poogie.add_gltf(&asset, None);
+                if graph.is_none() && instance.animations.len() > 1 {
+                    clip_count = instance.animations.len();
+                    let mut layer = AnimationLayer::new("base");
+                    for (i, clip) in instance.animations.into_iter().enumerate() {
+                        let state = layer.add_state(AnimationState::new(clip.name.clone(), clip));
+                        layer.add_transition(
+                            Transition::new(None, state, 0.3)
+                                .when(Condition::Greater("clip".into(), i as f32 - 0.5))
+                                .when(Condition::Less("clip".into(), i as f32 + 0.5)),
+                        );
+                    }
+                    graph = Some(AnimationGraph::new().with_layer(layer));
+                    continue;
+                }
+                players.extend(instance.animations.into_iter().map(AnimationPlayer::new));
+            }
+            Err(e) => log::error!("Failed to load {path}: {e}"),
+        }
+    }
+
+    if let Some(player) = players.first_mut() {
+        player.play();
+    }
+
+    poogie.add_light(
+        Light::directional(vec3(-0.4, -1.0, -0.6), Vec3::ONE, 3.0)
+            .with_shadows(ShadowSettings::default()),
+    );
+    poogie.add_light(
+        Light::point(vec3(1.5, 1.0, 1.5), vec3(1.0, 0.5, 0.2), 5.0)
+            .with_range(10.0)
+            .with_shadows(ShadowSettings::default()),
+    );
+
+    let mut many_lights = vec![];
+
+    let start = Instant::now();
+    let mut last_frame = start;
+
     event_loop
         .borrow_mut()
         .run_return(|event, _, control_flow| {
@@ -48,6 +204,381 @@ fn main() {
                     poogie.terminate();
                     *control_flow = ControlFlow::Exit;
                 }
+                Event::WindowEvent {
+                    event:
+                        WindowEvent::KeyboardInput {
+                            input:
+                                KeyboardInput {
+                                    state: ElementState::Pressed,
+                                    virtual_keycode: Some(VirtualKeyCode::Tab),
+                                    ..
+                                },
+                            ..
+                        },
+                    ..
+                } => {
+                    // switch between flying around and inspecting the origin
+                    orbiting = !orbiting;
+                    camera_controller = if orbiting {
+                        Box::new(OrbitCameraController::new(&poogie.camera, Vec3::ZERO))
+                    } else {
+                        Box::new(FlyCameraController::new(&poogie.camera))
+                    };
+                }
+                Event::WindowEvent {
+                    event:
+                        WindowEvent::KeyboardInput {
+                            input:
+                                KeyboardInput {
+                                    state: ElementState::Pressed,
+                                    virtual_keycode:
+                                        Some(
+                                            key @ (VirtualKeyCode::T
+                                            | VirtualKeyCode::B
+                                            | VirtualKeyCode::Equals
+                                            | VirtualKeyCode::Minus),
+                                        ),
+                                    ..
+                                },
+                            ..
+                        },
+                    ..
+                } => {
+                    match key {
+                        VirtualKeyCode::T => {
+                            poogie.tonemap.tonemapper = poogie.tonemap.tonemapper.next()
+                        }
+                        VirtualKeyCode::B => poogie.bloom.enabled = !poogie.bloom.enabled,
+                        VirtualKeyCode::Equals => poogie.tonemap.exposure += 0.5,
+                        _ => poogie.tonemap.exposure -= 0.5,
+                    }
+                    log::info!(
+                        "Tonemapping: {:?}, bloom: {:?}",
+                        poogie.tonemap,
+                        poogie.bloom
+                    );
+                }
+                Event::WindowEvent {
+                    event:
+                        WindowEvent::KeyboardInput {
+                            input:
+                                KeyboardInput {
+                                    state: ElementState::Pressed,
+                                    virtual_keycode:
+                                        Some(
+                                            key @ (VirtualKeyCode::Key1
+                                            | VirtualKeyCode::Key2
+                                            | VirtualKeyCode::Key3
+                                            | VirtualKeyCode::Key4),
+                                        ),
+                                    ..
+                                },
+                            ..
+                        },
+                    ..
+                } => {
+                    let effects = poogie.builtin_post_effects;
+                    let handle = match key {
+                        VirtualKeyCode::Key1 => effects.color_grade,
+                        VirtualKeyCode::Key2 => effects.chromatic_aberration,
+                        VirtualKeyCode::Key3 => effects.film_grain,
+                        _ => effects.vignette,
+                    };
+                    if let Some(effect) = poogie.post_effect_mut(handle) {
+                        effect.enabled = !effect.enabled;
+                        log::info!("Post effect {}: {}", effect.name, effect.enabled);
+                    }
+                }
+                Event::WindowEvent {
+                    event:
+                        WindowEvent::KeyboardInput {
+                            input:
+                                KeyboardInput {
+                                    state: ElementState::Pressed,
+                                    virtual_keycode:
+                                        Some(
+                                            key @ (VirtualKeyCode::O
+                                            | VirtualKeyCode::I
+                                            | VirtualKeyCode::P
+                                            | VirtualKeyCode::X),
+                                        ),
+                                    ..
+                                },
+                            ..
+                        },
+                    ..
+                } => match key {
+                    VirtualKeyCode::O => {
+                        poogie.ssao.enabled = !poogie.ssao.enabled;
+                        log::info!("SSAO: {:?}", poogie.ssao);
+                    }
+                    VirtualKeyCode::I => {
+                        poogie.ssao.quality = poogie.ssao.quality.next();
+                        log::info!("SSAO: {:?}", poogie.ssao);
+                    }
+                    VirtualKeyCode::X => {
+                        poogie.environment.enabled = !poogie.environment.enabled;
+                        log::info!("Environment lighting: {:?}", poogie.environment);
+                    }
+                    _ => {
+                        for timing in poogie.gpu_timings() {
+                            log::info!(
+                                "{}: {:.3} ms",
+                                timing.name,
+                                timing.duration.as_secs_f64() * 1e3
+                            );
+                        }
+                    }
+                },
+                Event::WindowEvent {
+                    event:
+                        WindowEvent::KeyboardInput {
+                            input:
+                                KeyboardInput {
+                                    state: ElementState::Pressed,
+                                    virtual_keycode:
+                                        Some(
+                                            key @ (VirtualKeyCode::R
+                                            | VirtualKeyCode::G
+                                            | VirtualKeyCode::K),
+                                        ),
+                                    ..
+                                },
+                            ..
+                        },
+                    ..
+                } => {
+                    match key {
+                        VirtualKeyCode::R => {
+                            poogie.render_path = match poogie.render_path {
+                                RenderPath::Forward => RenderPath::Deferred,
+                                RenderPath::Deferred => RenderPath::Forward,
+                            };
+                        }
+                        VirtualKeyCode::G => poogie.gbuffer_view = poogie.gbuffer_view.next(),
+                        _ => {
+                            poogie.background = match poogie.background {
+                                Background::Color(_) => Background::Skybox,
+                                Background::Skybox => Background::Environment,
+                                Background::Environment => Background::Sky(SkySettings::default()),
+                                Background::Sky(_) => Background::default(),
+                            };
+                        }
+                    }
+                    log::info!(
+                        "Render path: {:?}, G-buffer view: {:?}, background: {:?}",
+                        poogie.render_path,
+                        poogie.gbuffer_view,
+                        poogie.background
+                    );
+                }
+                Event::WindowEvent {
+                    event:
+                        WindowEvent::KeyboardInput {
+                            input:
+                                KeyboardInput {
+                                    state: ElementState::Pressed,
+                                    virtual_keycode:
+                                        Some(
+                                            key @ (VirtualKeyCode::C
+                                            | VirtualKeyCode::H
+                                            | VirtualKeyCode::L),
+                                        ),
+                                    ..
+                                },
+                            ..
+                        },
+                    ..
+                } => {
+                    match key {
+                        VirtualKeyCode::C => poogie.clusters.enabled = !poogie.clusters.enabled,
+                        VirtualKeyCode::H => poogie.clusters.heatmap = !poogie.clusters.heatmap,
+                        _ if many_lights.is_empty() => {
+                            // a grid of small colored lights just above the floor
+                            for z in 0..16 {
+                                for x in 0..16 {
+                                    let position =
+                                        vec3(x as f32 * 0.25 - 1.9, -0.8, z as f32 * 0.25 - 1.9);
+                                    let color = vec3(x as f32 / 15.0, 0.5, z as f32 / 15.0);
+                                    many_lights.push(poogie.add_light(
+                                        Light::point(position, color, 0.2).with_range(0.5),
+                                    ));
+                                }
+                            }
+                        }
+                        _ => {
+                            for light in many_lights.drain(..) {
+                                poogie.remove_light(light).unwrap();
+                            }
+                        }
+                    }
+                    log::info!(
+                        "Light culling: {:?}, extra lights: {}",
+                        poogie.clusters,
+                        many_lights.len()
+                    );
+                }
+                Event::WindowEvent {
+                    event:
+                        WindowEvent::KeyboardInput {
+                            input:
+                                KeyboardInput {
+                                    state: ElementState::Pressed,
+                                    virtual_keycode:
+                                        Some(
+                                            key @ (VirtualKeyCode::Space
+                                            | VirtualKeyCode::Return
+                                            | VirtualKeyCode::Left
+                                            | VirtualKeyCode::Right
+                                            | VirtualKeyCode::Up
+                                            | VirtualKeyCode::Down),
+                                        ),
+                                    ..
+                                },
+                            ..
+                        },
+                    ..
+                } => {
+                    let Some(player) = players.first_mut() else {
+                        return;
+                    };
+                    match key {
+                        VirtualKeyCode::Space if player.is_playing() => player.pause(),
+                        VirtualKeyCode::Space => player.play(),
+                        VirtualKeyCode::Return => player.looping = !player.looping,
+                        VirtualKeyCode::Left => player.seek(player.time() - 1.0),
+                        VirtualKeyCode::Right => player.seek(player.time() + 1.0),
+                        VirtualKeyCode::Up => player.speed += 0.25,
+                        _ => player.speed -= 0.25,
+                    }
+                    log::info!(
+                        "Animation {:?}: {:.2}/{:.2}s, playing: {}, looping: {}, speed: {}",
+                        player.clip.name,
+                        player.time(),
+                        player.clip.duration,
+                        player.is_playing(),
+                        player.looping,
+                        player.speed
+                    );
+                }
+                Event::WindowEvent {
+                    event:
+                        WindowEvent::KeyboardInput {
+                            input:
+                                KeyboardInput {
+                                    state: ElementState::Pressed,
+                                    virtual_keycode: Some(VirtualKeyCode::V),
+                                    ..
+                                },
+                            ..
+                        },
+                    ..
+                } => {
+                    poogie.culling.frustum = !poogie.culling.frustum;
+                    log::info!("Frustum culling: {}", poogie.culling.frustum);
+                }
+                Event::WindowEvent {
+                    event:
+                        WindowEvent::KeyboardInput {
+                            input:
+                                KeyboardInput {
+                                    state: ElementState::Pressed,
+                                    virtual_keycode: Some(VirtualKeyCode::F),
+                                    ..
+                                },
+                            ..
+                        },
+                    ..
+                } => {
+                    poogie.culling.gpu = !poogie.culling.gpu;
+                    log::info!("GPU culling: {}", poogie.culling.gpu);
+                }
+                Event::WindowEvent {
+                    event:
+                        WindowEvent::KeyboardInput {
+                            input:
+                                KeyboardInput {
+                                    state: ElementState::Pressed,
+                                    virtual_keycode:
+                                        Some(key @ (VirtualKeyCode::Z | VirtualKeyCode::M)),
+                                    ..
+                                },
+                            ..
+                        },
+                    ..
+                } => {
+                    let culling = &mut poogie.culling;
+                    match key {
+                        VirtualKeyCode::Z => culling.occlusion = !culling.occlusion,
+                        _ => culling.show_occluded = !culling.show_occluded,
+                    }
+                    log::info!("Occlusion culling: {:?}", poogie.culling);
+                }
+                Event::WindowEvent {
+                    event:
+                        WindowEvent::KeyboardInput {
+                            input:
+                                KeyboardInput {
+                                    state: ElementState::Pressed,
+                                    virtual_keycode:
+                                        Some(key @ (VirtualKeyCode::Y | VirtualKeyCode::U)),
+                                    ..
+                                },
+                            ..
+                        },
+                    ..
+                } => {
+                    let lod = &mut poogie.lod;
+                    match key {
+                        VirtualKeyCode::Y => lod.enabled = !lod.enabled,
+                        _ => lod.cross_fade = !lod.cross_fade,
+                    }
+                    log::info!("Levels of detail: {:?}", poogie.lod);
+                }
+                Event::WindowEvent {
+                    event:
+                        WindowEvent::KeyboardInput {
+                            input:
+                                KeyboardInput {
+                                    state: ElementState::Pressed,
+                                    virtual_keycode:
+                                        Some(
+                                            key @ (VirtualKeyCode::J
+                                            | VirtualKeyCode::Semicolon),
+                                        ),
+                                    ..
+                                },
+                            ..
+                        },
+                    ..
+                } => {
+                    let culling = &mut poogie.culling;
+                    match key {
+                        VirtualKeyCode::J => culling.meshlets = !culling.meshlets,
+                        _ => culling.backface = !culling.backface,
+                    }
+                    log::info!("Meshlet culling: {:?}", poogie.culling);
+                }
+                Event::WindowEvent {
+                    event:
+                        WindowEvent::KeyboardInput {
+                            input:
+                                KeyboardInput {
+                                    state: ElementState::Pressed,
+                                    virtual_keycode: Some(VirtualKeyCode::N),
+                                    ..
+                                },
+                            ..
+                        },
+                    ..
+                } => {
+                    if let Some(graph) = &mut graph {
+                        let clip = (graph.parameter("clip") as usize + 1) % clip_count;
+                        graph.set_parameter("clip", clip as f32);
+                        let layer = &graph.layers[0];
+                        log::info!("Cross-fading to {:?}", layer.state(clip).unwrap().name);
+                    }
+                }
                 Event::WindowEvent {
                     event: WindowEvent::Resized(_),
                     ..
@@ -56,12 +587,40 @@ fn main() {
                         log::warn!("Failed to create swapchain: {e:?}: {e}");
                     }
                 }
+                Event::WindowEvent { event, .. } => {
+                    camera_controller.handle_window_event(&window, &event);
+                }
                 Event::MainEventsCleared => {
+                    let now = Instant::now();
+                    let delta_time = (now - last_frame).as_secs_f32();
+                    last_frame = now;
+
+                    camera_controller.update(&mut poogie.camera, delta_time);
+
+                    if let Some(player) = players.first_mut() {
+                        player.update(delta_time, &mut poogie.scene);
+                    }
+                    if let Some(graph) = &mut graph {
+                        graph.update(delta_time, &mut poogie.scene);
+                    }
+
+                    let spin = Quat::from_rotation_y((now - start).as_secs_f32());
+                    poogie
+                        .update_instance(spinning_node, Transform::from_rotation(spin))
+                        .unwrap();
+
                     if let Ok(elapsed) = poogie.draw() {
+                        let stats = poogie.stats();
                         window.set_title(&format!(
-                            "Frame time: {:.2}ms, FPS: {}",
+                            "Frame time: {:.2}ms, FPS: {}, objects: {} ({} culled, {} occluded), meshlets: {} ({} culled), draws: {}",
                             elapsed.as_secs_f64() * 1000.0,
-                            (1.0 / elapsed.as_secs_f32()) as u32
+                            (1.0 / elapsed.as_secs_f32()) as u32,
+                            stats.objects,
+                            stats.culled_objects,
+                            stats.occluded_objects,
+                            stats.meshlets,
+                            stats.culled_meshlets,
+                            stats.draw_calls
                         ));
                     };
                 }
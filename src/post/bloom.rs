@@ -0,0 +1,413 @@
+use super::{
+    stack::COMPUTE_GROUP_SIZE,
+    targets::{PostTargets, SCENE_TARGET},
+};
+use crate::{
+    backend_vulkan::{
+        descriptor::{write_image_descriptor, DescriptorPool},
+        device::Device,
+        image::{Image, ImageDesc},
+        pipeline::{compute_write_barrier, ComputePipeline},
+        sampler::create_sampler,
+        shader::{ShaderLanguage, ShaderSource, ShaderStage},
+    },
+    HDR_FORMAT,
+};
+use anyhow::Result;
+use ash::vk;
+use gpu_allocator::vulkan::Allocator;
+use std::mem::size_of;
+
+/// Maximum number of mips in the bloom chain, the first one is half the screen size
+pub const BLOOM_MIPS: u32 = 6;
+
+/// Post target the scene with bloom applied is written to
+const BLOOM_TARGET: usize = 1;
+
+#[derive(Clone, Copy, Debug)]
+pub struct BloomSettings {
+    pub enabled: bool,
+    /// Brightness below which pixels stop contributing, zero lets everything bloom
+    /// which is the physically plausible choice
+    pub threshold: f32,
+    /// Width of the soft transition around the threshold, relative to it
+    pub knee: f32,
+    /// Fraction of the final color taken from the bloom
+    pub intensity: f32,
+    /// Radius of the upsampling filter in UV units of each mip
+    pub radius: f32,
+}
+
+impl Default for BloomSettings {
+    fn default() -> Self {
+        BloomSettings {
+            enabled: true,
+            threshold: 0.0,
+            knee: 0.5,
+            intensity: 0.05,
+            radius: 0.005,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+#[repr(C)]
+struct BloomPushConstants {
+    threshold: f32,
+    knee: f32,
+    radius: f32,
+    intensity: f32,
+    mip_count: u32,
+}
+
+/// Progressive downsample and upsample of the scene through a mip chain,
+/// every mip stays in `GENERAL` layout so it can be both sampled and written
+pub struct BloomPass {
+    chain: Image,
+    mip_views: Vec<vk::ImageView>,
+    input_sets: Vec<vk::DescriptorSet>,
+    output_sets: Vec<vk::DescriptorSet>,
+    descriptor_pool: DescriptorPool,
+    sampler: vk::Sampler,
+    downsample_first: ComputePipeline,
+    downsample: ComputePipeline,
+    upsample: ComputePipeline,
+    composite: ComputePipeline,
+}
+
+impl BloomPass {
+    pub fn new(allocator: &mut Allocator, device: &Device, targets: &PostTargets) -> Result<Self> {
+        let descriptor_pool = DescriptorPool::new(
+            device,
+            2 * BLOOM_MIPS,
+            &[
+                vk::DescriptorPoolSize {
+                    ty: vk::DescriptorType::SAMPLED_IMAGE,
+                    descriptor_count: BLOOM_MIPS,
+                },
+                vk::DescriptorPoolSize {
+                    ty: vk::DescriptorType::SAMPLER,
+                    descriptor_count: BLOOM_MIPS,
+                },
+                vk::DescriptorPoolSize {
+                    ty: vk::DescriptorType::STORAGE_IMAGE,
+                    descriptor_count: BLOOM_MIPS,
+                },
+            ],
+        )?;
+
+        let input_sets = (0..BLOOM_MIPS)
+            .map(|_| descriptor_pool.allocate(device, targets.input_layout))
+            .collect::<Result<Vec<_>>>()?;
+        let output_sets = (0..BLOOM_MIPS)
+            .map(|_| descriptor_pool.allocate(device, targets.output_layout))
+            .collect::<Result<Vec<_>>>()?;
+
+        let sampler = create_sampler(
+            device,
+            vk::Filter::LINEAR,
+            vk::SamplerAddressMode::CLAMP_TO_EDGE,
+        )?;
+
+        // set 0 is the scene, set 1 the source and set 2 the output of every pass
+        let set_layouts = [
+            targets.input_layout,
+            targets.input_layout,
+            targets.output_layout,
+        ];
+        let pipeline = |entry: &str| {
+            let shader = ShaderSource::builder().entry(entry).build(
+                ShaderStage::Compute,
+                ShaderLanguage::WGSL,
+                "./src/shaders/bloom.wgsl",
+            );
+            ComputePipeline::new(
+                device,
+                &shader,
+                &set_layouts,
+                size_of::<BloomPushConstants>(),
+            )
+        };
+
+        let chain = Self::create_chain(allocator, device, targets.extent());
+
+        let mut bloom = BloomPass {
+            chain,
+            mip_views: vec![],
+            input_sets,
+            output_sets,
+            descriptor_pool,
+            sampler,
+            downsample_first: pipeline("cs_downsample_first")?,
+            downsample: pipeline("cs_downsample")?,
+            upsample: pipeline("cs_upsample")?,
+            composite: pipeline("cs_composite")?,
+        };
+        bloom.write_descriptors(device);
+
+        Ok(bloom)
+    }
+
+    fn create_chain(allocator: &mut Allocator, device: &Device, extent: vk::Extent2D) -> Image {
+        let extent = vk::Extent2D {
+            width: (extent.width / 2).max(1),
+            height: (extent.height / 2).max(1),
+        };
+        // stop before the smaller side drops below a single pixel
+        let mips = BLOOM_MIPS.min(32 - extent.width.min(extent.height).leading_zeros());
+
+        let desc = ImageDesc::new_2d(
+            HDR_FORMAT,
+            extent,
+            vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::STORAGE,
+        )
+        .mip_levels(mips);
+
+        Image::new(allocator, device, desc, "bloom chain")
+    }
+
+    fn write_descriptors(&mut self, device: &Device) {
+        self.mip_views = (0..self.chain.desc.mip_levels)
+            .map(|mip| self.chain.create_mip_view(device, mip))
+            .collect();
+
+        for (i, &view) in self.mip_views.iter().enumerate() {
+            write_image_descriptor(
+                device,
+                self.input_sets[i],
+                0,
+                vk::DescriptorType::SAMPLED_IMAGE,
+                view,
+                vk::ImageLayout::GENERAL,
+                vk::Sampler::null(),
+            );
+            write_image_descriptor(
+                device,
+                self.input_sets[i],
+                1,
+                vk::DescriptorType::SAMPLER,
+                vk::ImageView::null(),
+                vk::ImageLayout::UNDEFINED,
+                self.sampler,
+            );
+            write_image_descriptor(
+                device,
+                self.output_sets[i],
+                0,
+                vk::DescriptorType::STORAGE_IMAGE,
+                view,
+                vk::ImageLayout::GENERAL,
+                vk::Sampler::null(),
+            );
+        }
+    }
+
+    /// Recreates the mip chain for the new size of `targets`, the device must be idle
+    pub fn resize(&mut self, allocator: &mut Allocator, device: &Device, targets: &PostTargets) {
+        self.destroy_chain(device, allocator);
+        self.chain = Self::create_chain(allocator, device, targets.extent());
+        self.write_descriptors(device);
+    }
+
+    /// Blooms the scene target into another post target and returns its index,
+    /// which is left ready to be sampled. Returns the scene target when disabled.
+    pub fn record(
+        &self,
+        device: &Device,
+        cmd: vk::CommandBuffer,
+        settings: &BloomSettings,
+        targets: &PostTargets,
+    ) -> usize {
+        if !settings.enabled {
+            return SCENE_TARGET;
+        }
+
+        let mips = self.chain.desc.mip_levels;
+        let constants = BloomPushConstants {
+            threshold: settings.threshold,
+            knee: settings.knee,
+            radius: settings.radius,
+            intensity: settings.intensity,
+            mip_count: mips,
+        };
+
+        // the chain is fully rewritten, but the previous frame may still be reading it
+        let chain_barrier = vk::ImageMemoryBarrier::builder()
+            .old_layout(vk::ImageLayout::UNDEFINED)
+            .new_layout(vk::ImageLayout::GENERAL)
+            .dst_access_mask(vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE)
+            .image(self.chain.raw)
+            .subresource_range(self.chain.desc.subresource_range());
+
+        unsafe {
+            device.raw.cmd_pipeline_barrier(
+                cmd,
+                vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[chain_barrier.build()],
+            );
+        }
+
+        let chain_extent = self.chain.desc.extent_2d();
+        let mip_extent = |mip: u32| vk::Extent2D {
+            width: (chain_extent.width >> mip).max(1),
+            height: (chain_extent.height >> mip).max(1),
+        };
+        let scene_set = targets.input_sets[SCENE_TARGET];
+
+        self.dispatch(
+            device,
+            cmd,
+            &self.downsample_first,
+            &constants,
+            [scene_set, scene_set, self.output_sets[0]],
+            mip_extent(0),
+        );
+        for mip in 1..mips {
+            compute_write_barrier(device, cmd);
+            self.dispatch(
+                device,
+                cmd,
+                &self.downsample,
+                &constants,
+                [
+                    scene_set,
+                    self.input_sets[mip as usize - 1],
+                    self.output_sets[mip as usize],
+                ],
+                mip_extent(mip),
+            );
+        }
+        for mip in (0..mips - 1).rev() {
+            compute_write_barrier(device, cmd);
+            self.dispatch(
+                device,
+                cmd,
+                &self.upsample,
+                &constants,
+                [
+                    scene_set,
+                    self.input_sets[mip as usize + 1],
+                    self.output_sets[mip as usize],
+                ],
+                mip_extent(mip),
+            );
+        }
+
+        // the output was last sampled by the post stack or the tonemapper
+        let output = &targets.images[BLOOM_TARGET];
+        let output_barrier = vk::ImageMemoryBarrier::builder()
+            .old_layout(vk::ImageLayout::UNDEFINED)
+            .new_layout(vk::ImageLayout::GENERAL)
+            .dst_access_mask(vk::AccessFlags::SHADER_WRITE)
+            .image(output.raw)
+            .subresource_range(output.desc.subresource_range());
+        let chain_memory_barrier = vk::MemoryBarrier::builder()
+            .src_access_mask(vk::AccessFlags::SHADER_WRITE)
+            .dst_access_mask(vk::AccessFlags::SHADER_READ);
+
+        unsafe {
+            device.raw.cmd_pipeline_barrier(
+                cmd,
+                vk::PipelineStageFlags::FRAGMENT_SHADER | vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::DependencyFlags::empty(),
+                &[chain_memory_barrier.build()],
+                &[],
+                &[output_barrier.build()],
+            );
+        }
+
+        self.dispatch(
+            device,
+            cmd,
+            &self.composite,
+            &constants,
+            [
+                scene_set,
+                self.input_sets[0],
+                targets.output_sets[BLOOM_TARGET],
+            ],
+            targets.extent(),
+        );
+
+        let to_input = vk::ImageMemoryBarrier::builder()
+            .old_layout(vk::ImageLayout::GENERAL)
+            .new_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            .src_access_mask(vk::AccessFlags::SHADER_WRITE)
+            .dst_access_mask(vk::AccessFlags::SHADER_READ)
+            .image(output.raw)
+            .subresource_range(output.desc.subresource_range());
+
+        unsafe {
+            device.raw.cmd_pipeline_barrier(
+                cmd,
+                vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::PipelineStageFlags::FRAGMENT_SHADER | vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[to_input.build()],
+            );
+        }
+
+        BLOOM_TARGET
+    }
+
+    fn dispatch(
+        &self,
+        device: &Device,
+        cmd: vk::CommandBuffer,
+        pipeline: &ComputePipeline,
+        constants: &BloomPushConstants,
+        sets: [vk::DescriptorSet; 3],
+        extent: vk::Extent2D,
+    ) {
+        unsafe {
+            device
+                .raw
+                .cmd_bind_pipeline(cmd, vk::PipelineBindPoint::COMPUTE, pipeline.pipeline);
+            device.raw.cmd_bind_descriptor_sets(
+                cmd,
+                vk::PipelineBindPoint::COMPUTE,
+                pipeline.layout,
+                0,
+                &sets,
+                &[],
+            );
+        }
+
+        pipeline.push_constants(device, cmd, constants);
+
+        unsafe {
+            device.raw.cmd_dispatch(
+                cmd,
+                extent.width.div_ceil(COMPUTE_GROUP_SIZE),
+                extent.height.div_ceil(COMPUTE_GROUP_SIZE),
+                1,
+            );
+        }
+    }
+
+    fn destroy_chain(&mut self, device: &Device, allocator: &mut Allocator) {
+        unsafe {
+            for view in self.mip_views.drain(..) {
+                device.raw.destroy_image_view(view, None);
+            }
+        }
+        self.chain.destroy(device, allocator);
+    }
+
+    pub fn destroy(&mut self, device: &Device, allocator: &mut Allocator) {
+        self.destroy_chain(device, allocator);
+        self.descriptor_pool.destroy(device);
+        unsafe { device.raw.destroy_sampler(self.sampler, None) };
+        self.downsample_first.destroy(device);
+        self.downsample.destroy(device);
+        self.upsample.destroy(device);
+        self.composite.destroy(device);
+    }
+}
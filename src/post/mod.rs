@@ -0,0 +1,11 @@
+pub mod bloom;
+pub mod stack;
+pub mod targets;
+pub mod tonemap;
+
+pub use bloom::{BloomPass, BloomSettings};
+pub use stack::{
+    BuiltinPostEffects, PostEffect, PostEffectDesc, PostEffectHandle, PostEffectStage, PostStack,
+};
+pub use targets::PostTargets;
+pub use tonemap::{TonemapPass, TonemapSettings, Tonemapper};
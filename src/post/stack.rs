@@ -0,0 +1,419 @@
+use super::targets::PostTargets;
+use crate::{
+    backend_vulkan::{
+        device::Device,
+        pipeline::{ComputePipeline, GraphicsPipeline},
+        shader::{ShaderLanguage, ShaderSource, ShaderStage},
+    },
+    resource::{deletion_queue::Retired, Handle, Pool},
+    HDR_FORMAT,
+};
+use anyhow::Result;
+use ash::vk;
+use std::{mem::size_of, path::PathBuf};
+
+/// Number of floats every effect receives as push constants
+pub const POST_EFFECT_PARAMS: usize = 16;
+
+/// Workgroup size of compute effects in both dimensions
+pub(crate) const COMPUTE_GROUP_SIZE: u32 = 8;
+
+const BUILTIN_EFFECTS_SHADER: &str = "./src/shaders/post_effects.wgsl";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PostEffectStage {
+    /// Drawn as a fullscreen triangle into the output
+    Fragment,
+    /// Dispatched over the output in 8x8 groups, writing it as a storage image
+    Compute,
+}
+
+/// Describes an effect to add to the post-processing chain.
+///
+/// Every effect binds the frame's descriptor set at set 0 and its input at set 1
+/// (sampled image at 0, sampler at 1). Compute effects additionally get their output
+/// as an `rgba16float` storage image at set 2, binding 0. `params` are pushed as
+/// `array<vec4<f32>, 4>`.
+#[derive(Clone, Debug)]
+pub struct PostEffectDesc {
+    pub name: String,
+    pub stage: PostEffectStage,
+    pub shader: PathBuf,
+    pub entry: String,
+    pub params: [f32; POST_EFFECT_PARAMS],
+    pub enabled: bool,
+}
+
+impl PostEffectDesc {
+    pub fn fragment(
+        name: impl Into<String>,
+        shader: impl Into<PathBuf>,
+        entry: impl Into<String>,
+    ) -> Self {
+        PostEffectDesc {
+            name: name.into(),
+            stage: PostEffectStage::Fragment,
+            shader: shader.into(),
+            entry: entry.into(),
+            params: [0.0; POST_EFFECT_PARAMS],
+            enabled: true,
+        }
+    }
+
+    pub fn compute(
+        name: impl Into<String>,
+        shader: impl Into<PathBuf>,
+        entry: impl Into<String>,
+    ) -> Self {
+        PostEffectDesc {
+            stage: PostEffectStage::Compute,
+            ..Self::fragment(name, shader, entry)
+        }
+    }
+
+    /// Sets the first `params.len()` parameters
+    pub fn params(mut self, params: &[f32]) -> Self {
+        self.params[..params.len()].copy_from_slice(params);
+        self
+    }
+
+    pub fn enabled(mut self, enabled: bool) -> Self {
+        self.enabled = enabled;
+        self
+    }
+
+    /// Darkens the corners, params are intensity, radius and softness
+    pub fn vignette() -> Self {
+        Self::fragment("vignette", BUILTIN_EFFECTS_SHADER, "fs_vignette").params(&[0.4, 0.8, 0.5])
+    }
+
+    /// Splits the color channels towards the edges, param is the strength in UV units
+    pub fn chromatic_aberration() -> Self {
+        Self::fragment(
+            "chromatic aberration",
+            BUILTIN_EFFECTS_SHADER,
+            "fs_chromatic_aberration",
+        )
+        .params(&[0.004])
+    }
+
+    /// Animated per-pixel noise, param is the intensity
+    pub fn film_grain() -> Self {
+        Self::compute("film grain", BUILTIN_EFFECTS_SHADER, "cs_film_grain").params(&[0.08])
+    }
+
+    /// Params are saturation, contrast around middle grey and an RGB tint
+    pub fn color_grade() -> Self {
+        Self::compute("color grade", BUILTIN_EFFECTS_SHADER, "cs_color_grade")
+            .params(&[1.1, 1.1, 1.0, 1.0, 1.0])
+    }
+}
+
+enum PostPipeline {
+    Graphics(GraphicsPipeline),
+    Compute(ComputePipeline),
+}
+
+pub struct PostEffect {
+    pub name: String,
+    pub params: [f32; POST_EFFECT_PARAMS],
+    pub enabled: bool,
+    pipeline: PostPipeline,
+}
+
+impl PostEffect {
+    pub fn stage(&self) -> PostEffectStage {
+        match self.pipeline {
+            PostPipeline::Graphics(_) => PostEffectStage::Fragment,
+            PostPipeline::Compute(_) => PostEffectStage::Compute,
+        }
+    }
+
+    pub(crate) fn into_retired(self) -> Retired {
+        match self.pipeline {
+            PostPipeline::Graphics(pipeline) => pipeline.into(),
+            PostPipeline::Compute(pipeline) => pipeline.into(),
+        }
+    }
+}
+
+pub type PostEffectHandle = Handle<PostEffect>;
+
+/// Handles of the effects every renderer starts out with, all disabled
+#[derive(Clone, Copy, Debug)]
+pub struct BuiltinPostEffects {
+    pub color_grade: PostEffectHandle,
+    pub chromatic_aberration: PostEffectHandle,
+    pub film_grain: PostEffectHandle,
+    pub vignette: PostEffectHandle,
+}
+
+/// Ordered chain of effects run on the HDR image between the scene and tonemapping
+#[derive(Default)]
+pub struct PostStack {
+    effects: Pool<PostEffect>,
+    order: Vec<PostEffectHandle>,
+}
+
+impl PostStack {
+    /// Compiles the effect and appends it to the end of the chain
+    pub fn add(
+        &mut self,
+        device: &Device,
+        targets: &PostTargets,
+        frame_descriptor_layout: vk::DescriptorSetLayout,
+        desc: PostEffectDesc,
+    ) -> Result<PostEffectHandle> {
+        let push_constant_size = size_of::<[f32; POST_EFFECT_PARAMS]>();
+
+        let pipeline = match desc.stage {
+            PostEffectStage::Fragment => {
+                let vertex_shader = ShaderSource::builder().entry("vs_fullscreen").build(
+                    ShaderStage::Vertex,
+                    ShaderLanguage::WGSL,
+                    "./src/shaders/fullscreen.wgsl",
+                );
+                let fragment_shader = ShaderSource::builder().entry(&desc.entry).build(
+                    ShaderStage::Fragment,
+                    ShaderLanguage::WGSL,
+                    &desc.shader,
+                );
+
+                PostPipeline::Graphics(
+                    GraphicsPipeline::builder()
+                        .color_formats(&[HDR_FORMAT])
+                        .descriptor_set_layouts(&[frame_descriptor_layout, targets.input_layout])
+                        .push_constant_size(push_constant_size)
+                        .build(device, &[vertex_shader, fragment_shader])?,
+                )
+            }
+            PostEffectStage::Compute => {
+                let shader = ShaderSource::builder().entry(&desc.entry).build(
+                    ShaderStage::Compute,
+                    ShaderLanguage::WGSL,
+                    &desc.shader,
+                );
+
+                PostPipeline::Compute(ComputePipeline::new(
+                    device,
+                    &shader,
+                    &[
+                        frame_descriptor_layout,
+                        targets.input_layout,
+                        targets.output_layout,
+                    ],
+                    push_constant_size,
+                )?)
+            }
+        };
+
+        let handle = self.effects.insert(PostEffect {
+            name: desc.name,
+            params: desc.params,
+            enabled: desc.enabled,
+            pipeline,
+        });
+        self.order.push(handle);
+
+        Ok(handle)
+    }
+
+    /// Takes the effect out of the chain, its pipeline must be retired by the caller
+    pub fn remove(&mut self, handle: PostEffectHandle) -> Option<PostEffect> {
+        let effect = self.effects.remove(handle)?;
+        self.order.retain(|&h| h != handle);
+        Some(effect)
+    }
+
+    pub fn get(&self, handle: PostEffectHandle) -> Option<&PostEffect> {
+        self.effects.get(handle)
+    }
+
+    pub fn get_mut(&mut self, handle: PostEffectHandle) -> Option<&mut PostEffect> {
+        self.effects.get_mut(handle)
+    }
+
+    /// Handles of all effects in the order they are applied
+    pub fn order(&self) -> &[PostEffectHandle] {
+        &self.order
+    }
+
+    /// Applies every enabled effect, starting from target `input` which must be in
+    /// `SHADER_READ_ONLY_OPTIMAL`. Returns the index of the target holding the result,
+    /// which is left in the same layout.
+    pub fn record(
+        &self,
+        device: &Device,
+        cmd: vk::CommandBuffer,
+        targets: &PostTargets,
+        frame_descriptor_set: vk::DescriptorSet,
+        mut input: usize,
+    ) -> usize {
+        let extent = targets.extent();
+        let readers =
+            vk::PipelineStageFlags::FRAGMENT_SHADER | vk::PipelineStageFlags::COMPUTE_SHADER;
+
+        for effect in self
+            .order
+            .iter()
+            .map(|&h| &self.effects[h])
+            .filter(|e| e.enabled)
+        {
+            // ping-pong between the two post targets, never writing the input
+            let output = if input == 1 { 2 } else { 1 };
+            let image = &targets.images[output];
+
+            let (layout, stage, access) = match effect.pipeline {
+                PostPipeline::Graphics(_) => (
+                    vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                    vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+                    vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+                ),
+                PostPipeline::Compute(_) => (
+                    vk::ImageLayout::GENERAL,
+                    vk::PipelineStageFlags::COMPUTE_SHADER,
+                    vk::AccessFlags::SHADER_WRITE,
+                ),
+            };
+
+            // the output was last sampled by an earlier effect or the tonemapper
+            let to_output = vk::ImageMemoryBarrier::builder()
+                .old_layout(vk::ImageLayout::UNDEFINED)
+                .new_layout(layout)
+                .dst_access_mask(access)
+                .image(image.raw)
+                .subresource_range(image.desc.subresource_range());
+
+            unsafe {
+                device.raw.cmd_pipeline_barrier(
+                    cmd,
+                    readers,
+                    stage,
+                    vk::DependencyFlags::empty(),
+                    &[],
+                    &[],
+                    &[to_output.build()],
+                );
+            }
+
+            match &effect.pipeline {
+                PostPipeline::Graphics(pipeline) => {
+                    let color_attachments = [vk::RenderingAttachmentInfo::builder()
+                        .image_view(image.view)
+                        .image_layout(layout)
+                        .load_op(vk::AttachmentLoadOp::DONT_CARE)
+                        .store_op(vk::AttachmentStoreOp::STORE)
+                        .build()];
+
+                    let render_area = vk::Rect2D {
+                        extent,
+                        ..Default::default()
+                    };
+                    let rendering_info = vk::RenderingInfo::builder()
+                        .render_area(render_area)
+                        .layer_count(1)
+                        .color_attachments(&color_attachments);
+
+                    let viewports = [vk::Viewport::builder()
+                        .width(extent.width as f32)
+                        .height(extent.height as f32)
+                        .min_depth(0.0)
+                        .max_depth(1.0)
+                        .build()];
+
+                    unsafe {
+                        device.raw.cmd_begin_rendering(cmd, &rendering_info);
+                        device.raw.cmd_set_viewport(cmd, 0, &viewports);
+                        device.raw.cmd_set_scissor(cmd, 0, &[render_area]);
+                        device.raw.cmd_bind_pipeline(
+                            cmd,
+                            vk::PipelineBindPoint::GRAPHICS,
+                            pipeline.pipeline,
+                        );
+                        device.raw.cmd_bind_descriptor_sets(
+                            cmd,
+                            vk::PipelineBindPoint::GRAPHICS,
+                            pipeline.layout,
+                            0,
+                            &[frame_descriptor_set, targets.input_sets[input]],
+                            &[],
+                        );
+                    }
+
+                    pipeline.push_constants(device, cmd, &effect.params);
+
+                    unsafe {
+                        device.raw.cmd_draw(cmd, 3, 1, 0, 0);
+                        device.raw.cmd_end_rendering(cmd);
+                    }
+                }
+                PostPipeline::Compute(pipeline) => {
+                    unsafe {
+                        device.raw.cmd_bind_pipeline(
+                            cmd,
+                            vk::PipelineBindPoint::COMPUTE,
+                            pipeline.pipeline,
+                        );
+                        device.raw.cmd_bind_descriptor_sets(
+                            cmd,
+                            vk::PipelineBindPoint::COMPUTE,
+                            pipeline.layout,
+                            0,
+                            &[
+                                frame_descriptor_set,
+                                targets.input_sets[input],
+                                targets.output_sets[output],
+                            ],
+                            &[],
+                        );
+                    }
+
+                    pipeline.push_constants(device, cmd, &effect.params);
+
+                    unsafe {
+                        device.raw.cmd_dispatch(
+                            cmd,
+                            extent.width.div_ceil(COMPUTE_GROUP_SIZE),
+                            extent.height.div_ceil(COMPUTE_GROUP_SIZE),
+                            1,
+                        );
+                    }
+                }
+            }
+
+            let to_input = vk::ImageMemoryBarrier::builder()
+                .old_layout(layout)
+                .new_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+                .src_access_mask(access)
+                .dst_access_mask(vk::AccessFlags::SHADER_READ)
+                .image(image.raw)
+                .subresource_range(image.desc.subresource_range());
+
+            unsafe {
+                device.raw.cmd_pipeline_barrier(
+                    cmd,
+                    stage,
+                    readers,
+                    vk::DependencyFlags::empty(),
+                    &[],
+                    &[],
+                    &[to_input.build()],
+                );
+            }
+
+            input = output;
+        }
+
+        input
+    }
+
+    pub fn destroy(&mut self, device: &Device) {
+        for effect in self.effects.drain() {
+            match effect.pipeline {
+                PostPipeline::Graphics(pipeline) => pipeline.destroy(device),
+                PostPipeline::Compute(pipeline) => pipeline.destroy(device),
+            }
+        }
+        self.order.clear();
+    }
+}
@@ -0,0 +1,171 @@
+use crate::{
+    backend_vulkan::{
+        descriptor::{write_image_descriptor, DescriptorPool, DescriptorSetLayoutBuilder},
+        device::Device,
+        image::{Image, ImageDesc},
+        sampler::create_sampler,
+    },
+    HDR_FORMAT,
+};
+use anyhow::Result;
+use ash::vk;
+use gpu_allocator::vulkan::Allocator;
+
+/// The scene HDR image followed by the two images post effects ping-pong between
+pub const POST_TARGET_COUNT: usize = 3;
+
+/// Index of the image the scene is rendered into
+pub const SCENE_TARGET: usize = 0;
+
+/// Screen-sized HDR images shared by the scene and the post-processing chain.
+///
+/// Each image has a descriptor set to sample it and one to write it from compute
+/// shaders, so passes pick their input and output without rewriting descriptors.
+pub struct PostTargets {
+    pub images: Vec<Image>,
+    /// Binding 0 is the sampled image, binding 1 a linear clamping sampler
+    pub input_layout: vk::DescriptorSetLayout,
+    /// Binding 0 is the image as a storage image in `GENERAL` layout
+    pub output_layout: vk::DescriptorSetLayout,
+    pub input_sets: Vec<vk::DescriptorSet>,
+    pub output_sets: Vec<vk::DescriptorSet>,
+    descriptor_pool: DescriptorPool,
+    sampler: vk::Sampler,
+}
+
+impl PostTargets {
+    pub fn new(allocator: &mut Allocator, device: &Device, extent: vk::Extent2D) -> Result<Self> {
+        let stages = vk::ShaderStageFlags::FRAGMENT | vk::ShaderStageFlags::COMPUTE;
+
+        let input_layout = DescriptorSetLayoutBuilder::default()
+            .binding(0, vk::DescriptorType::SAMPLED_IMAGE, stages)
+            .binding(1, vk::DescriptorType::SAMPLER, stages)
+            .build(device)?;
+        let output_layout = DescriptorSetLayoutBuilder::default()
+            .binding(0, vk::DescriptorType::STORAGE_IMAGE, stages)
+            .build(device)?;
+
+        let count = POST_TARGET_COUNT as u32;
+        let descriptor_pool = DescriptorPool::new(
+            device,
+            2 * count,
+            &[
+                vk::DescriptorPoolSize {
+                    ty: vk::DescriptorType::SAMPLED_IMAGE,
+                    descriptor_count: count,
+                },
+                vk::DescriptorPoolSize {
+                    ty: vk::DescriptorType::SAMPLER,
+                    descriptor_count: count,
+                },
+                vk::DescriptorPoolSize {
+                    ty: vk::DescriptorType::STORAGE_IMAGE,
+                    descriptor_count: count,
+                },
+            ],
+        )?;
+
+        let input_sets = (0..POST_TARGET_COUNT)
+            .map(|_| descriptor_pool.allocate(device, input_layout))
+            .collect::<Result<Vec<_>>>()?;
+        let output_sets = (0..POST_TARGET_COUNT)
+            .map(|_| descriptor_pool.allocate(device, output_layout))
+            .collect::<Result<Vec<_>>>()?;
+
+        let sampler = create_sampler(
+            device,
+            vk::Filter::LINEAR,
+            vk::SamplerAddressMode::CLAMP_TO_EDGE,
+        )?;
+
+        let mut targets = PostTargets {
+            images: vec![],
+            input_layout,
+            output_layout,
+            input_sets,
+            output_sets,
+            descriptor_pool,
+            sampler,
+        };
+        targets.create_images(allocator, device, extent);
+
+        Ok(targets)
+    }
+
+    fn create_images(&mut self, allocator: &mut Allocator, device: &Device, extent: vk::Extent2D) {
+        let usage = vk::ImageUsageFlags::COLOR_ATTACHMENT
+            | vk::ImageUsageFlags::SAMPLED
+            | vk::ImageUsageFlags::STORAGE;
+
+        let names = ["hdr", "post ping", "post pong"];
+        self.images = names
+            .into_iter()
+            .map(|name| {
+                Image::new(
+                    allocator,
+                    device,
+                    ImageDesc::new_2d(HDR_FORMAT, extent, usage),
+                    name,
+                )
+            })
+            .collect();
+
+        for (i, image) in self.images.iter().enumerate() {
+            write_image_descriptor(
+                device,
+                self.input_sets[i],
+                0,
+                vk::DescriptorType::SAMPLED_IMAGE,
+                image.view,
+                vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                vk::Sampler::null(),
+            );
+            write_image_descriptor(
+                device,
+                self.input_sets[i],
+                1,
+                vk::DescriptorType::SAMPLER,
+                vk::ImageView::null(),
+                vk::ImageLayout::UNDEFINED,
+                self.sampler,
+            );
+            write_image_descriptor(
+                device,
+                self.output_sets[i],
+                0,
+                vk::DescriptorType::STORAGE_IMAGE,
+                image.view,
+                vk::ImageLayout::GENERAL,
+                vk::Sampler::null(),
+            );
+        }
+    }
+
+    /// Recreates the images at a new size, the device must be idle
+    pub fn resize(&mut self, allocator: &mut Allocator, device: &Device, extent: vk::Extent2D) {
+        for image in &mut self.images {
+            image.destroy(device, allocator);
+        }
+        self.create_images(allocator, device, extent);
+    }
+
+    pub fn extent(&self) -> vk::Extent2D {
+        self.images[SCENE_TARGET].desc.extent_2d()
+    }
+
+    pub fn destroy(&mut self, device: &Device, allocator: &mut Allocator) {
+        for image in &mut self.images {
+            image.destroy(device, allocator);
+        }
+        self.descriptor_pool.destroy(device);
+        unsafe {
+            device.raw.destroy_sampler(self.sampler, None);
+            device
+                .raw
+                .destroy_descriptor_set_layout(self.input_layout, None);
+            device
+                .raw
+                .destroy_descriptor_set_layout(self.output_layout, None);
+        }
+    }
+}
@@ -0,0 +1,150 @@
+use crate::backend_vulkan::{
+    device::Device,
+    pipeline::GraphicsPipeline,
+    shader::{ShaderLanguage, ShaderSource, ShaderStage},
+};
+use anyhow::Result;
+use ash::vk;
+use std::mem::size_of;
+
+/// Curve mapping HDR scene radiance to the displayable range
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Tonemapper {
+    /// Stephen Hill's fit of the ACES reference rendering and output transforms
+    Aces,
+    /// Troy Sobotka's AgX with the default look
+    #[default]
+    AgX,
+    Reinhard,
+}
+
+impl Tonemapper {
+    pub const ALL: [Tonemapper; 3] = [Tonemapper::Aces, Tonemapper::AgX, Tonemapper::Reinhard];
+
+    /// The next operator, wrapping around after the last one
+    pub fn next(self) -> Self {
+        let index = Self::ALL.iter().position(|&t| t == self).unwrap();
+        Self::ALL[(index + 1) % Self::ALL.len()]
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TonemapSettings {
+    pub tonemapper: Tonemapper,
+    /// Exposure compensation in stops, the scene is scaled by `2^exposure`
+    pub exposure: f32,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+#[repr(C)]
+struct TonemapPushConstants {
+    exposure: f32,
+    tonemapper: u32,
+}
+
+/// Fullscreen pass resolving an HDR image to the swapchain
+pub struct TonemapPass {
+    pipeline: GraphicsPipeline,
+}
+
+impl TonemapPass {
+    /// `input_layout` is the layout of the set the HDR image is sampled through
+    pub fn new(
+        device: &Device,
+        output_format: vk::Format,
+        input_layout: vk::DescriptorSetLayout,
+    ) -> Result<Self> {
+        let vertex_shader = ShaderSource::builder().entry("vs_fullscreen").build(
+            ShaderStage::Vertex,
+            ShaderLanguage::WGSL,
+            "./src/shaders/fullscreen.wgsl",
+        );
+        let fragment_shader = ShaderSource::builder().entry("fs_tonemap").build(
+            ShaderStage::Fragment,
+            ShaderLanguage::WGSL,
+            "./src/shaders/tonemap.wgsl",
+        );
+
+        let pipeline = GraphicsPipeline::builder()
+            .color_formats(&[output_format])
+            .descriptor_set_layouts(&[input_layout])
+            .push_constant_size(size_of::<TonemapPushConstants>())
+            .build(device, &[vertex_shader, fragment_shader])?;
+
+        Ok(TonemapPass { pipeline })
+    }
+
+    /// Draws into `output`, which must be in `COLOR_ATTACHMENT_OPTIMAL`
+    /// while the image behind `input_set` is in `SHADER_READ_ONLY_OPTIMAL`
+    pub fn record(
+        &self,
+        device: &Device,
+        cmd: vk::CommandBuffer,
+        settings: &TonemapSettings,
+        input_set: vk::DescriptorSet,
+        output: vk::ImageView,
+        extent: vk::Extent2D,
+    ) {
+        let color_attachments = [vk::RenderingAttachmentInfo::builder()
+            .image_view(output)
+            .image_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+            .load_op(vk::AttachmentLoadOp::DONT_CARE)
+            .store_op(vk::AttachmentStoreOp::STORE)
+            .build()];
+
+        let render_area = vk::Rect2D {
+            extent,
+            ..Default::default()
+        };
+
+        let rendering_info = vk::RenderingInfo::builder()
+            .render_area(render_area)
+            .layer_count(1)
+            .color_attachments(&color_attachments);
+
+        let viewports = [vk::Viewport::builder()
+            .width(extent.width as f32)
+            .height(extent.height as f32)
+            .min_depth(0.0)
+            .max_depth(1.0)
+            .build()];
+
+        let constants = TonemapPushConstants {
+            exposure: settings.exposure.exp2(),
+            tonemapper: settings.tonemapper as u32,
+        };
+
+        unsafe {
+            device.raw.cmd_begin_rendering(cmd, &rendering_info);
+
+            device.raw.cmd_set_viewport(cmd, 0, &viewports);
+            device.raw.cmd_set_scissor(cmd, 0, &[render_area]);
+
+            device.raw.cmd_bind_pipeline(
+                cmd,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipeline.pipeline,
+            );
+            device.raw.cmd_bind_descriptor_sets(
+                cmd,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipeline.layout,
+                0,
+                &[input_set],
+                &[],
+            );
+        }
+
+        self.pipeline.push_constants(device, cmd, &constants);
+
+        unsafe {
+            // a single triangle covering the whole screen
+            device.raw.cmd_draw(cmd, 3, 1, 0, 0);
+            device.raw.cmd_end_rendering(cmd);
+        }
+    }
+
+    pub fn destroy(&mut self, device: &Device) {
+        self.pipeline.destroy(device);
+    }
+}
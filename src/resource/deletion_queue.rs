@@ -0,0 +1,88 @@
+use crate::backend_vulkan::{
+    buffer::Buffer,
+    device::Device,
+    image::Image,
+    pipeline::{ComputePipeline, GraphicsPipeline},
+};
+use gpu_allocator::vulkan::Allocator;
+use std::collections::VecDeque;
+
+pub enum Retired {
+    Buffer(Buffer),
+    Image(Image),
+    GraphicsPipeline(GraphicsPipeline),
+    ComputePipeline(ComputePipeline),
+}
+
+impl From<Buffer> for Retired {
+    fn from(buffer: Buffer) -> Self {
+        Retired::Buffer(buffer)
+    }
+}
+
+impl From<Image> for Retired {
+    fn from(image: Image) -> Self {
+        Retired::Image(image)
+    }
+}
+
+impl From<GraphicsPipeline> for Retired {
+    fn from(pipeline: GraphicsPipeline) -> Self {
+        Retired::GraphicsPipeline(pipeline)
+    }
+}
+
+impl From<ComputePipeline> for Retired {
+    fn from(pipeline: ComputePipeline) -> Self {
+        Retired::ComputePipeline(pipeline)
+    }
+}
+
+impl Retired {
+    fn destroy(self, device: &Device, allocator: &mut Allocator) {
+        match self {
+            Retired::Buffer(mut buffer) => buffer.destroy(device, allocator),
+            Retired::Image(mut image) => image.destroy(device, allocator),
+            Retired::GraphicsPipeline(pipeline) => pipeline.destroy(device),
+            Retired::ComputePipeline(pipeline) => pipeline.destroy(device),
+        }
+    }
+}
+
+/// Holds on to GPU resources removed while frames that may still
+/// use them are in flight, and destroys them once those have finished.
+#[derive(Default)]
+pub struct DeletionQueue {
+    pending: VecDeque<(u64, Retired)>,
+}
+
+impl DeletionQueue {
+    /// `frame_number` is the first frame that no longer uses the resource
+    pub fn push(&mut self, frame_number: u64, resource: impl Into<Retired>) {
+        self.pending.push_back((frame_number, resource.into()));
+    }
+
+    /// Destroys every resource retired at or before `first_pending_frame`,
+    /// which must be the oldest frame the GPU has not finished yet
+    pub fn collect(
+        &mut self,
+        first_pending_frame: u64,
+        device: &Device,
+        allocator: &mut Allocator,
+    ) {
+        while let Some((frame, _)) = self.pending.front() {
+            if *frame > first_pending_frame {
+                break;
+            }
+            let (_, resource) = self.pending.pop_front().unwrap();
+            resource.destroy(device, allocator);
+        }
+    }
+
+    /// Destroys everything regardless of frame, the device must be idle
+    pub fn flush(&mut self, device: &Device, allocator: &mut Allocator) {
+        for (_, resource) in self.pending.drain(..) {
+            resource.destroy(device, allocator);
+        }
+    }
+}
@@ -0,0 +1,17 @@
+pub mod deletion_queue;
+pub mod pool;
+
+pub use deletion_queue::DeletionQueue;
+pub use pool::{Handle, Pool};
+
+use crate::{backend_vulkan::mesh::Mesh, scene::Material};
+use thiserror::Error;
+
+pub type MeshHandle = Handle<Mesh>;
+pub type MaterialHandle = Handle<Material>;
+
+#[derive(Error, Debug)]
+pub enum ResourceError {
+    #[error("Handle refers to a resource that was removed or never existed")]
+    InvalidHandle,
+}
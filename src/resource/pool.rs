@@ -0,0 +1,258 @@
+use std::{
+    fmt,
+    hash::{Hash, Hasher},
+    marker::PhantomData,
+};
+
+/// Generational index into a [`Pool`], stale handles to removed
+/// items never alias whatever later reuses the same slot.
+pub struct Handle<T> {
+    index: u32,
+    generation: u32,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<T> Handle<T> {
+    fn new(index: u32, generation: u32) -> Self {
+        Handle {
+            index,
+            generation,
+            _marker: PhantomData,
+        }
+    }
+
+    pub fn index(&self) -> u32 {
+        self.index
+    }
+
+    pub fn generation(&self) -> u32 {
+        self.generation
+    }
+}
+
+// implemented by hand as deriving would put bounds on `T`
+impl<T> Clone for Handle<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for Handle<T> {}
+
+impl<T> PartialEq for Handle<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.index == other.index && self.generation == other.generation
+    }
+}
+
+impl<T> Eq for Handle<T> {}
+
+impl<T> Hash for Handle<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.index.hash(state);
+        self.generation.hash(state);
+    }
+}
+
+impl<T> fmt::Debug for Handle<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Handle({}v{})", self.index, self.generation)
+    }
+}
+
+struct Slot<T> {
+    generation: u32,
+    value: Option<T>,
+}
+
+pub struct Pool<T> {
+    slots: Vec<Slot<T>>,
+    free: Vec<u32>,
+    len: usize,
+}
+
+impl<T> Default for Pool<T> {
+    fn default() -> Self {
+        Pool {
+            slots: vec![],
+            free: vec![],
+            len: 0,
+        }
+    }
+}
+
+impl<T> Pool<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(&mut self, value: T) -> Handle<T> {
+        self.len += 1;
+
+        match self.free.pop() {
+            Some(index) => {
+                let slot = &mut self.slots[index as usize];
+                slot.value = Some(value);
+                Handle::new(index, slot.generation)
+            }
+            None => {
+                self.slots.push(Slot {
+                    generation: 0,
+                    value: Some(value),
+                });
+                Handle::new(self.slots.len() as u32 - 1, 0)
+            }
+        }
+    }
+
+    pub fn remove(&mut self, handle: Handle<T>) -> Option<T> {
+        let slot = self.slots.get_mut(handle.index as usize)?;
+        if slot.generation != handle.generation {
+            return None;
+        }
+
+        let value = slot.value.take()?;
+        slot.generation = slot.generation.wrapping_add(1);
+        self.free.push(handle.index);
+        self.len -= 1;
+
+        Some(value)
+    }
+
+    pub fn get(&self, handle: Handle<T>) -> Option<&T> {
+        self.slots
+            .get(handle.index as usize)
+            .filter(|slot| slot.generation == handle.generation)
+            .and_then(|slot| slot.value.as_ref())
+    }
+
+    pub fn get_mut(&mut self, handle: Handle<T>) -> Option<&mut T> {
+        self.slots
+            .get_mut(handle.index as usize)
+            .filter(|slot| slot.generation == handle.generation)
+            .and_then(|slot| slot.value.as_mut())
+    }
+
+    pub fn contains(&self, handle: Handle<T>) -> bool {
+        self.get(handle).is_some()
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Upper bound on handle indices, removed slots included
+    pub fn slot_count(&self) -> usize {
+        self.slots.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (Handle<T>, &T)> {
+        self.slots.iter().enumerate().filter_map(|(i, slot)| {
+            slot.value
+                .as_ref()
+                .map(|value| (Handle::new(i as u32, slot.generation), value))
+        })
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (Handle<T>, &mut T)> {
+        self.slots.iter_mut().enumerate().filter_map(|(i, slot)| {
+            let generation = slot.generation;
+            slot.value
+                .as_mut()
+                .map(|value| (Handle::new(i as u32, generation), value))
+        })
+    }
+
+    /// Removes every item, leaving all outstanding handles stale
+    pub fn drain(&mut self) -> Vec<T> {
+        let mut values = Vec::with_capacity(self.len);
+
+        for (i, slot) in self.slots.iter_mut().enumerate() {
+            if let Some(value) = slot.value.take() {
+                slot.generation = slot.generation.wrapping_add(1);
+                self.free.push(i as u32);
+                values.push(value);
+            }
+        }
+
+        self.len = 0;
+        values
+    }
+}
+
+impl<T> std::ops::Index<Handle<T>> for Pool<T> {
+    type Output = T;
+
+    fn index(&self, handle: Handle<T>) -> &T {
+        self.get(handle).expect("Stale or invalid handle")
+    }
+}
+
+impl<T> std::ops::IndexMut<Handle<T>> for Pool<T> {
+    fn index_mut(&mut self, handle: Handle<T>) -> &mut T {
+        self.get_mut(handle).expect("Stale or invalid handle")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reused_slots_get_a_new_generation() {
+        let mut pool = Pool::new();
+        let first = pool.insert("a");
+        assert_eq!(pool.remove(first), Some("a"));
+
+        let second = pool.insert("b");
+        assert_eq!(second.index(), first.index());
+        assert_eq!(second.generation(), first.generation() + 1);
+        assert_ne!(second, first);
+
+        // the stale handle neither reads nor removes what took its slot
+        assert!(!pool.contains(first));
+        assert_eq!(pool.get(first), None);
+        assert_eq!(pool.get_mut(first), None);
+        assert_eq!(pool.remove(first), None);
+        assert_eq!(pool[second], "b");
+        assert_eq!(pool.len(), 1);
+        assert_eq!(pool.slot_count(), 1);
+    }
+
+    #[test]
+    fn removing_twice() {
+        let mut pool = Pool::new();
+        let handle = pool.insert(1);
+        pool.insert(2);
+        assert_eq!(pool.remove(handle), Some(1));
+        assert_eq!(pool.remove(handle), None);
+        assert_eq!(pool.len(), 1);
+
+        // the slot was freed once, so it is only handed out once
+        let (a, b) = (pool.insert(3), pool.insert(4));
+        assert_ne!(a.index(), b.index());
+        assert_eq!(pool.slot_count(), 3);
+    }
+
+    #[test]
+    fn drain_invalidates_handles() {
+        let mut pool = Pool::new();
+        let handles = [pool.insert(1), pool.insert(2)];
+        assert_eq!(pool.drain(), vec![1, 2]);
+        assert!(pool.is_empty());
+
+        let handle = pool.insert(3);
+        assert!(handles.iter().all(|&old| !pool.contains(old)));
+        assert_eq!(handle.generation(), 1);
+        assert_eq!(
+            pool.iter()
+                .map(|(handle, &value)| (handle, value))
+                .collect::<Vec<_>>(),
+            vec![(handle, 3)]
+        );
+    }
+}
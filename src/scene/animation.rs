@@ -0,0 +1,431 @@
+use super::{NodeHandle, Pose, Scene, Transform};
+use glam::{Quat, Vec3};
+use std::ops::{Add, Mul};
+
+/// How values are blended between keyframes, as in glTF
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Interpolation {
+    /// The previous keyframe is held until the next one
+    Step,
+    /// Linear for translations and scales, spherical for rotations
+    Linear,
+    /// Hermite spline, every keyframe stores an in-tangent, a value and an out-tangent
+    CubicSpline,
+}
+
+/// Values of a channel, one per keyframe or three per keyframe for cubic splines
+#[derive(Clone, Debug)]
+pub enum Keyframes {
+    Translation(Vec<Vec3>),
+    Rotation(Vec<Quat>),
+    Scale(Vec<Vec3>),
+    /// One weight per morph target for every value, see [`super::Node::morph_weights`]
+    Weights(Vec<f32>),
+}
+
+/// Animates one property of a node, `T` identifies the node
+#[derive(Clone, Debug)]
+pub struct AnimationChannel<T> {
+    pub target: T,
+    pub interpolation: Interpolation,
+    /// Keyframe times in seconds, in increasing order
+    pub times: Vec<f32>,
+    pub keyframes: Keyframes,
+}
+
+/// A set of channels played together, the targets are scene nodes unless the
+/// clip was just loaded from an asset
+#[derive(Clone, Debug)]
+pub struct AnimationClip<T = NodeHandle> {
+    pub name: String,
+    pub channels: Vec<AnimationChannel<T>>,
+    /// Time of the last keyframe of any channel
+    pub duration: f32,
+}
+
+impl<T> AnimationClip<T> {
+    pub fn new(name: impl Into<String>, channels: Vec<AnimationChannel<T>>) -> Self {
+        let duration = channels
+            .iter()
+            .filter_map(|channel| channel.times.last().copied())
+            .fold(0.0, f32::max);
+        AnimationClip {
+            name: name.into(),
+            channels,
+            duration,
+        }
+    }
+
+    /// Retargets the clip, channels for which `map` returns `None` are dropped
+    pub fn map_targets<U>(&self, mut map: impl FnMut(&T) -> Option<U>) -> AnimationClip<U> {
+        AnimationClip {
+            name: self.name.clone(),
+            channels: self
+                .channels
+                .iter()
+                .filter_map(|channel| {
+                    Some(AnimationChannel {
+                        target: map(&channel.target)?,
+                        interpolation: channel.interpolation,
+                        times: channel.times.clone(),
+                        keyframes: channel.keyframes.clone(),
+                    })
+                })
+                .collect(),
+            duration: self.duration,
+        }
+    }
+}
+
+impl<T> AnimationChannel<T> {
+    /// Writes the animated property at `time` into `transform`, unless the channel
+    /// animates morph weights
+    fn sample_transform(&self, time: f32, transform: &mut Transform) {
+        let (interpolation, times) = (self.interpolation, &self.times[..]);
+        match &self.keyframes {
+            Keyframes::Translation(values) => {
+                transform.translation = sample(interpolation, times, |i| values[i], time)
+            }
+            Keyframes::Rotation(values) => {
+                transform.rotation = sample(interpolation, times, |i| values[i], time).normalize()
+            }
+            Keyframes::Scale(values) => {
+                transform.scale = sample(interpolation, times, |i| values[i], time)
+            }
+            Keyframes::Weights(_) => {}
+        }
+    }
+
+    /// Morph weights at `time`, when the channel animates them
+    fn sample_weights(&self, time: f32) -> Option<Vec<f32>> {
+        let Keyframes::Weights(values) = &self.keyframes else {
+            return None;
+        };
+        let (interpolation, times) = (self.interpolation, &self.times[..]);
+        let values_per_key = match interpolation {
+            Interpolation::CubicSpline => 3,
+            _ => 1,
+        };
+        let count = values.len() / (times.len() * values_per_key).max(1);
+        Some(
+            (0..count)
+                .map(|target| sample(interpolation, times, |i| values[i * count + target], time))
+                .collect(),
+        )
+    }
+}
+
+impl AnimationClip {
+    /// Poses the target nodes at `time` seconds, targets that no longer exist are skipped
+    pub fn apply(&self, time: f32, scene: &mut Scene) {
+        for channel in &self.channels {
+            let Some(node) = scene.node_mut(channel.target) else {
+                continue;
+            };
+
+            if let Some(weights) = channel.sample_weights(time) {
+                node.morph_weights = weights;
+            } else {
+                let mut transform = *node.transform();
+                channel.sample_transform(time, &mut transform);
+                scene.set_transform(channel.target, transform);
+            }
+        }
+    }
+
+    /// Overwrites the properties of `pose` animated at `time` seconds,
+    /// channels targeting nodes missing from `pose` are skipped
+    pub fn sample_pose(&self, time: f32, pose: &mut Pose) {
+        for channel in &self.channels {
+            let Some(transform) = pose.transforms.get_mut(&channel.target) else {
+                continue;
+            };
+
+            if let Some(weights) = channel.sample_weights(time) {
+                pose.morph_weights.insert(channel.target, weights);
+            } else {
+                channel.sample_transform(time, transform);
+            }
+        }
+    }
+
+    /// Every node animated by the clip
+    pub fn targets(&self) -> impl Iterator<Item = NodeHandle> + '_ {
+        self.channels.iter().map(|channel| channel.target)
+    }
+}
+
+trait Keyframe: Copy + Add<Output = Self> + Mul<f32, Output = Self> {
+    fn interpolate(self, other: Self, t: f32) -> Self;
+}
+
+impl Keyframe for Vec3 {
+    fn interpolate(self, other: Self, t: f32) -> Self {
+        self.lerp(other, t)
+    }
+}
+
+impl Keyframe for f32 {
+    fn interpolate(self, other: Self, t: f32) -> Self {
+        self + (other - self) * t
+    }
+}
+
+impl Keyframe for Quat {
+    fn interpolate(self, other: Self, t: f32) -> Self {
+        self.slerp(other, t)
+    }
+}
+
+/// Value of the keyframes at `time`, held constant before the first and after the last one.
+/// `values` returns the channel's `i`th value.
+fn sample<T: Keyframe>(
+    interpolation: Interpolation,
+    times: &[f32],
+    values: impl Fn(usize) -> T,
+    time: f32,
+) -> T {
+    // cubic splines store the value between the two tangents
+    let value = |key: usize| match interpolation {
+        Interpolation::CubicSpline => values(key * 3 + 1),
+        _ => values(key),
+    };
+
+    let next = times.partition_point(|&t| t <= time);
+    if next == 0 {
+        return value(0);
+    }
+    if next == times.len() {
+        return value(times.len() - 1);
+    }
+
+    let previous = next - 1;
+    let delta = times[next] - times[previous];
+    let t = (time - times[previous]) / delta;
+
+    match interpolation {
+        Interpolation::Step => value(previous),
+        Interpolation::Linear => value(previous).interpolate(value(next), t),
+        Interpolation::CubicSpline => {
+            let out_tangent = values(previous * 3 + 2) * delta;
+            let in_tangent = values(next * 3) * delta;
+            let (t2, t3) = (t * t, t * t * t);
+            value(previous) * (2.0 * t3 - 3.0 * t2 + 1.0)
+                + out_tangent * (t3 - 2.0 * t2 + t)
+                + value(next) * (-2.0 * t3 + 3.0 * t2)
+                + in_tangent * (t3 - t2)
+        }
+    }
+}
+
+/// Plays an [`AnimationClip`] on the scene, call [`Self::update`] once per frame
+#[derive(Clone, Debug)]
+pub struct AnimationPlayer {
+    pub clip: AnimationClip,
+    time: f32,
+    playing: bool,
+    /// Starts over at the end instead of stopping, also when playing backwards
+    pub looping: bool,
+    /// Playback rate, negative values play backwards
+    pub speed: f32,
+}
+
+impl AnimationPlayer {
+    /// The player starts paused at the beginning of `clip`
+    pub fn new(clip: AnimationClip) -> Self {
+        AnimationPlayer {
+            clip,
+            time: 0.0,
+            playing: false,
+            looping: true,
+            speed: 1.0,
+        }
+    }
+
+    pub fn play(&mut self) {
+        self.playing = true;
+    }
+
+    pub fn pause(&mut self) {
+        self.playing = false;
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.playing
+    }
+
+    /// Current position in seconds
+    pub fn time(&self) -> f32 {
+        self.time
+    }
+
+    /// Jumps to `time` seconds, clamped to the clip or wrapped around when looping
+    pub fn seek(&mut self, time: f32) {
+        let duration = self.clip.duration;
+        self.time = if self.looping && duration > 0.0 {
+            time.rem_euclid(duration)
+        } else {
+            time.clamp(0.0, duration)
+        };
+    }
+
+    /// Advances playback by `delta_time` seconds and poses the scene, which
+    /// also happens while paused so seeking takes effect
+    pub fn update(&mut self, delta_time: f32, scene: &mut Scene) {
+        if self.playing {
+            self.seek(self.time + delta_time * self.speed);
+
+            let finished = if self.speed < 0.0 {
+                self.time <= 0.0
+            } else {
+                self.time >= self.clip.duration
+            };
+            if finished && !self.looping {
+                self.playing = false;
+            }
+        }
+
+        self.clip.apply(self.time, scene);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn new_channel(
+        interpolation: Interpolation,
+        times: &[f32],
+        keyframes: Keyframes,
+    ) -> AnimationChannel<()> {
+        AnimationChannel {
+            target: (),
+            interpolation,
+            times: times.to_vec(),
+            keyframes,
+        }
+    }
+
+    fn translation(channel: &AnimationChannel<()>, time: f32) -> Vec3 {
+        let mut transform = Transform::default();
+        channel.sample_transform(time, &mut transform);
+        transform.translation
+    }
+
+    #[test]
+    fn step() {
+        let values = vec![Vec3::X, Vec3::Y, Vec3::Z];
+        let channel = new_channel(
+            Interpolation::Step,
+            &[0.0, 1.0, 2.0],
+            Keyframes::Translation(values),
+        );
+
+        assert_eq!(translation(&channel, 0.0), Vec3::X);
+        assert_eq!(translation(&channel, 0.99), Vec3::X);
+        assert_eq!(translation(&channel, 1.0), Vec3::Y);
+        assert_eq!(translation(&channel, 1.5), Vec3::Y);
+    }
+
+    #[test]
+    fn linear() {
+        let values = vec![Vec3::ZERO, Vec3::new(2.0, 4.0, 0.0)];
+        let channel = new_channel(
+            Interpolation::Linear,
+            &[1.0, 3.0],
+            Keyframes::Translation(values),
+        );
+
+        assert_eq!(translation(&channel, 1.5), Vec3::new(0.5, 1.0, 0.0));
+        assert_eq!(translation(&channel, 2.0), Vec3::new(1.0, 2.0, 0.0));
+    }
+
+    #[test]
+    fn clamped() {
+        let values = vec![Vec3::X, Vec3::Y];
+        for interpolation in [Interpolation::Step, Interpolation::Linear] {
+            let channel = new_channel(
+                interpolation,
+                &[1.0, 2.0],
+                Keyframes::Translation(values.clone()),
+            );
+            assert_eq!(translation(&channel, -5.0), Vec3::X);
+            assert_eq!(translation(&channel, 2.0), Vec3::Y);
+            assert_eq!(translation(&channel, 100.0), Vec3::Y);
+        }
+
+        // the values of cubic splines, never their tangents
+        let values = [Vec3::splat(9.0), Vec3::X, Vec3::splat(9.0)];
+        let channel = new_channel(
+            Interpolation::CubicSpline,
+            &[1.0, 2.0],
+            Keyframes::Translation(values.repeat(2)),
+        );
+        assert_eq!(translation(&channel, 0.0), Vec3::X);
+        assert_eq!(translation(&channel, 3.0), Vec3::X);
+    }
+
+    #[test]
+    fn cubic_spline() {
+        // in-tangent, value and out-tangent per key, the tangents are scaled by the
+        // 2 seconds between the keys. Unused tangents are large so misindexing shows.
+        let values = vec![
+            Vec3::splat(100.0),
+            Vec3::ZERO,
+            Vec3::X,
+            Vec3::X * 2.0,
+            Vec3::X,
+            Vec3::splat(100.0),
+        ];
+        let channel = new_channel(
+            Interpolation::CubicSpline,
+            &[0.0, 2.0],
+            Keyframes::Translation(values),
+        );
+
+        // halfway, the Hermite basis is 1/2, 1/8, 1/2 and -1/8
+        let expected = 2.0 * 0.125 + 0.5 - 4.0 * 0.125;
+        assert!((translation(&channel, 1.0) - Vec3::X * expected).length() < 1e-6);
+        assert_eq!(translation(&channel, 0.0), Vec3::ZERO);
+        assert_eq!(translation(&channel, 2.0), Vec3::X);
+    }
+
+    #[test]
+    fn weights() {
+        // two targets, each key holds the weights of all targets
+        let values = vec![0.0, 1.0, 1.0, 0.0];
+        let channel = new_channel(
+            Interpolation::Linear,
+            &[0.0, 1.0],
+            Keyframes::Weights(values),
+        );
+        assert_eq!(channel.sample_weights(0.25), Some(vec![0.25, 0.75]));
+        assert_eq!(channel.sample_weights(-1.0), Some(vec![0.0, 1.0]));
+        assert_eq!(channel.sample_weights(2.0), Some(vec![1.0, 0.0]));
+
+        // in-tangents, values then out-tangents of both targets, key after key
+        let values = vec![
+            9.0, 9.0, 0.0, 1.0, 1.0, -1.0, //
+            0.0, 0.0, 1.0, 0.0, 9.0, 9.0,
+        ];
+        let channel = new_channel(
+            Interpolation::CubicSpline,
+            &[0.0, 1.0],
+            Keyframes::Weights(values),
+        );
+        assert_eq!(channel.sample_weights(-1.0), Some(vec![0.0, 1.0]));
+        assert_eq!(channel.sample_weights(5.0), Some(vec![1.0, 0.0]));
+        // the first target leaves with slope 1 and arrives flat, the second mirrors it
+        let halfway = channel.sample_weights(0.5).unwrap();
+        let expected = 0.125 + 0.5;
+        assert!((halfway[0] - expected).abs() < 1e-6);
+        assert!((halfway[1] - (1.0 - expected)).abs() < 1e-6);
+
+        let translation = new_channel(
+            Interpolation::Linear,
+            &[0.0],
+            Keyframes::Translation(vec![Vec3::X]),
+        );
+        assert_eq!(translation.sample_weights(0.0), None);
+    }
+}
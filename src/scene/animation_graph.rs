@@ -0,0 +1,495 @@
+use super::{AnimationClip, NodeHandle, Scene, Transform};
+use glam::{Quat, Vec3};
+use std::collections::HashMap;
+
+/// Local transforms and morph weights of a set of nodes
+#[derive(Clone, Debug, Default)]
+pub struct Pose {
+    pub transforms: HashMap<NodeHandle, Transform>,
+    pub morph_weights: HashMap<NodeHandle, Vec<f32>>,
+}
+
+impl Pose {
+    /// Captures the current state of `nodes`, those that no longer exist are skipped
+    pub fn from_scene(scene: &Scene, nodes: impl IntoIterator<Item = NodeHandle>) -> Self {
+        let mut pose = Pose::default();
+        pose.capture(scene, nodes);
+        pose
+    }
+
+    /// Adds the current state of those `nodes` that are not part of the pose yet
+    pub fn capture(&mut self, scene: &Scene, nodes: impl IntoIterator<Item = NodeHandle>) {
+        for id in nodes {
+            if self.transforms.contains_key(&id) {
+                continue;
+            }
+            if let Some(node) = scene.node(id) {
+                self.transforms.insert(id, *node.transform());
+                self.morph_weights.insert(id, node.morph_weights.clone());
+            }
+        }
+    }
+
+    /// Moves the scene's nodes into this pose, nodes that no longer exist are skipped
+    pub fn apply(&self, scene: &mut Scene) {
+        for (&id, &transform) in &self.transforms {
+            if scene.contains(id) {
+                scene.set_transform(id, transform);
+            }
+        }
+        for (&id, weights) in &self.morph_weights {
+            if let Some(node) = scene.node_mut(id) {
+                node.morph_weights.clone_from(weights);
+            }
+        }
+    }
+
+    /// Moves the nodes shared with `other` towards it, by `weight` scaled by `mask`
+    pub fn blend(&mut self, other: &Pose, weight: f32, mask: Option<&BoneMask>) {
+        let weight_of = |id| weight * mask.map_or(1.0, |mask| mask.weight(id));
+
+        for (&id, transform) in &mut self.transforms {
+            let (Some(other), w) = (other.transforms.get(&id), weight_of(id)) else {
+                continue;
+            };
+            if w <= 0.0 {
+                continue;
+            }
+            transform.translation = transform.translation.lerp(other.translation, w);
+            transform.rotation = transform.rotation.slerp(other.rotation, w);
+            transform.scale = transform.scale.lerp(other.scale, w);
+        }
+
+        for (&id, weights) in &mut self.morph_weights {
+            let (Some(other), w) = (other.morph_weights.get(&id), weight_of(id)) else {
+                continue;
+            };
+            if w <= 0.0 {
+                continue;
+            }
+            if weights.len() < other.len() {
+                weights.resize(other.len(), 0.0);
+            }
+            for (i, weight) in weights.iter_mut().enumerate() {
+                let target = other.get(i).copied().unwrap_or_default();
+                *weight += (target - *weight) * w;
+            }
+        }
+    }
+
+    /// Adds the difference from `reference` to `additive` on top of the shared nodes,
+    /// scaled by `weight` and `mask`
+    pub fn add(&mut self, additive: &Pose, reference: &Pose, weight: f32, mask: Option<&BoneMask>) {
+        let weight_of = |id| weight * mask.map_or(1.0, |mask| mask.weight(id));
+
+        for (&id, transform) in &mut self.transforms {
+            let (Some(additive), Some(reference)) =
+                (additive.transforms.get(&id), reference.transforms.get(&id))
+            else {
+                continue;
+            };
+            let w = weight_of(id);
+            if w <= 0.0 {
+                continue;
+            }
+            transform.translation += (additive.translation - reference.translation) * w;
+            let rotation = reference.rotation.inverse() * additive.rotation;
+            transform.rotation =
+                (transform.rotation * Quat::IDENTITY.slerp(rotation, w)).normalize();
+            // a reference scaled to zero has no ratio, those components keep their scale
+            let ratio = Vec3::select(
+                reference.scale.cmpeq(Vec3::ZERO),
+                Vec3::ONE,
+                additive.scale / reference.scale,
+            );
+            transform.scale *= Vec3::ONE.lerp(ratio, w);
+        }
+
+        for (&id, weights) in &mut self.morph_weights {
+            let (Some(additive), Some(reference)) = (
+                additive.morph_weights.get(&id),
+                reference.morph_weights.get(&id),
+            ) else {
+                continue;
+            };
+            let w = weight_of(id);
+            if weights.len() < additive.len() {
+                weights.resize(additive.len(), 0.0);
+            }
+            for (i, weight) in weights.iter_mut().enumerate() {
+                let delta = additive.get(i).copied().unwrap_or_default()
+                    - reference.get(i).copied().unwrap_or_default();
+                *weight += delta * w;
+            }
+        }
+    }
+}
+
+/// Per-node weights limiting a layer to part of a hierarchy, nodes outside of it weigh 0
+#[derive(Clone, Debug, Default)]
+pub struct BoneMask {
+    weights: HashMap<NodeHandle, f32>,
+}
+
+impl BoneMask {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the weight of `root` and all of its descendants
+    pub fn with_hierarchy(mut self, scene: &Scene, root: NodeHandle, weight: f32) -> Self {
+        let mut stack = vec![root];
+        while let Some(id) = stack.pop() {
+            if let Some(node) = scene.node(id) {
+                self.weights.insert(id, weight);
+                stack.extend_from_slice(node.children());
+            }
+        }
+        self
+    }
+
+    pub fn set_weight(&mut self, node: NodeHandle, weight: f32) {
+        self.weights.insert(node, weight);
+    }
+
+    pub fn weight(&self, node: NodeHandle) -> f32 {
+        self.weights.get(&node).copied().unwrap_or_default()
+    }
+}
+
+/// Index of a state within its [`AnimationLayer`]
+pub type StateId = usize;
+
+/// A clip played by a layer of an [`AnimationGraph`]
+#[derive(Clone, Debug)]
+pub struct AnimationState {
+    pub name: String,
+    pub clip: AnimationClip,
+    /// Playback rate, negative values play backwards
+    pub speed: f32,
+    pub looping: bool,
+}
+
+impl AnimationState {
+    pub fn new(name: impl Into<String>, clip: AnimationClip) -> Self {
+        AnimationState {
+            name: name.into(),
+            clip,
+            speed: 1.0,
+            looping: true,
+        }
+    }
+
+    pub fn with_speed(mut self, speed: f32) -> Self {
+        self.speed = speed;
+        self
+    }
+
+    pub fn with_looping(mut self, looping: bool) -> Self {
+        self.looping = looping;
+        self
+    }
+}
+
+/// Test of the graph's parameters, missing parameters are 0
+#[derive(Clone, Debug)]
+pub enum Condition {
+    /// The parameter is non-zero
+    True(String),
+    /// The parameter is zero
+    False(String),
+    Greater(String, f32),
+    Less(String, f32),
+    /// The current state played through its clip at least once
+    Finished,
+}
+
+/// Cross-fades a layer into `to` once all of its conditions hold
+#[derive(Clone, Debug)]
+pub struct Transition {
+    /// Taken from any state when unset
+    pub from: Option<StateId>,
+    pub to: StateId,
+    /// Length of the cross-fade in seconds
+    pub duration: f32,
+    pub conditions: Vec<Condition>,
+}
+
+impl Transition {
+    pub fn new(from: Option<StateId>, to: StateId, duration: f32) -> Self {
+        Transition {
+            from,
+            to,
+            duration,
+            conditions: vec![],
+        }
+    }
+
+    pub fn when(mut self, condition: Condition) -> Self {
+        self.conditions.push(condition);
+        self
+    }
+}
+
+/// How a layer is combined with the layers below it
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LayerBlend {
+    /// Replaces the pose below
+    #[default]
+    Override,
+    /// Adds the difference from the first frame of its clips to the pose below
+    Additive,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Playback {
+    state: StateId,
+    time: f32,
+    finished: bool,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Fade {
+    from: Playback,
+    elapsed: f32,
+    duration: f32,
+}
+
+/// State machine playing one state at a time, or cross-fading between two
+#[derive(Clone, Debug)]
+pub struct AnimationLayer {
+    pub name: String,
+    pub weight: f32,
+    pub blend: LayerBlend,
+    pub mask: Option<BoneMask>,
+    states: Vec<AnimationState>,
+    transitions: Vec<Transition>,
+    current: Option<Playback>,
+    fade: Option<Fade>,
+}
+
+impl AnimationLayer {
+    pub fn new(name: impl Into<String>) -> Self {
+        AnimationLayer {
+            name: name.into(),
+            weight: 1.0,
+            blend: LayerBlend::Override,
+            mask: None,
+            states: vec![],
+            transitions: vec![],
+            current: None,
+            fade: None,
+        }
+    }
+
+    pub fn with_weight(mut self, weight: f32) -> Self {
+        self.weight = weight;
+        self
+    }
+
+    pub fn with_blend(mut self, blend: LayerBlend) -> Self {
+        self.blend = blend;
+        self
+    }
+
+    pub fn with_mask(mut self, mask: BoneMask) -> Self {
+        self.mask = Some(mask);
+        self
+    }
+
+    /// The first state added is the one the layer starts in
+    pub fn add_state(&mut self, state: AnimationState) -> StateId {
+        self.states.push(state);
+        if self.current.is_none() {
+            self.current = Some(Playback::new(0));
+        }
+        self.states.len() - 1
+    }
+
+    /// Panics when either state does not exist
+    pub fn add_transition(&mut self, transition: Transition) {
+        assert!(
+            transition.from.is_none_or(|from| from < self.states.len())
+                && transition.to < self.states.len(),
+            "Transition state does not exist"
+        );
+        self.transitions.push(transition);
+    }
+
+    pub fn state(&self, id: StateId) -> Option<&AnimationState> {
+        self.states.get(id)
+    }
+
+    pub fn state_mut(&mut self, id: StateId) -> Option<&mut AnimationState> {
+        self.states.get_mut(id)
+    }
+
+    pub fn current_state(&self) -> Option<StateId> {
+        self.current.map(|current| current.state)
+    }
+
+    /// Position within the current state's clip in seconds
+    pub fn time(&self) -> f32 {
+        self.current.map_or(0.0, |current| current.time)
+    }
+
+    /// Cross-fades into `state` from its start over `duration` seconds, an ongoing
+    /// cross-fade is cut short. Panics when the state does not exist.
+    pub fn play(&mut self, state: StateId, duration: f32) {
+        assert!(state < self.states.len(), "State does not exist");
+        self.fade = match self.current {
+            Some(from) if duration > 0.0 => Some(Fade {
+                from,
+                elapsed: 0.0,
+                duration,
+            }),
+            _ => None,
+        };
+        self.current = Some(Playback::new(state));
+    }
+
+    fn update(&mut self, delta_time: f32, parameters: &HashMap<String, f32>) {
+        let Some(current) = self.current else {
+            return;
+        };
+
+        // transitions into the current state are ignored so any-state ones don't restart it
+        let parameter = |name: &String| parameters.get(name).copied().unwrap_or_default();
+        let transition = self.transitions.iter().find(|transition| {
+            transition.from.is_none_or(|from| from == current.state)
+                && transition.to != current.state
+                && transition
+                    .conditions
+                    .iter()
+                    .all(|condition| match condition {
+                        Condition::True(name) => parameter(name) != 0.0,
+                        Condition::False(name) => parameter(name) == 0.0,
+                        Condition::Greater(name, value) => parameter(name) > *value,
+                        Condition::Less(name, value) => parameter(name) < *value,
+                        Condition::Finished => current.finished,
+                    })
+        });
+        if let Some(transition) = transition {
+            let (to, duration) = (transition.to, transition.duration);
+            self.play(to, duration);
+        }
+
+        let states = &self.states;
+        if let Some(current) = &mut self.current {
+            current.advance(&states[current.state], delta_time);
+        }
+        if let Some(fade) = &mut self.fade {
+            fade.from.advance(&states[fade.from.state], delta_time);
+            fade.elapsed += delta_time;
+            if fade.elapsed >= fade.duration {
+                self.fade = None;
+            }
+        }
+    }
+
+    /// The layer's pose on top of `rest`, or the pose its additive differences are
+    /// relative to when `reference` is set
+    fn sample(&self, rest: &Pose, reference: bool) -> Option<Pose> {
+        let current = self.current?;
+        let time = |playback: Playback| if reference { 0.0 } else { playback.time };
+
+        let mut pose = rest.clone();
+        self.states[current.state]
+            .clip
+            .sample_pose(time(current), &mut pose);
+
+        if let Some(fade) = self.fade {
+            let mut from = rest.clone();
+            self.states[fade.from.state]
+                .clip
+                .sample_pose(time(fade.from), &mut from);
+            from.blend(&pose, fade.elapsed / fade.duration, None);
+            pose = from;
+        }
+
+        Some(pose)
+    }
+}
+
+impl Playback {
+    fn new(state: StateId) -> Self {
+        Playback {
+            state,
+            time: 0.0,
+            finished: false,
+        }
+    }
+
+    fn advance(&mut self, state: &AnimationState, delta_time: f32) {
+        let duration = state.clip.duration;
+        let time = self.time + delta_time * state.speed;
+        if time >= duration || time < 0.0 {
+            self.finished = true;
+        }
+        self.time = if state.looping && duration > 0.0 {
+            time.rem_euclid(duration)
+        } else {
+            time.clamp(0.0, duration)
+        };
+    }
+}
+
+/// Layers of state machines blended into a single pose, the first layer is at the bottom
+#[derive(Clone, Debug, Default)]
+pub struct AnimationGraph {
+    pub layers: Vec<AnimationLayer>,
+    parameters: HashMap<String, f32>,
+    /// State of every animated node before the graph first touched it
+    rest: Pose,
+}
+
+impl AnimationGraph {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_layer(mut self, layer: AnimationLayer) -> Self {
+        self.layers.push(layer);
+        self
+    }
+
+    /// Read by transition conditions, booleans are 1 or 0
+    pub fn set_parameter(&mut self, name: impl Into<String>, value: f32) {
+        self.parameters.insert(name.into(), value);
+    }
+
+    pub fn set_bool(&mut self, name: impl Into<String>, value: bool) {
+        self.set_parameter(name, value as u32 as f32);
+    }
+
+    pub fn parameter(&self, name: &str) -> f32 {
+        self.parameters.get(name).copied().unwrap_or_default()
+    }
+
+    /// Takes the transitions whose conditions hold, advances every layer by
+    /// `delta_time` seconds and poses the scene
+    pub fn update(&mut self, delta_time: f32, scene: &mut Scene) {
+        for layer in &mut self.layers {
+            layer.update(delta_time, &self.parameters);
+            for state in &layer.states {
+                self.rest.capture(scene, state.clip.targets());
+            }
+        }
+
+        let mut pose = self.rest.clone();
+        for layer in &self.layers {
+            let Some(layer_pose) = layer.sample(&self.rest, false) else {
+                continue;
+            };
+            match layer.blend {
+                LayerBlend::Override => pose.blend(&layer_pose, layer.weight, layer.mask.as_ref()),
+                LayerBlend::Additive => {
+                    let reference = layer.sample(&self.rest, true).unwrap();
+                    pose.add(&layer_pose, &reference, layer.weight, layer.mask.as_ref());
+                }
+            }
+        }
+
+        pose.apply(scene);
+    }
+}
@@ -0,0 +1,48 @@
+use glam::{Vec3, Vec4};
+
+/// Factors of the glTF metallic-roughness material model
+#[derive(Clone, Debug)]
+pub struct Material {
+    pub name: String,
+    /// Linear RGBA
+    pub base_color: Vec4,
+    pub metallic: f32,
+    pub roughness: f32,
+    /// Linear RGB, added on top of the lit color
+    pub emissive: Vec3,
+}
+
+impl Default for Material {
+    fn default() -> Self {
+        Material {
+            name: String::from("default"),
+            base_color: Vec4::ONE,
+            metallic: 0.0,
+            roughness: 0.5,
+            emissive: Vec3::ZERO,
+        }
+    }
+}
+
+/// Layout of a material in the per-frame material buffer
+#[derive(Clone, Copy, Debug, Default)]
+#[repr(C)]
+pub struct MaterialData {
+    pub base_color: Vec4,
+    pub emissive: Vec3,
+    pub metallic: f32,
+    pub roughness: f32,
+    pub _padding: [f32; 3],
+}
+
+impl From<&Material> for MaterialData {
+    fn from(material: &Material) -> Self {
+        MaterialData {
+            base_color: material.base_color,
+            emissive: material.emissive,
+            metallic: material.metallic,
+            roughness: material.roughness,
+            _padding: [0.0; 3],
+        }
+    }
+}
@@ -0,0 +1,256 @@
+pub mod animation;
+pub mod animation_graph;
+pub mod material;
+pub mod skin;
+pub mod transform;
+
+pub use animation::{AnimationChannel, AnimationClip, AnimationPlayer, Interpolation, Keyframes};
+pub use animation_graph::{
+    AnimationGraph, AnimationLayer, AnimationState, BoneMask, Condition, LayerBlend, Pose, StateId,
+    Transition,
+};
+pub use material::{Material, MaterialData};
+pub use skin::Skin;
+pub use transform::Transform;
+
+use crate::resource::{Handle, MaterialHandle, MeshHandle, Pool};
+use glam::{Mat4, Vec3};
+
+pub type NodeHandle = Handle<Node>;
+pub type SkinHandle = Handle<Skin>;
+
+#[derive(Clone, Debug)]
+pub struct Node {
+    pub name: String,
+    pub mesh: Option<MeshHandle>,
+    /// The renderer's default material is used when unset
+    pub material: Option<MaterialHandle>,
+    /// Linear RGB multiplying the material's base color
+    pub color: Vec3,
+    /// Deforms `mesh`, which needs joints and weights
+    pub skin: Option<SkinHandle>,
+    /// Weights of the morph targets of `mesh`, missing ones are 0. Nodes without any use
+    /// their parent's, glTF primitives are children of the node their weights are set on.
+    pub morph_weights: Vec<f32>,
+    transform: Transform,
+    parent: Option<NodeHandle>,
+    children: Vec<NodeHandle>,
+    world_matrix: Mat4,
+    dirty: bool,
+}
+
+impl Node {
+    pub fn new(name: impl Into<String>) -> Self {
+        Node {
+            name: name.into(),
+            mesh: None,
+            material: None,
+            color: Vec3::ONE,
+            skin: None,
+            morph_weights: vec![],
+            transform: Transform::IDENTITY,
+            parent: None,
+            children: vec![],
+            world_matrix: Mat4::IDENTITY,
+            dirty: true,
+        }
+    }
+
+    pub fn with_transform(mut self, transform: Transform) -> Self {
+        self.transform = transform;
+        self
+    }
+
+    pub fn with_mesh(mut self, mesh: MeshHandle) -> Self {
+        self.mesh = Some(mesh);
+        self
+    }
+
+    pub fn with_material(mut self, material: MaterialHandle) -> Self {
+        self.material = Some(material);
+        self
+    }
+
+    pub fn with_color(mut self, color: Vec3) -> Self {
+        self.color = color;
+        self
+    }
+
+    pub fn with_skin(mut self, skin: SkinHandle) -> Self {
+        self.skin = Some(skin);
+        self
+    }
+
+    pub fn with_morph_weights(mut self, weights: Vec<f32>) -> Self {
+        self.morph_weights = weights;
+        self
+    }
+
+    pub fn transform(&self) -> &Transform {
+        &self.transform
+    }
+
+    pub fn parent(&self) -> Option<NodeHandle> {
+        self.parent
+    }
+
+    pub fn children(&self) -> &[NodeHandle] {
+        &self.children
+    }
+
+    /// World matrix as of the last call to [`Scene::update_world_matrices`]
+    pub fn world_matrix(&self) -> Mat4 {
+        self.world_matrix
+    }
+}
+
+#[derive(Default)]
+pub struct Scene {
+    nodes: Pool<Node>,
+    roots: Vec<NodeHandle>,
+    skins: Pool<Skin>,
+}
+
+impl Scene {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Panics when `parent` is not part of this scene
+    pub fn add_node(&mut self, mut node: Node, parent: Option<NodeHandle>) -> NodeHandle {
+        assert!(
+            parent.is_none_or(|parent| self.nodes.contains(parent)),
+            "Parent node does not exist"
+        );
+
+        node.parent = parent;
+        node.children.clear();
+        node.dirty = true;
+        let id = self.nodes.insert(node);
+
+        match parent {
+            Some(parent) => self.nodes[parent].children.push(id),
+            None => self.roots.push(id),
+        }
+
+        id
+    }
+
+    /// Removes `id` along with all of its descendants, returns `false`
+    /// when the node no longer exists
+    pub fn remove_node(&mut self, id: NodeHandle) -> bool {
+        let node = match self.nodes.get(id) {
+            Some(node) => node,
+            None => return false,
+        };
+
+        match node.parent {
+            Some(parent) => self.nodes[parent].children.retain(|&c| c != id),
+            None => self.roots.retain(|&r| r != id),
+        }
+
+        let mut stack = vec![id];
+        while let Some(id) = stack.pop() {
+            if let Some(node) = self.nodes.remove(id) {
+                stack.extend(node.children);
+            }
+        }
+
+        true
+    }
+
+    pub fn contains(&self, id: NodeHandle) -> bool {
+        self.nodes.contains(id)
+    }
+
+    pub fn node(&self, id: NodeHandle) -> Option<&Node> {
+        self.nodes.get(id)
+    }
+
+    pub fn node_mut(&mut self, id: NodeHandle) -> Option<&mut Node> {
+        self.nodes.get_mut(id)
+    }
+
+    pub fn nodes(&self) -> impl Iterator<Item = (NodeHandle, &Node)> {
+        self.nodes.iter()
+    }
+
+    pub fn nodes_mut(&mut self) -> impl Iterator<Item = (NodeHandle, &mut Node)> {
+        self.nodes.iter_mut()
+    }
+
+    pub fn roots(&self) -> &[NodeHandle] {
+        &self.roots
+    }
+
+    pub fn add_skin(&mut self, skin: Skin) -> SkinHandle {
+        self.skins.insert(skin)
+    }
+
+    pub fn skin(&self, id: SkinHandle) -> Option<&Skin> {
+        self.skins.get(id)
+    }
+
+    /// Nodes still using the skin are drawn undeformed
+    pub fn remove_skin(&mut self, id: SkinHandle) -> Option<Skin> {
+        self.skins.remove(id)
+    }
+
+    pub fn set_transform(&mut self, id: NodeHandle, transform: Transform) {
+        let node = &mut self.nodes[id];
+        node.transform = transform;
+        node.dirty = true;
+    }
+
+    /// Moves `id` under `parent`, or makes it a root when `None`.
+    /// Panics when this would make a node its own ancestor.
+    pub fn set_parent(&mut self, id: NodeHandle, parent: Option<NodeHandle>) {
+        let mut ancestor = parent;
+        while let Some(a) = ancestor {
+            assert_ne!(a, id, "Cannot parent a node to itself or its descendants");
+            ancestor = self.nodes[a].parent;
+        }
+
+        match self.nodes[id].parent {
+            Some(old) => self.nodes[old].children.retain(|&c| c != id),
+            None => self.roots.retain(|&r| r != id),
+        }
+
+        match parent {
+            Some(parent) => self.nodes[parent].children.push(id),
+            None => self.roots.push(id),
+        }
+
+        let node = &mut self.nodes[id];
+        node.parent = parent;
+        node.dirty = true;
+    }
+
+    /// Recomputes the world matrices of dirty nodes and everything below them
+    pub fn update_world_matrices(&mut self) {
+        let mut stack = self
+            .roots
+            .iter()
+            .rev()
+            .map(|&root| (root, Mat4::IDENTITY, false))
+            .collect::<Vec<_>>();
+
+        while let Some((id, parent_world, parent_changed)) = stack.pop() {
+            let node = &mut self.nodes[id];
+            let changed = node.dirty || parent_changed;
+
+            if changed {
+                node.world_matrix = parent_world * node.transform.matrix();
+                node.dirty = false;
+            }
+
+            let world = node.world_matrix;
+            stack.extend(
+                node.children
+                    .iter()
+                    .rev()
+                    .map(|&child| (child, world, changed)),
+            );
+        }
+    }
+}
@@ -0,0 +1,26 @@
+use super::{NodeHandle, Scene};
+use glam::Mat4;
+
+/// Joints deforming a skinned mesh, the joint indices of its vertices index into `joints`
+#[derive(Clone, Debug)]
+pub struct Skin {
+    pub name: String,
+    pub joints: Vec<NodeHandle>,
+    /// One per joint, from mesh space to the joint's space in the bind pose
+    pub inverse_bind_matrices: Vec<Mat4>,
+}
+
+impl Skin {
+    /// Appends the matrices moving vertices from the bind pose to the current pose, relative
+    /// to `mesh_world` so the mesh's own transform still applies. Joints that no longer exist
+    /// stay in the bind pose.
+    pub fn joint_matrices(&self, scene: &Scene, mesh_world: Mat4, matrices: &mut Vec<Mat4>) {
+        let world_to_mesh = mesh_world.inverse();
+        matrices.extend(self.joints.iter().zip(&self.inverse_bind_matrices).map(
+            |(&joint, inverse_bind)| match scene.node(joint) {
+                Some(joint) => world_to_mesh * joint.world_matrix() * *inverse_bind,
+                None => Mat4::IDENTITY,
+            },
+        ));
+    }
+}
@@ -0,0 +1,186 @@
+struct GlobalUniforms {
+    view: mat4x4<f32>,
+    projection: mat4x4<f32>,
+    view_projection: mat4x4<f32>,
+    camera_position: vec4<f32>,
+    resolution: vec2<f32>,
+    time: f32,
+    frame_number: u32,
+    ambient_light: vec4<f32>,
+    light_count: u32,
+    cascade_count: u32,
+    ambient_occlusion: u32,
+    light_culling: u32,
+    cascade_splits: vec4<f32>,
+    inverse_projection: mat4x4<f32>,
+    inverse_view_projection: mat4x4<f32>,
+}
+
+let LIGHT_KIND_DIRECTIONAL: u32 = 0u;
+
+struct LightData {
+    position: vec3<f32>,
+    kind: u32,
+    direction: vec3<f32>,
+    range: f32,
+    color: vec3<f32>,
+    intensity: f32,
+    inner_cone_cos: f32,
+    outer_cone_cos: f32,
+    shadow_index: i32,
+    normal_bias: f32,
+    pcf_radius: u32,
+}
+
+struct Lights {
+    lights: array<LightData>,
+}
+
+// matches the `BACKGROUND_*` constants in `background.rs`
+let BACKGROUND_SKYBOX: u32 = 1u;
+let BACKGROUND_ENVIRONMENT: u32 = 2u;
+
+struct BackgroundPushConstants {
+    mode: u32,
+    intensity: f32,
+    mie_anisotropy: f32,
+    sun_disc: u32,
+}
+
+@group(0) @binding(0)
+var<uniform> globals: GlobalUniforms;
+@group(0) @binding(3)
+var<storage, read> lights: Lights;
+
+@group(1) @binding(0)
+var skybox: texture_cube<f32>;
+@group(1) @binding(1)
+var environment: texture_cube<f32>;
+@group(1) @binding(2)
+var cube_sampler: sampler;
+
+var<push_constant> background_pc: BackgroundPushConstants;
+
+struct FullscreenOut {
+    @builtin(position) pos: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+};
+
+let PI: f32 = 3.14159265359;
+
+// all distances are in meters, the camera floats just above the ground
+let EARTH_RADIUS: f32 = 6360000.0;
+let ATMOSPHERE_RADIUS: f32 = 6420000.0;
+let RAYLEIGH_SCALE_HEIGHT: f32 = 8000.0;
+let MIE_SCALE_HEIGHT: f32 = 1200.0;
+let RAYLEIGH_SCATTERING: vec3<f32> = vec3<f32>(5.8e-6, 13.5e-6, 33.1e-6);
+let MIE_SCATTERING: f32 = 21e-6;
+// aerosols absorb a little on top of what they scatter
+let MIE_EXTINCTION: f32 = 23.1e-6;
+let VIEW_SAMPLES: u32 = 16u;
+let LIGHT_SAMPLES: u32 = 8u;
+// the real sun is about 0.0047 radians, a bigger disc reads better on screen
+let SUN_ANGULAR_RADIUS: f32 = 0.01;
+// the disc is not scaled physically, just bright enough to bloom
+let SUN_DISC_RADIANCE: f32 = 40.0;
+
+// distance from `origin` inside the sphere of `radius` around the planet center to its surface
+fn sphere_exit(origin: vec3<f32>, direction: vec3<f32>, radius: f32) -> f32 {
+    let b = dot(origin, direction);
+    let c = dot(origin, origin) - radius * radius;
+    return -b + sqrt(max(b * b - c, 0.0));
+}
+
+fn density(position: vec3<f32>) -> vec2<f32> {
+    let height = max(length(position) - EARTH_RADIUS, 0.0);
+    return exp(-height / vec2(RAYLEIGH_SCALE_HEIGHT, MIE_SCALE_HEIGHT));
+}
+
+fn extinction(optical_depth: vec2<f32>) -> vec3<f32> {
+    return RAYLEIGH_SCATTERING * optical_depth.x + MIE_EXTINCTION * optical_depth.y;
+}
+
+// single Rayleigh and Mie scattering of unit sun radiance along `direction`
+fn atmosphere(direction: vec3<f32>, sun: vec3<f32>) -> vec3<f32> {
+    let origin = vec3(0.0, EARTH_RADIUS + 1.0, 0.0);
+    let view_step = sphere_exit(origin, direction, ATMOSPHERE_RADIUS) / f32(VIEW_SAMPLES);
+
+    var rayleigh = vec3(0.0);
+    var mie = vec3(0.0);
+    var view_depth = vec2(0.0);
+    for (var i = 0u; i < VIEW_SAMPLES; i++) {
+        let position = origin + direction * (f32(i) + 0.5) * view_step;
+        let sample_density = density(position) * view_step;
+        view_depth += sample_density;
+
+        // light reaching the sample from the sun, unless the planet is in the way
+        let light_step = sphere_exit(position, sun, ATMOSPHERE_RADIUS) / f32(LIGHT_SAMPLES);
+        var light_depth = vec2(0.0);
+        var shadowed = false;
+        for (var j = 0u; j < LIGHT_SAMPLES; j++) {
+            let light_position = position + sun * (f32(j) + 0.5) * light_step;
+            if (length(light_position) < EARTH_RADIUS) {
+                shadowed = true;
+                break;
+            }
+            light_depth += density(light_position) * light_step;
+        }
+
+        if (!shadowed) {
+            let transmittance = exp(-extinction(view_depth + light_depth));
+            rayleigh += transmittance * sample_density.x;
+            mie += transmittance * sample_density.y;
+        }
+    }
+
+    let mu = dot(direction, sun);
+    let g = background_pc.mie_anisotropy;
+    let rayleigh_phase = 3.0 / (16.0 * PI) * (1.0 + mu * mu);
+    // Cornette-Shanks
+    let mie_phase = 3.0 / (8.0 * PI) * ((1.0 - g * g) * (1.0 + mu * mu))
+        / ((2.0 + g * g) * pow(1.0 + g * g - 2.0 * g * mu, 1.5));
+    var radiance = rayleigh * RAYLEIGH_SCATTERING * rayleigh_phase + mie * MIE_SCATTERING * mie_phase;
+
+    if (background_pc.sun_disc != 0u && mu > cos(SUN_ANGULAR_RADIUS)) {
+        radiance += exp(-extinction(view_depth)) * SUN_DISC_RADIANCE;
+    }
+    return radiance;
+}
+
+fn sky(direction: vec3<f32>) -> vec3<f32> {
+    // the first directional light is the sun, without one it stands straight up
+    var sun = vec3(0.0, 1.0, 0.0);
+    var sun_radiance = vec3(1.0);
+    for (var i = 0u; i < globals.light_count; i++) {
+        let light = lights.lights[i];
+        if (light.kind == LIGHT_KIND_DIRECTIONAL) {
+            sun = -light.direction;
+            sun_radiance = light.color * light.intensity;
+            break;
+        }
+    }
+
+    // below the horizon the sky just fades into a dark ground
+    let above = normalize(vec3(direction.x, max(direction.y, 0.0), direction.z));
+    let ground = mix(1.0, 0.2, smoothstep(0.0, -0.1, direction.y));
+    return atmosphere(above, sun) * sun_radiance * ground;
+}
+
+@fragment
+fn fs_background(in: FullscreenOut) -> @location(0) vec4<f32> {
+    // y is flipped when rendering, the far plane is at depth 1
+    let ndc = vec4(in.uv.x * 2.0 - 1.0, 1.0 - in.uv.y * 2.0, 1.0, 1.0);
+    let far_position = globals.inverse_view_projection * ndc;
+    let direction = normalize(far_position.xyz / far_position.w - globals.camera_position.xyz);
+
+    var radiance: vec3<f32>;
+    if (background_pc.mode == BACKGROUND_SKYBOX) {
+        radiance = textureSampleLevel(skybox, cube_sampler, direction, 0.0).rgb;
+    } else if (background_pc.mode == BACKGROUND_ENVIRONMENT) {
+        radiance = textureSampleLevel(environment, cube_sampler, direction, 0.0).rgb;
+    } else {
+        radiance = sky(direction);
+    }
+
+    return vec4(radiance * background_pc.intensity, 1.0);
+}
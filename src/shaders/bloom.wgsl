@@ -0,0 +1,154 @@
+// Bloom as presented in "Next Generation Post Processing in Call of Duty: Advanced Warfare"
+
+struct BloomParams {
+    threshold: f32,
+    knee: f32,
+    radius: f32,
+    intensity: f32,
+    mip_count: u32,
+}
+
+@group(0) @binding(0)
+var scene_image: texture_2d<f32>;
+@group(0) @binding(1)
+var scene_sampler: sampler;
+
+@group(1) @binding(0)
+var source_image: texture_2d<f32>;
+@group(1) @binding(1)
+var source_sampler: sampler;
+
+@group(2) @binding(0)
+var output_image: texture_storage_2d<rgba16float, read_write>;
+
+var<push_constant> pc: BloomParams;
+
+fn luminance(color: vec3<f32>) -> f32 {
+    return dot(color, vec3(0.2126, 0.7152, 0.0722));
+}
+
+fn sample_source(uv: vec2<f32>) -> vec3<f32> {
+    return textureSampleLevel(source_image, source_sampler, uv, 0.0).rgb;
+}
+
+// soft knee threshold, a threshold of zero passes everything through
+fn prefilter(color: vec3<f32>) -> vec3<f32> {
+    let brightness = max(color.r, max(color.g, color.b));
+    let knee = pc.threshold * pc.knee;
+    var soft = clamp(brightness - pc.threshold + knee, 0.0, 2.0 * knee);
+    soft = soft * soft / (4.0 * knee + 0.00001);
+    let contribution = max(soft, brightness - pc.threshold) / max(brightness, 0.00001);
+    return color * contribution;
+}
+
+// 13-tap filter, `karis` weighs the five 2x2 blocks by inverse luminance to suppress fireflies
+fn downsample(uv: vec2<f32>, karis: bool) -> vec3<f32> {
+    let t = 1.0 / vec2<f32>(textureDimensions(source_image));
+
+    let a = sample_source(uv + t * vec2(-2.0, -2.0));
+    let b = sample_source(uv + t * vec2(0.0, -2.0));
+    let c = sample_source(uv + t * vec2(2.0, -2.0));
+    let d = sample_source(uv + t * vec2(-2.0, 0.0));
+    let e = sample_source(uv);
+    let f = sample_source(uv + t * vec2(2.0, 0.0));
+    let g = sample_source(uv + t * vec2(-2.0, 2.0));
+    let h = sample_source(uv + t * vec2(0.0, 2.0));
+    let i = sample_source(uv + t * vec2(2.0, 2.0));
+    let j = sample_source(uv + t * vec2(-1.0, -1.0));
+    let k = sample_source(uv + t * vec2(1.0, -1.0));
+    let l = sample_source(uv + t * vec2(-1.0, 1.0));
+    let m = sample_source(uv + t * vec2(1.0, 1.0));
+
+    let center = (j + k + l + m) * 0.25;
+    let top_left = (a + b + d + e) * 0.25;
+    let top_right = (b + c + e + f) * 0.25;
+    let bottom_left = (d + e + g + h) * 0.25;
+    let bottom_right = (e + f + h + i) * 0.25;
+
+    var weights = vec4(0.125);
+    var center_weight = 0.5;
+    if (karis) {
+        weights /= 1.0 + vec4(
+            luminance(top_left),
+            luminance(top_right),
+            luminance(bottom_left),
+            luminance(bottom_right),
+        );
+        center_weight /= 1.0 + luminance(center);
+    }
+
+    let sum = center * center_weight
+        + top_left * weights.x
+        + top_right * weights.y
+        + bottom_left * weights.z
+        + bottom_right * weights.w;
+    return sum / (center_weight + dot(weights, vec4(1.0)));
+}
+
+fn output_uv(id: vec2<u32>, size: vec2<i32>) -> vec2<f32> {
+    return (vec2<f32>(id) + 0.5) / vec2<f32>(size);
+}
+
+@compute @workgroup_size(8, 8)
+fn cs_downsample_first(@builtin(global_invocation_id) id: vec3<u32>) {
+    let size = textureDimensions(output_image);
+    if (i32(id.x) >= size.x || i32(id.y) >= size.y) {
+        return;
+    }
+
+    let color = prefilter(max(downsample(output_uv(id.xy, size), true), vec3(0.0)));
+    textureStore(output_image, vec2<i32>(id.xy), vec4(color, 1.0));
+}
+
+@compute @workgroup_size(8, 8)
+fn cs_downsample(@builtin(global_invocation_id) id: vec3<u32>) {
+    let size = textureDimensions(output_image);
+    if (i32(id.x) >= size.x || i32(id.y) >= size.y) {
+        return;
+    }
+
+    let color = downsample(output_uv(id.xy, size), false);
+    textureStore(output_image, vec2<i32>(id.xy), vec4(color, 1.0));
+}
+
+// 3x3 tent filter of the smaller mip, added onto the output's own downsampled color
+@compute @workgroup_size(8, 8)
+fn cs_upsample(@builtin(global_invocation_id) id: vec3<u32>) {
+    let size = textureDimensions(output_image);
+    if (i32(id.x) >= size.x || i32(id.y) >= size.y) {
+        return;
+    }
+
+    let uv = output_uv(id.xy, size);
+    // keep the filter round on non-square targets
+    let r = vec2(pc.radius * f32(size.y) / f32(size.x), pc.radius);
+
+    var color = sample_source(uv) * 4.0;
+    color += (sample_source(uv + vec2(-r.x, 0.0))
+        + sample_source(uv + vec2(r.x, 0.0))
+        + sample_source(uv + vec2(0.0, -r.y))
+        + sample_source(uv + vec2(0.0, r.y))) * 2.0;
+    color += sample_source(uv + vec2(-r.x, -r.y))
+        + sample_source(uv + vec2(r.x, -r.y))
+        + sample_source(uv + vec2(-r.x, r.y))
+        + sample_source(uv + vec2(r.x, r.y));
+    color /= 16.0;
+
+    let current = textureLoad(output_image, vec2<i32>(id.xy));
+    textureStore(output_image, vec2<i32>(id.xy), vec4(current.rgb + color, 1.0));
+}
+
+@compute @workgroup_size(8, 8)
+fn cs_composite(@builtin(global_invocation_id) id: vec3<u32>) {
+    let size = textureDimensions(output_image);
+    if (i32(id.x) >= size.x || i32(id.y) >= size.y) {
+        return;
+    }
+
+    let uv = output_uv(id.xy, size);
+    let scene = textureLoad(scene_image, vec2<i32>(id.xy), 0);
+    // every mip of the chain was added onto the first one
+    let bloom = sample_source(uv) / f32(pc.mip_count);
+
+    textureStore(output_image, vec2<i32>(id.xy), vec4(mix(scene.rgb, bloom, pc.intensity), scene.a));
+}
@@ -0,0 +1,121 @@
+struct GlobalUniforms {
+    view: mat4x4<f32>,
+    projection: mat4x4<f32>,
+    view_projection: mat4x4<f32>,
+    camera_position: vec4<f32>,
+    resolution: vec2<f32>,
+    time: f32,
+    frame_number: u32,
+    ambient_light: vec4<f32>,
+    light_count: u32,
+    cascade_count: u32,
+    ambient_occlusion: u32,
+    light_culling: u32,
+    cascade_splits: vec4<f32>,
+    inverse_projection: mat4x4<f32>,
+    inverse_view_projection: mat4x4<f32>,
+    z_near: f32,
+    z_far: f32,
+}
+
+let LIGHT_KIND_DIRECTIONAL: u32 = 0u;
+
+struct LightData {
+    position: vec3<f32>,
+    kind: u32,
+    direction: vec3<f32>,
+    range: f32,
+    color: vec3<f32>,
+    intensity: f32,
+    inner_cone_cos: f32,
+    outer_cone_cos: f32,
+    shadow_index: i32,
+    normal_bias: f32,
+    pcf_radius: u32,
+}
+
+struct Lights {
+    lights: array<LightData>,
+}
+
+// matches `CLUSTER_GRID` and `MAX_LIGHTS_PER_CLUSTER` in `clusters.rs`
+let CLUSTER_GRID: vec3<u32> = vec3<u32>(16u, 9u, 24u);
+let MAX_LIGHTS_PER_CLUSTER: u32 = 127u;
+
+struct Cluster {
+    count: u32,
+    lights: array<u32, 127>,
+}
+
+struct Clusters {
+    clusters: array<Cluster>,
+}
+
+@group(0) @binding(0)
+var<uniform> globals: GlobalUniforms;
+@group(0) @binding(3)
+var<storage, read> lights: Lights;
+@group(0) @binding(8)
+var<storage, read_write> clusters: Clusters;
+
+// view-space position on the near plane through `uv`
+fn near_plane_position(uv: vec2<f32>) -> vec3<f32> {
+    // y is flipped when rendering
+    let ndc = vec4(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, 0.0, 1.0);
+    let position = globals.inverse_projection * ndc;
+    return position.xyz / position.w;
+}
+
+// slices are spaced exponentially, so clusters keep a similar shape at every distance
+fn slice_depth(slice: u32) -> f32 {
+    let t = f32(slice) / f32(CLUSTER_GRID.z);
+    return globals.z_near * pow(globals.z_far / globals.z_near, t);
+}
+
+@compute @workgroup_size(4, 4, 4)
+fn cs_cluster_lights(@builtin(global_invocation_id) id: vec3<u32>) {
+    if (any(id >= CLUSTER_GRID)) {
+        return;
+    }
+
+    // view-space bounds of the cluster, from the corners of its tile at both slice depths
+    let tile_min = vec2<f32>(id.xy) / vec2<f32>(CLUSTER_GRID.xy);
+    let tile_max = vec2<f32>(id.xy + 1u) / vec2<f32>(CLUSTER_GRID.xy);
+    let near_depth = slice_depth(id.z);
+    let far_depth = slice_depth(id.z + 1u);
+
+    var bounds_min = vec3(1.0e30);
+    var bounds_max = vec3(-1.0e30);
+    for (var corner = 0u; corner < 4u; corner++) {
+        let uv = select(tile_min, tile_max, vec2((corner & 1u) != 0u, (corner & 2u) != 0u));
+        let direction = near_plane_position(uv);
+        // the camera looks down -z
+        for (var i = 0u; i < 2u; i++) {
+            let depth = select(near_depth, far_depth, i == 1u);
+            let corner_position = direction * (depth / -direction.z);
+            bounds_min = min(bounds_min, corner_position);
+            bounds_max = max(bounds_max, corner_position);
+        }
+    }
+
+    let index = id.x + CLUSTER_GRID.x * (id.y + CLUSTER_GRID.y * id.z);
+    var count = 0u;
+    for (var i = 0u; i < globals.light_count && count < MAX_LIGHTS_PER_CLUSTER; i++) {
+        let light = lights.lights[i];
+
+        // directional and unbounded lights reach every cluster
+        var visible = light.kind == LIGHT_KIND_DIRECTIONAL || light.range <= 0.0;
+        if (!visible) {
+            let center = (globals.view * vec4(light.position, 1.0)).xyz;
+            let closest = clamp(center, bounds_min, bounds_max);
+            let offset = closest - center;
+            visible = dot(offset, offset) <= light.range * light.range;
+        }
+
+        if (visible) {
+            clusters.clusters[index].lights[count] = i;
+            count++;
+        }
+    }
+    clusters.clusters[index].count = count;
+}
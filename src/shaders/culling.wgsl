@@ -0,0 +1,223 @@
+struct GlobalUniforms {
+    view: mat4x4<f32>,
+    projection: mat4x4<f32>,
+    view_projection: mat4x4<f32>,
+    camera_position: vec4<f32>,
+    resolution: vec2<f32>,
+    time: f32,
+    frame_number: u32,
+    ambient_light: vec4<f32>,
+    light_count: u32,
+    cascade_count: u32,
+    ambient_occlusion: u32,
+    light_culling: u32,
+    cascade_splits: vec4<f32>,
+    inverse_projection: mat4x4<f32>,
+    inverse_view_projection: mat4x4<f32>,
+    z_near: f32,
+    z_far: f32,
+    environment: u32,
+    environment_intensity: f32,
+}
+
+struct InstanceData {
+    model: mat4x4<f32>,
+    normal_matrix: mat4x4<f32>,
+    color: vec3<f32>,
+    material_index: u32,
+    lod_fade: f32,
+}
+
+struct Instances {
+    instances: array<InstanceData>,
+}
+
+struct GpuObject {
+    instance: u32,
+    mesh: u32,
+}
+
+struct GpuObjects {
+    objects: array<GpuObject>,
+}
+
+struct GpuMesh {
+    center: vec3<f32>,
+    radius: f32,
+    index_count: u32,
+    first_index: u32,
+    vertex_offset: i32,
+    _padding: u32,
+}
+
+struct GpuMeshes {
+    meshes: array<GpuMesh>,
+}
+
+// matches `VkDrawIndexedIndirectCommand`
+struct DrawCommand {
+    index_count: u32,
+    instance_count: u32,
+    first_index: u32,
+    vertex_offset: i32,
+    first_instance: u32,
+}
+
+struct DrawCommands {
+    commands: array<DrawCommand>,
+}
+
+// one list of commands per `LIST_*`, each with room for every object
+struct DrawCounts {
+    counts: array<atomic<u32>, 3>,
+}
+
+// what phase 0 found for each object, read by phase 1
+struct Visibility {
+    objects: array<u32>,
+}
+
+let LIST_VISIBLE: u32 = 0u;
+let LIST_DISOCCLUDED: u32 = 1u;
+let LIST_OCCLUDED: u32 = 2u;
+
+let HIDDEN: u32 = 0u;
+let MAYBE_OCCLUDED: u32 = 1u;
+
+struct CullingPushConstants {
+    // normals point inside the frustum
+    planes: array<vec4<f32>, 6>,
+    object_count: u32,
+    // 0 culls every object, 1 retests those phase 0 found occluded
+    phase: u32,
+    // whether phase 0 tests against the pyramid of the previous frame
+    occlusion: u32,
+    _padding: u32,
+}
+
+@group(0) @binding(0)
+var<uniform> globals: GlobalUniforms;
+@group(0) @binding(1)
+var<storage, read> instances: Instances;
+
+@group(1) @binding(0)
+var<storage, read> objects: GpuObjects;
+@group(1) @binding(1)
+var<storage, read> meshes: GpuMeshes;
+@group(1) @binding(2)
+var<storage, read_write> commands: DrawCommands;
+@group(1) @binding(3)
+var<storage, read_write> draw_counts: DrawCounts;
+@group(1) @binding(4)
+var<storage, read_write> visibility: Visibility;
+// nearest depth in r, farthest in g
+@group(1) @binding(5)
+var depth_pyramid: texture_2d<f32>;
+
+var<push_constant> culling_pc: CullingPushConstants;
+
+// whether a sphere is behind the depth in the pyramid everywhere it covers
+fn is_occluded(center: vec3<f32>, radius: f32) -> bool {
+    var ndc_min = vec2(1.0);
+    var ndc_max = vec2(-1.0);
+    var nearest = 1.0;
+    for (var i = 0u; i < 8u; i = i + 1u) {
+        let corner = center + radius * vec3(
+            select(-1.0, 1.0, (i & 1u) != 0u),
+            select(-1.0, 1.0, (i & 2u) != 0u),
+            select(-1.0, 1.0, (i & 4u) != 0u)
+        );
+        let clip = globals.view_projection * vec4(corner, 1.0);
+        // crosses the near plane, nothing can be in front of it
+        if (clip.w <= 0.0) {
+            return false;
+        }
+        let ndc = clip.xyz / clip.w;
+        ndc_min = min(ndc_min, ndc.xy);
+        ndc_max = max(ndc_max, ndc.xy);
+        nearest = min(nearest, ndc.z);
+    }
+
+    // covered depth buffer pixels, level n of the pyramid halves them n + 1 times. y is
+    // flipped when rendering, so the top of the rectangle is row 0.
+    let size = vec2<i32>(globals.resolution);
+    let top_left = vec2(ndc_min.x, ndc_max.y) * vec2(0.5, -0.5) + 0.5;
+    let bottom_right = vec2(ndc_max.x, ndc_min.y) * vec2(0.5, -0.5) + 0.5;
+    let start = clamp(vec2<i32>(top_left * globals.resolution), vec2(0), size - 1);
+    let end = clamp(vec2<i32>(bottom_right * globals.resolution), vec2(0), size - 1);
+
+    // the lowest level at which at most 2x2 texels are covered
+    let levels = textureNumLevels(depth_pyramid);
+    var level = 0;
+    var a = start >> vec2(1u);
+    var b = end >> vec2(1u);
+    loop {
+        if (all(b - a <= vec2(1)) || level == levels - 1) {
+            break;
+        }
+        level = level + 1;
+        a = a >> vec2(1u);
+        b = b >> vec2(1u);
+    }
+    // levels round down, so the last pixels can land one past their last texel
+    let last = textureDimensions(depth_pyramid, level) - 1;
+    a = min(a, last);
+    b = min(b, last);
+
+    let farthest = max(
+        max(textureLoad(depth_pyramid, a, level).g, textureLoad(depth_pyramid, vec2(b.x, a.y), level).g),
+        max(textureLoad(depth_pyramid, vec2(a.x, b.y), level).g, textureLoad(depth_pyramid, b, level).g)
+    );
+    return nearest > farthest;
+}
+
+fn emit(list: u32, mesh: GpuMesh, instance: u32) {
+    let slot = atomicAdd(&draw_counts.counts[list], 1u);
+    commands.commands[list * culling_pc.object_count + slot] = DrawCommand(
+        mesh.index_count,
+        1u,
+        mesh.first_index,
+        mesh.vertex_offset,
+        instance
+    );
+}
+
+@compute @workgroup_size(64, 1, 1)
+fn cs_cull(@builtin(global_invocation_id) id: vec3<u32>) {
+    if (id.x >= culling_pc.object_count) {
+        return;
+    }
+
+    let object = objects.objects[id.x];
+    let mesh = meshes.meshes[object.mesh];
+    let model = instances.instances[object.instance].model;
+
+    let center = (model * vec4(mesh.center, 1.0)).xyz;
+    let scale = sqrt(max(
+        dot(model[0].xyz, model[0].xyz),
+        max(dot(model[1].xyz, model[1].xyz), dot(model[2].xyz, model[2].xyz))
+    ));
+    let radius = mesh.radius * scale;
+
+    if (culling_pc.phase == 1u) {
+        if (visibility.objects[id.x] == MAYBE_OCCLUDED) {
+            let list = select(LIST_DISOCCLUDED, LIST_OCCLUDED, is_occluded(center, radius));
+            emit(list, mesh, object.instance);
+        }
+        return;
+    }
+
+    visibility.objects[id.x] = HIDDEN;
+    for (var i = 0; i < 6; i = i + 1) {
+        let plane = culling_pc.planes[i];
+        if (dot(plane.xyz, center) + plane.w < -radius) {
+            return;
+        }
+    }
+
+    if (culling_pc.occlusion != 0u && is_occluded(center, radius)) {
+        visibility.objects[id.x] = MAYBE_OCCLUDED;
+        return;
+    }
+    emit(LIST_VISIBLE, mesh, object.instance);
+}
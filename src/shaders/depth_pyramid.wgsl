@@ -0,0 +1,64 @@
+@group(0) @binding(0)
+var depth: texture_depth_2d;
+@group(0) @binding(1)
+var source: texture_2d<f32>;
+// nearest depth in r, farthest in g
+@group(0) @binding(2)
+var destination: texture_storage_2d<rg32float, write>;
+
+// source texels covered by destination texel `id`, conservative for odd sizes
+fn footprint_start(id: vec2<u32>, source_size: vec2<u32>, size: vec2<u32>) -> vec2<u32> {
+    return id * source_size / size;
+}
+
+fn footprint_end(id: vec2<u32>, source_size: vec2<u32>, size: vec2<u32>) -> vec2<u32> {
+    return ((id + 1u) * source_size + size - 1u) / size;
+}
+
+@compute @workgroup_size(8, 8, 1)
+fn cs_reduce_depth(@builtin(global_invocation_id) id: vec3<u32>) {
+    let size = vec2<u32>(textureDimensions(destination));
+    if (any(id.xy >= size)) {
+        return;
+    }
+
+    let source_size = vec2<u32>(textureDimensions(depth));
+    let start = footprint_start(id.xy, source_size, size);
+    let end = footprint_end(id.xy, source_size, size);
+
+    var nearest = 1.0;
+    var farthest = 0.0;
+    for (var y = start.y; y < end.y; y = y + 1u) {
+        for (var x = start.x; x < end.x; x = x + 1u) {
+            let d = textureLoad(depth, vec2<i32>(vec2(x, y)), 0);
+            nearest = min(nearest, d);
+            farthest = max(farthest, d);
+        }
+    }
+
+    textureStore(destination, vec2<i32>(id.xy), vec4(nearest, farthest, 0.0, 0.0));
+}
+
+@compute @workgroup_size(8, 8, 1)
+fn cs_reduce(@builtin(global_invocation_id) id: vec3<u32>) {
+    let size = vec2<u32>(textureDimensions(destination));
+    if (any(id.xy >= size)) {
+        return;
+    }
+
+    let source_size = vec2<u32>(textureDimensions(source));
+    let start = footprint_start(id.xy, source_size, size);
+    let end = footprint_end(id.xy, source_size, size);
+
+    var nearest = 1.0;
+    var farthest = 0.0;
+    for (var y = start.y; y < end.y; y = y + 1u) {
+        for (var x = start.x; x < end.x; x = x + 1u) {
+            let d = textureLoad(source, vec2<i32>(vec2(x, y)), 0).rg;
+            nearest = min(nearest, d.r);
+            farthest = max(farthest, d.g);
+        }
+    }
+
+    textureStore(destination, vec2<i32>(id.xy), vec4(nearest, farthest, 0.0, 0.0));
+}
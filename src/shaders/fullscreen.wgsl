@@ -0,0 +1,17 @@
+struct FullscreenOut {
+    @builtin(position) pos: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+};
+
+// a single triangle covering the whole screen, drawn with three vertices and no buffers
+@vertex
+fn vs_fullscreen(@builtin(vertex_index) vertex_index: u32) -> FullscreenOut {
+    var out: FullscreenOut;
+
+    let uv = vec2(f32((vertex_index << 1u) & 2u), f32(vertex_index & 2u));
+    out.uv = uv;
+    // y is flipped once more on the way out, so uv (0, 0) ends up in the top left
+    out.pos = vec4(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, 0.0, 1.0);
+
+    return out;
+}
@@ -0,0 +1,250 @@
+struct BakeParams {
+    roughness: f32,
+    sample_count: u32,
+    // mip of the source cube that is sampled when the lod is not computed per sample
+    source_lod: f32,
+}
+
+@group(0) @binding(0)
+var equirect: texture_2d<f32>;
+@group(0) @binding(1)
+var source_cube: texture_cube<f32>;
+@group(0) @binding(2)
+var source_mip: texture_2d_array<f32>;
+@group(0) @binding(3)
+var source_sampler: sampler;
+@group(0) @binding(4)
+var output_cube: texture_storage_2d_array<rgba16float, write>;
+@group(0) @binding(5)
+var output_lut: texture_storage_2d<rgba16float, write>;
+
+var<push_constant> params: BakeParams;
+
+let PI: f32 = 3.14159265359;
+
+// direction through `uv` of cube face `face`, faces are stored as +X, -X, +Y, -Y, +Z, -Z
+fn cube_direction(face: u32, uv: vec2<f32>) -> vec3<f32> {
+    let st = uv * 2.0 - 1.0;
+    var direction: vec3<f32>;
+    if (face == 0u) {
+        direction = vec3(1.0, -st.y, -st.x);
+    } else if (face == 1u) {
+        direction = vec3(-1.0, -st.y, st.x);
+    } else if (face == 2u) {
+        direction = vec3(st.x, 1.0, st.y);
+    } else if (face == 3u) {
+        direction = vec3(st.x, -1.0, -st.y);
+    } else if (face == 4u) {
+        direction = vec3(st.x, -st.y, 1.0);
+    } else {
+        direction = vec3(-st.x, -st.y, -1.0);
+    }
+    return normalize(direction);
+}
+
+// direction through the center of texel `id` of the output cube, or zero outside of it
+fn output_direction(id: vec3<u32>) -> vec3<f32> {
+    let size = textureDimensions(output_cube);
+    if (any(vec2<i32>(id.xy) >= size)) {
+        return vec3(0.0);
+    }
+    let uv = (vec2<f32>(id.xy) + 0.5) / vec2<f32>(size);
+    return cube_direction(id.z, uv);
+}
+
+fn store_output(id: vec3<u32>, color: vec3<f32>) {
+    textureStore(output_cube, vec2<i32>(id.xy), i32(id.z), vec4(color, 1.0));
+}
+
+fn equirect_texel(coord: vec2<i32>) -> vec3<f32> {
+    let size = textureDimensions(equirect);
+    // wrap around horizontally, clamp at the poles
+    let x = (coord.x % size.x + size.x) % size.x;
+    let y = clamp(coord.y, 0, size.y - 1);
+    return textureLoad(equirect, vec2(x, y), 0).rgb;
+}
+
+// bilinear filtering by hand, 32-bit float images are not filterable everywhere
+fn sample_equirect(direction: vec3<f32>) -> vec3<f32> {
+    let uv = vec2(
+        atan2(direction.z, direction.x) / (2.0 * PI) + 0.5,
+        acos(clamp(direction.y, -1.0, 1.0)) / PI,
+    );
+    let position = uv * vec2<f32>(textureDimensions(equirect)) - 0.5;
+    let base = vec2<i32>(floor(position));
+    let t = fract(position);
+
+    let top = mix(equirect_texel(base), equirect_texel(base + vec2(1, 0)), t.x);
+    let bottom = mix(equirect_texel(base + vec2(0, 1)), equirect_texel(base + vec2(1, 1)), t.x);
+    return mix(top, bottom, t.y);
+}
+
+fn radical_inverse(i: u32) -> f32 {
+    var bits = (i << 16u) | (i >> 16u);
+    bits = ((bits & 0x55555555u) << 1u) | ((bits & 0xAAAAAAAAu) >> 1u);
+    bits = ((bits & 0x33333333u) << 2u) | ((bits & 0xCCCCCCCCu) >> 2u);
+    bits = ((bits & 0x0F0F0F0Fu) << 4u) | ((bits & 0xF0F0F0F0u) >> 4u);
+    bits = ((bits & 0x00FF00FFu) << 8u) | ((bits & 0xFF00FF00u) >> 8u);
+    return f32(bits) * 2.3283064365386963e-10;
+}
+
+fn hammersley(i: u32, count: u32) -> vec2<f32> {
+    return vec2(f32(i) / f32(count), radical_inverse(i));
+}
+
+// rotates `v` from tangent space around `n` to world space
+fn tangent_to_world(v: vec3<f32>, n: vec3<f32>) -> vec3<f32> {
+    var up = vec3(0.0, 1.0, 0.0);
+    if (abs(n.y) > 0.999) {
+        up = vec3(1.0, 0.0, 0.0);
+    }
+    let tangent = normalize(cross(up, n));
+    let bitangent = cross(n, tangent);
+    return tangent * v.x + bitangent * v.y + n * v.z;
+}
+
+// half vector distributed like the GGX normal distribution around `n`
+fn importance_sample_ggx(xi: vec2<f32>, n: vec3<f32>, alpha: f32) -> vec3<f32> {
+    let phi = 2.0 * PI * xi.x;
+    let cos_theta = sqrt((1.0 - xi.y) / (1.0 + (alpha * alpha - 1.0) * xi.y));
+    let sin_theta = sqrt(1.0 - cos_theta * cos_theta);
+    return tangent_to_world(vec3(cos(phi) * sin_theta, sin(phi) * sin_theta, cos_theta), n);
+}
+
+fn distribution_ggx(n_dot_h: f32, alpha: f32) -> f32 {
+    let alpha2 = alpha * alpha;
+    let d = n_dot_h * n_dot_h * (alpha2 - 1.0) + 1.0;
+    return alpha2 / (PI * d * d);
+}
+
+@compute @workgroup_size(8, 8, 1)
+fn cs_equirect_to_cube(@builtin(global_invocation_id) id: vec3<u32>) {
+    let direction = output_direction(id);
+    if (all(direction == vec3(0.0))) {
+        return;
+    }
+    store_output(id, sample_equirect(direction));
+}
+
+// box filters the previous mip of every face
+@compute @workgroup_size(8, 8, 1)
+fn cs_downsample(@builtin(global_invocation_id) id: vec3<u32>) {
+    if (any(vec2<i32>(id.xy) >= textureDimensions(output_cube))) {
+        return;
+    }
+
+    let coord = vec2<i32>(id.xy) * 2;
+    let layer = i32(id.z);
+    let sum = textureLoad(source_mip, coord, layer, 0) + textureLoad(source_mip, coord + vec2(1, 0), layer, 0)
+        + textureLoad(source_mip, coord + vec2(0, 1), layer, 0) + textureLoad(source_mip, coord + vec2(1, 1), layer, 0);
+    store_output(id, sum.rgb * 0.25);
+}
+
+// cosine weighted integral of the radiance over the hemisphere around each direction
+@compute @workgroup_size(8, 8, 1)
+fn cs_irradiance(@builtin(global_invocation_id) id: vec3<u32>) {
+    let n = output_direction(id);
+    if (all(n == vec3(0.0))) {
+        return;
+    }
+
+    let delta = 0.05;
+    var sum = vec3(0.0);
+    var count = 0.0;
+    for (var phi = 0.0; phi < 2.0 * PI; phi += delta) {
+        for (var theta = 0.0; theta < 0.5 * PI; theta += delta) {
+            let direction = vec3(sin(theta) * cos(phi), sin(theta) * sin(phi), cos(theta));
+            let radiance = textureSampleLevel(source_cube, source_sampler, tangent_to_world(direction, n), params.source_lod).rgb;
+            sum += radiance * cos(theta) * sin(theta);
+            count += 1.0;
+        }
+    }
+
+    store_output(id, PI * sum / count);
+}
+
+// GGX filtered radiance for `params.roughness`, assuming the view and normal match the reflection
+@compute @workgroup_size(8, 8, 1)
+fn cs_prefilter(@builtin(global_invocation_id) id: vec3<u32>) {
+    let n = output_direction(id);
+    if (all(n == vec3(0.0))) {
+        return;
+    }
+
+    if (params.roughness == 0.0) {
+        store_output(id, textureSampleLevel(source_cube, source_sampler, n, params.source_lod).rgb);
+        return;
+    }
+
+    let alpha = params.roughness * params.roughness;
+    let source_size = f32(textureDimensions(source_cube).x);
+    let texel_solid_angle = 4.0 * PI / (6.0 * source_size * source_size);
+
+    var sum = vec3(0.0);
+    var weight = 0.0;
+    for (var i = 0u; i < params.sample_count; i++) {
+        let h = importance_sample_ggx(hammersley(i, params.sample_count), n, alpha);
+        let l = 2.0 * dot(n, h) * h - n;
+        let n_dot_l = dot(n, l);
+        if (n_dot_l <= 0.0) {
+            continue;
+        }
+
+        // filtered importance sampling, read a mip whose texels cover the sample's solid angle
+        let n_dot_h = max(dot(n, h), 0.0);
+        let pdf = distribution_ggx(n_dot_h, alpha) * 0.25 + 0.0001;
+        let sample_solid_angle = 1.0 / (f32(params.sample_count) * pdf);
+        let lod = max(0.5 * log2(sample_solid_angle / texel_solid_angle) + 1.0, 0.0);
+
+        sum += textureSampleLevel(source_cube, source_sampler, l, lod).rgb * n_dot_l;
+        weight += n_dot_l;
+    }
+
+    store_output(id, sum / max(weight, 0.0001));
+}
+
+fn geometry_schlick_ggx(n_dot_x: f32, roughness: f32) -> f32 {
+    // k for image based lighting
+    let k = roughness * roughness * 0.5;
+    return n_dot_x / (n_dot_x * (1.0 - k) + k);
+}
+
+// scale and bias applied to F0 by the split sum approximation, indexed by n.v and roughness
+@compute @workgroup_size(8, 8, 1)
+fn cs_brdf_lut(@builtin(global_invocation_id) id: vec3<u32>) {
+    let size = textureDimensions(output_lut);
+    if (any(vec2<i32>(id.xy) >= size)) {
+        return;
+    }
+
+    let uv = (vec2<f32>(id.xy) + 0.5) / vec2<f32>(size);
+    let n_dot_v = uv.x;
+    let roughness = uv.y;
+    let alpha = roughness * roughness;
+
+    let n = vec3(0.0, 0.0, 1.0);
+    let v = vec3(sqrt(1.0 - n_dot_v * n_dot_v), 0.0, n_dot_v);
+
+    var scale = 0.0;
+    var bias = 0.0;
+    for (var i = 0u; i < params.sample_count; i++) {
+        let h = importance_sample_ggx(hammersley(i, params.sample_count), n, alpha);
+        let l = 2.0 * dot(v, h) * h - v;
+        let n_dot_l = max(l.z, 0.0);
+        if (n_dot_l <= 0.0) {
+            continue;
+        }
+
+        let n_dot_h = max(h.z, 0.0);
+        let v_dot_h = max(dot(v, h), 0.0);
+        let g = geometry_schlick_ggx(n_dot_v, roughness) * geometry_schlick_ggx(n_dot_l, roughness);
+        let visibility = g * v_dot_h / (n_dot_h * n_dot_v);
+        let fresnel = pow(1.0 - v_dot_h, 5.0);
+
+        scale += (1.0 - fresnel) * visibility;
+        bias += fresnel * visibility;
+    }
+
+    let count = f32(params.sample_count);
+    textureStore(output_lut, vec2<i32>(id.xy), vec4(scale / count, bias / count, 0.0, 1.0));
+}
@@ -0,0 +1,249 @@
+struct GlobalUniforms {
+    view: mat4x4<f32>,
+    projection: mat4x4<f32>,
+    view_projection: mat4x4<f32>,
+    camera_position: vec4<f32>,
+    resolution: vec2<f32>,
+    time: f32,
+    frame_number: u32,
+    ambient_light: vec4<f32>,
+    light_count: u32,
+    cascade_count: u32,
+    ambient_occlusion: u32,
+    light_culling: u32,
+    cascade_splits: vec4<f32>,
+    inverse_projection: mat4x4<f32>,
+    inverse_view_projection: mat4x4<f32>,
+    z_near: f32,
+    z_far: f32,
+    environment: u32,
+    environment_intensity: f32,
+}
+
+struct InstanceData {
+    model: mat4x4<f32>,
+    normal_matrix: mat4x4<f32>,
+    color: vec3<f32>,
+    material_index: u32,
+    lod_fade: f32,
+}
+
+struct Instances {
+    instances: array<InstanceData>,
+}
+
+struct Meshlet {
+    center: vec3<f32>,
+    radius: f32,
+    cone_axis: vec3<f32>,
+    cone_cutoff: f32,
+    first_vertex: u32,
+    vertex_count: u32,
+    first_triangle: u32,
+    triangle_count: u32,
+}
+
+struct Meshlets {
+    meshlets: array<Meshlet>,
+}
+
+struct MeshletTask {
+    instance: u32,
+    meshlet: u32,
+    // where the vertices of the meshlet's mesh start in the arena
+    vertex_offset: u32,
+}
+
+struct MeshletTasks {
+    tasks: array<MeshletTask>,
+}
+
+struct Indices {
+    indices: array<u32>,
+}
+
+// `Vertex` is nine floats, starting with the position
+struct Vertices {
+    floats: array<f32>,
+}
+
+// matches `VkDrawIndexedIndirectCommand`
+struct DrawCommand {
+    index_count: u32,
+    instance_count: u32,
+    first_index: u32,
+    vertex_offset: i32,
+    first_instance: u32,
+}
+
+struct DrawCommands {
+    commands: array<DrawCommand>,
+}
+
+struct DrawCounts {
+    draws: atomic<u32>,
+    indices: atomic<u32>,
+}
+
+struct MeshletPushConstants {
+    // normals point inside the frustum
+    planes: array<vec4<f32>, 6>,
+    task_count: u32,
+    // whether meshlets and triangles facing away from the camera are culled
+    backface: u32,
+    // workgroups per row of the task shader dispatch
+    group_count_x: u32,
+    _padding: u32,
+}
+
+let VERTEX_FLOATS: u32 = 9u;
+let GROUP_SIZE: u32 = 64u;
+
+@group(0) @binding(0)
+var<uniform> globals: GlobalUniforms;
+@group(0) @binding(1)
+var<storage, read> instances: Instances;
+
+@group(1) @binding(0)
+var<storage, read> tasks: MeshletTasks;
+@group(1) @binding(1)
+var<storage, read> meshlets: Meshlets;
+@group(1) @binding(2)
+var<storage, read> meshlet_vertices: Indices;
+@group(1) @binding(3)
+var<storage, read> meshlet_triangles: Indices;
+@group(1) @binding(4)
+var<storage, read> vertices: Vertices;
+@group(1) @binding(5)
+var<storage, read_write> indices: Indices;
+@group(1) @binding(6)
+var<storage, read_write> commands: DrawCommands;
+@group(1) @binding(7)
+var<storage, read_write> draw_counts: DrawCounts;
+
+var<push_constant> meshlet_pc: MeshletPushConstants;
+
+var<workgroup> visible_count: atomic<u32>;
+var<workgroup> first_index: u32;
+
+fn meshlet_visible(meshlet: Meshlet, instance: InstanceData) -> bool {
+    let model = instance.model;
+    let center = (model * vec4(meshlet.center, 1.0)).xyz;
+    let scale = sqrt(max(
+        dot(model[0].xyz, model[0].xyz),
+        max(dot(model[1].xyz, model[1].xyz), dot(model[2].xyz, model[2].xyz))
+    ));
+    let radius = meshlet.radius * scale;
+
+    for (var i = 0; i < 6; i = i + 1) {
+        let plane = meshlet_pc.planes[i];
+        if (dot(plane.xyz, center) + plane.w < -radius) {
+            return false;
+        }
+    }
+
+    // meshopt gives meshlets whose normals spread too far a cutoff of 1 and maybe no axis
+    if (meshlet_pc.backface == 0u || meshlet.cone_cutoff >= 1.0) {
+        return true;
+    }
+    let axis = normalize((instance.normal_matrix * vec4(meshlet.cone_axis, 0.0)).xyz);
+    let view = center - globals.camera_position.xyz;
+    return dot(view, axis) < meshlet.cone_cutoff * length(view) + radius;
+}
+
+fn world_position(model: mat4x4<f32>, vertex: u32) -> vec3<f32> {
+    let i = vertex * VERTEX_FLOATS;
+    let position = vec3(vertices.floats[i], vertices.floats[i + 1u], vertices.floats[i + 2u]);
+    return (model * vec4(position, 1.0)).xyz;
+}
+
+// counter-clockwise triangles face the camera, those entirely outside of a plane are culled
+fn triangle_visible(a: vec3<f32>, b: vec3<f32>, c: vec3<f32>) -> bool {
+    for (var i = 0; i < 6; i = i + 1) {
+        let plane = meshlet_pc.planes[i];
+        let distances = vec3(dot(plane.xyz, a), dot(plane.xyz, b), dot(plane.xyz, c)) + plane.w;
+        if (all(distances < vec3(0.0))) {
+            return false;
+        }
+    }
+
+    let normal = cross(b - a, c - a);
+    return meshlet_pc.backface == 0u || dot(normal, a - globals.camera_position.xyz) < 0.0;
+}
+
+// one workgroup per task, every invocation culls up to two triangles of the meshlet and
+// the visible ones are compacted into a single draw
+@compute @workgroup_size(64, 1, 1)
+fn cs_cull_meshlets(
+    @builtin(workgroup_id) group: vec3<u32>,
+    @builtin(num_workgroups) group_count: vec3<u32>,
+    @builtin(local_invocation_index) local: u32,
+) {
+    let task_index = group.y * group_count.x + group.x;
+    if (task_index >= meshlet_pc.task_count) {
+        return;
+    }
+
+    let task = tasks.tasks[task_index];
+    let meshlet = meshlets.meshlets[task.meshlet];
+    let instance = instances.instances[task.instance];
+    if (!meshlet_visible(meshlet, instance)) {
+        return;
+    }
+
+    if (local == 0u) {
+        atomicStore(&visible_count, 0u);
+    }
+    workgroupBarrier();
+
+    var triangles = array<vec3<u32>, 2>(vec3(0u), vec3(0u));
+    var slots = array<u32, 2>(0xffffffffu, 0xffffffffu);
+    for (var i = 0u; i < 2u; i = i + 1u) {
+        let local_triangle = local + i * GROUP_SIZE;
+        if (local_triangle >= meshlet.triangle_count) {
+            break;
+        }
+
+        let packed = meshlet_triangles.indices[meshlet.first_triangle + local_triangle];
+        let corners = (vec3(packed) >> vec3(0u, 8u, 16u)) & vec3(0xffu);
+        let vertex = vec3(
+            meshlet_vertices.indices[meshlet.first_vertex + corners.x],
+            meshlet_vertices.indices[meshlet.first_vertex + corners.y],
+            meshlet_vertices.indices[meshlet.first_vertex + corners.z]
+        );
+        let model = instance.model;
+        let a = world_position(model, task.vertex_offset + vertex.x);
+        let b = world_position(model, task.vertex_offset + vertex.y);
+        let c = world_position(model, task.vertex_offset + vertex.z);
+        if (triangle_visible(a, b, c)) {
+            triangles[i] = vertex;
+            slots[i] = atomicAdd(&visible_count, 1u);
+        }
+    }
+    workgroupBarrier();
+
+    if (local == 0u) {
+        let count = atomicLoad(&visible_count);
+        if (count > 0u) {
+            first_index = atomicAdd(&draw_counts.indices, count * 3u);
+            let draw = atomicAdd(&draw_counts.draws, 1u);
+            commands.commands[draw] = DrawCommand(
+                count * 3u,
+                1u,
+                first_index,
+                i32(task.vertex_offset),
+                task.instance
+            );
+        }
+    }
+    workgroupBarrier();
+
+    for (var i = 0u; i < 2u; i = i + 1u) {
+        if (slots[i] != 0xffffffffu) {
+            let index = first_index + slots[i] * 3u;
+            indices.indices[index] = triangles[i].x;
+            indices.indices[index + 1u] = triangles[i].y;
+            indices.indices[index + 2u] = triangles[i].z;
+        }
+    }
+}
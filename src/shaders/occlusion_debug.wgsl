@@ -0,0 +1,53 @@
+struct GlobalUniforms {
+    view: mat4x4<f32>,
+    projection: mat4x4<f32>,
+    view_projection: mat4x4<f32>,
+    camera_position: vec4<f32>,
+    resolution: vec2<f32>,
+    time: f32,
+    frame_number: u32,
+    ambient_light: vec4<f32>,
+    light_count: u32,
+    cascade_count: u32,
+    ambient_occlusion: u32,
+    light_culling: u32,
+    cascade_splits: vec4<f32>,
+    inverse_projection: mat4x4<f32>,
+    inverse_view_projection: mat4x4<f32>,
+    z_near: f32,
+    z_far: f32,
+    environment: u32,
+    environment_intensity: f32,
+}
+
+struct InstanceData {
+    model: mat4x4<f32>,
+    normal_matrix: mat4x4<f32>,
+    color: vec3<f32>,
+    material_index: u32,
+    lod_fade: f32,
+}
+
+struct Instances {
+    instances: array<InstanceData>,
+}
+
+@group(0) @binding(0)
+var<uniform> globals: GlobalUniforms;
+@group(0) @binding(1)
+var<storage, read> instances: Instances;
+
+@vertex
+fn vs_occluded(
+    @location(0) vert_position: vec3<f32>,
+    @builtin(instance_index) instance_index: u32,
+) -> @builtin(position) vec4<f32> {
+    let instance = instances.instances[instance_index];
+    return globals.view_projection * (instance.model * vec4(vert_position, 1.0));
+}
+
+// occluded objects are drawn on top of everything in a flat color
+@fragment
+fn fs_occluded() -> @location(0) vec4<f32> {
+    return vec4(2.0, 0.1, 0.1, 1.0);
+}
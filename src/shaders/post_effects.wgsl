@@ -0,0 +1,106 @@
+struct FullscreenOut {
+    @location(0) uv: vec2<f32>,
+};
+
+struct GlobalUniforms {
+    view: mat4x4<f32>,
+    projection: mat4x4<f32>,
+    view_projection: mat4x4<f32>,
+    camera_position: vec4<f32>,
+    resolution: vec2<f32>,
+    time: f32,
+    frame_number: u32,
+}
+
+struct PostParams {
+    params: array<vec4<f32>, 4>,
+}
+
+@group(0) @binding(0)
+var<uniform> globals: GlobalUniforms;
+
+@group(1) @binding(0)
+var input_image: texture_2d<f32>;
+@group(1) @binding(1)
+var input_sampler: sampler;
+
+@group(2) @binding(0)
+var output_image: texture_storage_2d<rgba16float, write>;
+
+var<push_constant> pc: PostParams;
+
+fn luminance(color: vec3<f32>) -> f32 {
+    return dot(color, vec3(0.2126, 0.7152, 0.0722));
+}
+
+@fragment
+fn fs_vignette(in: FullscreenOut) -> @location(0) vec4<f32> {
+    let intensity = pc.params[0].x;
+    let radius = pc.params[0].y;
+    let softness = pc.params[0].z;
+
+    let color = textureSampleLevel(input_image, input_sampler, in.uv, 0.0);
+
+    // distance from the center, 1 in the corners
+    let d = length(in.uv - 0.5) * sqrt(2.0);
+    let falloff = smoothstep(radius, radius - softness, d);
+
+    return vec4(color.rgb * mix(1.0 - intensity, 1.0, falloff), color.a);
+}
+
+@fragment
+fn fs_chromatic_aberration(in: FullscreenOut) -> @location(0) vec4<f32> {
+    let strength = pc.params[0].x;
+    let offset = (in.uv - 0.5) * strength;
+
+    let r = textureSampleLevel(input_image, input_sampler, in.uv + offset, 0.0).r;
+    let ga = textureSampleLevel(input_image, input_sampler, in.uv, 0.0).ga;
+    let b = textureSampleLevel(input_image, input_sampler, in.uv - offset, 0.0).b;
+
+    return vec4(r, ga.x, b, ga.y);
+}
+
+// PCG based hash, see "Hash Functions for GPU Rendering" by Jarzynski and Olano
+fn hash(seed: vec3<u32>) -> f32 {
+    var v = seed * 1664525u + 1013904223u;
+    v.x += v.y * v.z;
+    v.y += v.z * v.x;
+    v.z += v.x * v.y;
+    v ^= v >> vec3(16u);
+    v.x += v.y * v.z;
+    return f32(v.x) / 4294967295.0;
+}
+
+@compute @workgroup_size(8, 8)
+fn cs_film_grain(@builtin(global_invocation_id) id: vec3<u32>) {
+    let size = textureDimensions(output_image);
+    if (i32(id.x) >= size.x || i32(id.y) >= size.y) {
+        return;
+    }
+
+    let intensity = pc.params[0].x;
+    let color = textureLoad(input_image, vec2<i32>(id.xy), 0);
+
+    let noise = hash(vec3(id.xy, globals.frame_number)) - 0.5;
+    textureStore(output_image, vec2<i32>(id.xy), vec4(color.rgb * (1.0 + noise * intensity), color.a));
+}
+
+@compute @workgroup_size(8, 8)
+fn cs_color_grade(@builtin(global_invocation_id) id: vec3<u32>) {
+    let size = textureDimensions(output_image);
+    if (i32(id.x) >= size.x || i32(id.y) >= size.y) {
+        return;
+    }
+
+    let saturation = pc.params[0].x;
+    let contrast = pc.params[0].y;
+    let tint = vec3(pc.params[0].z, pc.params[0].w, pc.params[1].x);
+
+    let color = textureLoad(input_image, vec2<i32>(id.xy), 0);
+
+    // contrast is applied in log space around middle grey so it does not shift exposure
+    var graded = 0.18 * pow(max(color.rgb, vec3(0.0)) / 0.18, vec3(contrast));
+    graded = mix(vec3(luminance(graded)), graded, saturation) * tint;
+
+    textureStore(output_image, vec2<i32>(id.xy), vec4(max(graded, vec3(0.0)), color.a));
+}
@@ -1,32 +1,557 @@
 struct VertOut {
-    @builtin(position) pos: vec4<f32>,
+    // the depth prepass must produce exactly the same depth
+    @builtin(position) @invariant pos: vec4<f32>,
     @location(0) color: vec3<f32>,
+    @location(1) world_position: vec3<f32>,
+    @location(2) world_normal: vec3<f32>,
+    @location(3) @interpolate(flat) material_index: u32,
+    @location(4) @interpolate(flat) lod_fade: f32,
 };
 
-struct MeshPushConstants {
-    data: vec4<f32>,
-    render_matrix: mat4x4<f32>,
+struct GlobalUniforms {
+    view: mat4x4<f32>,
+    projection: mat4x4<f32>,
+    view_projection: mat4x4<f32>,
+    camera_position: vec4<f32>,
+    resolution: vec2<f32>,
+    time: f32,
+    frame_number: u32,
+    ambient_light: vec4<f32>,
+    light_count: u32,
+    cascade_count: u32,
+    ambient_occlusion: u32,
+    light_culling: u32,
+    cascade_splits: vec4<f32>,
+    inverse_projection: mat4x4<f32>,
+    inverse_view_projection: mat4x4<f32>,
+    z_near: f32,
+    z_far: f32,
+    environment: u32,
+    environment_intensity: f32,
+}
+
+struct InstanceData {
+    model: mat4x4<f32>,
+    normal_matrix: mat4x4<f32>,
+    color: vec3<f32>,
+    material_index: u32,
+    lod_fade: f32,
+}
+
+struct Instances {
+    instances: array<InstanceData>,
+}
+
+struct MaterialData {
+    base_color: vec4<f32>,
+    emissive: vec3<f32>,
+    metallic: f32,
+    roughness: f32,
+}
+
+struct Materials {
+    materials: array<MaterialData>,
+}
+
+let LIGHT_KIND_DIRECTIONAL: u32 = 0u;
+let LIGHT_KIND_POINT: u32 = 1u;
+let LIGHT_KIND_SPOT: u32 = 2u;
+
+struct LightData {
+    position: vec3<f32>,
+    kind: u32,
+    direction: vec3<f32>,
+    range: f32,
+    color: vec3<f32>,
+    intensity: f32,
+    inner_cone_cos: f32,
+    outer_cone_cos: f32,
+    shadow_index: i32,
+    normal_bias: f32,
+    pcf_radius: u32,
+}
+
+struct Lights {
+    lights: array<LightData>,
+}
+
+struct ShadowData {
+    view_projection: mat4x4<f32>,
+    texel_size: f32,
+    perspective: u32,
 }
 
-var<push_constant> pc: MeshPushConstants;
+struct Shadows {
+    shadows: array<ShadowData>,
+}
+
+let LIGHT_CULLING_NONE: u32 = 0u;
+let LIGHT_CULLING_CLUSTERED: u32 = 1u;
+let LIGHT_CULLING_HEATMAP: u32 = 2u;
+
+// matches `CLUSTER_GRID` and `MAX_LIGHTS_PER_CLUSTER` in `clusters.rs`
+let CLUSTER_GRID: vec3<u32> = vec3<u32>(16u, 9u, 24u);
+let MAX_LIGHTS_PER_CLUSTER: u32 = 127u;
+
+struct Cluster {
+    count: u32,
+    lights: array<u32, 127>,
+}
+
+struct Clusters {
+    clusters: array<Cluster>,
+}
+
+@group(0) @binding(0)
+var<uniform> globals: GlobalUniforms;
+@group(0) @binding(1)
+var<storage, read> instances: Instances;
+@group(0) @binding(2)
+var<storage, read> materials: Materials;
+@group(0) @binding(3)
+var<storage, read> lights: Lights;
+@group(0) @binding(4)
+var<storage, read> shadows: Shadows;
+@group(0) @binding(5)
+var shadow_maps: texture_depth_2d_array;
+@group(0) @binding(6)
+var shadow_sampler: sampler_comparison;
+@group(0) @binding(7)
+var ambient_occlusion_map: texture_2d<f32>;
+@group(0) @binding(8)
+var<storage, read> clusters: Clusters;
+@group(0) @binding(9)
+var irradiance_map: texture_cube<f32>;
+@group(0) @binding(10)
+var prefiltered_map: texture_cube<f32>;
+@group(0) @binding(11)
+var brdf_lut: texture_2d<f32>;
+@group(0) @binding(12)
+var environment_sampler: sampler;
+
+let PI: f32 = 3.14159265359;
 
 @vertex
 fn vs_main(
     @location(0) vert_position: vec3<f32>,
-    // @location(1) vert_normal: vec3<f32>,
+    @location(1) vert_normal: vec3<f32>,
     @location(2) vert_color: vec3<f32>,
+    @builtin(instance_index) instance_index: u32,
 ) -> VertOut {
     var out: VertOut;
 
-    out.pos = pc.render_matrix * vec4(vert_position, 1.0);
-    out.color = vert_color;
+    let instance = instances.instances[instance_index];
+    let world_position = instance.model * vec4(vert_position, 1.0);
+
+    out.pos = globals.view_projection * world_position;
+    out.color = vert_color * instance.color;
+    out.world_position = world_position.xyz;
+    out.world_normal = (instance.normal_matrix * vec4(vert_normal, 0.0)).xyz;
+    out.material_index = instance.material_index;
+    out.lod_fade = instance.lod_fade;
 
     return out;
 }
 
+// `lod_fade` is at its location in `VertOut` so the meshlet mesh shader can feed `fs_depth`
+struct DepthOut {
+    @builtin(position) @invariant pos: vec4<f32>,
+    @location(4) @interpolate(flat) lod_fade: f32,
+};
+
+@vertex
+fn vs_depth(
+    @location(0) vert_position: vec3<f32>,
+    @builtin(instance_index) instance_index: u32,
+) -> DepthOut {
+    var out: DepthOut;
+
+    let instance = instances.instances[instance_index];
+    out.pos = globals.view_projection * (instance.model * vec4(vert_position, 1.0));
+    out.lod_fade = instance.lod_fade;
+
+    return out;
+}
+
+// the two instances of a mesh fading between levels of detail keep complementary pixels,
+// a positive fade keeps that fraction of them and a negative one the others
+fn lod_discarded(frag_coord: vec2<f32>, fade: f32) -> bool {
+    // interleaved gradient noise
+    let noise = fract(52.9829189 * fract(dot(floor(frag_coord), vec2(0.06711056, 0.00583715))));
+    return (fade > 0.0 && noise >= fade) || (fade < 0.0 && noise < -fade);
+}
+
+@fragment
+fn fs_depth(in: DepthOut) {
+    if (lod_discarded(in.pos.xy, in.lod_fade)) {
+        discard;
+    }
+}
+
+// GGX / Trowbridge-Reitz normal distribution
+fn distribution_ggx(n_dot_h: f32, alpha: f32) -> f32 {
+    let alpha2 = alpha * alpha;
+    let d = n_dot_h * n_dot_h * (alpha2 - 1.0) + 1.0;
+    return alpha2 / (PI * d * d);
+}
+
+// height-correlated Smith visibility, includes the 1 / (4 n.l n.v) term
+fn visibility_smith_ggx(n_dot_v: f32, n_dot_l: f32, alpha: f32) -> f32 {
+    let alpha2 = alpha * alpha;
+    let ggx_v = n_dot_l * sqrt(n_dot_v * n_dot_v * (1.0 - alpha2) + alpha2);
+    let ggx_l = n_dot_v * sqrt(n_dot_l * n_dot_l * (1.0 - alpha2) + alpha2);
+    return 0.5 / max(ggx_v + ggx_l, 0.0001);
+}
+
+fn fresnel_schlick(v_dot_h: f32, f0: vec3<f32>) -> vec3<f32> {
+    return f0 + (vec3(1.0) - f0) * pow(1.0 - v_dot_h, 5.0);
+}
+
+// Fresnel averaged over the microfacets, rough surfaces reflect less at grazing angles
+fn fresnel_schlick_roughness(n_dot_v: f32, f0: vec3<f32>, roughness: f32) -> vec3<f32> {
+    return f0 + (max(vec3(1.0 - roughness), f0) - f0) * pow(1.0 - n_dot_v, 5.0);
+}
+
+// matches `PREFILTERED_MIPS` in `ibl.rs`
+let PREFILTERED_MIPS: u32 = 5u;
+
+// split sum approximation of the light reflected from the environment
+fn environment_light(
+    n: vec3<f32>,
+    v: vec3<f32>,
+    n_dot_v: f32,
+    f0: vec3<f32>,
+    diffuse_color: vec3<f32>,
+    roughness: f32,
+) -> vec3<f32> {
+    let f = fresnel_schlick_roughness(n_dot_v, f0, roughness);
+
+    let irradiance = textureSampleLevel(irradiance_map, environment_sampler, n, 0.0).rgb;
+    let diffuse = (vec3(1.0) - f) * diffuse_color * irradiance;
+
+    let r = reflect(-v, n);
+    let lod = roughness * f32(PREFILTERED_MIPS - 1u);
+    let prefiltered = textureSampleLevel(prefiltered_map, environment_sampler, r, lod).rgb;
+    let brdf = textureSampleLevel(brdf_lut, environment_sampler, vec2(n_dot_v, roughness), 0.0).rg;
+    let specular = prefiltered * (f0 * brdf.x + brdf.y);
+
+    return (diffuse + specular) * globals.environment_intensity;
+}
+
+// incoming radiance from `light` at `position`, `to_light` is set to the normalized light direction
+fn light_radiance(light: LightData, position: vec3<f32>, to_light: ptr<function, vec3<f32>>) -> vec3<f32> {
+    if (light.kind == LIGHT_KIND_DIRECTIONAL) {
+        *to_light = -light.direction;
+        return light.color * light.intensity;
+    }
+
+    let offset = light.position - position;
+    let distance2 = max(dot(offset, offset), 0.0001);
+    let distance = sqrt(distance2);
+    *to_light = offset / distance;
+
+    // KHR_lights_punctual recommended falloff
+    var attenuation = 1.0 / distance2;
+    if (light.range > 0.0) {
+        attenuation *= clamp(1.0 - pow(distance / light.range, 4.0), 0.0, 1.0);
+    }
+
+    if (light.kind == LIGHT_KIND_SPOT) {
+        let cos_angle = dot(light.direction, -*to_light);
+        attenuation *= smoothstep(light.outer_cone_cos, light.inner_cone_cos, cos_angle);
+    }
+
+    return light.color * light.intensity * attenuation;
+}
+
+// PCF filtered visibility of `world_position` in shadow map `index`
+fn sample_shadow(index: u32, light: LightData, world_position: vec3<f32>, normal: vec3<f32>) -> f32 {
+    let shadow = shadows.shadows[index];
+
+    // offset the receiver by a constant number of texels to avoid acne on slopes
+    var texel_size = shadow.texel_size;
+    if (shadow.perspective != 0u) {
+        texel_size *= distance(light.position, world_position);
+    }
+    let position = world_position + normal * light.normal_bias * texel_size;
+
+    let clip = shadow.view_projection * vec4(position, 1.0);
+    let ndc = clip.xyz / clip.w;
+    // y is flipped when rendering the shadow map
+    let uv = vec2(ndc.x, -ndc.y) * 0.5 + 0.5;
+    if (ndc.z > 1.0 || any(uv < vec2(0.0)) || any(uv > vec2(1.0))) {
+        return 1.0;
+    }
+
+    let texel = 1.0 / vec2<f32>(textureDimensions(shadow_maps));
+    let radius = i32(light.pcf_radius);
+
+    var visibility = 0.0;
+    for (var y = -radius; y <= radius; y++) {
+        for (var x = -radius; x <= radius; x++) {
+            let offset = vec2(f32(x), f32(y)) * texel;
+            visibility += textureSampleCompareLevel(shadow_maps, shadow_sampler, uv + offset, i32(index), ndc.z);
+        }
+    }
+
+    let taps = f32((2 * radius + 1) * (2 * radius + 1));
+    return visibility / taps;
+}
+
+fn light_shadow(light: LightData, world_position: vec3<f32>, normal: vec3<f32>) -> f32 {
+    if (light.shadow_index < 0) {
+        return 1.0;
+    }
+
+    var index = u32(light.shadow_index);
+
+    if (light.kind == LIGHT_KIND_DIRECTIONAL) {
+        // pick the first cascade that still covers this view depth
+        let view_depth = -(globals.view * vec4(world_position, 1.0)).z;
+        var cascade = 0u;
+        while (cascade < globals.cascade_count && view_depth > globals.cascade_splits[cascade]) {
+            cascade++;
+        }
+        if (cascade == globals.cascade_count) {
+            return 1.0;
+        }
+        index += cascade;
+    } else if (light.kind == LIGHT_KIND_POINT) {
+        // cube faces are stored as +X, -X, +Y, -Y, +Z, -Z
+        let d = world_position - light.position;
+        let a = abs(d);
+        if (a.x >= a.y && a.x >= a.z) {
+            index += select(1u, 0u, d.x > 0.0);
+        } else if (a.y >= a.z) {
+            index += select(3u, 2u, d.y > 0.0);
+        } else {
+            index += select(5u, 4u, d.z > 0.0);
+        }
+    }
+
+    return sample_shadow(index, light, world_position, normal);
+}
+
+// cluster containing the fragment at `frag_coord` and world-space `position`
+fn cluster_index(frag_coord: vec2<f32>, position: vec3<f32>) -> u32 {
+    let tile = vec2<u32>(frag_coord / globals.resolution * vec2<f32>(CLUSTER_GRID.xy));
+    let view_depth = -(globals.view * vec4(position, 1.0)).z;
+    let slice = log(view_depth / globals.z_near) / log(globals.z_far / globals.z_near);
+    let cluster = min(vec3(tile, u32(max(slice, 0.0) * f32(CLUSTER_GRID.z))), CLUSTER_GRID - 1u);
+    return cluster.x + CLUSTER_GRID.x * (cluster.y + CLUSTER_GRID.y * cluster.z);
+}
+
+// blue for few lights through green to red for many
+fn heatmap(light_count: u32) -> vec3<f32> {
+    let t = clamp(f32(light_count) / 16.0, 0.0, 1.0);
+    return clamp(1.5 - abs(4.0 * t - vec3(3.0, 2.0, 1.0)), vec3(0.0), vec3(1.0));
+}
+
+// a point on a surface with everything needed to light it
+struct Surface {
+    position: vec3<f32>,
+    normal: vec3<f32>,
+    base_color: vec3<f32>,
+    metallic: f32,
+    roughness: f32,
+    emissive: vec3<f32>,
+}
+
+// outgoing radiance towards the camera, `frag_coord` locates the ambient occlusion
+fn shade(surface: Surface, frag_coord: vec2<f32>) -> vec3<f32> {
+    let base_color = surface.base_color;
+    let metallic = clamp(surface.metallic, 0.0, 1.0);
+    let roughness = clamp(surface.roughness, 0.04, 1.0);
+    let alpha = roughness * roughness;
+
+    let n = surface.normal;
+    let v = normalize(globals.camera_position.xyz - surface.position);
+    let n_dot_v = max(dot(n, v), 0.0001);
+
+    let f0 = mix(vec3(0.04), base_color, metallic);
+    let diffuse_color = base_color * (1.0 - metallic);
+
+    // only iterate the lights binned into this fragment's cluster
+    let clustered = globals.light_culling != LIGHT_CULLING_NONE;
+    var cluster = 0u;
+    var light_count = globals.light_count;
+    if (clustered) {
+        cluster = cluster_index(frag_coord, surface.position);
+        light_count = clusters.clusters[cluster].count;
+    }
+
+    var color = vec3(0.0);
+    for (var i = 0u; i < light_count; i++) {
+        var light_index = i;
+        if (clustered) {
+            light_index = clusters.clusters[cluster].lights[i];
+        }
+        let light = lights.lights[light_index];
+        var l: vec3<f32>;
+        let radiance = light_radiance(light, surface.position, &l);
+
+        let n_dot_l = dot(n, l);
+        if (n_dot_l <= 0.0) {
+            continue;
+        }
+
+        let h = normalize(v + l);
+        let n_dot_h = max(dot(n, h), 0.0);
+        let v_dot_h = max(dot(v, h), 0.0);
+
+        let f = fresnel_schlick(v_dot_h, f0);
+        let specular = f * distribution_ggx(n_dot_h, alpha) * visibility_smith_ggx(n_dot_v, n_dot_l, alpha);
+        let diffuse = (vec3(1.0) - f) * diffuse_color / PI;
+
+        let shadow = light_shadow(light, surface.position, n);
+
+        color += (diffuse + specular) * radiance * n_dot_l * shadow;
+    }
+
+    var ambient_occlusion = 1.0;
+    if (globals.ambient_occlusion != 0u) {
+        ambient_occlusion = textureLoad(ambient_occlusion_map, vec2<i32>(frag_coord), 0).r;
+    }
+    var ambient = globals.ambient_light.rgb * base_color;
+    if (globals.environment != 0u) {
+        ambient = environment_light(n, v, n_dot_v, f0, diffuse_color, roughness);
+    }
+    color += ambient * ambient_occlusion;
+    color += surface.emissive;
+
+    if (globals.light_culling == LIGHT_CULLING_HEATMAP) {
+        return mix(min(color, vec3(1.0)), heatmap(light_count), 0.75);
+    }
+
+    return color;
+}
+
+// light back faces as if they were front faces since culling is disabled
+fn facing_normal(world_normal: vec3<f32>, world_position: vec3<f32>) -> vec3<f32> {
+    let n = normalize(world_normal);
+    let v = globals.camera_position.xyz - world_position;
+    return select(n, -n, dot(n, v) < 0.0);
+}
+
+fn vertex_surface(in: VertOut, material: MaterialData) -> Surface {
+    var surface: Surface;
+    surface.position = in.world_position;
+    surface.normal = facing_normal(in.world_normal, in.world_position);
+    surface.base_color = in.color * material.base_color.rgb;
+    surface.metallic = material.metallic;
+    surface.roughness = material.roughness;
+    surface.emissive = material.emissive;
+    return surface;
+}
+
 @fragment
 fn fs_main(
     in: VertOut
 ) -> @location(0) vec4<f32> {
-    return vec4<f32>(in.color, 1.0);
-}
\ No newline at end of file
+    if (lod_discarded(in.pos.xy, in.lod_fade)) {
+        discard;
+    }
+
+    let material = materials.materials[in.material_index];
+    let color = shade(vertex_surface(in, material), in.pos.xy);
+
+    return vec4<f32>(color, material.base_color.a);
+}
+
+struct GBufferOut {
+    @location(0) albedo: vec4<f32>,
+    @location(1) normal: vec4<f32>,
+    @location(2) metallic_roughness: vec4<f32>,
+    @location(3) emissive: vec4<f32>,
+}
+
+@fragment
+fn fs_gbuffer(
+    in: VertOut
+) -> GBufferOut {
+    if (lod_discarded(in.pos.xy, in.lod_fade)) {
+        discard;
+    }
+
+    let material = materials.materials[in.material_index];
+    let surface = vertex_surface(in, material);
+
+    var out: GBufferOut;
+    out.albedo = vec4(surface.base_color, 1.0);
+    out.normal = vec4(surface.normal, 0.0);
+    out.metallic_roughness = vec4(surface.metallic, surface.roughness, 0.0, 0.0);
+    out.emissive = vec4(surface.emissive, 0.0);
+
+    return out;
+}
+
+let GBUFFER_VIEW_ALBEDO: u32 = 1u;
+let GBUFFER_VIEW_NORMAL: u32 = 2u;
+let GBUFFER_VIEW_METALLIC_ROUGHNESS: u32 = 3u;
+let GBUFFER_VIEW_EMISSIVE: u32 = 4u;
+let GBUFFER_VIEW_DEPTH: u32 = 5u;
+
+struct DeferredPushConstants {
+    view: u32,
+}
+
+@group(1) @binding(0)
+var gbuffer_albedo: texture_2d<f32>;
+@group(1) @binding(1)
+var gbuffer_normal: texture_2d<f32>;
+@group(1) @binding(2)
+var gbuffer_metallic_roughness: texture_2d<f32>;
+@group(1) @binding(3)
+var gbuffer_emissive: texture_2d<f32>;
+@group(1) @binding(4)
+var gbuffer_depth: texture_depth_2d;
+
+var<push_constant> deferred_pc: DeferredPushConstants;
+
+struct FullscreenOut {
+    @builtin(position) pos: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+};
+
+@fragment
+fn fs_deferred(
+    in: FullscreenOut
+) -> @location(0) vec4<f32> {
+    let coord = vec2<i32>(in.pos.xy);
+    let depth = textureLoad(gbuffer_depth, coord, 0);
+
+    // y is flipped when rendering
+    let ndc = vec4(in.uv.x * 2.0 - 1.0, 1.0 - in.uv.y * 2.0, depth, 1.0);
+    let world_position = globals.inverse_view_projection * ndc;
+
+    var surface: Surface;
+    surface.position = world_position.xyz / world_position.w;
+    surface.normal = textureLoad(gbuffer_normal, coord, 0).xyz;
+    surface.base_color = textureLoad(gbuffer_albedo, coord, 0).rgb;
+    let metallic_roughness = textureLoad(gbuffer_metallic_roughness, coord, 0).rg;
+    surface.metallic = metallic_roughness.r;
+    surface.roughness = metallic_roughness.g;
+    surface.emissive = textureLoad(gbuffer_emissive, coord, 0).rgb;
+
+    let view = deferred_pc.view;
+    if (view == GBUFFER_VIEW_ALBEDO) {
+        return vec4(surface.base_color, 1.0);
+    } else if (view == GBUFFER_VIEW_NORMAL) {
+        return vec4(surface.normal * 0.5 + 0.5, 1.0);
+    } else if (view == GBUFFER_VIEW_METALLIC_ROUGHNESS) {
+        return vec4(metallic_roughness, 0.0, 1.0);
+    } else if (view == GBUFFER_VIEW_EMISSIVE) {
+        return vec4(surface.emissive, 1.0);
+    } else if (view == GBUFFER_VIEW_DEPTH) {
+        // distance to the camera, brighter is closer
+        let distance = length(surface.position - globals.camera_position.xyz);
+        return vec4(vec3(exp(-distance * 0.05)), 1.0);
+    }
+
+    // nothing was drawn here, keep the background
+    if (depth >= 1.0) {
+        discard;
+    }
+
+    return vec4(shade(surface, in.pos.xy), 1.0);
+}
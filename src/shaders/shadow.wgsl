@@ -0,0 +1,65 @@
+struct InstanceData {
+    model: mat4x4<f32>,
+    normal_matrix: mat4x4<f32>,
+    color: vec3<f32>,
+    material_index: u32,
+    lod_fade: f32,
+}
+
+struct Instances {
+    instances: array<InstanceData>,
+}
+
+struct ShadowData {
+    view_projection: mat4x4<f32>,
+    texel_size: f32,
+    perspective: u32,
+}
+
+struct Shadows {
+    shadows: array<ShadowData>,
+}
+
+struct ShadowPushConstants {
+    shadow_index: u32,
+}
+
+@group(0) @binding(1)
+var<storage, read> instances: Instances;
+@group(0) @binding(4)
+var<storage, read> shadows: Shadows;
+
+var<push_constant> pc: ShadowPushConstants;
+
+struct ShadowOut {
+    @builtin(position) pos: vec4<f32>,
+    @location(0) lod_fade: f32,
+}
+
+@vertex
+fn vs_shadow(
+    @location(0) vert_position: vec3<f32>,
+    @builtin(instance_index) instance_index: u32,
+) -> ShadowOut {
+    var out: ShadowOut;
+
+    let instance = instances.instances[instance_index];
+    out.pos = shadows.shadows[pc.shadow_index].view_projection * instance.model * vec4(vert_position, 1.0);
+    out.lod_fade = instance.lod_fade;
+
+    return out;
+}
+
+// matches `lod_discarded` in `shader_new.wgsl`, so instances fading between levels of
+// detail cast a single shadow
+fn lod_discarded(frag_coord: vec2<f32>, fade: f32) -> bool {
+    let noise = fract(52.9829189 * fract(dot(floor(frag_coord), vec2(0.06711056, 0.00583715))));
+    return (fade > 0.0 && noise >= fade) || (fade < 0.0 && noise < -fade);
+}
+
+@fragment
+fn fs_shadow(in: ShadowOut) {
+    if (lod_discarded(in.pos.xy, in.lod_fade)) {
+        discard;
+    }
+}
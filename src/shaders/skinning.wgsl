@@ -0,0 +1,110 @@
+struct SkinVertex {
+    joints: vec4<u32>,
+    weights: vec4<f32>,
+}
+
+struct SkinVertices {
+    vertices: array<SkinVertex>,
+}
+
+struct JointMatrices {
+    matrices: array<mat4x4<f32>>,
+}
+
+// vertices are packed as in `Vertex`: position, normal and color, three floats each
+struct Vertices {
+    floats: array<f32>,
+}
+
+// position and normal offsets of every vertex, target after target, three floats each
+struct MorphDeltas {
+    floats: array<f32>,
+}
+
+struct MorphWeights {
+    weights: array<f32>,
+}
+
+struct SkinningPushConstants {
+    vertex_count: u32,
+    // first matrix of the draw's joint palette
+    joint_offset: u32,
+    // first vertex written to the output
+    output_offset: u32,
+    // the skin binding is only valid when non-zero
+    skinned: u32,
+    morph_target_count: u32,
+    // first weight of the draw's morph targets
+    weight_offset: u32,
+}
+
+let VERTEX_FLOATS: u32 = 9u;
+let MORPH_DELTA_FLOATS: u32 = 6u;
+
+@group(0) @binding(0)
+var<storage, read> joints: JointMatrices;
+@group(0) @binding(1)
+var<storage, read_write> output: Vertices;
+@group(0) @binding(2)
+var<storage, read> input: Vertices;
+@group(0) @binding(3)
+var<storage, read> skin: SkinVertices;
+@group(0) @binding(4)
+var<storage, read> morph: MorphDeltas;
+@group(0) @binding(5)
+var<storage, read> morph_weights: MorphWeights;
+
+var<push_constant> skinning_pc: SkinningPushConstants;
+
+fn read_vec3(base: u32) -> vec3<f32> {
+    return vec3(input.floats[base], input.floats[base + 1u], input.floats[base + 2u]);
+}
+
+fn read_delta(base: u32) -> vec3<f32> {
+    return vec3(morph.floats[base], morph.floats[base + 1u], morph.floats[base + 2u]);
+}
+
+fn write_vec3(base: u32, value: vec3<f32>) {
+    output.floats[base] = value.x;
+    output.floats[base + 1u] = value.y;
+    output.floats[base + 2u] = value.z;
+}
+
+fn joint(index: u32) -> mat4x4<f32> {
+    return joints.matrices[skinning_pc.joint_offset + index];
+}
+
+@compute @workgroup_size(64, 1, 1)
+fn cs_skin(@builtin(global_invocation_id) id: vec3<u32>) {
+    if (id.x >= skinning_pc.vertex_count) {
+        return;
+    }
+
+    let source = id.x * VERTEX_FLOATS;
+    var position = read_vec3(source);
+    var normal = read_vec3(source + 3u);
+
+    for (var t = 0u; t < skinning_pc.morph_target_count; t = t + 1u) {
+        let weight = morph_weights.weights[skinning_pc.weight_offset + t];
+        let delta = (t * skinning_pc.vertex_count + id.x) * MORPH_DELTA_FLOATS;
+        position = position + read_delta(delta) * weight;
+        normal = normal + read_delta(delta + 3u) * weight;
+    }
+
+    if (skinning_pc.skinned != 0u) {
+        let influence = skin.vertices[id.x];
+        let skin_matrix = joint(influence.joints.x) * influence.weights.x
+            + joint(influence.joints.y) * influence.weights.y
+            + joint(influence.joints.z) * influence.weights.z
+            + joint(influence.joints.w) * influence.weights.w;
+
+        position = (skin_matrix * vec4(position, 1.0)).xyz;
+        // fine as long as the joints are not scaled unevenly
+        normal = (skin_matrix * vec4(normal, 0.0)).xyz;
+    }
+
+    let destination = (skinning_pc.output_offset + id.x) * VERTEX_FLOATS;
+    write_vec3(destination, position);
+    write_vec3(destination + 3u, normalize(normal));
+    write_vec3(destination + 6u, read_vec3(source + 6u));
+}
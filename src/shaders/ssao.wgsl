@@ -0,0 +1,176 @@
+struct GlobalUniforms {
+    view: mat4x4<f32>,
+    projection: mat4x4<f32>,
+    view_projection: mat4x4<f32>,
+    camera_position: vec4<f32>,
+    resolution: vec2<f32>,
+    time: f32,
+    frame_number: u32,
+    ambient_light: vec4<f32>,
+    light_count: u32,
+    cascade_count: u32,
+    ambient_occlusion: u32,
+    cascade_splits: vec4<f32>,
+    inverse_projection: mat4x4<f32>,
+}
+
+struct SsaoParams {
+    radius: f32,
+    bias: f32,
+    intensity: f32,
+    sample_count: u32,
+    blur_radius: u32,
+    blur_sharpness: f32,
+    blur_direction: vec2<i32>,
+}
+
+@group(0) @binding(0)
+var<uniform> globals: GlobalUniforms;
+
+@group(1) @binding(0)
+var depth_image: texture_depth_2d;
+@group(1) @binding(1)
+var input_image: texture_2d<f32>;
+@group(1) @binding(2)
+var output_image: texture_storage_2d<r32float, write>;
+
+var<push_constant> pc: SsaoParams;
+
+let PI: f32 = 3.14159265359;
+
+fn depth_size() -> vec2<i32> {
+    return textureDimensions(depth_image);
+}
+
+// view-space position of the depth buffer texel at `coord`
+fn view_position(coord: vec2<i32>) -> vec3<f32> {
+    let depth = textureLoad(depth_image, coord, 0);
+    let uv = (vec2<f32>(coord) + 0.5) / vec2<f32>(depth_size());
+    // y is flipped when rendering
+    let ndc = vec4(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, depth, 1.0);
+    let position = globals.inverse_projection * ndc;
+    return position.xyz / position.w;
+}
+
+// reconstructs the normal from the neighbours on the same surface, which are
+// the ones closest in depth, so edges do not bend the normals
+fn view_normal(coord: vec2<i32>, center: vec3<f32>) -> vec3<f32> {
+    let max_coord = depth_size() - 1;
+    let left = view_position(clamp(coord - vec2(1, 0), vec2(0), max_coord));
+    let right = view_position(clamp(coord + vec2(1, 0), vec2(0), max_coord));
+    let up = view_position(clamp(coord - vec2(0, 1), vec2(0), max_coord));
+    let down = view_position(clamp(coord + vec2(0, 1), vec2(0), max_coord));
+
+    var dx = right - center;
+    if (abs(left.z - center.z) < abs(right.z - center.z)) {
+        dx = center - left;
+    }
+    var dy = center - up;
+    if (abs(down.z - center.z) < abs(up.z - center.z)) {
+        dy = down - center;
+    }
+
+    var n = normalize(cross(dy, dx));
+    if (dot(n, center) > 0.0) {
+        n = -n;
+    }
+    return n;
+}
+
+// "Interleaved gradient noise" from Jimenez' "Next Generation Post Processing in Call of Duty"
+fn interleaved_gradient_noise(coord: vec2<i32>) -> f32 {
+    return fract(52.9829189 * fract(dot(vec2<f32>(coord), vec2(0.06711056, 0.00583715))));
+}
+
+fn radical_inverse(i: u32) -> f32 {
+    var bits = (i << 16u) | (i >> 16u);
+    bits = ((bits & 0x55555555u) << 1u) | ((bits & 0xAAAAAAAAu) >> 1u);
+    bits = ((bits & 0x33333333u) << 2u) | ((bits & 0xCCCCCCCCu) >> 2u);
+    bits = ((bits & 0x0F0F0F0Fu) << 4u) | ((bits & 0xF0F0F0F0u) >> 4u);
+    bits = ((bits & 0x00FF00FFu) << 8u) | ((bits & 0xFF00FF00u) >> 8u);
+    return f32(bits) * 2.3283064365386963e-10;
+}
+
+@compute @workgroup_size(8, 8)
+fn cs_occlusion(@builtin(global_invocation_id) id: vec3<u32>) {
+    let size = depth_size();
+    let coord = vec2<i32>(id.xy);
+    if (coord.x >= size.x || coord.y >= size.y) {
+        return;
+    }
+
+    // nothing to occlude on the far plane
+    if (textureLoad(depth_image, coord, 0) >= 1.0) {
+        textureStore(output_image, coord, vec4(1.0));
+        return;
+    }
+
+    let position = view_position(coord);
+    let normal = view_normal(coord, position);
+
+    // rotate the kernel per pixel, the blur removes the resulting noise
+    let angle = interleaved_gradient_noise(coord) * 2.0 * PI;
+    let random = vec3(cos(angle), sin(angle), 0.0);
+    let tangent = normalize(random - normal * dot(random, normal));
+    let bitangent = cross(normal, tangent);
+
+    var occlusion = 0.0;
+    for (var i = 0u; i < pc.sample_count; i++) {
+        // cosine weighted hemisphere direction from a Hammersley point
+        let u = (f32(i) + 0.5) / f32(pc.sample_count);
+        let v = radical_inverse(i);
+        let phi = 2.0 * PI * v;
+        let sin_theta = sqrt(u);
+        let direction = vec3(cos(phi) * sin_theta, sin(phi) * sin_theta, sqrt(1.0 - u));
+
+        // place more samples close to the center
+        let scale = mix(0.1, 1.0, u * u);
+        let offset = tangent * direction.x + bitangent * direction.y + normal * direction.z;
+        let sample_position = position + offset * pc.radius * scale;
+
+        let clip = globals.projection * vec4(sample_position, 1.0);
+        let ndc = clip.xy / clip.w;
+        let uv = vec2(ndc.x, -ndc.y) * 0.5 + 0.5;
+        if (any(uv < vec2(0.0)) || any(uv >= vec2(1.0))) {
+            continue;
+        }
+
+        let scene = view_position(vec2<i32>(uv * vec2<f32>(size)));
+        // geometry far in front of the sample does not occlude it
+        let range = smoothstep(0.0, 1.0, pc.radius / abs(position.z - scene.z));
+        occlusion += select(0.0, range, scene.z >= sample_position.z + pc.bias);
+    }
+
+    let visibility = pow(1.0 - occlusion / f32(pc.sample_count), pc.intensity);
+    textureStore(output_image, coord, vec4(visibility));
+}
+
+// separable gaussian that ignores texels at a different depth, so occlusion does not bleed across edges
+@compute @workgroup_size(8, 8)
+fn cs_blur(@builtin(global_invocation_id) id: vec3<u32>) {
+    let size = depth_size();
+    let coord = vec2<i32>(id.xy);
+    if (coord.x >= size.x || coord.y >= size.y) {
+        return;
+    }
+
+    let center_z = view_position(coord).z;
+    let radius = i32(pc.blur_radius);
+    let sigma = max(f32(radius) * 0.5, 0.5);
+
+    var sum = 0.0;
+    var weight_sum = 0.0;
+    for (var i = -radius; i <= radius; i++) {
+        let sample_coord = clamp(coord + pc.blur_direction * i, vec2(0), size - 1);
+        let z = view_position(sample_coord).z;
+
+        let spatial = exp(-f32(i * i) / (2.0 * sigma * sigma));
+        let depth = exp(-abs(z - center_z) / max(abs(center_z), 0.001) * pc.blur_sharpness);
+        let weight = spatial * depth;
+
+        sum += textureLoad(input_image, sample_coord, 0).r * weight;
+        weight_sum += weight;
+    }
+
+    textureStore(output_image, coord, vec4(sum / weight_sum));
+}
@@ -0,0 +1,91 @@
+struct FullscreenOut {
+    @location(0) uv: vec2<f32>,
+};
+
+struct TonemapPushConstants {
+    exposure: f32,
+    tonemapper: u32,
+}
+
+let TONEMAPPER_ACES: u32 = 0u;
+let TONEMAPPER_AGX: u32 = 1u;
+
+@group(0) @binding(0)
+var hdr_image: texture_2d<f32>;
+@group(0) @binding(1)
+var hdr_sampler: sampler;
+
+var<push_constant> pc: TonemapPushConstants;
+
+fn rrt_and_odt_fit(v: vec3<f32>) -> vec3<f32> {
+    let a = v * (v + 0.0245786) - 0.000090537;
+    let b = v * (0.983729 * v + 0.4329510) + 0.238081;
+    return a / b;
+}
+
+fn tonemap_aces(color: vec3<f32>) -> vec3<f32> {
+    // sRGB => XYZ => D65_2_D60 => AP1 => RRT_SAT
+    let aces_input = mat3x3<f32>(
+        vec3(0.59719, 0.07600, 0.02840),
+        vec3(0.35458, 0.90834, 0.13383),
+        vec3(0.04823, 0.01566, 0.83777),
+    );
+    // ODT_SAT => XYZ => D60_2_D65 => sRGB
+    let aces_output = mat3x3<f32>(
+        vec3(1.60475, -0.10208, -0.00327),
+        vec3(-0.53108, 1.10813, -0.07276),
+        vec3(-0.07367, -0.00605, 1.07602),
+    );
+
+    return clamp(aces_output * rrt_and_odt_fit(aces_input * color), vec3(0.0), vec3(1.0));
+}
+
+fn agx_default_contrast(x: vec3<f32>) -> vec3<f32> {
+    let x2 = x * x;
+    let x4 = x2 * x2;
+    return 15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x + 0.4298 * x2 + 0.1191 * x - 0.00232;
+}
+
+fn tonemap_agx(color: vec3<f32>) -> vec3<f32> {
+    let agx_inset = mat3x3<f32>(
+        vec3(0.842479062253094, 0.0423282422610123, 0.0423756549057051),
+        vec3(0.0784335999999992, 0.878468636469772, 0.0784336),
+        vec3(0.0792237451477643, 0.0791661274605434, 0.879142973793104),
+    );
+    let agx_outset = mat3x3<f32>(
+        vec3(1.19687900512017, -0.0528968517574562, -0.0529716355144438),
+        vec3(-0.0980208811401368, 1.15190312990417, -0.0980434501171241),
+        vec3(-0.0990297440797205, -0.0989611768448433, 1.15107367264116),
+    );
+    let min_ev = -12.47393;
+    let max_ev = 4.026069;
+
+    var c = agx_inset * color;
+    c = clamp(log2(max(c, vec3(1e-10))), vec3(min_ev), vec3(max_ev));
+    c = (c - min_ev) / (max_ev - min_ev);
+    c = agx_default_contrast(c);
+    c = agx_outset * c;
+
+    // the curve outputs display encoded values, the swapchain expects linear ones
+    return pow(clamp(c, vec3(0.0), vec3(1.0)), vec3(2.2));
+}
+
+fn tonemap_reinhard(color: vec3<f32>) -> vec3<f32> {
+    return color / (1.0 + color);
+}
+
+@fragment
+fn fs_tonemap(in: FullscreenOut) -> @location(0) vec4<f32> {
+    let hdr = textureSampleLevel(hdr_image, hdr_sampler, in.uv, 0.0).rgb * pc.exposure;
+
+    var color: vec3<f32>;
+    if (pc.tonemapper == TONEMAPPER_ACES) {
+        color = tonemap_aces(hdr);
+    } else if (pc.tonemapper == TONEMAPPER_AGX) {
+        color = tonemap_agx(hdr);
+    } else {
+        color = tonemap_reinhard(hdr);
+    }
+
+    return vec4(color, 1.0);
+}
@@ -0,0 +1,3 @@
+pub mod pass;
+
+pub use pass::SkinningPass;
@@ -0,0 +1,327 @@
+use crate::{
+    backend_vulkan::{
+        buffer::Buffer,
+        descriptor::{write_buffer_descriptor, DescriptorPool, DescriptorSetLayoutBuilder},
+        device::{Device, FRAMES_IN_FLIGHT},
+        mesh::{Mesh, Vertex},
+        pipeline::ComputePipeline,
+        shader::{ShaderLanguage, ShaderSource, ShaderStage},
+    },
+    resource::{DeletionQueue, MeshHandle, Pool},
+};
+use anyhow::Result;
+use ash::vk;
+use glam::Mat4;
+use gpu_allocator::vulkan::Allocator;
+use std::mem::size_of;
+
+const JOINTS_BINDING: u32 = 0;
+const OUTPUT_BINDING: u32 = 1;
+const INPUT_BINDING: u32 = 2;
+const SKIN_BINDING: u32 = 3;
+const MORPH_BINDING: u32 = 4;
+const WEIGHTS_BINDING: u32 = 5;
+
+/// Workgroup size of the skinning shader
+const GROUP_SIZE: u32 = 64;
+
+#[derive(Clone, Copy, Debug, Default)]
+#[repr(C)]
+struct SkinningPushConstants {
+    vertex_count: u32,
+    joint_offset: u32,
+    output_offset: u32,
+    skinned: u32,
+    morph_target_count: u32,
+    weight_offset: u32,
+}
+
+/// A skinned or morphed mesh to deform this frame
+pub(crate) struct DeformedMesh {
+    pub(crate) mesh: MeshHandle,
+    /// First matrix of the mesh's joint palette, the mesh is not skinned when unset
+    pub(crate) joint_offset: Option<u32>,
+    /// First of the mesh's morph target weights
+    pub(crate) weight_offset: u32,
+}
+
+/// Buffers owned by a single frame in flight, they grow to fit all of its deformed meshes
+struct SkinningFrame {
+    joints: Buffer,
+    joint_capacity: usize,
+    weights: Buffer,
+    weight_capacity: usize,
+    vertices: Buffer,
+    vertex_capacity: usize,
+    descriptor_pool: DescriptorPool,
+    set_capacity: u32,
+    dispatches: Vec<(vk::DescriptorSet, SkinningPushConstants)>,
+}
+
+/// Compute pre-pass blending the morph targets of meshes and then deforming skinned ones by
+/// their joint palettes, the results are drawn in place of the meshes' own vertices by
+/// every later pass
+pub struct SkinningPass {
+    layout: vk::DescriptorSetLayout,
+    pipeline: ComputePipeline,
+    frames: Vec<SkinningFrame>,
+}
+
+impl SkinningPass {
+    pub fn new(allocator: &mut Allocator, device: &Device) -> Result<Self> {
+        let layout = [
+            JOINTS_BINDING,
+            OUTPUT_BINDING,
+            INPUT_BINDING,
+            SKIN_BINDING,
+            MORPH_BINDING,
+            WEIGHTS_BINDING,
+        ]
+        .into_iter()
+        .fold(DescriptorSetLayoutBuilder::default(), |builder, binding| {
+            builder.binding(
+                binding,
+                vk::DescriptorType::STORAGE_BUFFER,
+                vk::ShaderStageFlags::COMPUTE,
+            )
+        })
+        .build(device)?;
+
+        let shader = ShaderSource::builder().entry("cs_skin").build(
+            ShaderStage::Compute,
+            ShaderLanguage::WGSL,
+            "./src/shaders/skinning.wgsl",
+        );
+        let pipeline = ComputePipeline::new(
+            device,
+            &shader,
+            &[layout],
+            size_of::<SkinningPushConstants>(),
+        )?;
+
+        let frames = (0..FRAMES_IN_FLIGHT)
+            .map(|_| {
+                Ok(SkinningFrame {
+                    joints: Self::create_joints(allocator, device, 256),
+                    joint_capacity: 256,
+                    weights: Self::create_weights(allocator, device, 256),
+                    weight_capacity: 256,
+                    vertices: Self::create_vertices(allocator, device, 4096),
+                    vertex_capacity: 4096,
+                    descriptor_pool: Self::create_pool(device, 16)?,
+                    set_capacity: 16,
+                    dispatches: vec![],
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(SkinningPass {
+            layout,
+            pipeline,
+            frames,
+        })
+    }
+
+    fn create_joints(allocator: &mut Allocator, device: &Device, capacity: usize) -> Buffer {
+        Buffer::new(
+            allocator,
+            device,
+            capacity * size_of::<Mat4>(),
+            vk::BufferUsageFlags::STORAGE_BUFFER,
+            "joint matrices",
+        )
+    }
+
+    fn create_weights(allocator: &mut Allocator, device: &Device, capacity: usize) -> Buffer {
+        Buffer::new(
+            allocator,
+            device,
+            capacity * size_of::<f32>(),
+            vk::BufferUsageFlags::STORAGE_BUFFER,
+            "morph weights",
+        )
+    }
+
+    fn create_vertices(allocator: &mut Allocator, device: &Device, capacity: usize) -> Buffer {
+        Buffer::new(
+            allocator,
+            device,
+            capacity * size_of::<Vertex>(),
+            vk::BufferUsageFlags::STORAGE_BUFFER | vk::BufferUsageFlags::VERTEX_BUFFER,
+            "deformed vertices",
+        )
+    }
+
+    fn create_pool(device: &Device, max_sets: u32) -> Result<DescriptorPool> {
+        DescriptorPool::new(
+            device,
+            max_sets,
+            &[vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::STORAGE_BUFFER,
+                descriptor_count: 6 * max_sets,
+            }],
+        )
+    }
+
+    /// Uploads the joint palettes and morph weights of frame `frame_index` and prepares
+    /// a dispatch per mesh. Returns the buffer and byte offsets to draw each deformed
+    /// mesh's vertices from.
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn upload(
+        &mut self,
+        allocator: &mut Allocator,
+        device: &Device,
+        deletion_queue: &mut DeletionQueue,
+        frame_number: u64,
+        frame_index: usize,
+        meshes: &Pool<Mesh>,
+        joints: &[Mat4],
+        weights: &[f32],
+        deformed: &[DeformedMesh],
+    ) -> Result<(vk::Buffer, Vec<vk::DeviceSize>)> {
+        let frame = &mut self.frames[frame_index];
+        frame.dispatches.clear();
+
+        if joints.len() > frame.joint_capacity {
+            frame.joint_capacity = joints.len().next_power_of_two();
+            let buffer = Self::create_joints(allocator, device, frame.joint_capacity);
+            deletion_queue.push(frame_number, std::mem::replace(&mut frame.joints, buffer));
+        }
+        frame.joints.write(0, joints);
+
+        if weights.len() > frame.weight_capacity {
+            frame.weight_capacity = weights.len().next_power_of_two();
+            let buffer = Self::create_weights(allocator, device, frame.weight_capacity);
+            deletion_queue.push(frame_number, std::mem::replace(&mut frame.weights, buffer));
+        }
+        frame.weights.write(0, weights);
+
+        let vertex_count = deformed
+            .iter()
+            .map(|deformed| meshes[deformed.mesh].vertex_count as usize)
+            .sum::<usize>();
+        if vertex_count > frame.vertex_capacity {
+            frame.vertex_capacity = vertex_count.next_power_of_two();
+            let buffer = Self::create_vertices(allocator, device, frame.vertex_capacity);
+            deletion_queue.push(frame_number, std::mem::replace(&mut frame.vertices, buffer));
+        }
+
+        // the frame's previous sets are no longer in use once it is recorded again
+        if deformed.len() as u32 > frame.set_capacity {
+            frame.set_capacity = (deformed.len() as u32).next_power_of_two();
+            let pool = Self::create_pool(device, frame.set_capacity)?;
+            std::mem::replace(&mut frame.descriptor_pool, pool).destroy(device);
+        } else {
+            frame.descriptor_pool.reset(device)?;
+        }
+
+        let mut offsets = Vec::with_capacity(deformed.len());
+        let mut output_offset = 0;
+        for deformed in deformed {
+            let mesh = &meshes[deformed.mesh];
+            let skin_buffer = mesh
+                .skin_buffer
+                .as_ref()
+                .filter(|_| deformed.joint_offset.is_some());
+            let set = frame.descriptor_pool.allocate(device, self.layout)?;
+            // unused bindings still need a valid buffer, the input vertices will do
+            for (binding, buffer) in [
+                (JOINTS_BINDING, &frame.joints),
+                (OUTPUT_BINDING, &frame.vertices),
+                (INPUT_BINDING, &mesh.vertex_buffer),
+                (SKIN_BINDING, skin_buffer.unwrap_or(&mesh.vertex_buffer)),
+                (
+                    MORPH_BINDING,
+                    mesh.morph_buffer.as_ref().unwrap_or(&mesh.vertex_buffer),
+                ),
+                (WEIGHTS_BINDING, &frame.weights),
+            ] {
+                write_buffer_descriptor(
+                    device,
+                    set,
+                    binding,
+                    vk::DescriptorType::STORAGE_BUFFER,
+                    buffer,
+                );
+            }
+
+            frame.dispatches.push((
+                set,
+                SkinningPushConstants {
+                    vertex_count: mesh.vertex_count,
+                    joint_offset: deformed.joint_offset.unwrap_or_default(),
+                    output_offset,
+                    skinned: skin_buffer.is_some() as u32,
+                    morph_target_count: mesh.morph_target_count,
+                    weight_offset: deformed.weight_offset,
+                },
+            ));
+            offsets.push((output_offset as usize * size_of::<Vertex>()) as vk::DeviceSize);
+            output_offset += mesh.vertex_count;
+        }
+
+        Ok((frame.vertices.raw, offsets))
+    }
+
+    /// Deforms the meshes uploaded for frame `frame_index`, the results can be read
+    /// as vertex attributes afterwards
+    pub fn record(&self, device: &Device, cmd: vk::CommandBuffer, frame_index: usize) {
+        let frame = &self.frames[frame_index];
+        if frame.dispatches.is_empty() {
+            return;
+        }
+
+        unsafe {
+            device.raw.cmd_bind_pipeline(
+                cmd,
+                vk::PipelineBindPoint::COMPUTE,
+                self.pipeline.pipeline,
+            );
+        }
+
+        for (set, constants) in &frame.dispatches {
+            unsafe {
+                device.raw.cmd_bind_descriptor_sets(
+                    cmd,
+                    vk::PipelineBindPoint::COMPUTE,
+                    self.pipeline.layout,
+                    0,
+                    &[*set],
+                    &[],
+                );
+            }
+            self.pipeline.push_constants(device, cmd, constants);
+            unsafe {
+                device
+                    .raw
+                    .cmd_dispatch(cmd, constants.vertex_count.div_ceil(GROUP_SIZE), 1, 1)
+            };
+        }
+
+        unsafe {
+            let barrier = vk::MemoryBarrier::builder()
+                .src_access_mask(vk::AccessFlags::SHADER_WRITE)
+                .dst_access_mask(vk::AccessFlags::VERTEX_ATTRIBUTE_READ);
+            device.raw.cmd_pipeline_barrier(
+                cmd,
+                vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::PipelineStageFlags::VERTEX_INPUT,
+                vk::DependencyFlags::empty(),
+                &[barrier.build()],
+                &[],
+                &[],
+            );
+        }
+    }
+
+    pub fn destroy(&mut self, device: &Device, allocator: &mut Allocator) {
+        for frame in &mut self.frames {
+            frame.joints.destroy(device, allocator);
+            frame.weights.destroy(device, allocator);
+            frame.vertices.destroy(device, allocator);
+            frame.descriptor_pool.destroy(device);
+        }
+        self.pipeline.destroy(device);
+        unsafe { device.raw.destroy_descriptor_set_layout(self.layout, None) };
+    }
+}